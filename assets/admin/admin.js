@@ -0,0 +1,244 @@
+// Lists, creates, edits and deletes users through the API, signed in as a user of it. The page
+// itself holds nothing; what the signed-in user may do is up to the API's roles
+"use strict";
+
+const API = "/v1";
+const PAGE_SIZE = 20;
+// Tokens last for the browser tab, not beyond it
+const STORAGE_KEY = "rust-crud-api-admin";
+
+let tokens = JSON.parse(sessionStorage.getItem(STORAGE_KEY) || "null");
+let offset = 0;
+let filter = "";
+
+function element(tag, properties, ...children) {
+  const node = Object.assign(document.createElement(tag), properties);
+  node.append(...children.filter((child) => child !== null && child !== undefined));
+  return node;
+}
+
+function show(message, failed) {
+  const status = document.getElementById("status");
+  status.textContent = message;
+  status.className = failed ? "failed" : "";
+}
+
+function saveTokens(next) {
+  tokens = next;
+  if (tokens) {
+    sessionStorage.setItem(STORAGE_KEY, JSON.stringify(tokens));
+  } else {
+    sessionStorage.removeItem(STORAGE_KEY);
+  }
+}
+
+// The access token's claims; only read for display, the API checks them
+function claims() {
+  try {
+    const payload = tokens.access_token.split(".")[1].replace(/-/g, "+").replace(/_/g, "/");
+    return JSON.parse(atob(payload));
+  } catch (error) {
+    return {};
+  }
+}
+
+// An error body's message, with each invalid field's messages after it
+function describe(body, response) {
+  const error = body && body.error;
+  if (!error) {
+    return response.status + " " + response.statusText;
+  }
+  const fields = error.details && error.details.fields;
+  if (!fields) {
+    return error.message;
+  }
+  const problems = Object.entries(fields).map(([field, messages]) => field + ": " + [].concat(messages).join(", "));
+  return error.message + " (" + problems.join("; ") + ")";
+}
+
+// Call the API with the signed-in user's token, refreshing it once when it has expired. Resolves to
+// the response and its JSON body; rejects with the error message otherwise
+async function api(method, path, body, headers) {
+  const send = () => {
+    const init = { method, headers: Object.assign({ Accept: "application/json" }, headers) };
+    if (tokens) {
+      init.headers["Authorization"] = "Bearer " + tokens.access_token;
+    }
+    if (body !== undefined) {
+      init.headers["Content-Type"] = "application/json";
+      init.body = JSON.stringify(body);
+    }
+    return fetch(path.startsWith(API) ? path : API + path, init);
+  };
+
+  let response = await send();
+  if (response.status === 401 && tokens && (await refresh())) {
+    response = await send();
+  }
+  if (response.status === 401 && tokens) {
+    signOut("Your session has ended; sign in again");
+    throw new Error("Not signed in");
+  }
+  const text = await response.text();
+  const json = text ? JSON.parse(text) : null;
+  if (!response.ok) {
+    throw new Error(describe(json, response));
+  }
+  return { response, json };
+}
+
+async function refresh() {
+  const response = await fetch(API + "/auth/refresh", {
+    method: "POST",
+    headers: { "Content-Type": "application/json", Accept: "application/json" },
+    body: JSON.stringify({ refresh_token: tokens.refresh_token }),
+  });
+  if (!response.ok) {
+    return false;
+  }
+  saveTokens(await response.json());
+  return true;
+}
+
+function render() {
+  const signedIn = Boolean(tokens);
+  document.getElementById("login").hidden = signedIn;
+  document.getElementById("users").hidden = !signedIn;
+  document.getElementById("session").hidden = !signedIn;
+  if (signedIn) {
+    const { sub, role } = claims();
+    document.getElementById("who").textContent = "User " + sub + " (" + role + ")";
+    load();
+  }
+}
+
+function signOut(message) {
+  saveTokens(null);
+  render();
+  show(message || "", Boolean(message));
+}
+
+async function load() {
+  const query = new URLSearchParams({ limit: PAGE_SIZE, offset, sort: "id" });
+  if (filter) {
+    query.set("email_contains", filter);
+  }
+  try {
+    const { json: page } = await api("GET", "/users?" + query);
+    const rows = document.getElementById("rows");
+    rows.textContent = "";
+    rows.append(...page.users.map(row));
+    const last = Math.min(offset + page.users.length, page.total);
+    document.getElementById("position").textContent = page.total ? offset + 1 + "–" + last + " of " + page.total : "No users";
+    document.getElementById("prev").disabled = offset === 0;
+    document.getElementById("next").disabled = offset + PAGE_SIZE >= page.total;
+  } catch (error) {
+    show(error.message, true);
+  }
+}
+
+// A user's row: its fields, editable in place, and what can be done with it. The user's own links
+// say where to send changes
+function row(user) {
+  const name = element("input", { value: user.name, required: true });
+  const email = element("input", { value: user.email, type: "email", required: true });
+  const created = user.created_at ? new Date(user.created_at).toLocaleString() : "";
+
+  const save = element("button", { type: "button", textContent: "Save" });
+  save.addEventListener("click", async () => {
+    try {
+      // Only the fields that changed, and only if nobody else changed the user meanwhile
+      const patch = {};
+      if (name.value !== user.name) {
+        patch.name = name.value;
+      }
+      if (email.value !== user.email) {
+        patch.email = email.value;
+      }
+      await api("PATCH", user._links.update.href, patch, { "If-Match": '"' + user.version + '"' });
+      show("Saved user " + user.id);
+      load();
+    } catch (error) {
+      show(error.message, true);
+    }
+  });
+
+  const remove = element("button", { type: "button", className: "danger", textContent: "Delete" });
+  remove.addEventListener("click", async () => {
+    if (!confirm("Delete " + user.email + "?")) {
+      return;
+    }
+    try {
+      await api("DELETE", user._links.delete.href);
+      show("Deleted user " + user.id);
+      load();
+    } catch (error) {
+      show(error.message, true);
+    }
+  });
+
+  return element(
+    "tr",
+    {},
+    element("td", { textContent: user.id }),
+    element("td", {}, name),
+    element("td", {}, email),
+    element("td", { textContent: created }),
+    element("td", { className: "actions" }, save, remove)
+  );
+}
+
+document.getElementById("login").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const form = new FormData(event.target);
+  try {
+    const { json } = await api("POST", "/auth/login", { email: form.get("email"), password: form.get("password") });
+    saveTokens(json);
+    event.target.reset();
+    show("");
+    render();
+  } catch (error) {
+    show(error.message, true);
+  }
+});
+
+document.getElementById("logout").addEventListener("click", async () => {
+  try {
+    await api("POST", "/auth/logout");
+  } catch (error) {
+    // Signed out here either way
+  }
+  signOut();
+});
+
+document.getElementById("create").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const form = new FormData(event.target);
+  try {
+    const { json: user } = await api("POST", "/users", { name: form.get("name"), email: form.get("email") });
+    event.target.reset();
+    show("Created user " + user.id);
+    load();
+  } catch (error) {
+    show(error.message, true);
+  }
+});
+
+document.getElementById("filter").addEventListener("submit", (event) => {
+  event.preventDefault();
+  filter = new FormData(event.target).get("email_contains").trim();
+  offset = 0;
+  load();
+});
+
+document.getElementById("prev").addEventListener("click", () => {
+  offset = Math.max(0, offset - PAGE_SIZE);
+  load();
+});
+
+document.getElementById("next").addEventListener("click", () => {
+  offset += PAGE_SIZE;
+  load();
+});
+
+render();
@@ -0,0 +1,211 @@
+// Renders /v1/openapi.json as a list of operations that can be tried from the browser
+"use strict";
+
+const SPEC_URL = "/v1/openapi.json";
+const METHODS = ["get", "post", "put", "patch", "delete"];
+
+let spec;
+
+fetch(SPEC_URL)
+  .then((response) => response.json())
+  .then((document_) => {
+    spec = document_;
+    render();
+  })
+  .catch((error) => {
+    document.getElementById("operations").textContent = "Failed to load " + SPEC_URL + ": " + error;
+  });
+
+function element(tag, properties, ...children) {
+  const node = Object.assign(document.createElement(tag), properties);
+  node.append(...children.filter((child) => child !== null && child !== undefined));
+  return node;
+}
+
+function render() {
+  document.title = spec.info.title + " API docs";
+  document.getElementById("title").textContent = spec.info.title + " " + spec.info.version;
+
+  // Operations grouped by the path's first segment: users, posts, groups, ...
+  const groups = new Map();
+  for (const [path, item] of Object.entries(spec.paths)) {
+    for (const method of METHODS.filter((method) => item[method])) {
+      const group = path.split("/")[1];
+      if (!groups.has(group)) {
+        groups.set(group, []);
+      }
+      groups.get(group).push(operation(method, path, item[method]));
+    }
+  }
+
+  const main = document.getElementById("operations");
+  main.textContent = "";
+  for (const [group, operations] of groups) {
+    main.append(element("h2", { textContent: group }), ...operations);
+  }
+}
+
+function operation(method, path, op) {
+  const summary = element(
+    "summary",
+    { className: op.security ? "secured" : "" },
+    element("span", { className: "method " + method, textContent: method.toUpperCase() }),
+    element("span", { className: "path", textContent: path }),
+    element("span", { textContent: op.summary })
+  );
+
+  const inputs = op.parameters.map((parameter) => {
+    const input = element("input", { name: parameter.name, placeholder: parameter.schema.type });
+    input.dataset.in = parameter.in;
+    const label = element(
+      "label",
+      {},
+      parameter.name + (parameter.required ? " *" : ""),
+      input,
+      parameter.description ? element("small", { textContent: parameter.description }) : null
+    );
+    return { parameter, input, label };
+  });
+
+  let body = null;
+  let mediaType = null;
+  if (op.requestBody) {
+    const content = op.requestBody.content;
+    mediaType = element("select", {}, ...Object.keys(content).map((type) => element("option", { value: type, textContent: type })));
+    body = element("textarea", { spellcheck: false });
+    const fill = () => {
+      const example = exampleFor(content[mediaType.value].schema, 0);
+      body.value = typeof example === "string" ? example : JSON.stringify(example, null, 2);
+    };
+    mediaType.addEventListener("change", fill);
+    fill();
+  }
+
+  const output = element("pre", { hidden: true });
+  const button = element("button", { type: "button", textContent: "Send" });
+  button.addEventListener("click", () => send(method, path, inputs, mediaType, body, output));
+
+  const responses = element(
+    "ul",
+    { className: "responses" },
+    ...Object.entries(op.responses).map(([status, response]) =>
+      element("li", {}, element("strong", { textContent: status }), " " + response.description + schemaName(response))
+    )
+  );
+
+  return element(
+    "details",
+    {},
+    summary,
+    element(
+      "div",
+      { className: "body" },
+      inputs.length ? element("h3", { textContent: "Parameters" }) : null,
+      ...inputs.map((input) => input.label),
+      body ? element("h3", { textContent: "Request body" }) : null,
+      mediaType,
+      body,
+      element("h3", { textContent: "Responses" }),
+      responses,
+      button,
+      output
+    )
+  );
+}
+
+// " (User)" for a response whose JSON body is a named schema
+function schemaName(response) {
+  const schema = response.content && response.content["application/json"] && response.content["application/json"].schema;
+  if (!schema) {
+    return "";
+  }
+  const ref = schema.$ref || (schema.items && schema.items.$ref);
+  return ref ? " (" + (schema.items ? "array of " : "") + ref.split("/").pop() + ")" : "";
+}
+
+function resolve(schema) {
+  return schema && schema.$ref ? spec.components.schemas[schema.$ref.split("/").pop()] : schema;
+}
+
+// A request body to start editing from; read-only fields are left out
+function exampleFor(schema, depth) {
+  schema = resolve(schema);
+  if (!schema || depth > 4) {
+    return null;
+  }
+  if (schema.allOf) {
+    return exampleFor(schema.allOf[0], depth + 1);
+  }
+  if (schema.enum) {
+    return schema.enum[0];
+  }
+  switch (schema.type) {
+    case "object": {
+      const example = {};
+      for (const [name, property] of Object.entries(schema.properties || {})) {
+        const resolved = resolve(property);
+        if (!property.readOnly && !(resolved && resolved.readOnly)) {
+          example[name] = exampleFor(property, depth + 1);
+        }
+      }
+      return example;
+    }
+    case "array":
+      return [exampleFor(schema.items, depth + 1)];
+    case "integer":
+    case "number":
+      return 0;
+    case "boolean":
+      return false;
+    case "string":
+      return schema.format === "email" ? "user@example.com" : "";
+    default:
+      return null;
+  }
+}
+
+async function send(method, path, inputs, mediaType, body, output) {
+  const query = new URLSearchParams();
+  for (const { parameter, input } of inputs) {
+    if (parameter.in === "path") {
+      path = path.replace("{" + parameter.name + "}", encodeURIComponent(input.value));
+    } else if (input.value !== "") {
+      query.append(parameter.name, input.value);
+    }
+  }
+
+  const headers = {};
+  const secret = document.getElementById("secret").value.trim();
+  if (secret) {
+    if (document.getElementById("scheme").value === "bearer") {
+      headers["Authorization"] = "Bearer " + secret;
+    } else {
+      headers[spec.components.securitySchemes.apiKey.name] = secret;
+    }
+  }
+  const init = { method: method.toUpperCase(), headers };
+  if (body) {
+    headers["Content-Type"] = mediaType.value;
+    init.body = body.value;
+  }
+
+  // Paths are relative to the API version's prefix, the document's server
+  const server = spec.servers && spec.servers.length ? spec.servers[0].url : "";
+  const url = server + path + (query.toString() ? "?" + query : "");
+  output.hidden = false;
+  output.textContent = init.method + " " + url + "\n\n…";
+  try {
+    const response = await fetch(url, init);
+    const text = await response.text();
+    let shown = text;
+    try {
+      shown = JSON.stringify(JSON.parse(text), null, 2);
+    } catch (_) {
+      // Not JSON: shown as is
+    }
+    const responseHeaders = [...response.headers].map(([name, value]) => name + ": " + value).join("\n");
+    output.textContent = init.method + " " + url + "\n\n" + response.status + " " + response.statusText + "\n" + responseHeaders + "\n\n" + shown;
+  } catch (error) {
+    output.textContent = init.method + " " + url + "\n\nRequest failed: " + error;
+  }
+}
@@ -0,0 +1,117 @@
+// A small benchmark harness for `cargo bench`: each benchmark is warmed up, then timed over a
+// number of samples, and the median time per iteration is compared with the last run's, which is
+// kept under target/bench-baselines. Arguments that aren't flags filter benchmarks by name
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+const WARM_UP: Duration = Duration::from_millis(500);
+const MEASUREMENT: Duration = Duration::from_secs(3);
+const SAMPLES: u64 = 50;
+
+// A change in median beyond this is reported as a regression or an improvement
+const NOISE_THRESHOLD: f64 = 0.05;
+
+pub struct Bench {
+    group: String,
+    filters: Vec<String>,
+    baselines: PathBuf,
+}
+
+impl Bench {
+    pub fn new(group: &str) -> Bench {
+        // `cargo bench` passes --bench; `cargo test --benches` runs each benchmark once as a smoke test
+        let filters = env::args().skip(1).filter(|arg| !arg.starts_with('-')).collect();
+        let target = env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| concat!(env!("CARGO_MANIFEST_DIR"), "/target").to_string());
+        Bench {
+            group: group.to_string(),
+            filters,
+            baselines: PathBuf::from(target).join("bench-baselines").join(group),
+        }
+    }
+
+    fn selected(&self, name: &str) -> bool {
+        let full = format!("{}/{}", self.group, name);
+        self.filters.is_empty() || self.filters.iter().any(|filter| full.contains(filter.as_str()))
+    }
+
+    // Time `f`, which runs the benchmarked code once per call
+    pub fn function(&self, name: &str, mut f: impl FnMut()) {
+        self.custom(name, |iterations| {
+            let started = Instant::now();
+            for _ in 0..iterations {
+                f();
+            }
+            started.elapsed()
+        });
+    }
+
+    // Time `f`, which runs the benchmarked code the given number of times and returns how long
+    // that took, leaving out any setup
+    pub fn custom(&self, name: &str, mut f: impl FnMut(u64) -> Duration) {
+        if !self.selected(name) {
+            return;
+        }
+        if !env::args().any(|arg| arg == "--bench") {
+            f(1);
+            println!("{}/{}: ok", self.group, name);
+            return;
+        }
+
+        // Warm up while working out how many iterations fill one sample
+        let mut iterations = 1;
+        let mut spent = Duration::ZERO;
+        let started = Instant::now();
+        while started.elapsed() < WARM_UP {
+            spent = f(iterations);
+            if spent < WARM_UP / 10 {
+                iterations *= 2;
+            }
+        }
+        let per_iteration = spent.as_secs_f64() / iterations as f64;
+        let per_sample = ((MEASUREMENT.as_secs_f64() / SAMPLES as f64 / per_iteration) as u64).max(1);
+
+        let mut times: Vec<f64> = (0..SAMPLES).map(|_| f(per_sample).as_secs_f64() / per_sample as f64).collect();
+        times.sort_by(f64::total_cmp);
+        let median = times[times.len() / 2];
+        let (low, high) = (times[times.len() / 20], times[times.len() * 19 / 20]);
+        println!(
+            "{}/{:<32} {:>12} [{} .. {}]{}",
+            self.group,
+            name,
+            format_time(median),
+            format_time(low),
+            format_time(high),
+            self.compare(name, median)
+        );
+    }
+
+    // How `median` compares with the previous run's, which it then replaces
+    fn compare(&self, name: &str, median: f64) -> String {
+        let path = self.baselines.join(name.replace('/', "_"));
+        let previous = fs::read_to_string(&path).ok().and_then(|text| text.trim().parse::<f64>().ok());
+        let _ = fs::create_dir_all(&self.baselines);
+        let _ = fs::write(&path, median.to_string());
+        let Some(previous) = previous else {
+            return String::new();
+        };
+        let change = median / previous - 1.0;
+        let verdict = match change {
+            _ if change > NOISE_THRESHOLD => "regressed",
+            _ if change < -NOISE_THRESHOLD => "improved",
+            _ => "no change",
+        };
+        format!("  {:+.1}% {}", change * 100.0, verdict)
+    }
+}
+
+fn format_time(seconds: f64) -> String {
+    match seconds {
+        s if s < 1e-6 => format!("{:.1} ns", s * 1e9),
+        s if s < 1e-3 => format!("{:.2} µs", s * 1e6),
+        s if s < 1.0 => format!("{:.2} ms", s * 1e3),
+        s => format!("{:.2} s", s),
+    }
+}
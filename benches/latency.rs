@@ -0,0 +1,186 @@
+// End-to-end latency: a server on a random port backed by a pooled Postgres connection, and the
+// repository calls beneath it. Needs BENCH_DATABASE_URL (or TEST_DATABASE_URL); skipped without
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+use rust_crud_api::config::Config;
+use rust_crud_api::models::{Role, User};
+use rust_crud_api::repository::{self, Stores, UserListQuery};
+use rust_crud_api::{auth, server};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+const PASSWORD: &str = "correct horse battery";
+
+// Users inserted up front, so listing reads full pages
+const USERS: usize = 500;
+
+fn main() {
+    let Ok(database_url) = env::var("BENCH_DATABASE_URL").or_else(|_| env::var("TEST_DATABASE_URL")) else {
+        eprintln!("BENCH_DATABASE_URL is not set; skipping the latency benchmarks");
+        return;
+    };
+    let bench = common::Bench::new("latency");
+    let config = Arc::new(config(&database_url));
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().expect("building the runtime");
+
+    let (stores, admin, user_id) = runtime.block_on(prepare(&config));
+    let list = UserListQuery {
+        limit: 50,
+        offset: 0,
+        after: None,
+        sort: "id",
+        order: "ASC",
+        name: None,
+        email_contains: Some("bench-".to_string()),
+        include_deleted: false,
+        fields: None,
+    };
+    bench.custom("repository_get", |iterations| {
+        runtime.block_on(async {
+            let started = Instant::now();
+            for _ in 0..iterations {
+                stores.users.get(user_id, false).await.expect("getting a user");
+            }
+            started.elapsed()
+        })
+    });
+    bench.custom("repository_list_50", |iterations| {
+        runtime.block_on(async {
+            let started = Instant::now();
+            for _ in 0..iterations {
+                stores.users.list(&list).await.expect("listing users");
+            }
+            started.elapsed()
+        })
+    });
+
+    let (sender, receiver) = mpsc::channel();
+    let server_config = Arc::clone(&config);
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().expect("building the runtime");
+        runtime.block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("binding a port");
+            sender.send(listener.local_addr().expect("local address")).expect("reporting the address");
+            server::serve_until(server_config, Some(listener), std::future::pending()).await;
+        });
+    });
+    let addr = receiver.recv().expect("the server failed to start");
+    let mut client = Client { stream: BufReader::new(TcpStream::connect(addr).expect("connecting")) };
+    let token = client.login(&admin);
+
+    bench.function("get_user", || {
+        client.send("GET", &format!("/users/{}", user_id), None, None);
+    });
+    bench.function("list_users_50", || {
+        client.send("GET", "/users?limit=50&email_contains=bench-", None, None);
+    });
+    bench.function("create_user", || {
+        let body = format!(r#"{{"name":"Bench Writer","email":"bench-write-{}@example.test"}}"#, Uuid::new_v4().simple());
+        client.send("POST", "/users", Some(&token), Some(&body));
+    });
+    bench.function("healthz", || {
+        client.send("GET", "/healthz", None, None);
+    });
+}
+
+fn config(database_url: &str) -> Config {
+    let path = env::temp_dir().join(format!("rust-crud-api-bench-{}.toml", Uuid::new_v4()));
+    let file = format!("log_level = \"error\"\n[database]\nurl = {:?}\n[auth]\njwt_secret = \"{}\"\n", database_url, "x".repeat(48));
+    fs::write(&path, file).expect("writing the bench config");
+    let mut config = Config::load(path.to_str()).expect("loading the bench config");
+    let _ = fs::remove_file(&path);
+    config.db_url = database_url.to_string();
+    config.tls = None;
+    config.cors = None;
+    config.rate_limit = None;
+    // Generous, so a slow sample doesn't drop the connection
+    config.keep_alive_timeout = Duration::from_secs(60);
+    config
+}
+
+// Connect, insert the users to read (once; later runs find them), and create an admin to write with
+async fn prepare(config: &Config) -> (Stores, String, i32) {
+    let stores = repository::connect(config).await.expect("connecting to the database");
+    let users = (1..=USERS)
+        .map(|n| User {
+            id: None,
+            name: format!("Bench User {}", n),
+            email: format!("bench-{}@example.test", n),
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        })
+        .collect();
+    stores.users.create_many(users).await.expect("inserting users");
+    let first = UserListQuery {
+        limit: 1,
+        offset: 0,
+        after: None,
+        sort: "id",
+        order: "ASC",
+        name: None,
+        email_contains: Some("bench-1@example.test".to_string()),
+        include_deleted: false,
+        fields: None,
+    };
+    let user_id = stores.users.list(&first).await.expect("finding a user").users[0].id.expect("user id");
+
+    let email = format!("bench-admin-{}@example.test", Uuid::new_v4().simple());
+    let admin = stores.users.create("Bench Admin", &email).await.expect("creating the admin").id.expect("admin id");
+    let hash = auth::hash_password(PASSWORD).expect("hashing the password");
+    stores.users.set_password(admin, &hash).await.expect("setting the password");
+    stores.users.set_role(admin, Role::Admin).await.expect("making the admin");
+    (stores, email, user_id)
+}
+
+// One keep-alive connection, reused by every request like a pooled HTTP client would
+struct Client {
+    stream: BufReader<TcpStream>,
+}
+
+impl Client {
+    fn login(&mut self, email: &str) -> String {
+        let body = format!(r#"{{"email":"{}","password":"{}"}}"#, email, PASSWORD);
+        let response = self.send("POST", "/auth/login", None, Some(&body));
+        let tokens: serde_json::Value = serde_json::from_slice(&response).expect("login JSON");
+        tokens["access_token"].as_str().expect("access_token").to_string()
+    }
+
+    // Send a request and read the whole response, panicking on anything but a 2xx
+    fn send(&mut self, method: &str, path: &str, token: Option<&str>, body: Option<&str>) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        if let Some(token) = token {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        let body = body.unwrap_or_default();
+        head.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+        self.stream.get_mut().write_all(head.as_bytes()).expect("writing the request");
+
+        let mut status = String::new();
+        self.stream.read_line(&mut status).expect("reading the status line");
+        assert!(status.split(' ').nth(1).is_some_and(|code| code.starts_with('2')), "{} {} answered {}", method, path, status.trim());
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stream.read_line(&mut line).expect("reading a header");
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().expect("Content-Length");
+                }
+            }
+        }
+        let mut response = vec![0; length];
+        self.stream.read_exact(&mut response).expect("reading the body");
+        response
+    }
+}
@@ -0,0 +1,48 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Record what is being built for GET /version and --version: the commit, from GIT_COMMIT when the
+// build has no git checkout (e.g. a Docker build without .git), and the time. That is
+// SOURCE_DATE_EPOCH when the build is meant to be reproducible, else the commit's time, so that
+// building the same tree again doesn't recompile the crate; only without either is it now
+fn main() {
+    let commit = env::var("GIT_COMMIT").ok().filter(|commit| !commit.is_empty()).or_else(git_commit);
+    let commit = commit.unwrap_or_else(|| "unknown".to_string());
+    let built_at = env::var("SOURCE_DATE_EPOCH").ok().filter(|epoch| !epoch.is_empty()).or_else(|| {
+        let committed = git(&["log", "-1", "--format=%ct"])?;
+        Some(committed).filter(|committed| committed.parse::<u64>().is_ok())
+    });
+    let built_at = built_at.unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or_default().to_string()
+    });
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", built_at);
+
+    // Rebuilt for a new commit as well as for changed sources. Cargo reruns the script on every
+    // build for a path that doesn't exist, so only those that do are watched, e.g. packed-refs is
+    // only there once git has packed some refs
+    let mut watched: Vec<String> = ["src", "assets", "migrations", "proto", "Cargo.toml", "build.rs"].map(String::from).into();
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        watched.extend(["HEAD", "refs", "packed-refs"].map(|path| Path::new(&git_dir).join(path).display().to_string()));
+    }
+    for path in watched.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+// The checked-out commit, marked `-dirty` when there are uncommitted changes
+fn git_commit() -> Option<String> {
+    let commit = git(&["rev-parse", "HEAD"])?;
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    Some(if dirty { format!("{}-dirty", commit) } else { commit })
+}
+
+// What a git command printed, trimmed; None outside a checkout or without git
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok().filter(|output| output.status.success())?;
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
@@ -0,0 +1,6 @@
+// Arbitrary bytes as a Content-Type line and a request body
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| rust_crud_api::fuzzing::body(data));
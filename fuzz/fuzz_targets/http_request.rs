@@ -0,0 +1,6 @@
+// Arbitrary bytes as a request off the wire
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| rust_crud_api::fuzzing::request(data));
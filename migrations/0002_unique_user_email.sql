@@ -0,0 +1,2 @@
+-- Emails are unique regardless of case; fails if existing rows already collide
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_key ON users (lower(email));
@@ -0,0 +1,2 @@
+-- Creation time, for per-day statistics; existing users are stamped with the migration time
+ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,4 @@
+-- Trigram indexes for GET /users/search: fuzzy (<%) and substring (LIKE) matches on name and email
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX IF NOT EXISTS users_name_trgm_idx ON users USING gin (lower(name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_email_trgm_idx ON users USING gin (lower(email) gin_trgm_ops);
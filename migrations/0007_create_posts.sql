@@ -0,0 +1,9 @@
+-- Users are only soft-deleted, so the foreign key's cascade applies when a user row is purged by hand;
+-- POSTS_ON_USER_DELETE decides what a soft delete does to the posts
+CREATE TABLE posts (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    title VARCHAR NOT NULL,
+    body TEXT NOT NULL
+);
+CREATE INDEX posts_user_id_idx ON posts (user_id);
@@ -0,0 +1,13 @@
+CREATE TABLE groups (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL
+);
+CREATE UNIQUE INDEX groups_name_key ON groups (lower(name));
+
+-- Memberships; the primary key also serves lookups by user, the second index lookups by group
+CREATE TABLE user_groups (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    group_id INTEGER NOT NULL REFERENCES groups (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, group_id)
+);
+CREATE INDEX user_groups_group_id_idx ON user_groups (group_id);
@@ -0,0 +1,2 @@
+-- Argon2 PHC string; users without one can't log in
+ALTER TABLE users ADD COLUMN password_hash VARCHAR;
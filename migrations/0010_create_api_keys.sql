@@ -0,0 +1,9 @@
+-- Keys for service-to-service callers. Only a SHA-256 digest of each key is stored; revoked keys
+-- are kept so their ids stay meaningful in listings
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    key_hash VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
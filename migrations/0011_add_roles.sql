@@ -0,0 +1,5 @@
+-- Existing users become viewers and existing keys editors; grant admin with `set-role`
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'viewer'
+    CHECK (role IN ('admin', 'editor', 'viewer'));
+ALTER TABLE api_keys ADD COLUMN role VARCHAR NOT NULL DEFAULT 'editor'
+    CHECK (role IN ('admin', 'editor', 'viewer'));
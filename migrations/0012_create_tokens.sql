@@ -0,0 +1,17 @@
+-- One row per login session; refreshing revokes the row and issues a new one. Only a SHA-256
+-- digest of each refresh token is stored
+CREATE TABLE refresh_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+CREATE INDEX refresh_tokens_user_id_idx ON refresh_tokens (user_id);
+
+-- Access tokens logged out before they expire, by jti; rows are pruned once the token expires
+CREATE TABLE revoked_tokens (
+    jti VARCHAR PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,15 @@
+-- One row per mutation request: who made it, what it touched, and the entity before and after.
+-- Secrets such as tokens and API keys are never stored
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    -- `user:<id>` or `api_key:<id>`; NULL for requests made without credentials, like logins
+    actor VARCHAR,
+    action VARCHAR NOT NULL,
+    path VARCHAR NOT NULL,
+    entity_id INTEGER,
+    status SMALLINT NOT NULL,
+    old_value JSONB,
+    new_value JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor);
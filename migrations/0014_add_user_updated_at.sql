@@ -0,0 +1,3 @@
+-- Last change to the user, sent as Last-Modified; existing users count as unchanged since creation
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+UPDATE users SET updated_at = created_at;
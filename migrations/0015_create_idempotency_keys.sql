@@ -0,0 +1,13 @@
+-- Responses to requests sent with an Idempotency-Key, replayed when the same request is retried.
+-- `key_hash` is a SHA-256 digest of the caller and the key; `status` stays NULL while the first
+-- request is still running. Rows are pruned once they expire
+CREATE TABLE idempotency_keys (
+    key_hash VARCHAR PRIMARY KEY,
+    request_hash VARCHAR NOT NULL,
+    status SMALLINT,
+    headers JSONB,
+    body BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
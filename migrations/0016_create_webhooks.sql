@@ -0,0 +1,9 @@
+-- Subscriptions to user events. The secret signs each delivery, so it is kept as given rather
+-- than hashed; `events` is a comma-separated list of event types
+CREATE TABLE webhooks (
+    id SERIAL PRIMARY KEY,
+    url VARCHAR NOT NULL,
+    secret VARCHAR NOT NULL,
+    events VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
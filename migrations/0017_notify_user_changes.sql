@@ -0,0 +1,36 @@
+-- Announce every visible change to a user on the user_changes channel, whoever makes it, so the
+-- server can publish events for changes made outside the API too. The payload is only the event
+-- type and the user's id, which keeps it well under NOTIFY's size limit
+CREATE FUNCTION notify_user_change() RETURNS trigger AS $$
+DECLARE
+    event_type TEXT;
+    user_id INTEGER;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        event_type := 'user.created';
+        user_id := NEW.id;
+    ELSIF TG_OP = 'DELETE' THEN
+        -- Already gone as far as clients are concerned when it was soft-deleted
+        IF OLD.deleted_at IS NOT NULL THEN
+            RETURN NULL;
+        END IF;
+        event_type := 'user.deleted';
+        user_id := OLD.id;
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        event_type := 'user.deleted';
+        user_id := NEW.id;
+    -- Passwords, roles and changes to deleted users aren't part of what clients see
+    ELSIF NEW.deleted_at IS NOT NULL
+        OR (NEW.name, NEW.email, NEW.deleted_at) IS NOT DISTINCT FROM (OLD.name, OLD.email, OLD.deleted_at) THEN
+        RETURN NULL;
+    ELSE
+        event_type := 'user.updated';
+        user_id := NEW.id;
+    END IF;
+    PERFORM pg_notify('user_changes', json_build_object('type', event_type, 'id', user_id)::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_notify_change AFTER INSERT OR UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE FUNCTION notify_user_change();
@@ -0,0 +1,7 @@
+-- The default case-insensitive collation makes the plain unique key match lower(email)
+CREATE TABLE IF NOT EXISTS users (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    email VARCHAR(254) NOT NULL,
+    UNIQUE KEY users_email_key (email)
+)
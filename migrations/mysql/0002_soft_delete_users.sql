@@ -0,0 +1,2 @@
+-- MySQL has no partial indexes, so a deleted user's email stays reserved until it is restored
+ALTER TABLE users ADD COLUMN deleted_at DATETIME(6) NULL
@@ -0,0 +1,2 @@
+-- Bumped on every change, for optimistic concurrency control via ETag / If-Match
+ALTER TABLE users ADD COLUMN version INT NOT NULL DEFAULT 1
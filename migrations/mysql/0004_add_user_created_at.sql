@@ -0,0 +1,2 @@
+-- Creation time in UTC, for per-day statistics; existing users are stamped with the migration time
+ALTER TABLE users ADD COLUMN created_at DATETIME(6) NOT NULL DEFAULT (UTC_TIMESTAMP(6))
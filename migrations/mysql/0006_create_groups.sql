@@ -0,0 +1,6 @@
+-- GROUPS is a reserved word since MySQL 8.0.2, so the table name is always quoted
+CREATE TABLE `groups` (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    UNIQUE KEY groups_name_key (name)
+)
@@ -0,0 +1,8 @@
+-- The primary key serves lookups by user; the group foreign key gets its own index
+CREATE TABLE user_groups (
+    user_id INT NOT NULL,
+    group_id INT NOT NULL,
+    PRIMARY KEY (user_id, group_id),
+    CONSTRAINT user_groups_user_id_fkey FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT user_groups_group_id_fkey FOREIGN KEY (group_id) REFERENCES `groups` (id) ON DELETE CASCADE
+)
@@ -0,0 +1,9 @@
+-- Only a SHA-256 digest of each key is stored, as 64 hex characters
+CREATE TABLE api_keys (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    key_hash CHAR(64) NOT NULL,
+    created_at DATETIME(6) NOT NULL DEFAULT (UTC_TIMESTAMP(6)),
+    revoked_at DATETIME(6) NULL,
+    UNIQUE KEY api_keys_key_hash_key (key_hash)
+)
@@ -0,0 +1,2 @@
+-- Existing users become viewers; grant admin with `set-role`
+ALTER TABLE users ADD COLUMN role ENUM('admin', 'editor', 'viewer') NOT NULL DEFAULT 'viewer'
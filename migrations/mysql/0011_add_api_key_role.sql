@@ -0,0 +1 @@
+ALTER TABLE api_keys ADD COLUMN role ENUM('admin', 'editor', 'viewer') NOT NULL DEFAULT 'editor'
@@ -0,0 +1,5 @@
+-- Access tokens logged out before they expire, by jti; rows are pruned once the token expires
+CREATE TABLE revoked_tokens (
+    jti VARCHAR(64) PRIMARY KEY,
+    expires_at DATETIME(6) NOT NULL
+)
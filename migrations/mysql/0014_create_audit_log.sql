@@ -0,0 +1,13 @@
+-- One row per mutation request; secrets such as tokens and API keys are never stored
+CREATE TABLE audit_log (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    actor VARCHAR(64) NULL,
+    action VARCHAR(16) NOT NULL,
+    path VARCHAR(2048) NOT NULL,
+    entity_id INT NULL,
+    status SMALLINT NOT NULL,
+    old_value JSON NULL,
+    new_value JSON NULL,
+    created_at DATETIME(6) NOT NULL DEFAULT (UTC_TIMESTAMP(6)),
+    KEY audit_log_actor_idx (actor)
+)
@@ -0,0 +1,2 @@
+-- Last change to the user in UTC, sent as Last-Modified
+ALTER TABLE users ADD COLUMN updated_at DATETIME(6) NOT NULL DEFAULT (UTC_TIMESTAMP(6))
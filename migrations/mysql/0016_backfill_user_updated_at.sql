@@ -0,0 +1,2 @@
+-- Existing users count as unchanged since creation
+UPDATE users SET updated_at = created_at
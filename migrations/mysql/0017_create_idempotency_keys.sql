@@ -0,0 +1,11 @@
+-- Responses to requests sent with an Idempotency-Key; `status` stays NULL while the first request runs
+CREATE TABLE idempotency_keys (
+    key_hash CHAR(64) PRIMARY KEY,
+    request_hash CHAR(64) NOT NULL,
+    status SMALLINT NULL,
+    headers JSON NULL,
+    body LONGBLOB NULL,
+    created_at DATETIME(6) NOT NULL DEFAULT (UTC_TIMESTAMP(6)),
+    expires_at DATETIME(6) NOT NULL,
+    KEY idempotency_keys_expires_at_idx (expires_at)
+)
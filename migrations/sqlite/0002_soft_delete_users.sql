@@ -0,0 +1,5 @@
+ALTER TABLE users ADD COLUMN deleted_at TEXT;
+
+-- Only live users need unique emails, so a deleted user's address can be reused
+DROP INDEX users_email_key;
+CREATE UNIQUE INDEX users_email_key ON users (lower(email)) WHERE deleted_at IS NULL;
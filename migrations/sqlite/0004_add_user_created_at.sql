@@ -0,0 +1,4 @@
+-- SQLite can't add a column with a non-constant default, so inserts supply created_at;
+-- existing users are stamped with the migration time in the format rusqlite writes
+ALTER TABLE users ADD COLUMN created_at TEXT;
+UPDATE users SET created_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now');
@@ -0,0 +1,12 @@
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    actor TEXT,
+    action TEXT NOT NULL,
+    path TEXT NOT NULL,
+    entity_id INTEGER,
+    status INTEGER NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor);
@@ -0,0 +1,4 @@
+-- Last change to the user, sent as Last-Modified. Like created_at (see 0004) it has no column
+-- default, so inserts and updates supply it; existing users count as unchanged since creation
+ALTER TABLE users ADD COLUMN updated_at TEXT;
+UPDATE users SET updated_at = created_at;
@@ -0,0 +1,111 @@
+use std::fs::OpenOptions;
+use std::io::{self, LineWriter, Write};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use tracing::warn;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+
+// What is known about a request once its response has been written
+pub struct Entry<'a> {
+    pub time: DateTime<Utc>,
+    pub client: IpAddr,
+    pub request_id: &'a str,
+    // None for requests that couldn't be parsed
+    pub method: Option<&'a str>,
+    // The path with its query string
+    pub target: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub status: u16,
+    // Body bytes written, after compression
+    pub bytes: u64,
+    // From the first byte of the request until the last of the response was written
+    pub duration: Duration,
+}
+
+// How an entry is written as a line
+pub trait Format: Send + Sync {
+    fn line(&self, entry: &Entry) -> String;
+}
+
+// The Common Log Format, followed by the seconds taken and the request id:
+// `192.0.2.1 - - [15/Oct/2026:13:55:36 +0000] "GET /v1/users HTTP/1.1" 200 2326 0.004 4f1c...`
+pub struct Common;
+
+impl Format for Common {
+    fn line(&self, entry: &Entry) -> String {
+        let request = match (entry.method, entry.target, entry.version) {
+            (Some(method), Some(target), Some(version)) => format!("{} {} {}", method, target, version),
+            _ => "-".to_string(),
+        };
+        let bytes = match entry.bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+        format!(
+            "{} - - [{}] \"{}\" {} {} {:.3} {}",
+            entry.client,
+            entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            request.replace('"', "%22"),
+            entry.status,
+            bytes,
+            entry.duration.as_secs_f64(),
+            entry.request_id
+        )
+    }
+}
+
+// One JSON object per line, for log collectors
+pub struct Json;
+
+impl Format for Json {
+    fn line(&self, entry: &Entry) -> String {
+        json!({
+            "time": entry.time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "client": entry.client.to_string(),
+            "request_id": entry.request_id,
+            "method": entry.method,
+            "target": entry.target,
+            "version": entry.version,
+            "status": entry.status,
+            "bytes": entry.bytes,
+            "duration_ms": entry.duration.as_secs_f64() * 1000.0,
+        })
+        .to_string()
+    }
+}
+
+// One line per request, apart from the application's logs: to a file when one is configured,
+// otherwise to stdout
+pub struct AccessLog {
+    format: Box<dyn Format>,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn open(config: &AccessLogConfig) -> io::Result<AccessLog> {
+        let format: Box<dyn Format> = match config.format {
+            AccessLogFormat::Common => Box::new(Common),
+            AccessLogFormat::Json => Box::new(Json),
+        };
+        let out: Box<dyn Write + Send> = match &config.path {
+            Some(path) => Box::new(LineWriter::new(OpenOptions::new().create(true).append(true).open(path)?)),
+            None => Box::new(io::stdout()),
+        };
+        Ok(AccessLog { format, out: Mutex::new(out) })
+    }
+
+    // Write the entry's line. A failure is logged, never passed on to the request
+    pub fn record(&self, entry: &Entry) {
+        let mut line = self.format.line(entry);
+        line.push('\n');
+        let mut out = self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = out.write_all(line.as_bytes()).and_then(|()| out.flush()) {
+            warn!("Error writing the access log: {}", e);
+        }
+    }
+}
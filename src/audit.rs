@@ -0,0 +1,96 @@
+use serde_json::Value;
+use tracing::error;
+
+use crate::http::{Request, Response};
+use crate::repository::{AuditRecord, Stores};
+use crate::versioning;
+
+// Response fields that hold secrets; they are blanked before a body is logged
+const REDACTED_FIELDS: [&str; 4] = ["key", "access_token", "refresh_token", "secret"];
+
+// Every request that can change state is audited
+pub fn is_audited(request: &Request) -> bool {
+    matches!(request.method.as_str(), "POST" | "PUT" | "PATCH" | "DELETE")
+}
+
+// A mutation that is being handled: what is known about it before the response exists
+pub struct Pending {
+    actor: Option<String>,
+    method: String,
+    path: String,
+    old_value: Option<Value>,
+}
+
+// Note the request, and the state of what it addresses, before the request runs
+pub async fn begin(stores: &Stores, request: &Request, actor: Option<String>) -> Pending {
+    Pending {
+        actor,
+        method: request.method.clone(),
+        path: request.path.clone(),
+        old_value: snapshot(stores, &request.path).await,
+    }
+}
+
+impl Pending {
+    // Store the entry once the response is ready. A failure is logged but never fails the request
+    pub async fn finish(self, stores: &Stores, response: &Response) {
+        let new_value = response_body(response);
+        let entity_id = segments(&self.path)
+            .get(1)
+            .and_then(|segment| segment.parse().ok())
+            .or_else(|| i32::try_from(new_value.as_ref()?.get("id")?.as_i64()?).ok());
+        let record = AuditRecord {
+            actor: self.actor,
+            action: self.method,
+            path: self.path,
+            entity_id,
+            status: response.status,
+            old_value: self.old_value,
+            new_value,
+        };
+        let description = format!("{} {}", record.action, record.path);
+        if let Err(e) = stores.audit.record(record).await {
+            error!("Failed to record audit entry for {}: {}", description, e);
+        }
+    }
+}
+
+// The stored state of the user, post or group a path addresses directly (`/users/{id}` and the
+// like). None for other paths or when nothing is stored
+async fn snapshot(stores: &Stores, path: &str) -> Option<Value> {
+    let (collection, id) = match segments(path).as_slice() {
+        [collection, id] => (collection.to_string(), id.parse::<i32>().ok()?),
+        _ => return None,
+    };
+    let value = match collection.as_str() {
+        "users" => serde_json::to_value(stores.users.get(id, true).await.ok()??),
+        "posts" => serde_json::to_value(stores.posts.get(id).await.ok()??),
+        "groups" => serde_json::to_value(stores.groups.get(id).await.ok()??),
+        _ => return None,
+    };
+    value.ok()
+}
+
+// A path's segments, as any API version's routes would be written
+fn segments(path: &str) -> Vec<&str> {
+    versioning::unversioned(path).split('/').filter(|s| !s.is_empty()).collect()
+}
+
+// A successful response's JSON body, with secrets blanked
+fn response_body(response: &Response) -> Option<Value> {
+    if !(200..300).contains(&response.status)
+        || response.stream.is_some()
+        || response.body.is_empty()
+    {
+        return None;
+    }
+    let mut value: Value = serde_json::from_slice(&response.body).ok()?;
+    if let Some(object) = value.as_object_mut() {
+        for field in REDACTED_FIELDS {
+            if let Some(secret) = object.get_mut(field) {
+                *secret = Value::String("[redacted]".to_string());
+            }
+        }
+    }
+    Some(value)
+}
@@ -0,0 +1,258 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::http::{Request, Response};
+use crate::models::Role;
+use crate::repository::Stores;
+use crate::versioning;
+
+// The mutations that can be made without credentials: they hand them out
+pub const LOGIN_PATH: &str = "/auth/login";
+pub const REFRESH_PATH: &str = "/auth/refresh";
+
+// Service callers send their API key in this header instead of a bearer token
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+// Prefixes of API keys, refresh tokens and webhook secrets, so leaked secrets are easy to recognize
+const API_KEY_PREFIX: &str = "rk_";
+const REFRESH_TOKEN_PREFIX: &str = "rt_";
+const WEBHOOK_SECRET_PREFIX: &str = "whsec_";
+
+// How tokens are signed and how long they last
+pub struct TokenSettings {
+    pub secret: String,
+    pub access_ttl: Duration,
+    pub refresh_ttl: Duration,
+}
+
+// Access token payload: the user it was issued to, their role at the time, and its lifetime in
+// seconds since the epoch. A role change takes effect at the next login or refresh
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    // User id, as a string per RFC 7519
+    pub sub: String,
+    pub role: Role,
+    // The tenant the user belongs to; user ids only mean something within one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    // The login session (refresh token) it was issued under
+    pub sid: i32,
+    // Unique id, so this one token can be revoked
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+// Who is making a request: a logged-in user or a service holding an API key
+pub struct Caller {
+    user_id: Option<i32>,
+    api_key_id: Option<i32>,
+    role: Role,
+}
+
+impl Caller {
+    // How the audit log names the caller, e.g. "user:3" or "api_key:7"
+    pub fn actor(&self) -> String {
+        match (self.user_id, self.api_key_id) {
+            (Some(id), _) => format!("user:{}", id),
+            (None, Some(id)) => format!("api_key:{}", id),
+            (None, None) => "unknown".to_string(),
+        }
+    }
+}
+
+// Hash a password into a PHC string (Argon2id with a random salt)
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Error hashing password: {}", e))
+}
+
+// Check a password against a stored PHC string; a malformed hash never matches
+fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Check a login attempt. Without a stored hash a throwaway one is verified instead, so unknown
+// emails take as long to reject as wrong passwords
+pub fn verify_login(password: &str, hash: Option<&str>) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    match hash {
+        Some(hash) => verify_password(password, hash),
+        None => {
+            let dummy = DUMMY_HASH.get_or_init(|| hash_password("not a password").unwrap_or_default());
+            verify_password(password, dummy);
+            false
+        }
+    }
+}
+
+// Sign an access token for the user's session, valid for `ttl`
+pub fn issue_token(
+    secret: &str,
+    user_id: i32,
+    role: Role,
+    tenant: Option<&str>,
+    session_id: i32,
+    ttl: Duration,
+) -> Result<String, String> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        role,
+        tenant: tenant.map(str::to_string),
+        sid: session_id,
+        jti: random_secret(""),
+        iat: now,
+        exp: now + ttl.as_secs() as i64,
+    };
+    jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret.as_bytes()))
+        .map_err(|e| format!("Error signing token: {}", e))
+}
+
+pub fn generate_api_key() -> String {
+    random_secret(API_KEY_PREFIX)
+}
+
+pub fn generate_webhook_secret() -> String {
+    random_secret(WEBHOOK_SECRET_PREFIX)
+}
+
+pub fn generate_refresh_token() -> String {
+    random_secret(REFRESH_TOKEN_PREFIX)
+}
+
+// The prefix and 256 random bits, base64url-encoded
+fn random_secret(prefix: &str) -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", prefix, URL_SAFE_NO_PAD.encode(bytes))
+}
+
+// The stored form of an API key or refresh token: its SHA-256 digest in hex. They are random
+// rather than chosen by people, so a fast hash is enough and lets them be looked up by digest
+pub fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes()).iter().fold(String::with_capacity(64), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+pub fn requires_token(request: &Request) -> bool {
+    requires_credentials(&request.method, &request.path)
+}
+
+// Writes, the API key, webhook and tenant endpoints and the audit log need credentials; other
+// reads, logging in and refreshing don't. Paths are compared by segment, as the router does, so
+// route patterns work too
+pub fn requires_credentials(method: &str, path: &str) -> bool {
+    let path = segments(path);
+    let writes = matches!(method, "POST" | "PUT" | "PATCH" | "DELETE")
+        && path != segments(LOGIN_PATH)
+        && path != segments(REFRESH_PATH);
+    writes || matches!(path.first(), Some(&"api-keys") | Some(&"audit") | Some(&"webhooks") | Some(&"tenants"))
+}
+
+// A path's segments, as any API version's routes would be written
+fn segments(path: &str) -> Vec<&str> {
+    versioning::unversioned(path).split('/').filter(|s| !s.is_empty()).collect()
+}
+
+// Check the request's credentials and that their role may make it: an X-Api-Key header must name
+// an unrevoked key of the tenant's, otherwise a valid bearer token issued for the tenant is needed.
+// 401 without valid credentials, 403 when the role falls short
+pub async fn authorize(secret: &str, stores: &Stores, request: &Request) -> Result<Caller, Response> {
+    let caller = match request.header(API_KEY_HEADER) {
+        Some(key) => match stores.api_keys.find_active(&hash_secret(key.trim())).await {
+            Ok(Some((id, role))) => Caller {
+                user_id: None,
+                api_key_id: Some(id),
+                role,
+            },
+            Ok(None) => return Err(Response::error(401, "invalid_api_key", "The API key is unknown or revoked")),
+            Err(e) => return Err(e.into()),
+        },
+        None => {
+            let claims = authenticate(secret, request)?;
+            if claims.tenant != stores.tenant {
+                return Err(invalid_token("The token was issued for another tenant"));
+            }
+            match stores.tokens.is_revoked(&claims.jti).await {
+                Ok(false) => {}
+                Ok(true) => return Err(invalid_token("The token has been revoked")),
+                Err(e) => return Err(e.into()),
+            }
+            Caller {
+                user_id: claims.sub.parse().ok(),
+                api_key_id: None,
+                role: claims.role,
+            }
+        }
+    };
+    permit(&caller, &request.method, &segments(&request.path))?;
+    Ok(caller)
+}
+
+// Per-route permissions. Deleting users, changing roles, managing API keys, webhooks and tenants,
+// reading the audit log and seeding take an admin; other writes take an editor. Anyone logged in
+// may log out and change their own password
+fn permit(caller: &Caller, method: &str, path: &[&str]) -> Result<(), Response> {
+    let required = match (method, path) {
+        ("POST", ["auth", "logout"]) => Role::Viewer,
+        (_, ["api-keys", ..]) | (_, ["audit", ..]) | (_, ["admin", ..]) | (_, ["webhooks", ..]) | (_, ["tenants", ..]) => {
+            Role::Admin
+        }
+        ("DELETE", ["users", ..]) | ("PUT", ["users", _, "role"]) => Role::Admin,
+        ("PUT", ["users", id, "password"]) if caller.user_id.is_some_and(|user_id| id.parse() == Ok(user_id)) => {
+            Role::Viewer
+        }
+        ("PUT", ["users", _, "password"]) => Role::Admin,
+        _ => Role::Editor,
+    };
+    if caller.role >= required {
+        return Ok(());
+    }
+    Err(Response::error_with_details(
+        403,
+        "forbidden",
+        format!("This requires the {} role", required.as_str()),
+        serde_json::json!({ "required_role": required, "role": caller.role }),
+    ))
+}
+
+// The claims of the request's `Authorization: Bearer` token, or a 401 saying what was wrong with it.
+// Revocation isn't checked here; `authorize` does that
+pub fn authenticate(secret: &str, request: &Request) -> Result<Claims, Response> {
+    let header = request.header("authorization").unwrap_or_default();
+    let token = match header.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ => {
+            return Err(Response::error(401, "unauthorized", "A bearer token or API key is required")
+                .with_header("WWW-Authenticate", "Bearer"))
+        }
+    };
+
+    let validation = Validation::new(Algorithm::HS256);
+    match jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation) {
+        Ok(data) => Ok(data.claims),
+        Err(e) => Err(invalid_token(&format!("Invalid token: {}", e))),
+    }
+}
+
+fn invalid_token(message: &str) -> Response {
+    Response::error(401, "invalid_token", message).with_header("WWW-Authenticate", "Bearer error=\"invalid_token\"")
+}
@@ -0,0 +1,32 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+
+// Cargo features that change what the server can do
+const FEATURES: [(&str, bool); 4] = [
+    ("sqlite", cfg!(feature = "sqlite")),
+    ("mysql", cfg!(feature = "mysql")),
+    ("redis", cfg!(feature = "redis")),
+    ("fuzzing", cfg!(feature = "fuzzing")),
+];
+
+// What is running, as recorded when it was compiled (see build.rs), so operators can check what's
+// deployed. Body of GET /version
+#[derive(Serialize, JsonSchema)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    // The commit built, with `-dirty` when it had uncommitted changes; `unknown` outside a checkout
+    pub commit: &'static str,
+    // SOURCE_DATE_EPOCH, else the time of the commit built; None if the build script's timestamp can't be read
+    pub built_at: Option<DateTime<Utc>>,
+    // The cargo features compiled in
+    pub features: Vec<&'static str>,
+}
+
+pub fn current() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("GIT_COMMIT"),
+        built_at: env!("BUILD_TIMESTAMP").parse().ok().and_then(|secs| DateTime::from_timestamp(secs, 0)),
+        features: FEATURES.into_iter().filter_map(|(feature, enabled)| enabled.then_some(feature)).collect(),
+    }
+}
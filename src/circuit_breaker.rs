@@ -0,0 +1,77 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::config::CircuitBreakerConfig;
+
+// Stops calls to a dependency that is down, so they fail at once instead of each waiting for it to
+// time out. It opens after `failures` calls in a row fail, refuses calls for `cooldown`, then
+// half-opens: one call is let through as a probe, and closes it by succeeding or opens it again
+pub struct CircuitBreaker {
+    // What is behind it, for logs
+    name: &'static str,
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+}
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // A probe is out; another is let through if it hasn't reported back by `until`
+    HalfOpen { until: Instant },
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, config: CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            name,
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    // Whether a call may go ahead; when it may not, how long until one will be let through. A call
+    // let through must report back with `succeeded` or `failed`
+    pub fn admit(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } | State::HalfOpen { until } if now >= until => {
+                *state = State::HalfOpen { until: now + self.config.cooldown };
+                Ok(())
+            }
+            State::Open { until } | State::HalfOpen { until } => Err(until - now),
+        }
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.lock();
+        if !matches!(*state, State::Closed { .. }) {
+            info!("The {} is back; circuit breaker closed", self.name);
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn failed(&self) {
+        let mut state = self.lock();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            State::HalfOpen { .. } => self.config.failures,
+            // A call let through before it opened
+            State::Open { .. } => return,
+        };
+        *state = match failures >= self.config.failures {
+            true => {
+                warn!("The {} is down; circuit breaker open for {}s", self.name, self.config.cooldown.as_secs());
+                State::Open { until: Instant::now() + self.config.cooldown }
+            }
+            false => State::Closed { failures },
+        };
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
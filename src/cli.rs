@@ -0,0 +1,121 @@
+use crate::models::Role;
+use crate::seed::SeedPlan;
+
+pub const USAGE: &str = "Usage: rust-crud-api [OPTIONS] [COMMAND]
+
+Commands:
+  serve                        Run the API server (the default)
+  migrate                      Apply pending database migrations and exit
+  seed [SEED OPTIONS]          Insert generated users, posts and groups
+  config check                 Validate the configuration without connecting to the database
+  set-password <ID>            Set a user's password, read from the first line of stdin
+  set-role <ID> <ROLE>         Set a user's role: admin, editor or viewer
+  help                         Print this message
+
+Options:
+  -c, --config <FILE>          Read settings from FILE instead of CONFIG_FILE or ./config.toml
+      --no-dotenv              Don't read a .env file
+  -h, --help                   Print this message
+  -V, --version                Print the version
+
+Seed options:
+  -n, --count <N>              Users to insert (default 100)
+      --posts <N>              Posts per created user (default 0)
+      --groups <N>             Groups to create and spread the users over (default 0)
+      --fake                   Realistic-looking names and text instead of numbered ones
+      --seed <N>               Random seed for --fake data, to repeat a run";
+
+// Users `seed` inserts when --count isn't given
+const DEFAULT_SEED_COUNT: usize = 100;
+
+// What the binary was asked to do
+pub enum Command {
+    Serve,
+    Migrate,
+    Seed(SeedPlan),
+    CheckConfig,
+    SetPassword { id: i32 },
+    SetRole { id: i32, role: Role },
+    Help,
+    Version,
+}
+
+// Parsed command line: global options, then one command
+pub struct Cli {
+    pub config_file: Option<String>,
+    pub dotenv: bool,
+    pub command: Command,
+}
+
+// Parse the arguments after the program name. Options may come before or after the command
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut config_file = None;
+    let mut dotenv = true;
+    let mut flags = None;
+    let mut plan = SeedPlan { users: DEFAULT_SEED_COUNT, posts_per_user: 0, groups: 0, fake: false, seed: None };
+    // Whether any seed option was given, which only the seed command accepts
+    let mut seeding = false;
+    let mut words = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name));
+        match name.as_str() {
+            "-c" | "--config" => config_file = Some(value(&name)?),
+            "--no-dotenv" => dotenv = false,
+            "-h" | "--help" => flags = Some(Command::Help),
+            "-V" | "--version" => flags = flags.or(Some(Command::Version)),
+            "-n" | "--count" => {
+                let raw = value(&name)?;
+                plan.users = raw.parse().ok().filter(|n| *n > 0).ok_or_else(|| format!("--count must be a positive number, not {:?}", raw))?;
+                seeding = true;
+            }
+            "--posts" | "--groups" | "--seed" => {
+                let raw = value(&name)?;
+                let number = raw.parse().map_err(|_| format!("{} must be a number, not {:?}", name, raw))?;
+                match name.as_str() {
+                    "--posts" => plan.posts_per_user = number as usize,
+                    "--groups" => plan.groups = number as usize,
+                    _ => plan.seed = Some(number),
+                }
+                seeding = true;
+            }
+            "--fake" => {
+                plan.fake = true;
+                seeding = true;
+            }
+            _ if name.starts_with('-') && name.len() > 1 => return Err(format!("Unknown option {}", name)),
+            _ => words.push(arg),
+        }
+    }
+    if let Some(command) = flags {
+        return Ok(Cli { config_file, dotenv, command });
+    }
+
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+        [] | ["serve"] => Command::Serve,
+        ["migrate"] => Command::Migrate,
+        ["seed"] => Command::Seed(plan),
+        ["config", "check"] => Command::CheckConfig,
+        ["set-password", id] => Command::SetPassword { id: parse_id(id)? },
+        ["set-role", id, role] => Command::SetRole {
+            id: parse_id(id)?,
+            role: Role::parse(role).ok_or_else(|| format!("Unknown role {:?}; expected admin, editor or viewer", role))?,
+        },
+        ["help"] => Command::Help,
+        _ => return Err(format!("Unknown or incomplete command: {}", words.join(" "))),
+    };
+    if seeding && !matches!(command, Command::Seed(_)) {
+        return Err("Seed options only apply to the seed command".to_string());
+    }
+    Ok(Cli { config_file, dotenv, command })
+}
+
+fn parse_id(id: &str) -> Result<i32, String> {
+    id.parse().map_err(|_| format!("Invalid user id {:?}", id))
+}
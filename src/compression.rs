@@ -0,0 +1,114 @@
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use tracing::warn;
+
+use crate::etag;
+use crate::http::Response;
+
+// Smaller bodies aren't worth the CPU, and may even grow
+const MIN_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// Pick a content coding from Accept-Encoding (RFC 9110 12.5.3): the highest q-value wins, gzip on a
+// tie; `*` stands for any coding not listed, and q=0 refuses one
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map(|(_, value)| value.trim().parse::<f32>().unwrap_or(0.0))
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "deflate" => deflate = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    match (gzip, deflate) {
+        (gzip, deflate) if gzip > 0.0 && gzip >= deflate => Some(Encoding::Gzip),
+        (_, deflate) if deflate > 0.0 => Some(Encoding::Deflate),
+        _ => None,
+    }
+}
+
+// Compress a JSON or XML response for a client that accepts it. Any response that could have been
+// compressed says Vary: Accept-Encoding, so caches keep the variants apart
+pub fn apply(response: Response, accept_encoding: Option<&str>) -> Response {
+    if !is_compressible(&response) {
+        return response;
+    }
+    let response = response.with_header("Vary", "Accept-Encoding");
+    let Some(encoding) = accept_encoding.and_then(negotiate) else {
+        return response;
+    };
+    match compress(&response.body, encoding) {
+        Ok(body) => {
+            let mut response = Response { body, ..response }.with_header("Content-Encoding", encoding.name());
+            etag::add_variant(&mut response, encoding.name());
+            response
+        }
+        Err(e) => {
+            warn!("Error compressing response: {}", e);
+            response
+        }
+    }
+}
+
+// Buffered JSON or XML bodies past the threshold that aren't already encoded
+fn is_compressible(response: &Response) -> bool {
+    let header = |name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let is_text = header("content-type")
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| {
+            let media_type = media_type.trim().to_ascii_lowercase();
+            matches!(media_type.as_str(), "application/json" | "application/xml") || media_type.ends_with("+json")
+        })
+        .unwrap_or(false);
+    response.stream.is_none() && response.body.len() >= MIN_SIZE && is_text && header("content-encoding").is_none()
+}
+
+fn compress(body: &[u8], encoding: Encoding) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        // HTTP's "deflate" is the zlib format (RFC 9110 8.4.1.2), not a raw deflate stream
+        Encoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
@@ -0,0 +1,689 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::error_reporting::Dsn;
+use crate::forwarded::Network;
+use crate::toml;
+
+// Read when neither --config nor CONFIG_FILE names a file, if it exists
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_DB_POOL_SIZE: usize = 16;
+const DEFAULT_DB_POOL_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_DB_REPLICA_CHECK_INTERVAL_SECS: u64 = 5;
+const DEFAULT_DB_BREAKER_FAILURES: u32 = 5;
+const DEFAULT_DB_BREAKER_COOLDOWN_SECS: u64 = 10;
+const DEFAULT_MAX_QUEUED_REQUESTS: usize = 64;
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+const DEFAULT_KEEP_ALIVE_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_WRITE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_TLS_PORT: u16 = 8443;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_CORS_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+const DEFAULT_CORS_HEADERS: &str = "Content-Type, Authorization, X-Api-Key, X-Request-Id, Idempotency-Key, X-Tenant-Id";
+const DEFAULT_CORS_MAX_AGE_SECS: u64 = 600;
+const DEFAULT_DB_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_DB_RETRY_BACKOFF_MS: u64 = 100;
+const DEFAULT_DB_RETRY_MAX_BACKOFF_MS: u64 = 2000;
+const DEFAULT_DB_SLOW_QUERY_MS: u64 = 1000;
+const DEFAULT_TOKEN_TTL_SECS: u64 = 3600;
+const DEFAULT_REFRESH_TTL_SECS: u64 = 30 * 24 * 3600;
+const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 24 * 3600;
+const DEFAULT_WEBHOOK_ATTEMPTS: u32 = 6;
+const DEFAULT_WEBHOOK_BACKOFF_MS: u64 = 1000;
+const DEFAULT_WEBHOOK_MAX_BACKOFF_MS: u64 = 5 * 60 * 1000;
+const DEFAULT_WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_REDIS_CACHE_TTL_SECS: u64 = 60;
+const DEFAULT_REDIS_TIMEOUT_MS: u64 = 100;
+const DEFAULT_STATIC_MAX_AGE_SECS: u64 = 3600;
+// HS256 keys shorter than the hash output are easier to brute-force
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+
+// Server settings read at startup from a TOML config file, with environment variables taking
+// precedence over it; see config.example.toml for every setting
+pub struct Config {
+    // The config file that was read, if any
+    pub file: Option<String>,
+    // Port of the plaintext listener
+    pub port: u16,
+    pub db_url: String,
+    // Maximum number of pooled database connections
+    pub db_pool_size: usize,
+    // How long a request waits for a pooled connection before failing
+    pub db_pool_timeout: Duration,
+    pub db_retry: RetryConfig,
+    // Fail fast while Postgres is down instead of waiting on it; None when it is off
+    pub db_circuit_breaker: Option<CircuitBreakerConfig>,
+    // PEM file of CA certificates trusted for TLS to Postgres; sslrootcert in the URL overrides it
+    pub db_ca_cert: Option<String>,
+    // Read replicas of the Postgres database, which take turns serving GET requests while they're up
+    pub db_replica_urls: Vec<String>,
+    // How often each replica is checked for being up
+    pub db_replica_check_interval: Duration,
+    // Postgres queries taking at least this long are logged; None when DB_SLOW_QUERY_MS is 0
+    pub db_slow_query: Option<Duration>,
+    pub worker_threads: usize,
+    pub max_body_size: usize,
+    pub keep_alive_timeout: Duration,
+    // Time allowed to receive a request's headers and body once it has started
+    pub read_timeout: Duration,
+    // Time allowed to write a response back to the client
+    pub write_timeout: Duration,
+    pub shutdown_timeout: Duration,
+    // Longest a handler may take before its request is answered with 504 and its queries are
+    // cancelled; None for no limit
+    pub handler_timeout: Option<Duration>,
+    // Budgets of particular routes, overriding handler_timeout, keyed by method and route pattern
+    // as in `GET /users/export`
+    pub route_timeouts: HashMap<String, Duration>,
+    // PUT on a missing id creates the user there; when false it answers 404 instead
+    pub put_upsert: bool,
+    pub posts_on_user_delete: OnUserDelete,
+    // What JSON responses look like when the client doesn't ask for a format by name
+    pub response_format: ResponseFormat,
+    pub trailing_slash: TrailingSlash,
+    // Key signing and verifying access tokens (HS256)
+    pub jwt_secret: String,
+    // How long an access token stays valid
+    pub token_ttl: Duration,
+    // How long a refresh token stays valid; each refresh replaces it with a new one
+    pub refresh_ttl: Duration,
+    // How long the response to a request with an Idempotency-Key is kept for replaying
+    pub idempotency_ttl: Duration,
+    // Take the client address from X-Forwarded-For, when running behind a reverse proxy
+    pub trust_forwarded_for: bool,
+    // Reverse proxies whose Forwarded and X-Forwarded-* headers are believed, by address or network
+    pub trusted_proxies: Vec<Network>,
+    pub tls: Option<TlsConfig>,
+    // Port of the gRPC listener (proto/users.proto); None when it is off
+    pub grpc_port: Option<u16>,
+    pub cors: Option<CorsConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub load_shedding: Option<LoadSheddingConfig>,
+    pub webhooks: WebhookConfig,
+    pub redis: Option<RedisConfig>,
+    // Separate datasets per tenant, each in a Postgres schema of its own; None when it is off
+    pub tenancy: Option<TenancyConfig>,
+    // Files served from a directory; None when no directory is set
+    pub static_files: Option<StaticConfig>,
+    // Users fetched by id kept in memory, dropping the least recently used; 0 disables the cache
+    pub user_cache_size: usize,
+    // Default log filter, e.g. `info` or `warn,rust_crud_api=debug`; RUST_LOG overrides it
+    pub log_level: String,
+    pub log_format: LogFormat,
+    // A line per request, apart from the logs above; None when it is off
+    pub access_log: Option<AccessLogConfig>,
+    // Where 5xx responses are reported; None unless SENTRY_DSN is set
+    pub sentry: Option<SentryConfig>,
+}
+
+// How log lines are written, from LOG_FORMAT; which ones are written comes from LOG_LEVEL or RUST_LOG
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    // Human-readable lines (the default)
+    Text,
+    // One JSON object per line, for log aggregation
+    Json,
+}
+
+// How access log lines are written, from ACCESS_LOG
+#[derive(Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    // The Common Log Format, with the time taken and the request id after it
+    Common,
+    // One JSON object per line
+    Json,
+}
+
+// A Sentry-compatible error tracker, from SENTRY_DSN
+pub struct SentryConfig {
+    pub dsn: Dsn,
+    // Sent with each report, e.g. `production`
+    pub environment: Option<String>,
+}
+
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    // File the lines are appended to; None for stdout
+    pub path: Option<PathBuf>,
+}
+
+// The shape of JSON responses, from RESPONSE_FORMAT. Clients can ask for either with Accept
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResponseFormat {
+    // The bodies handlers produce (the default)
+    Json,
+    // JSON:API documents (application/vnd.api+json), for clients standardized on it
+    JsonApi,
+}
+
+// What a path with a trailing slash, like `/v1/users/`, gets, from TRAILING_SLASH
+#[derive(Clone, Copy, PartialEq)]
+pub enum TrailingSlash {
+    // The route without it (the default)
+    Ignore,
+    // A permanent redirect to the path without it, so every resource has a single URL
+    Redirect,
+}
+
+// What soft-deleting a user does to their posts, from POSTS_ON_USER_DELETE
+#[derive(Clone, Copy, PartialEq)]
+pub enum OnUserDelete {
+    // Hide them along with the user; restoring the user brings them back (the default)
+    Keep,
+    // Delete them permanently
+    Cascade,
+    // Refuse to delete a user who still has posts
+    Restrict,
+}
+
+// Retry policy for transient database errors; delays double from `backoff` up to `max_backoff`
+#[derive(Clone)]
+pub struct RetryConfig {
+    // Total tries, including the first; 1 disables retries
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+// When the database counts as down: after `failures` operations in a row can't reach it, calls are
+// refused for `cooldown`, then one is let through to see whether it is back
+#[derive(Clone)]
+pub struct CircuitBreakerConfig {
+    pub failures: u32,
+    pub cooldown: Duration,
+}
+
+// How webhook deliveries are made: each is tried up to `retry.attempts` times, with delays doubling
+// from `retry.backoff`, and each attempt gets `timeout` to be answered
+#[derive(Clone)]
+pub struct WebhookConfig {
+    pub retry: RetryConfig,
+    pub timeout: Duration,
+}
+
+// Redis cache of users fetched by id; enabled when a URL is set, in builds with the `redis` feature
+#[derive(Clone)]
+pub struct RedisConfig {
+    pub url: String,
+    // How long a cached user is served before it's read again; bounds how stale a change made
+    // outside the API can look
+    pub cache_ttl: Duration,
+    // Time allowed for each Redis command, after which the database is used instead
+    pub timeout: Duration,
+}
+
+// Multi-tenancy, from TENANCY_ENABLED. A request's tenant is named by its X-Tenant-Id header or,
+// when `domain` is set, by the subdomain it was sent to, e.g. `acme` for acme.api.example.com
+pub struct TenancyConfig {
+    pub domain: Option<String>,
+}
+
+// Files under `dir` are served at /static/, and ones under its docs/ and admin/ replace the files of
+// the pages built in; enabled when STATIC_DIR is set
+pub struct StaticConfig {
+    pub dir: PathBuf,
+    // How long browsers may reuse a file without asking whether it changed
+    pub max_age: Duration,
+}
+
+// HTTPS listener settings; enabled when both a certificate and a key are set
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub port: u16,
+    // Serve HTTPS only, without the plaintext listener
+    pub only: bool,
+}
+
+// Cross-origin settings; enabled when allowed origins are set ("*" allows any origin)
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub max_age: u64,
+}
+
+// Per-client-IP rate limit; enabled when a per-minute limit is set
+pub struct RateLimitConfig {
+    // Requests a client may make per minute, sustained
+    pub per_minute: u32,
+    // Requests a client may make at once after being idle; defaults to per_minute
+    pub burst: u32,
+}
+
+// How many requests are handled at once; enabled when max_concurrent is set. Up to max_queued more
+// wait for a turn, and any beyond those are refused with 503
+pub struct LoadSheddingConfig {
+    pub max_concurrent: usize,
+    pub max_queued: usize,
+}
+
+impl Config {
+    // The budget of the route `pattern` (unversioned) for `method`, if it has one
+    pub fn route_timeout(&self, method: &str, pattern: &str) -> Option<Duration> {
+        self.route_timeouts.get(&format!("{} {}", method, pattern)).copied().or(self.handler_timeout)
+    }
+
+    // Read the config file (`file`, else CONFIG_FILE, else ./config.toml when there is one) and the
+    // environment, and check every setting. All the problems found are reported together
+    pub fn load(file: Option<&str>) -> Result<Config, Vec<String>> {
+        let mut settings = Settings::open(file)?;
+        let config = Config {
+            file: settings.path.clone(),
+            port: settings.parse("PORT", "port", DEFAULT_PORT, |n| *n > 0, "a port number"),
+            db_url: settings.required("DATABASE_URL", "database.url"),
+            db_pool_size: settings.parse("DB_POOL_SIZE", "database.pool_size", DEFAULT_DB_POOL_SIZE, |n| *n > 0, "a positive number"),
+            db_pool_timeout: settings.secs("DB_POOL_TIMEOUT", "database.pool_timeout", DEFAULT_DB_POOL_TIMEOUT_SECS),
+            db_retry: RetryConfig {
+                attempts: settings.parse(
+                    "DB_RETRY_ATTEMPTS",
+                    "database.retry_attempts",
+                    DEFAULT_DB_RETRY_ATTEMPTS,
+                    |n| *n > 0,
+                    "a positive number",
+                ),
+                backoff: settings.millis("DB_RETRY_BACKOFF_MS", "database.retry_backoff_ms", DEFAULT_DB_RETRY_BACKOFF_MS),
+                max_backoff: settings.millis("DB_RETRY_MAX_BACKOFF_MS", "database.retry_max_backoff_ms", DEFAULT_DB_RETRY_MAX_BACKOFF_MS),
+            },
+            db_circuit_breaker: get_circuit_breaker_config(&mut settings),
+            db_ca_cert: settings.string("DB_CA_CERT", "database.ca_cert"),
+            db_replica_urls: split_list(&settings.string("DATABASE_REPLICA_URLS", "database.replica_urls").unwrap_or_default()),
+            db_replica_check_interval: Duration::from_secs(settings.parse(
+                "DB_REPLICA_CHECK_INTERVAL",
+                "database.replica_check_interval",
+                DEFAULT_DB_REPLICA_CHECK_INTERVAL_SECS,
+                |n| *n > 0,
+                "a positive number of seconds",
+            )),
+            db_slow_query: Some(settings.millis("DB_SLOW_QUERY_MS", "database.slow_query_ms", DEFAULT_DB_SLOW_QUERY_MS))
+                .filter(|threshold| !threshold.is_zero()),
+            worker_threads: settings.parse(
+                "WORKER_THREADS",
+                "worker_threads",
+                thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+                |n| *n > 0,
+                "a positive number",
+            ),
+            max_body_size: settings.parse("MAX_BODY_SIZE", "max_body_size", DEFAULT_MAX_BODY_SIZE, |_| true, "a number of bytes"),
+            keep_alive_timeout: settings.secs("KEEP_ALIVE_TIMEOUT", "timeouts.keep_alive", DEFAULT_KEEP_ALIVE_TIMEOUT_SECS),
+            read_timeout: settings.secs("READ_TIMEOUT", "timeouts.read", DEFAULT_READ_TIMEOUT_SECS),
+            write_timeout: settings.secs("WRITE_TIMEOUT", "timeouts.write", DEFAULT_WRITE_TIMEOUT_SECS),
+            shutdown_timeout: settings.secs("SHUTDOWN_TIMEOUT", "timeouts.shutdown", DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+            handler_timeout: Some(settings.millis("HANDLER_TIMEOUT_MS", "timeouts.handler_ms", 0)).filter(|timeout| !timeout.is_zero()),
+            route_timeouts: get_route_timeouts(&mut settings),
+            put_upsert: settings.flag("PUT_UPSERT", "put_upsert", true),
+            posts_on_user_delete: settings.choice(
+                "POSTS_ON_USER_DELETE",
+                "posts_on_user_delete",
+                &[("keep", OnUserDelete::Keep), ("cascade", OnUserDelete::Cascade), ("restrict", OnUserDelete::Restrict)],
+            ),
+            response_format: settings.choice(
+                "RESPONSE_FORMAT",
+                "response_format",
+                &[("json", ResponseFormat::Json), ("jsonapi", ResponseFormat::JsonApi)],
+            ),
+            trailing_slash: settings.choice(
+                "TRAILING_SLASH",
+                "trailing_slash",
+                &[("ignore", TrailingSlash::Ignore), ("redirect", TrailingSlash::Redirect)],
+            ),
+            jwt_secret: get_jwt_secret(&mut settings),
+            token_ttl: settings.secs("JWT_TTL", "auth.token_ttl", DEFAULT_TOKEN_TTL_SECS),
+            refresh_ttl: settings.secs("REFRESH_TTL", "auth.refresh_ttl", DEFAULT_REFRESH_TTL_SECS),
+            idempotency_ttl: settings.secs("IDEMPOTENCY_TTL", "idempotency_ttl", DEFAULT_IDEMPOTENCY_TTL_SECS),
+            trust_forwarded_for: settings.flag("TRUST_X_FORWARDED_FOR", "trust_forwarded_for", false),
+            trusted_proxies: get_trusted_proxies(&mut settings),
+            tls: get_tls_config(&mut settings),
+            grpc_port: Some(settings.parse("GRPC_PORT", "grpc.port", 0, |_| true, "a port number")).filter(|port| *port > 0),
+            cors: get_cors_config(&mut settings),
+            rate_limit: get_rate_limit_config(&mut settings),
+            load_shedding: get_load_shedding_config(&mut settings),
+            webhooks: WebhookConfig {
+                retry: RetryConfig {
+                    attempts: settings.parse(
+                        "WEBHOOK_ATTEMPTS",
+                        "webhooks.attempts",
+                        DEFAULT_WEBHOOK_ATTEMPTS,
+                        |n| *n > 0,
+                        "a positive number",
+                    ),
+                    backoff: settings.millis("WEBHOOK_BACKOFF_MS", "webhooks.backoff_ms", DEFAULT_WEBHOOK_BACKOFF_MS),
+                    max_backoff: settings.millis("WEBHOOK_MAX_BACKOFF_MS", "webhooks.max_backoff_ms", DEFAULT_WEBHOOK_MAX_BACKOFF_MS),
+                },
+                timeout: settings.secs("WEBHOOK_TIMEOUT", "webhooks.timeout", DEFAULT_WEBHOOK_TIMEOUT_SECS),
+            },
+            redis: get_redis_config(&mut settings),
+            tenancy: get_tenancy_config(&mut settings),
+            static_files: get_static_config(&mut settings),
+            user_cache_size: settings.parse("USER_CACHE_SIZE", "cache.size", 0, |_| true, "a number of users"),
+            log_level: get_log_level(&mut settings),
+            log_format: settings.choice("LOG_FORMAT", "log_format", &[("text", LogFormat::Text), ("json", LogFormat::Json)]),
+            access_log: get_access_log_config(&mut settings),
+            sentry: get_sentry_config(&mut settings),
+        };
+
+        if let Some(tls) = config.tls.as_ref().filter(|tls| !tls.only && tls.port == config.port) {
+            settings.errors.push(format!("The TLS port must differ from the plaintext port {} unless TLS is the only listener", tls.port));
+        }
+        let http_ports = [config.tls.as_ref().map(|tls| tls.port), Some(config.port).filter(|_| !config.tls.as_ref().is_some_and(|tls| tls.only))];
+        if let Some(port) = config.grpc_port.filter(|port| http_ports.contains(&Some(*port))) {
+            settings.errors.push(format!("The gRPC port must differ from the HTTP ports, but both use {}", port));
+        }
+        settings.check_unused();
+        match settings.errors.is_empty() {
+            true => Ok(config),
+            false => Err(settings.errors),
+        }
+    }
+}
+
+// Where a setting's value came from, for error messages
+struct Setting {
+    value: String,
+    source: String,
+}
+
+// Settings looked up by environment variable first and config file key second. Problems are
+// collected rather than returned, so one run reports all of them
+struct Settings {
+    path: Option<String>,
+    file: Vec<(String, toml::Value)>,
+    // File keys that were looked up; any other key is a typo or an unknown setting
+    read: HashSet<String>,
+    errors: Vec<String>,
+}
+
+impl Settings {
+    fn open(file: Option<&str>) -> Result<Settings, Vec<String>> {
+        let path = match (file, env::var("CONFIG_FILE")) {
+            (Some(file), _) => Some(file.to_string()),
+            (None, Ok(path)) => Some(path),
+            (None, Err(_)) if Path::new(DEFAULT_CONFIG_FILE).exists() => Some(DEFAULT_CONFIG_FILE.to_string()),
+            (None, Err(_)) => None,
+        };
+        let file = match &path {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|e| vec![format!("Error reading config file {}: {}", path, e)])?;
+                toml::parse(&text).map_err(|e| vec![format!("Invalid config file {}: {}", path, e)])?
+            }
+            None => Vec::new(),
+        };
+        Ok(Settings { path, file, read: HashSet::new(), errors: Vec::new() })
+    }
+
+    fn get(&mut self, var: &str, key: &str) -> Option<Setting> {
+        self.read.insert(key.to_string());
+        if let Ok(value) = env::var(var) {
+            return Some(Setting { value, source: var.to_string() });
+        }
+        let (_, value) = self.file.iter().find(|(k, _)| k == key)?;
+        let source = format!("{} in {}", key, self.path.as_deref().unwrap_or_default());
+        Some(Setting { value: value.to_setting(), source })
+    }
+
+    fn string(&mut self, var: &str, key: &str) -> Option<String> {
+        self.get(var, key).map(|setting| setting.value)
+    }
+
+    fn required(&mut self, var: &str, key: &str) -> String {
+        match self.string(var, key) {
+            Some(value) if !value.is_empty() => value,
+            _ => {
+                self.errors.push(format!("{} (or {} in the config file) must be set", var, key));
+                String::new()
+            }
+        }
+    }
+
+    // A setting parsed as `T` that passes `valid`, or `default` when it isn't set
+    fn parse<T: FromStr>(&mut self, var: &str, key: &str, default: T, valid: fn(&T) -> bool, expected: &str) -> T {
+        let Some(setting) = self.get(var, key) else {
+            return default;
+        };
+        match setting.value.trim().parse() {
+            Ok(value) if valid(&value) => value,
+            _ => {
+                self.errors.push(format!("{} must be {}, not {:?}", setting.source, expected, setting.value));
+                default
+            }
+        }
+    }
+
+    fn secs(&mut self, var: &str, key: &str, default: u64) -> Duration {
+        Duration::from_secs(self.parse(var, key, default, |_| true, "a number of seconds"))
+    }
+
+    fn millis(&mut self, var: &str, key: &str, default: u64) -> Duration {
+        Duration::from_millis(self.parse(var, key, default, |_| true, "a number of milliseconds"))
+    }
+
+    // true/1 or false/0
+    fn flag(&mut self, var: &str, key: &str, default: bool) -> bool {
+        let Some(setting) = self.get(var, key) else {
+            return default;
+        };
+        match setting.value.as_str() {
+            "1" | "true" => true,
+            "0" | "false" => false,
+            _ => {
+                self.errors.push(format!("{} must be true or false, not {:?}", setting.source, setting.value));
+                default
+            }
+        }
+    }
+
+    // One of a fixed set of names; the first is the default
+    fn choice<T: Copy>(&mut self, var: &str, key: &str, choices: &[(&str, T)]) -> T {
+        let Some(setting) = self.get(var, key) else {
+            return choices[0].1;
+        };
+        match choices.iter().find(|(name, _)| *name == setting.value) {
+            Some((_, value)) => *value,
+            None => {
+                let names: Vec<&str> = choices.iter().map(|(name, _)| *name).collect();
+                self.errors.push(format!("{} must be one of {}, not {:?}", setting.source, names.join(", "), setting.value));
+                choices[0].1
+            }
+        }
+    }
+
+    fn check_unused(&mut self) {
+        for (key, _) in &self.file {
+            if !self.read.contains(key) {
+                self.errors.push(format!("Unknown setting {} in {}", key, self.path.as_deref().unwrap_or_default()));
+            }
+        }
+    }
+}
+
+// Retrieve the access token signing key
+fn get_jwt_secret(settings: &mut Settings) -> String {
+    let secret = settings.required("JWT_SECRET", "auth.jwt_secret");
+    if !secret.is_empty() && secret.len() < MIN_JWT_SECRET_LENGTH {
+        settings.errors.push(format!("The JWT secret must be at least {} bytes", MIN_JWT_SECRET_LENGTH));
+    }
+    secret
+}
+
+// Retrieve the default log filter, which must be valid RUST_LOG syntax
+fn get_log_level(settings: &mut Settings) -> String {
+    let Some(setting) = settings.get("LOG_LEVEL", "log_level") else {
+        return DEFAULT_LOG_LEVEL.to_string();
+    };
+    if let Err(e) = EnvFilter::try_new(&setting.value) {
+        settings.errors.push(format!("{} is not a valid log filter: {}", setting.source, e));
+    }
+    setting.value
+}
+
+// Retrieve the optional HTTPS listener settings
+fn get_tls_config(settings: &mut Settings) -> Option<TlsConfig> {
+    let port = settings.parse("TLS_PORT", "tls.port", DEFAULT_TLS_PORT, |n| *n > 0, "a port number");
+    let only = settings.flag("TLS_ONLY", "tls.only", false);
+    match (settings.string("TLS_CERT_PATH", "tls.cert_path"), settings.string("TLS_KEY_PATH", "tls.key_path")) {
+        (Some(cert_path), Some(key_path)) => Some(TlsConfig { cert_path, key_path, port, only }),
+        (None, None) => None,
+        _ => {
+            settings.errors.push("The TLS certificate and key paths must be set together".to_string());
+            None
+        }
+    }
+}
+
+// Retrieve the optional CORS settings
+fn get_cors_config(settings: &mut Settings) -> Option<CorsConfig> {
+    let methods = settings.string("CORS_ALLOWED_METHODS", "cors.allowed_methods").unwrap_or_else(|| DEFAULT_CORS_METHODS.to_string());
+    let headers = settings.string("CORS_ALLOWED_HEADERS", "cors.allowed_headers").unwrap_or_else(|| DEFAULT_CORS_HEADERS.to_string());
+    let max_age = settings.parse("CORS_MAX_AGE", "cors.max_age", DEFAULT_CORS_MAX_AGE_SECS, |_| true, "a number of seconds");
+    let origins = settings.string("CORS_ALLOWED_ORIGINS", "cors.allowed_origins")?;
+    Some(CorsConfig {
+        allowed_origins: split_list(&origins),
+        allowed_methods: split_list(&methods),
+        allowed_headers: split_list(&headers),
+        max_age,
+    })
+}
+
+// Retrieve the database circuit breaker settings; 0 failures turns it off
+fn get_circuit_breaker_config(settings: &mut Settings) -> Option<CircuitBreakerConfig> {
+    let failures =
+        settings.parse("DB_BREAKER_FAILURES", "database.breaker_failures", DEFAULT_DB_BREAKER_FAILURES, |_| true, "a number of failures");
+    let cooldown = settings.secs("DB_BREAKER_COOLDOWN", "database.breaker_cooldown", DEFAULT_DB_BREAKER_COOLDOWN_SECS);
+    if failures == 0 {
+        return None;
+    }
+    Some(CircuitBreakerConfig { failures, cooldown })
+}
+
+// Retrieve the optional rate limit
+fn get_rate_limit_config(settings: &mut Settings) -> Option<RateLimitConfig> {
+    let burst = settings.parse("RATE_LIMIT_BURST", "rate_limit.burst", 0, |n| *n > 0, "a positive number");
+    let per_minute = settings.parse("RATE_LIMIT", "rate_limit.per_minute", 0, |n| *n > 0, "a positive number of requests per minute");
+    if per_minute == 0 {
+        return None;
+    }
+    Some(RateLimitConfig {
+        per_minute,
+        burst: if burst > 0 { burst } else { per_minute },
+    })
+}
+
+// Retrieve the addresses and networks of trusted reverse proxies, e.g. `10.0.0.0/8, 192.0.2.7`
+fn get_trusted_proxies(settings: &mut Settings) -> Vec<Network> {
+    let Some(setting) = settings.get("TRUSTED_PROXIES", "trusted_proxies") else {
+        return Vec::new();
+    };
+    let mut networks = Vec::new();
+    for entry in split_list(&setting.value) {
+        match entry.parse() {
+            Ok(network) => networks.push(network),
+            Err(()) => settings.errors.push(format!("{} must list addresses or networks like 10.0.0.0/8, not {:?}", setting.source, entry)),
+        }
+    }
+    networks
+}
+
+// Retrieve the per-route handler budgets, each written `METHOD /pattern=milliseconds`
+fn get_route_timeouts(settings: &mut Settings) -> HashMap<String, Duration> {
+    let Some(setting) = settings.get("ROUTE_TIMEOUTS_MS", "timeouts.routes_ms") else {
+        return HashMap::new();
+    };
+    let mut timeouts = HashMap::new();
+    for entry in split_list(&setting.value) {
+        let parsed = entry.rsplit_once('=').and_then(|(route, millis)| {
+            let (method, pattern) = route.trim().split_once(' ')?;
+            let valid = method.bytes().all(|b| b.is_ascii_uppercase()) && pattern.trim().starts_with('/');
+            let millis = millis.trim().parse().ok().filter(|millis| *millis > 0 && valid)?;
+            Some((format!("{} {}", method, pattern.trim()), Duration::from_millis(millis)))
+        });
+        match parsed {
+            Some((route, timeout)) => {
+                timeouts.insert(route, timeout);
+            }
+            None => settings.errors.push(format!("{} must list `METHOD /pattern=milliseconds` entries, not {:?}", setting.source, entry)),
+        }
+    }
+    timeouts
+}
+
+// Retrieve the optional limit on requests handled at once
+fn get_load_shedding_config(settings: &mut Settings) -> Option<LoadSheddingConfig> {
+    let max_queued =
+        settings.parse("MAX_QUEUED_REQUESTS", "load_shedding.max_queued", DEFAULT_MAX_QUEUED_REQUESTS, |_| true, "a number of requests");
+    let max_concurrent = settings.parse("MAX_CONCURRENT_REQUESTS", "load_shedding.max_concurrent", 0, |_| true, "a number of requests");
+    if max_concurrent == 0 {
+        return None;
+    }
+    Some(LoadSheddingConfig { max_concurrent, max_queued })
+}
+
+// Retrieve the optional Redis cache settings
+fn get_redis_config(settings: &mut Settings) -> Option<RedisConfig> {
+    let cache_ttl = settings.secs("REDIS_CACHE_TTL", "redis.cache_ttl", DEFAULT_REDIS_CACHE_TTL_SECS);
+    let timeout = settings.millis("REDIS_TIMEOUT_MS", "redis.timeout_ms", DEFAULT_REDIS_TIMEOUT_MS);
+    let url = settings.string("REDIS_URL", "redis.url")?;
+    Some(RedisConfig { url, cache_ttl, timeout })
+}
+
+// Retrieve the optional access log settings
+fn get_access_log_config(settings: &mut Settings) -> Option<AccessLogConfig> {
+    let path = settings.string("ACCESS_LOG_PATH", "access_log.path").map(PathBuf::from);
+    let format = settings.choice(
+        "ACCESS_LOG",
+        "access_log.format",
+        &[("off", None), ("common", Some(AccessLogFormat::Common)), ("json", Some(AccessLogFormat::Json))],
+    )?;
+    Some(AccessLogConfig { format, path })
+}
+
+// Retrieve the optional error tracker settings; the DSN must be a Sentry one
+fn get_sentry_config(settings: &mut Settings) -> Option<SentryConfig> {
+    let environment = settings.string("SENTRY_ENVIRONMENT", "sentry.environment");
+    let setting = settings.get("SENTRY_DSN", "sentry.dsn").filter(|setting| !setting.value.is_empty())?;
+    match Dsn::parse(&setting.value) {
+        Ok(dsn) => Some(SentryConfig { dsn, environment }),
+        Err(e) => {
+            settings.errors.push(format!("{} {}", setting.source, e));
+            None
+        }
+    }
+}
+
+// Retrieve the optional static file settings; the directory must exist
+fn get_static_config(settings: &mut Settings) -> Option<StaticConfig> {
+    let max_age = settings.secs("STATIC_MAX_AGE", "static.max_age", DEFAULT_STATIC_MAX_AGE_SECS);
+    let dir = PathBuf::from(settings.string("STATIC_DIR", "static.dir")?);
+    if !dir.is_dir() {
+        settings.errors.push(format!("The static file directory {} doesn't exist", dir.display()));
+        return None;
+    }
+    Some(StaticConfig { dir, max_age })
+}
+
+// Retrieve the optional multi-tenancy settings
+fn get_tenancy_config(settings: &mut Settings) -> Option<TenancyConfig> {
+    let domain = settings.string("TENANT_DOMAIN", "tenancy.domain").map(|domain| domain.trim_matches('.').to_ascii_lowercase());
+    if !settings.flag("TENANCY_ENABLED", "tenancy.enabled", false) {
+        return None;
+    }
+    Some(TenancyConfig { domain })
+}
+
+// Split a comma-separated setting into trimmed, non-empty items
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
@@ -0,0 +1,37 @@
+use std::future::Future;
+use std::time::Instant;
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+// What the request being handled asks of the work done for it, down to the queries it makes.
+// Tasks don't inherit it: work handed to one is wrapped in `inherit` to keep it
+#[derive(Clone, Copy, Default)]
+pub struct Context {
+    // It doesn't write, so its queries may be sent to a read replica
+    pub read_only: bool,
+    // When it must be answered by; queries still running then are cancelled
+    pub deadline: Option<Instant>,
+}
+
+impl Context {
+    // The context of the running task; the default outside of any
+    pub fn current() -> Context {
+        CONTEXT.try_with(|context| *context).unwrap_or_default()
+    }
+
+    pub async fn scope<F: Future>(self, work: F) -> F::Output {
+        CONTEXT.scope(self, work).await
+    }
+}
+
+// Run `work`, which doesn't write, with its queries sent to a replica when one is up
+pub async fn read_only<F: Future>(work: F) -> F::Output {
+    Context { read_only: true, ..Context::current() }.scope(work).await
+}
+
+// `work` in the caller's context, for handing to a task of its own
+pub fn inherit<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    Context::current().scope(work)
+}
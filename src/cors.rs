@@ -0,0 +1,67 @@
+use crate::config::CorsConfig;
+use crate::http::{Request, Response};
+
+impl CorsConfig {
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods.iter().any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    // The Access-Control-Allow-Origin value echoed back for an allowed origin
+    fn allow_origin_value<'a>(&self, origin: &'a str) -> &'a str {
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            "*"
+        } else {
+            origin
+        }
+    }
+}
+
+// Answer a CORS preflight (OPTIONS with Access-Control-Request-Method);
+// returns None for requests that should go to the router
+pub fn preflight(cors: &CorsConfig, request: &Request) -> Option<Response> {
+    if request.method != "OPTIONS" {
+        return None;
+    }
+    let origin = request.header("origin")?;
+    let requested_method = request.header("access-control-request-method")?;
+
+    if !cors.allows_origin(origin) || !cors.allows_method(requested_method) {
+        return Some(Response::error(403, "cors_not_allowed", "CORS request not allowed"));
+    }
+
+    // Only echo back requested headers that are on the allow list
+    let allowed_headers: Vec<&str> = request
+        .header("access-control-request-headers")
+        .unwrap_or_default()
+        .split(',')
+        .map(|h| h.trim())
+        .filter(|h| !h.is_empty())
+        .filter(|h| cors.allowed_headers.iter().any(|allowed| allowed.eq_ignore_ascii_case(h)))
+        .collect();
+
+    let mut response = Response::new(204)
+        .with_header("Access-Control-Allow-Origin", cors.allow_origin_value(origin))
+        .with_header("Access-Control-Allow-Methods", cors.allowed_methods.join(", "));
+    if !allowed_headers.is_empty() {
+        response = response.with_header("Access-Control-Allow-Headers", allowed_headers.join(", "));
+    }
+    Some(
+        response
+            .with_header("Access-Control-Max-Age", cors.max_age.to_string())
+            .with_header("Vary", "Origin, Access-Control-Request-Method, Access-Control-Request-Headers"),
+    )
+}
+
+// Add CORS headers to a regular response for a request from an allowed origin
+pub fn apply_headers(cors: &CorsConfig, origin: Option<&str>, response: Response) -> Response {
+    match origin {
+        Some(origin) if cors.allows_origin(origin) => response
+            .with_header("Access-Control-Allow-Origin", cors.allow_origin_value(origin))
+            .with_header("Vary", "Origin"),
+        _ => response,
+    }
+}
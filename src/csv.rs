@@ -0,0 +1,87 @@
+// One parsed CSV record and the line it starts on (1-based)
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+// Parse RFC 4180 CSV: comma-separated, optionally double-quoted fields with "" as an escaped
+// quote, CRLF or LF line endings. Blank lines are skipped; an unterminated quote is an error
+pub fn parse(input: &str) -> Result<Vec<Record>, String> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut quoted = false;
+    // Set once the current field has any content, so `""` still counts as a field
+    let mut started = false;
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => {
+                quoted = true;
+                started = true;
+            }
+            ',' => {
+                fields.push(std::mem::take(&mut field));
+                started = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                end_record(&mut records, &mut fields, &mut field, started, start_line);
+                started = false;
+                line += 1;
+                start_line = line;
+            }
+            _ => {
+                field.push(c);
+                started = true;
+            }
+        }
+    }
+
+    if quoted {
+        return Err(format!("unterminated quoted field starting on line {}", start_line));
+    }
+    end_record(&mut records, &mut fields, &mut field, started, start_line);
+    Ok(records)
+}
+
+fn end_record(records: &mut Vec<Record>, fields: &mut Vec<String>, field: &mut String, started: bool, line: usize) {
+    if !started && fields.is_empty() {
+        return;
+    }
+    fields.push(std::mem::take(field));
+    records.push(Record {
+        line,
+        fields: std::mem::take(fields),
+    });
+}
+
+// Append a field, quoting it when it holds a delimiter, quote or line break
+pub fn write_field(field: &str, out: &mut Vec<u8>) {
+    if field.contains([',', '"', '\r', '\n']) {
+        out.push(b'"');
+        out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+        out.push(b'"');
+    } else {
+        out.extend_from_slice(field.as_bytes());
+    }
+}
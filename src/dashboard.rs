@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::static_files::StaticFiles;
+
+// A page for managing users from the browser. Its files are compiled into the binary like the docs
+// explorer's, and can be replaced the same way. It holds no data itself: it signs in through the API
+// and makes every change with the signed-in user's token, so the API's roles decide what it can do
+pub const PATH: &str = "/admin";
+
+const INDEX: &str = include_str!("../assets/admin/index.html");
+
+// Files the page loads, by name under /admin/
+const ASSETS: [(&str, &str); 2] = [("admin.js", include_str!("../assets/admin/admin.js")), ("admin.css", include_str!("../assets/admin/admin.css"))];
+
+// GET /admin
+pub async fn handle_index_request(files: Arc<StaticFiles>) -> Result<Response, AppError> {
+    Ok(protect(files.or_embedded("admin/index.html", INDEX).await))
+}
+
+// GET /admin/{file}
+pub async fn handle_asset_request(request: Request, files: Arc<StaticFiles>) -> Result<Response, AppError> {
+    let name = request.params.get("file").map(String::as_str).unwrap_or_default();
+    match ASSETS.iter().find(|(asset, _)| *asset == name) {
+        Some((asset, content)) => Ok(protect(files.or_embedded(&format!("admin/{}", asset), content).await)),
+        None => Err(AppError::NotFound("Not found")),
+    }
+}
+
+// Other sites may not frame the page, so a click on it can't be borrowed
+fn protect(response: Response) -> Response {
+    response
+        .with_header("X-Frame-Options", "DENY")
+        .with_header("Content-Security-Policy", "default-src 'self'; frame-ancestors 'none'")
+}
@@ -0,0 +1,476 @@
+use deadpool_postgres::{GenericClient, Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod, Runtime, Transaction};
+use rustls_pemfile::certs;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_postgres::config::SslMode as PgSslMode;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Error as PostgresError, Portal, Row, Statement, ToStatement};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{self, ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{CertificateError, ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore, SignatureScheme};
+
+use tracing::{info_span, warn, Instrument};
+
+use crate::config::Config;
+use crate::query::percent_decode;
+
+// Build the shared connection pool; connections are opened lazily on first use
+pub fn create_pool(config: &Config) -> Result<Pool, String> {
+    pool_for(config, &config.db_url)
+}
+
+// A pool like the shared one, for a read replica at `url`
+pub fn create_replica_pool(config: &Config, url: &str) -> Result<Pool, String> {
+    pool_for(config, url)
+}
+
+fn pool_for(config: &Config, url: &str) -> Result<Pool, String> {
+    let (pg_config, tls) = url_config(config, url)?;
+    let manager = Manager::from_config(
+        pg_config,
+        tls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        },
+    );
+    Pool::builder(manager)
+        .max_size(config.db_pool_size)
+        .wait_timeout(Some(config.db_pool_timeout))
+        .create_timeout(Some(config.db_pool_timeout))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// Settings and TLS for connecting to DATABASE_URL, shared by the pool and connections of its own
+pub fn connection_config(config: &Config) -> Result<(tokio_postgres::Config, MakeRustlsConnect), String> {
+    url_config(config, &config.db_url)
+}
+
+// Settings and TLS for connecting to `url`, with DB_CA_CERT trusted unless the URL names a root certificate
+fn url_config(config: &Config, url: &str) -> Result<(tokio_postgres::Config, MakeRustlsConnect), String> {
+    let (url, ssl_mode, root_cert) = take_tls_params(url)?;
+    let mut pg_config: tokio_postgres::Config = url.parse().map_err(|e| format!("invalid database URL: {}", e))?;
+
+    // Without an sslmode in the URL, keep whatever tokio-postgres parsed (prefer by default)
+    let ssl_mode = ssl_mode.unwrap_or(match pg_config.get_ssl_mode() {
+        PgSslMode::Disable => SslMode::Disable,
+        PgSslMode::Require => SslMode::Require,
+        _ => SslMode::Prefer,
+    });
+    pg_config.ssl_mode(match ssl_mode {
+        SslMode::Disable => PgSslMode::Disable,
+        SslMode::Prefer => PgSslMode::Prefer,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => PgSslMode::Require,
+    });
+    let root_cert = root_cert.or_else(|| config.db_ca_cert.clone());
+    Ok((pg_config, tls_connector(ssl_mode, root_cert.as_deref())?))
+}
+
+// A pooled connection, or a transaction on one, whose queries are traced: each runs in a `query`
+// span inside the request's, and those taking at least the slow query threshold are logged with
+// their parameters redacted. Work that isn't a query, like migrations, takes the client itself
+pub struct Traced<C> {
+    client: C,
+    slow_query: Option<Duration>,
+}
+
+impl<C> Traced<C> {
+    pub fn new(client: C, slow_query: Option<Duration>) -> Traced<C> {
+        Traced { client, slow_query }
+    }
+
+    // The client, for work whose statements aren't traced
+    pub fn untraced(&mut self) -> &mut C {
+        &mut self.client
+    }
+}
+
+impl<C: GenericClient> Traced<C> {
+    pub async fn query<S: Sql + ?Sized>(&self, statement: &S, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PostgresError> {
+        self.trace(statement.sql(), params, self.client.query(statement.statement(), params)).await
+    }
+
+    pub async fn query_one<S: Sql + ?Sized>(&self, statement: &S, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PostgresError> {
+        self.trace(statement.sql(), params, self.client.query_one(statement.statement(), params)).await
+    }
+
+    pub async fn query_opt<S: Sql + ?Sized>(&self, statement: &S, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PostgresError> {
+        self.trace(statement.sql(), params, self.client.query_opt(statement.statement(), params)).await
+    }
+
+    pub async fn execute<S: Sql + ?Sized>(&self, statement: &S, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PostgresError> {
+        self.trace(statement.sql(), params, self.client.execute(statement.statement(), params)).await
+    }
+
+    // Run statements that take no parameters, e.g. DDL or savepoints
+    pub async fn batch_execute(&self, sql: &str) -> Result<(), PostgresError> {
+        self.trace(sql, &[], self.client.batch_execute(sql)).await
+    }
+
+    pub async fn prepare(&self, sql: &str) -> Result<Prepared, PostgresError> {
+        let statement = self.client.prepare(sql).await?;
+        Ok(Prepared { statement, sql: sql.to_string() })
+    }
+
+    // Prepare `sql` once per connection, keeping it to log the statement by
+    pub async fn prepare_cached(&self, sql: &str) -> Result<Prepared, PostgresError> {
+        let statement = self.client.prepare_cached(sql).await?;
+        Ok(Prepared { statement, sql: sql.to_string() })
+    }
+
+    async fn trace<T>(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+        work: impl Future<Output = Result<T, PostgresError>>,
+    ) -> Result<T, PostgresError> {
+        self.trace_redacted(sql, || redact(params), work).await
+    }
+
+    async fn trace_redacted<T>(
+        &self,
+        sql: &str,
+        params: impl FnOnce() -> String,
+        work: impl Future<Output = Result<T, PostgresError>>,
+    ) -> Result<T, PostgresError> {
+        let started = Instant::now();
+        let result = work.instrument(info_span!("query", statement = sql)).await;
+        let elapsed = started.elapsed();
+        // Logged in the request's span rather than the query's, so the line carries its id
+        if is_slow(elapsed, self.slow_query) {
+            let statement = sql.split_whitespace().collect::<Vec<_>>().join(" ");
+            warn!("Slow query ({:.1}ms): {} with parameters {}", elapsed.as_secs_f64() * 1000.0, statement, params());
+        }
+        result
+    }
+}
+
+impl Traced<Object> {
+    pub async fn transaction(&mut self) -> Result<Traced<Transaction<'_>>, PostgresError> {
+        Ok(Traced::new(self.client.transaction().await?, self.slow_query))
+    }
+}
+
+impl Traced<Transaction<'_>> {
+    // Bind a prepared statement to its parameters, for its rows to be fetched a batch at a time
+    pub async fn bind(&self, statement: &Prepared, params: &[&(dyn ToSql + Sync)]) -> Result<Bound, PostgresError> {
+        let portal = self.trace(&statement.sql, params, self.client.bind(&statement.statement, params)).await?;
+        Ok(Bound { portal, sql: statement.sql.clone(), params: redact(params) })
+    }
+
+    // The next `max_rows` rows of a bound statement, traced as the statement it was bound from
+    pub async fn query_portal(&self, bound: &Bound, max_rows: i32) -> Result<Vec<Row>, PostgresError> {
+        let fetch = self.client.query_portal(&bound.portal, max_rows);
+        self.trace_redacted(&bound.sql, || bound.params.clone(), fetch).await
+    }
+}
+
+// A statement traced queries run: SQL, or a statement prepared from it, which is logged as its SQL
+pub trait Sql: Sync {
+    type Statement: ToStatement + Sync + Send + ?Sized;
+
+    fn sql(&self) -> &str;
+    fn statement(&self) -> &Self::Statement;
+}
+
+impl Sql for str {
+    type Statement = str;
+
+    fn sql(&self) -> &str {
+        self
+    }
+
+    fn statement(&self) -> &str {
+        self
+    }
+}
+
+impl Sql for String {
+    type Statement = String;
+
+    fn sql(&self) -> &str {
+        self
+    }
+
+    fn statement(&self) -> &String {
+        self
+    }
+}
+
+pub struct Prepared {
+    statement: Statement,
+    sql: String,
+}
+
+impl Sql for Prepared {
+    type Statement = Statement;
+
+    fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    fn statement(&self) -> &Statement {
+        &self.statement
+    }
+}
+
+// A statement bound to its parameters inside a transaction, keeping them, redacted, to log it by
+pub struct Bound {
+    portal: Portal,
+    sql: String,
+    params: String,
+}
+
+// Whether a query that took `elapsed` is logged; there is no threshold when the log is off
+fn is_slow(elapsed: Duration, threshold: Option<Duration>) -> bool {
+    threshold.is_some_and(|threshold| elapsed >= threshold)
+}
+
+// Parameters as the slow query log shows them: numbers, booleans and NULLs as they are, anything
+// else, which could be a name, an email or a secret's hash, as `?`
+fn redact(params: &[&(dyn ToSql + Sync)]) -> String {
+    let shown: Vec<String> = params
+        .iter()
+        .map(|param| {
+            let value = format!("{:?}", param);
+            match value.as_str() {
+                "None" | "true" | "false" => value,
+                _ if value.parse::<f64>().is_ok() => value,
+                _ => "?".to_string(),
+            }
+        })
+        .collect();
+    format!("[{}]", shown.join(", "))
+}
+
+// Future returned by the body of a transaction; boxed so it can borrow the transaction
+pub type TransactionFuture<'a, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>;
+
+// Run `body` between BEGIN and COMMIT on `client`, rolling back if it or the commit fails
+pub async fn transaction<T, E, F>(client: &mut Traced<Object>, body: F) -> Result<T, E>
+where
+    E: From<PoolError>,
+    F: for<'a> FnOnce(&'a Traced<Transaction<'a>>) -> TransactionFuture<'a, T, E>,
+{
+    let transaction = client.transaction().await.map_err(PoolError::Backend)?;
+
+    match body(&transaction).await {
+        Ok(value) => {
+            transaction.client.commit().await.map_err(PoolError::Backend)?;
+            Ok(value)
+        }
+        Err(e) => {
+            // The body's error is what matters; a failed rollback closes the connection anyway
+            let _ = transaction.client.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+// libpq's sslmode values; tokio-postgres itself only understands disable, prefer and require
+#[derive(Clone, Copy, PartialEq)]
+enum SslMode {
+    Disable,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+// Remove sslmode and sslrootcert from a postgres:// URL so tokio-postgres accepts every
+// libpq mode; key=value connection strings are returned untouched
+fn take_tls_params(url: &str) -> Result<(String, Option<SslMode>, Option<String>), String> {
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) if url.contains("://") => (base, query),
+        _ => return Ok((url.to_string(), None, None)),
+    };
+
+    let mut ssl_mode = None;
+    let mut root_cert = None;
+    let mut kept = Vec::new();
+    for pair in query.split('&') {
+        match pair.split_once('=') {
+            Some(("sslmode", value)) => {
+                ssl_mode = Some(match value {
+                    "disable" => SslMode::Disable,
+                    "prefer" => SslMode::Prefer,
+                    "require" => SslMode::Require,
+                    "verify-ca" => SslMode::VerifyCa,
+                    "verify-full" => SslMode::VerifyFull,
+                    other => return Err(format!("invalid database URL: unsupported sslmode {}", other)),
+                })
+            }
+            Some(("sslrootcert", value)) => root_cert = Some(percent_decode(value)),
+            _ => kept.push(pair),
+        }
+    }
+
+    let url = if kept.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, kept.join("&"))
+    };
+    Ok((url, ssl_mode, root_cert))
+}
+
+// Build the TLS connector for `ssl_mode`. As in libpq, prefer and require only encrypt,
+// unless require is given a root certificate, in which case it checks the chain like verify-ca.
+// verify-ca and verify-full trust the root certificate, or the public web roots without one.
+fn tls_connector(ssl_mode: SslMode, root_cert: Option<&str>) -> Result<MakeRustlsConnect, String> {
+    let provider = Arc::new(ring::default_provider());
+    let verifier: Arc<dyn ServerCertVerifier> = match (ssl_mode, root_cert) {
+        (SslMode::Disable | SslMode::Prefer, _) | (SslMode::Require, None) => {
+            Arc::new(EncryptOnly(Arc::clone(&provider)))
+        }
+        (ssl_mode, root_cert) => {
+            let roots = load_roots(root_cert)?;
+            let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+                .build()
+                .map_err(|e| format!("invalid database CA certificate: {}", e))?;
+            match ssl_mode {
+                SslMode::VerifyFull => webpki,
+                _ => Arc::new(IgnoreHostname(webpki)),
+            }
+        }
+    };
+
+    let client_config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(client_config))
+}
+
+// Trust anchors from the PEM file, or the bundled public web roots
+fn load_roots(path: Option<&str>) -> Result<RootCertStore, String> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())),
+    };
+    let file = File::open(path).map_err(|e| format!("Error opening database CA certificate {}: {}", path, e))?;
+    let mut roots = RootCertStore::empty();
+    for cert in certs(&mut BufReader::new(file)) {
+        let cert = cert.map_err(|e| format!("Error reading database CA certificate {}: {}", path, e))?;
+        roots.add(cert).map_err(|e| format!("invalid database CA certificate {}: {}", path, e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("no certificates found in {}", path));
+    }
+    Ok(roots)
+}
+
+// Accept any server certificate while still checking the handshake signatures
+#[derive(Debug)]
+struct EncryptOnly(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for EncryptOnly {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// verify-ca: check the chain against the trusted roots but not the server's hostname
+#[derive(Debug)]
+struct IgnoreHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        match self.0.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(TlsError::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redaction_shows_only_numbers_booleans_and_nulls() {
+        let name = "Ada Lovelace".to_string();
+        let params: [&(dyn ToSql + Sync); 7] = [&42i32, &-7i64, &1.5f64, &true, &None::<String>, &name, &"ada@example.com"];
+        assert_eq!(redact(&params), "[42, -7, 1.5, true, None, ?, ?]");
+        assert_eq!(redact(&[]), "[]");
+    }
+
+    #[test]
+    fn queries_are_slow_from_the_threshold_on() {
+        let threshold = Some(Duration::from_millis(100));
+        assert!(!is_slow(Duration::from_millis(99), threshold));
+        assert!(is_slow(Duration::from_millis(100), threshold));
+        assert!(is_slow(Duration::from_secs(5), threshold));
+        // DB_SLOW_QUERY_MS=0 turns the log off
+        assert!(!is_slow(Duration::from_secs(5), None));
+    }
+}
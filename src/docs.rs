@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::static_files::StaticFiles;
+
+// The browser explorer for the OpenAPI document. Its files are compiled into the binary, so the
+// page works without network access or a static file directory; one with a docs/ directory of its
+// own replaces them file by file
+pub const PATH: &str = "/docs";
+
+const INDEX: &str = include_str!("../assets/docs/index.html");
+
+// Files the page loads, by name under /docs/
+const ASSETS: [(&str, &str); 2] = [("docs.js", include_str!("../assets/docs/docs.js")), ("docs.css", include_str!("../assets/docs/docs.css"))];
+
+// GET /docs
+pub async fn handle_index_request(files: Arc<StaticFiles>) -> Result<Response, AppError> {
+    Ok(files.or_embedded("docs/index.html", INDEX).await)
+}
+
+// GET /docs/{file}
+pub async fn handle_asset_request(request: Request, files: Arc<StaticFiles>) -> Result<Response, AppError> {
+    let name = request.params.get("file").map(String::as_str).unwrap_or_default();
+    match ASSETS.iter().find(|(asset, _)| *asset == name) {
+        Some((asset, content)) => Ok(files.or_embedded(&format!("docs/{}", asset), content).await),
+        None => Err(AppError::NotFound("Not found")),
+    }
+}
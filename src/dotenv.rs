@@ -0,0 +1,96 @@
+use std::env;
+use std::fs;
+use std::io;
+
+// Read when present, relative to the working directory
+pub const FILE: &str = ".env";
+
+// Whether to read .env at all; set DOTENV=false in production so a stray file can't change settings
+pub fn enabled() -> bool {
+    !matches!(env::var("DOTENV").as_deref(), Ok("0") | Ok("false"))
+}
+
+// Set the variables of a .env file that aren't already in the environment, so the real environment
+// always wins. A missing file is not an error. Must run before any other thread starts, since it
+// changes the process environment
+pub fn load(path: &str) -> Result<(), String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Error reading {}: {}", path, e)),
+    };
+    for (name, value) in parse(&text).map_err(|e| format!("Invalid {}: {}", path, e))? {
+        if env::var_os(&name).is_none() {
+            env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+// Parse `NAME=value` lines, optionally prefixed with `export`. Values may be bare (a ` #` starts a
+// comment), 'single-quoted' and taken as written, or "double-quoted" with \n, \t, \" and \\ escapes
+// and able to span lines. Blank lines and lines starting with # are skipped
+fn parse(input: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = Vec::new();
+    let mut lines = input.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected NAME=value", index + 1));
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("line {}: invalid variable name {:?}", index + 1, name));
+        }
+
+        let value = value.trim_start();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            match quoted.split_once('\'') {
+                Some((value, _)) => value.to_string(),
+                None => return Err(format!("line {}: unterminated quote", index + 1)),
+            }
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let mut quoted = quoted.to_string();
+            loop {
+                if let Some(value) = unescape(&quoted) {
+                    break value;
+                }
+                match lines.next() {
+                    Some((_, next)) => {
+                        quoted.push('\n');
+                        quoted.push_str(next);
+                    }
+                    None => return Err(format!("line {}: unterminated quote", index + 1)),
+                }
+            }
+        } else {
+            let end = value.find(" #").unwrap_or(value.len());
+            value[..end].trim_end().to_string()
+        };
+        variables.push((name.to_string(), value));
+    }
+    Ok(variables)
+}
+
+// The content of a double-quoted value up to its closing quote, or None if it isn't closed yet
+fn unescape(quoted: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                other => value.push(other),
+            },
+            _ => value.push(c),
+        }
+    }
+    None
+}
@@ -0,0 +1,97 @@
+use tracing::error;
+
+use crate::http::Response;
+use crate::repository::RepositoryError;
+
+// Why a handler couldn't produce its normal response. Each variant maps to an HTTP status, so
+// handlers return errors with `?` instead of building error responses, or panicking, themselves
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+    // 404 with this message, e.g. "User not found"
+    #[error("{0}")]
+    NotFound(&'static str),
+    // 400 for a body that isn't the JSON the route expects
+    #[error("{0}")]
+    InvalidJson(String),
+    // 415 for a body in a media type or charset the route doesn't take
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    // 500; the cause is logged but not shown to the client
+    #[error("{0}")]
+    Internal(String),
+    // A failure that already has its response, like a validation error or a bad parameter
+    #[error("request rejected with status {}", .0.status)]
+    Rejected(Response),
+}
+
+impl From<Response> for AppError {
+    fn from(response: Response) -> Self {
+        AppError::Rejected(response)
+    }
+}
+
+// Conflicts are 409s and an unreachable store is a 503, with Retry-After once its circuit breaker opens
+impl From<AppError> for Response {
+    fn from(e: AppError) -> Self {
+        match e {
+            AppError::Repository(RepositoryError::EmailTaken) => Response::error_with_details(
+                409,
+                "email_taken",
+                "A user with this email already exists",
+                serde_json::json!({ "field": "email" }),
+            ),
+            AppError::Repository(RepositoryError::GroupNameTaken) => Response::error_with_details(
+                409,
+                "group_name_taken",
+                "A group with this name already exists",
+                serde_json::json!({ "field": "name" }),
+            ),
+            AppError::Repository(RepositoryError::Conflict) => Response::error(409, "conflict", "User conflicts with an existing user"),
+            AppError::Repository(RepositoryError::Unavailable(e)) => {
+                error!("Database unavailable: {}", e);
+                Response::error(503, "database_unavailable", "Database is unavailable")
+                    .with_cause(format!("Database unavailable: {}", e))
+            }
+            // Not logged: the breaker says when it opens, and every request refused meanwhile would be
+            AppError::Repository(RepositoryError::CircuitOpen(retry_after)) => {
+                let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+                Response::error_with_details(
+                    503,
+                    "database_unavailable",
+                    "Database is unavailable",
+                    serde_json::json!({ "retry_after": retry_after }),
+                )
+                .with_header("Retry-After", retry_after.to_string())
+            }
+            AppError::Repository(RepositoryError::TimedOut) => Response::error(504, "timeout", "The request took too long"),
+            AppError::Repository(RepositoryError::Rejected(response)) | AppError::Rejected(response) => response,
+            AppError::Repository(RepositoryError::HasPosts(user_ids)) => Response::error_with_details(
+                409,
+                "user_has_posts",
+                "Delete the user's posts before deleting the user",
+                serde_json::json!({ "user_ids": user_ids }),
+            ),
+            AppError::Repository(RepositoryError::Internal(e)) => {
+                error!("Database error: {}", e);
+                Response::error(500, "internal_error", "Internal server error")
+                    .with_cause(format!("Database error: {}", e))
+            }
+            AppError::NotFound(message) => Response::error(404, "not_found", message),
+            AppError::InvalidJson(message) => Response::error(400, "invalid_json", message),
+            AppError::UnsupportedMediaType(message) => Response::error(415, "unsupported_media_type", message),
+            AppError::Internal(e) => {
+                error!("Internal error: {}", e);
+                Response::error(500, "internal_error", "Internal server error")
+                    .with_cause(format!("Internal error: {}", e))
+            }
+        }
+    }
+}
+
+impl From<RepositoryError> for Response {
+    fn from(e: RepositoryError) -> Self {
+        AppError::from(e).into()
+    }
+}
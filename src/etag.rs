@@ -0,0 +1,160 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::http::{self, Request, Response};
+
+// Strong entity tag for a stored version
+pub fn for_version(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+// Strong entity tag for a body with no stored version, such as a page of a listing: the first
+// 128 bits of its SHA-256
+pub fn for_body(body: &[u8]) -> String {
+    let hex = Sha256::digest(body)[..16].iter().fold(String::with_capacity(32), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    });
+    format!("\"{}\"", hex)
+}
+
+// Give the response's entity tag the variant it has been turned into, e.g. `"5"` to `"5-gzip"`, as
+// each format and coding of a resource is a representation of its own, which a strong tag must tell
+// apart (RFC 9110 8.8.3)
+pub fn add_variant(response: &mut Response, variant: &str) {
+    for (name, value) in response.headers.iter_mut() {
+        if name.eq_ignore_ascii_case("etag") {
+            if let Some(opaque) = value.strip_suffix('"') {
+                *value = format!("{}-{}\"", opaque, variant);
+            }
+        }
+    }
+}
+
+// Give a buffered JSON 200 without an entity tag one from its body
+pub fn tag_body(response: Response) -> Response {
+    let is_json = header(&response, "content-type").is_some_and(|value| value.starts_with("application/json"));
+    if response.status != 200 || response.stream.is_some() || !is_json || header(&response, "etag").is_some() {
+        return response;
+    }
+    let etag = for_body(&response.body);
+    response.with_header("ETag", etag)
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+// Headers describing the content itself, which a 304 (having none) leaves out; the rest, such as
+// ETag, Vary and rate-limit headers, are kept (RFC 9110 15.4.5)
+const CONTENT_HEADERS: [&str; 3] = ["content-type", "content-encoding", "content-language"];
+
+// The validators of a GET or HEAD, for answering 304 Not Modified when the client's copy is current
+pub struct Conditional {
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+}
+
+impl Conditional {
+    // None for methods other than GET and HEAD
+    pub fn from_request(request: &Request) -> Option<Conditional> {
+        if request.method != "GET" && request.method != "HEAD" {
+            return None;
+        }
+        Some(Conditional {
+            if_none_match: request.header("if-none-match").map(str::to_string),
+            // Ignored when the date doesn't parse, as RFC 9110 13.1.3 asks
+            if_modified_since: request.header("if-modified-since").and_then(http::parse_http_date),
+        })
+    }
+
+    // Answer a buffered 200 with 304 instead when If-None-Match matches its entity tag (weak
+    // comparison), or, without If-None-Match, when it hasn't changed since If-Modified-Since. The
+    // response is the one sent, so its tag is that of the format and coding the client gets, and the
+    // 304 keeps its Vary
+    pub fn apply(self, response: Response) -> Response {
+        if response.status != 200 || response.stream.is_some() {
+            return response;
+        }
+        let not_modified = match (&self.if_none_match, header(&response, "etag")) {
+            (Some(if_none_match), etag) => etag.is_some_and(|etag| matches_weakly(if_none_match, &etag)),
+            (None, _) => {
+                let last_modified = header(&response, "last-modified").and_then(|value| http::parse_http_date(&value));
+                matches!((self.if_modified_since, last_modified), (Some(since), Some(modified)) if modified <= since)
+            }
+        };
+        if !not_modified {
+            return response;
+        }
+        let mut reply = Response::new(304);
+        reply.headers = response
+            .headers
+            .into_iter()
+            .filter(|(name, _)| !CONTENT_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+            .collect();
+        reply
+    }
+}
+
+// Weak comparison (RFC 9110 8.8.3.2): `W/` is ignored on both sides, and `*` matches any tag
+fn matches_weakly(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+// A version's tag from that of one of its representations: `"5-xml-gzip"` to `"5"`
+fn without_variants(tag: &str) -> String {
+    match tag.strip_prefix('"').and_then(|opaque| opaque.split_once('-')) {
+        Some((version, _)) => format!("\"{}\"", version),
+        None => tag.to_string(),
+    }
+}
+
+// A parsed If-Match header
+pub enum IfMatch {
+    // `*`: any current representation
+    Any,
+    Tags(Vec<String>),
+}
+
+impl IfMatch {
+    // Read If-Match from the request; None when the header is absent
+    pub fn from_request(request: &Request) -> Option<IfMatch> {
+        let value = request.header("if-match")?.trim();
+        if value == "*" {
+            return Some(IfMatch::Any);
+        }
+        let tags = value.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty());
+        Some(IfMatch::Tags(tags.collect()))
+    }
+
+    // Strong comparison (RFC 9110 13.1.1): weak tags never match. The client may have been sent the
+    // tag of any of the version's representations, so their variants are set aside
+    pub fn matches(&self, etag: &str) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => tags.iter().any(|tag| !tag.starts_with("W/") && without_variants(tag) == etag),
+        }
+    }
+
+    // Fail with 412 unless the stored version is one the client expects
+    pub fn check(&self, version: i32) -> Result<(), Response> {
+        let etag = for_version(version);
+        if self.matches(&etag) {
+            return Ok(());
+        }
+        Err(Response::error_with_details(
+            412,
+            "precondition_failed",
+            "The user has changed since it was read; fetch it again and retry",
+            serde_json::json!({ "etag": etag }),
+        ))
+    }
+}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::models::User;
+
+// Events a subscriber that falls this far behind misses, rather than holding up the rest
+const CAPACITY: usize = 1024;
+
+// What happened to a user
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum EventType {
+    #[serde(rename = "user.created")]
+    Created,
+    #[serde(rename = "user.updated")]
+    Updated,
+    #[serde(rename = "user.deleted")]
+    Deleted,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 3] = [EventType::Created, EventType::Updated, EventType::Deleted];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::Created => "user.created",
+            EventType::Updated => "user.updated",
+            EventType::Deleted => "user.deleted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<EventType> {
+        EventType::ALL.into_iter().find(|event_type| event_type.as_str() == value)
+    }
+}
+
+// A committed change to a user. `data` is the user as it now is; a deletion only carries the id
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    pub created_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    fn new(event_type: EventType, data: serde_json::Value) -> Event {
+        Event {
+            id: Uuid::new_v4().to_string(),
+            event_type,
+            created_at: Utc::now(),
+            data,
+        }
+    }
+}
+
+// Fans user changes out to whoever is listening in this process; publishing with nobody
+// subscribed drops the event
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+    closed: CancellationToken,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events {
+            sender: broadcast::channel(CAPACITY).0,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    // Tell long-lived subscribers, such as event streams, to finish up; done when shutting down
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    pub fn user_changed(&self, event_type: EventType, user: &User) {
+        let data = serde_json::to_value(user).unwrap_or_default();
+        let _ = self.sender.send(Event::new(event_type, data));
+    }
+
+    pub fn user_deleted(&self, id: i32) {
+        let _ = self.sender.send(Event::new(EventType::Deleted, serde_json::json!({ "id": id })));
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Events::new()
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::csv;
+use crate::http::Request;
+use crate::models::User;
+
+// Columns of a CSV export, in order
+const CSV_COLUMNS: [&str; 6] = ["id", "name", "email", "created_at", "deleted_at", "version"];
+
+// Encodings a user listing can be streamed in
+#[derive(Clone, Copy)]
+pub enum Format {
+    // One JSON array
+    Json,
+    // One JSON object per line
+    JsonLines,
+    // RFC 4180 CSV with a header row
+    Csv,
+}
+
+impl Format {
+    // Pick the export format from Accept: CSV unless JSON Lines is asked for first.
+    // None when the client accepts neither
+    pub fn from_accept(request: &Request) -> Option<Format> {
+        let accept = match request.header("accept") {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Some(Format::Csv),
+        };
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let refused = params.any(|param| matches!(param.trim().strip_prefix("q="), Some(q) if q.parse() == Ok(0.0)));
+            if refused {
+                continue;
+            }
+            match media_type.as_str() {
+                "text/csv" | "text/*" | "*/*" => return Some(Format::Csv),
+                "application/x-ndjson" | "application/jsonl" => return Some(Format::JsonLines),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::JsonLines => "application/x-ndjson",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::JsonLines => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+
+    // Bytes written before the first user
+    pub fn start(self) -> Vec<u8> {
+        match self {
+            Format::Json => b"[".to_vec(),
+            Format::JsonLines => Vec::new(),
+            Format::Csv => format!("{}\r\n", CSV_COLUMNS.join(",")).into_bytes(),
+        }
+    }
+
+    // Append one user; `first` is set for the first user of the listing
+    pub fn encode(self, user: &User, first: bool, out: &mut Vec<u8>) -> serde_json::Result<()> {
+        match self {
+            Format::Json => {
+                if !first {
+                    out.push(b',');
+                }
+                serde_json::to_writer(out, user)
+            }
+            Format::JsonLines => {
+                serde_json::to_writer(&mut *out, user)?;
+                out.push(b'\n');
+                Ok(())
+            }
+            Format::Csv => {
+                let fields = [
+                    user.id.map(|id| id.to_string()).unwrap_or_default(),
+                    user.name.clone(),
+                    user.email.clone(),
+                    timestamp(user.created_at),
+                    timestamp(user.deleted_at),
+                    user.version.to_string(),
+                ];
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    csv::write_field(field, out);
+                }
+                out.extend_from_slice(b"\r\n");
+                Ok(())
+            }
+        }
+    }
+
+    // Bytes written after the last user
+    pub fn end(self) -> &'static [u8] {
+        match self {
+            Format::Json => b"]",
+            Format::JsonLines | Format::Csv => b"",
+        }
+    }
+}
+
+// RFC 3339 in UTC, or an empty field when unset
+fn timestamp(at: Option<DateTime<Utc>>) -> String {
+    at.map(|at| at.to_rfc3339_opts(SecondsFormat::AutoSi, true)).unwrap_or_default()
+}
@@ -0,0 +1,146 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::http::Request;
+
+// Where a request came from: the client's address and the scheme it used. A connection's is its
+// peer's; behind trusted proxies the client's is recovered from what they forwarded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Client {
+    pub ip: IpAddr,
+    pub scheme: &'static str,
+}
+
+// A range of addresses, written `10.0.0.0/8` or `fd00::/8`, or a single address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(&network.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => prefix_matches(&network.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Network, ()> {
+        let (addr, prefix) = value.split_once('/').map_or((value, None), |(addr, prefix)| (addr, Some(prefix)));
+        let addr = addr.trim().parse::<IpAddr>().map_err(|_| ())?.to_canonical();
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= bits).ok_or(())?,
+            None => bits,
+        };
+        Ok(Network { addr, prefix })
+    }
+}
+
+// Whether the first `prefix` bits of two addresses of the same family agree
+fn prefix_matches(network: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = (usize::from(prefix / 8), prefix % 8);
+    network[..bytes] == ip[..bytes] && (bits == 0 || (network[bytes] ^ ip[bytes]) >> (8 - bits) == 0)
+}
+
+// One proxy's account of the request it received: who sent it, and over which scheme, when it says
+struct Hop {
+    ip: Option<IpAddr>,
+    scheme: Option<&'static str>,
+}
+
+// The client a request came from. Unless the connection is from a trusted proxy, that's its peer.
+// Otherwise the proxies' hops (Forwarded, or X-Forwarded-For and X-Forwarded-Proto without it)
+// are walked back from the last one appended, for as long as they name trusted proxies; the first
+// address that isn't one is the client, since anything before it could have been made up by that
+// client. TRUST_X_FORWARDED_FOR trusts the peer whatever its address, as for a proxy in front of
+// every connection
+pub fn client(request: &Request, peer: Client, config: &Config) -> Client {
+    let trusted = |ip: IpAddr| config.trusted_proxies.iter().any(|network| network.contains(ip));
+    if !config.trust_forwarded_for && !trusted(peer.ip) {
+        return peer;
+    }
+    let mut client = peer;
+    for hop in hops(request).into_iter().rev() {
+        // A hidden or unknown sender leaves the last proxy as the best known client
+        let Some(ip) = hop.ip else { break };
+        client = Client { ip, scheme: hop.scheme.unwrap_or(client.scheme) };
+        if !trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+// The hops in the order they were appended, from Forwarded (RFC 7239) when there is one, otherwise
+// from X-Forwarded-For, with X-Forwarded-Proto's schemes matched to them from the end
+fn hops(request: &Request) -> Vec<Hop> {
+    if let Some(forwarded) = request.header("forwarded") {
+        return split_unquoted(forwarded, ',')
+            .into_iter()
+            .map(|element| {
+                let mut hop = Hop { ip: None, scheme: None };
+                for (key, value) in split_unquoted(element, ';').into_iter().filter_map(|pair| pair.split_once('=')) {
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "for" => hop.ip = node(value),
+                        "proto" => hop.scheme = scheme(value),
+                        _ => {}
+                    }
+                }
+                hop
+            })
+            .collect();
+    }
+    let Some(addresses) = request.header("x-forwarded-for") else {
+        return Vec::new();
+    };
+    let mut schemes = request.header("x-forwarded-proto").unwrap_or_default().rsplit(',').map(scheme);
+    let mut hops: Vec<Hop> = addresses.rsplit(',').map(|address| Hop { ip: node(address), scheme: schemes.next().flatten() }).collect();
+    hops.reverse();
+    hops
+}
+
+// The address of a Forwarded node, such as `192.0.2.1`, `"192.0.2.1:8080"` or `"[2001:db8::1]:443"`;
+// None for `unknown` and obfuscated ones like `_proxy1`
+fn node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(bracketed) = value.strip_prefix('[') {
+        return bracketed.split_once(']').and_then(|(ip, _)| ip.parse().ok());
+    }
+    value.parse().ok().or_else(|| value.rsplit_once(':').and_then(|(ip, _)| ip.parse::<std::net::Ipv4Addr>().ok()).map(IpAddr::V4))
+}
+
+fn scheme(value: &str) -> Option<&'static str> {
+    match value.trim().trim_matches('"').to_ascii_lowercase().as_str() {
+        "http" => Some("http"),
+        "https" => Some("https"),
+        _ => None,
+    }
+}
+
+// Split on `separator` where it isn't inside a quoted string
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
@@ -0,0 +1,38 @@
+// Entry points for the cargo-fuzz targets in fuzz/, which feed them arbitrary bytes. Each one
+// should only ever return: a panic, hang or runaway allocation on any input is a bug
+use std::sync::OnceLock;
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{csv, http, multipart};
+
+// Small, so over-long bodies are rejected rather than buffered
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+// Parse `data` as a request off the wire: request line, headers, query string, and a
+// Content-Length or chunked body
+pub fn request(data: &[u8]) {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    let runtime = RUNTIME.get_or_init(|| Builder::new_current_thread().build().expect("building the runtime"));
+    let mut reader = data;
+    if let Ok(request) = runtime.block_on(http::read_request(&mut reader, MAX_BODY_SIZE)) {
+        assert!(request.body.len() <= MAX_BODY_SIZE, "body of {} bytes read past the limit", request.body.len());
+    }
+}
+
+// Decode a body the way the handlers do. The first line is the Content-Type, the rest the body:
+// multipart parts when it names a boundary, then CSV and JSON
+pub fn body(data: &[u8]) {
+    let (content_type, body) = match data.iter().position(|&b| b == b'\n') {
+        Some(end) => (String::from_utf8_lossy(&data[..end]), &data[end + 1..]),
+        None => (Default::default(), data),
+    };
+    if let Some(boundary) = multipart::boundary(&content_type) {
+        if let Ok(parts) = multipart::parse(body, &boundary) {
+            let total: usize = parts.iter().map(|part| part.body.len()).sum();
+            assert!(total <= body.len(), "parts hold more bytes than the body");
+        }
+    }
+    let _ = csv::parse(&String::from_utf8_lossy(body));
+    let _ = serde_json::from_slice::<serde_json::Value>(body);
+}
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+
+// Largest dynamic table a peer may ask for; it's also the SETTINGS_HEADER_TABLE_SIZE default, so
+// it is never advertised
+pub const MAX_TABLE_SIZE: usize = 4096;
+
+// Per-entry overhead counted against the table size (RFC 7541 4.1)
+const ENTRY_OVERHEAD: usize = 32;
+
+// RFC 7541 Appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// The Huffman code of RFC 7541 Appendix B. It is canonical, so listing each length's symbols in
+// order is enough to rebuild it: codes count up through the symbols, shorter lengths first.
+// Symbol 256 is EOS, which must never be decoded
+const HUFFMAN_LENGTHS: [(u32, &[u16]); 21] = [
+    (5, &[48, 49, 50, 97, 99, 101, 105, 111, 115, 116]),
+    (6, &[32, 37, 45, 46, 47, 51, 52, 53, 54, 55, 56, 57, 61, 65, 95, 98, 100, 102, 103, 104, 108, 109, 110, 112, 114, 117]),
+    (7, &[58, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 89, 106, 107, 113, 118, 119, 120, 121, 122]),
+    (8, &[38, 42, 44, 59, 88, 90]),
+    (10, &[33, 34, 40, 41, 63]),
+    (11, &[39, 43, 124]),
+    (12, &[35, 62]),
+    (13, &[0, 36, 64, 91, 93, 126]),
+    (14, &[94, 125]),
+    (15, &[60, 96, 123]),
+    (19, &[92, 195, 208]),
+    (20, &[128, 130, 131, 162, 184, 194, 224, 226]),
+    (21, &[153, 161, 167, 172, 176, 177, 179, 209, 216, 217, 227, 229, 230]),
+    (22, &[129, 132, 133, 134, 136, 146, 154, 156, 160, 163, 164, 169, 170, 173, 178, 181, 185, 186, 187, 189, 190, 196, 198, 228, 232, 233]),
+    (23, &[1, 135, 137, 138, 139, 140, 141, 143, 147, 149, 150, 151, 152, 155, 157, 158, 165, 166, 168, 174, 175, 180, 182, 183, 188, 191, 197, 231, 239]),
+    (24, &[9, 142, 144, 145, 148, 159, 171, 206, 215, 225, 236, 237]),
+    (25, &[199, 207, 234, 235]),
+    (26, &[192, 193, 200, 201, 202, 205, 210, 213, 218, 219, 238, 240, 242, 243, 255]),
+    (27, &[203, 204, 211, 212, 214, 221, 222, 223, 241, 244, 245, 246, 247, 248, 250, 251, 252, 253, 254]),
+    (28, &[2, 3, 4, 5, 6, 7, 8, 11, 12, 14, 15, 16, 17, 18, 19, 20, 21, 23, 24, 25, 26, 27, 28, 29, 30, 31, 127, 220, 249]),
+    (30, &[10, 13, 22, 256]),
+];
+
+const EOS: u16 = 256;
+
+// Decodes the header blocks of one connection, keeping the dynamic table they build up
+pub struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: MAX_TABLE_SIZE,
+        }
+    }
+
+    // Every field in a complete header block, in order. An error leaves the table unusable, so it
+    // ends the connection (COMPRESSION_ERROR)
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut fields = Vec::new();
+        let mut first = true;
+        while let Some(&byte) = block.first() {
+            if byte & 0x80 != 0 {
+                // Indexed field
+                let index = integer(&mut block, 7)?;
+                fields.push(self.entry(index)?);
+            } else if byte & 0xc0 == 0x40 {
+                // Literal added to the table
+                let (name, value) = self.literal(&mut block, 6)?;
+                self.insert(name.clone(), value.clone());
+                fields.push((name, value));
+            } else if byte & 0xe0 == 0x20 {
+                // Table size update, only allowed before any field
+                let size = integer(&mut block, 5)?;
+                if !first || size > MAX_TABLE_SIZE {
+                    return Err("invalid dynamic table size update".to_string());
+                }
+                self.max_size = size;
+                self.evict();
+                continue;
+            } else {
+                // Literal left out of the table, whether or not it may ever be indexed
+                fields.push(self.literal(&mut block, 4)?);
+            }
+            first = false;
+        }
+        Ok(fields)
+    }
+
+    // A literal field: a name by index or as a string, then the value
+    fn literal(&self, block: &mut &[u8], prefix: u32) -> Result<(String, String), String> {
+        let index = integer(block, prefix)?;
+        let name = match index {
+            0 => string(block)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, string(block)?))
+    }
+
+    // Index 1 is the first static entry; the dynamic table follows, newest first
+    fn entry(&self, index: usize) -> Result<(String, String), String> {
+        match index {
+            0 => Err("header field index 0".to_string()),
+            index if index <= STATIC_TABLE.len() => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            index => self.table.get(index - STATIC_TABLE.len() - 1).cloned().ok_or_else(|| format!("header field index {} out of range", index)),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.table.push_front((name, value));
+        self.size += size;
+        self.evict();
+    }
+
+    // Drop the oldest entries until the table fits; an entry larger than the table empties it
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => self.size = 0,
+            }
+        }
+    }
+}
+
+// Encode response fields as literals left out of the table, so nothing has to be tracked for the
+// peer's decoder. Names must already be lowercase
+pub fn encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in fields {
+        match STATIC_TABLE.iter().position(|(static_name, _)| static_name == name) {
+            Some(index) => put_integer(&mut block, 0x00, 4, index + 1),
+            None => {
+                block.push(0x00);
+                put_string(&mut block, name);
+            }
+        }
+        put_string(&mut block, value);
+    }
+    block
+}
+
+// An integer with an N-bit prefix (RFC 7541 5.1); the prefix's other bits are the caller's
+fn integer(block: &mut &[u8], prefix: u32) -> Result<usize, String> {
+    let mask = (1 << prefix) - 1;
+    let (&first, rest) = block.split_first().ok_or("truncated header block")?;
+    *block = rest;
+    let mut value = (first as usize) & mask;
+    if value < mask {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or("truncated header block")?;
+        *block = rest;
+        // Anything past 28 bits is far beyond any limit here, and could overflow
+        if shift > 21 {
+            return Err("header block integer too large".to_string());
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn put_integer(block: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+    let mask = (1 << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+// A string literal, Huffman-coded when its first bit says so
+fn string(block: &mut &[u8]) -> Result<String, String> {
+    let huffman = block.first().is_some_and(|byte| byte & 0x80 != 0);
+    let length = integer(block, 7)?;
+    let (bytes, rest) = block.split_at_checked(length).ok_or("truncated header block")?;
+    *block = rest;
+    let bytes = match huffman {
+        true => huffman_decode(bytes)?,
+        false => bytes.to_vec(),
+    };
+    String::from_utf8(bytes).map_err(|_| "header field is not UTF-8".to_string())
+}
+
+fn put_string(block: &mut Vec<u8>, value: &str) {
+    put_integer(block, 0x00, 7, value.len());
+    block.extend_from_slice(value.as_bytes());
+}
+
+fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(bytes.len() * 8 / 5);
+    let (mut code, mut length) = (0u32, 0u32);
+    for bit in bytes.iter().flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1)) {
+        code = (code << 1) | bit as u32;
+        length += 1;
+        if let Some(symbol) = huffman_symbol(code, length) {
+            if symbol == EOS {
+                return Err("EOS in a Huffman-coded string".to_string());
+            }
+            decoded.push(symbol as u8);
+            (code, length) = (0, 0);
+        } else if length >= 30 {
+            return Err("invalid Huffman code".to_string());
+        }
+    }
+    // What's left must be padding: fewer than 8 bits, all ones (a prefix of EOS)
+    if length >= 8 || code != (1 << length) - 1 {
+        return Err("invalid Huffman padding".to_string());
+    }
+    Ok(decoded)
+}
+
+// The symbol a code of `length` bits stands for, if it is a whole code
+fn huffman_symbol(code: u32, length: u32) -> Option<u16> {
+    let mut first = 0u32;
+    let mut previous = 0;
+    for (bits, symbols) in HUFFMAN_LENGTHS {
+        first <<= bits - previous;
+        previous = bits;
+        if bits == length {
+            return code.checked_sub(first).and_then(|offset| symbols.get(offset as usize)).copied();
+        }
+        if bits > length {
+            return None;
+        }
+        first += symbols.len() as u32;
+    }
+    None
+}
@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use super::hpack::{self, Decoder};
+use crate::config::Config;
+
+// What a client sends before its first frame (RFC 9113 3.4)
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types (RFC 9113 6)
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Frame flags; ACK shares END_STREAM's bit on SETTINGS and PING
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// Error codes (RFC 9113 7)
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+
+// Flow-control windows start at this many bytes and may not grow past MAX_WINDOW
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+// Largest frame payload either side sends by default; more is only sent once the client allows it
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE_LIMIT: usize = (1 << 24) - 1;
+
+// Streams a client may have open at once
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+// Largest header block accepted, CONTINUATION frames included
+const MAX_HEADER_BLOCK_SIZE: usize = 64 * 1024;
+
+// A request whose client has finished sending it. Header names are lowercase, and the pseudo-headers
+// (`:method`, `:path` and so on) are among them
+pub struct Request {
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+// Sent as a HEADERS frame, the body as DATA frames, then the trailers ending the stream. Without a
+// body, the trailers go in the one HEADERS frame
+pub struct Response {
+    pub status: u16,
+    pub headers: Fields,
+    pub body: Vec<u8>,
+    pub trailers: Fields,
+}
+
+// Response header or trailer fields, names lowercase
+pub type Fields = Vec<(&'static str, String)>;
+
+// Why a connection was given up on: the GOAWAY code sent, and what went wrong
+struct ConnectionError {
+    code: u32,
+    reason: String,
+}
+
+impl ConnectionError {
+    fn new(code: u32, reason: impl Into<String>) -> ConnectionError {
+        ConnectionError { code, reason: reason.into() }
+    }
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+struct Stream {
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+    // The body grew past the limit and was dropped
+    oversized: bool,
+    // The client has sent END_STREAM, so the request is being handled
+    received: bool,
+    send_window: i64,
+    // Response data waiting for flow-control window, and the trailers to send after it
+    pending: Option<(Vec<u8>, Fields)>,
+}
+
+struct Connection {
+    streams: HashMap<u32, Stream>,
+    decoder: Decoder,
+    // Highest stream id the client has opened; lower ids can't be opened again
+    last_stream_id: u32,
+    send_window: i64,
+    // The client's SETTINGS_INITIAL_WINDOW_SIZE, each new stream's send window
+    initial_window: i64,
+    max_frame_size: usize,
+    max_body_size: usize,
+    // A header block continuing in CONTINUATION frames: its stream, the block so far and END_STREAM
+    continuation: Option<(u32, Vec<u8>, bool)>,
+    // No new streams are taken once either side has sent GOAWAY
+    draining: bool,
+    // Frames written since the last flush
+    out: Vec<u8>,
+}
+
+// Serve one cleartext HTTP/2 connection (prior knowledge, no upgrade), running `handler` on its own
+// task for each request once the client has sent all of it. Ends once the client closes the
+// connection, or after in-flight requests are answered once `shutdown` is cancelled
+pub async fn serve<S, H, F>(stream: S, config: &Config, shutdown: &CancellationToken, handler: H) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: Fn(Request) -> F,
+    F: Future<Output = Response> + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut preface = [0; PREFACE.len()];
+    match tokio::time::timeout(config.read_timeout, reader.read_exact(&mut preface)).await {
+        Ok(Ok(_)) if preface == PREFACE => {}
+        Ok(Ok(_)) => return Err("not an HTTP/2 connection; clients must use prior knowledge".to_string()),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("timed out waiting for the connection preface".to_string()),
+    }
+
+    let mut connection = Connection {
+        streams: HashMap::new(),
+        decoder: Decoder::new(),
+        last_stream_id: 0,
+        send_window: DEFAULT_WINDOW,
+        initial_window: DEFAULT_WINDOW,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        max_body_size: config.max_body_size,
+        continuation: None,
+        draining: false,
+        out: Vec::new(),
+    };
+    let mut settings = Vec::new();
+    settings.extend_from_slice(&SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes());
+    settings.extend_from_slice(&(MAX_CONCURRENT_STREAMS as u32).to_be_bytes());
+    connection.frame(SETTINGS, 0, 0, &settings);
+
+    let (responder, mut responses) = mpsc::unbounded_channel();
+    let mut buf = Vec::with_capacity(DEFAULT_MAX_FRAME_SIZE);
+    loop {
+        let handled = loop {
+            let frame = match next_frame(&mut buf) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            match connection.receive(frame) {
+                Ok(Some((id, request))) => {
+                    let responder = responder.clone();
+                    let response = handler(request);
+                    tokio::spawn(
+                        async move {
+                            let _ = responder.send((id, response.await));
+                        }
+                        .in_current_span(),
+                    );
+                }
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+        };
+        if let Err(e) = handled {
+            connection.goaway(e.code);
+            let _ = flush(&mut writer, &mut connection.out, config).await;
+            return Err(e.reason);
+        }
+        flush(&mut writer, &mut connection.out, config).await?;
+        if connection.draining && connection.streams.is_empty() {
+            return Ok(());
+        }
+
+        tokio::select! {
+            read = reader.read_buf(&mut buf) => match read {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            },
+            Some((id, response)) = responses.recv() => connection.respond(id, response),
+            _ = shutdown.cancelled(), if !connection.draining => {
+                connection.draining = true;
+                connection.goaway(NO_ERROR);
+            }
+        }
+    }
+}
+
+// The next whole frame at the start of `buf`, if it has arrived
+fn next_frame(buf: &mut Vec<u8>) -> Result<Option<Frame>, ConnectionError> {
+    let Some(header) = buf.get(..9) else { return Ok(None) };
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    // Nothing larger was allowed, since the default frame size is never raised
+    if length > DEFAULT_MAX_FRAME_SIZE {
+        return Err(ConnectionError::new(FRAME_SIZE_ERROR, format!("frame of {} bytes", length)));
+    }
+    if buf.len() < 9 + length {
+        return Ok(None);
+    }
+    let frame = Frame {
+        kind: header[3],
+        flags: header[4],
+        stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+        payload: buf[9..9 + length].to_vec(),
+    };
+    buf.drain(..9 + length);
+    Ok(Some(frame))
+}
+
+async fn flush<W: AsyncWrite + Unpin>(writer: &mut W, out: &mut Vec<u8>, config: &Config) -> Result<(), String> {
+    if out.is_empty() {
+        return Ok(());
+    }
+    match tokio::time::timeout(config.write_timeout, writer.write_all(out)).await {
+        Ok(Ok(())) => {
+            out.clear();
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out writing to the connection".to_string()),
+    }
+}
+
+impl Connection {
+    // Act on a frame from the client; returns a request once one has been received in full
+    fn receive(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, ConnectionError> {
+        if let Some((id, _, _)) = &self.continuation {
+            if frame.kind != CONTINUATION || frame.stream_id != *id {
+                return Err(ConnectionError::new(PROTOCOL_ERROR, "expected a CONTINUATION frame"));
+            }
+        }
+        match frame.kind {
+            DATA => self.data(frame),
+            HEADERS => {
+                if frame.stream_id == 0 || frame.stream_id.is_multiple_of(2) {
+                    return Err(ConnectionError::new(PROTOCOL_ERROR, "HEADERS on an invalid stream"));
+                }
+                let mut fragment = unpad(&frame)?;
+                if frame.flags & PRIORITY_FLAG != 0 {
+                    fragment = fragment.get(5..).ok_or_else(|| ConnectionError::new(FRAME_SIZE_ERROR, "short HEADERS frame"))?;
+                }
+                let end_stream = frame.flags & END_STREAM != 0;
+                match frame.flags & END_HEADERS != 0 {
+                    true => self.header_block(frame.stream_id, fragment, end_stream),
+                    false => {
+                        self.continuation = Some((frame.stream_id, fragment.to_vec(), end_stream));
+                        Ok(None)
+                    }
+                }
+            }
+            CONTINUATION => {
+                let Some((id, mut block, end_stream)) = self.continuation.take() else {
+                    return Err(ConnectionError::new(PROTOCOL_ERROR, "unexpected CONTINUATION frame"));
+                };
+                block.extend_from_slice(&frame.payload);
+                if block.len() > MAX_HEADER_BLOCK_SIZE {
+                    return Err(ConnectionError::new(PROTOCOL_ERROR, "header block too large"));
+                }
+                match frame.flags & END_HEADERS != 0 {
+                    true => self.header_block(id, &block, end_stream),
+                    false => {
+                        self.continuation = Some((id, block, end_stream));
+                        Ok(None)
+                    }
+                }
+            }
+            PRIORITY => match frame.payload.len() {
+                5 => Ok(None),
+                _ => Err(ConnectionError::new(FRAME_SIZE_ERROR, "PRIORITY frame of the wrong size")),
+            },
+            RST_STREAM => {
+                if frame.stream_id == 0 || frame.payload.len() != 4 {
+                    return Err(ConnectionError::new(PROTOCOL_ERROR, "invalid RST_STREAM frame"));
+                }
+                self.streams.remove(&frame.stream_id);
+                Ok(None)
+            }
+            SETTINGS => self.settings(frame),
+            PING => {
+                if frame.stream_id != 0 || frame.payload.len() != 8 {
+                    return Err(ConnectionError::new(PROTOCOL_ERROR, "invalid PING frame"));
+                }
+                if frame.flags & ACK == 0 {
+                    self.frame(PING, ACK, 0, &frame.payload);
+                }
+                Ok(None)
+            }
+            GOAWAY => {
+                self.draining = true;
+                Ok(None)
+            }
+            WINDOW_UPDATE => self.window_update(frame),
+            PUSH_PROMISE => Err(ConnectionError::new(PROTOCOL_ERROR, "clients can't push")),
+            // Unknown frame types are ignored (RFC 9113 4.1)
+            _ => Ok(None),
+        }
+    }
+
+    fn data(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, ConnectionError> {
+        if frame.stream_id == 0 {
+            return Err(ConnectionError::new(PROTOCOL_ERROR, "DATA on stream 0"));
+        }
+        let data = unpad(&frame)?;
+        // Padding counts against the window too; what was taken is handed straight back
+        let consumed = frame.payload.len() as u32;
+        if consumed > 0 {
+            self.frame(WINDOW_UPDATE, 0, 0, &consumed.to_be_bytes());
+        }
+        let Some(stream) = self.streams.get_mut(&frame.stream_id).filter(|stream| !stream.received) else {
+            if frame.stream_id > self.last_stream_id {
+                return Err(ConnectionError::new(PROTOCOL_ERROR, "DATA on an idle stream"));
+            }
+            self.reset(frame.stream_id, STREAM_CLOSED);
+            return Ok(None);
+        };
+        if !stream.oversized {
+            stream.body.extend_from_slice(data);
+            if stream.body.len() > self.max_body_size {
+                stream.oversized = true;
+                stream.body = Vec::new();
+            }
+        }
+        if frame.flags & END_STREAM != 0 {
+            return Ok(self.received(frame.stream_id));
+        }
+        if consumed > 0 {
+            self.frame(WINDOW_UPDATE, 0, frame.stream_id, &consumed.to_be_bytes());
+        }
+        Ok(None)
+    }
+
+    // A complete header block: a new request, or the trailers ending one
+    fn header_block(&mut self, id: u32, block: &[u8], end_stream: bool) -> Result<Option<(u32, Request)>, ConnectionError> {
+        // Always decoded, even when the stream is refused, to keep the table in step with the client
+        let fields = self.decoder.decode(block).map_err(|e| ConnectionError::new(COMPRESSION_ERROR, e))?;
+        if let Some(stream) = self.streams.get(&id) {
+            if stream.received {
+                self.reset(id, STREAM_CLOSED);
+                return Ok(None);
+            }
+            if !end_stream {
+                self.reset(id, PROTOCOL_ERROR);
+                return Ok(None);
+            }
+            return Ok(self.received(id));
+        }
+        if id <= self.last_stream_id {
+            return Err(ConnectionError::new(PROTOCOL_ERROR, "HEADERS on a closed stream"));
+        }
+        self.last_stream_id = id;
+        if self.draining || self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.reset(id, REFUSED_STREAM);
+            return Ok(None);
+        }
+        let headers: HashMap<String, String> = fields.into_iter().collect();
+        if !headers.contains_key(":method") || !headers.contains_key(":path") {
+            self.reset(id, PROTOCOL_ERROR);
+            return Ok(None);
+        }
+        let stream = Stream {
+            headers,
+            body: Vec::new(),
+            oversized: false,
+            received: false,
+            send_window: self.initial_window,
+            pending: None,
+        };
+        self.streams.insert(id, stream);
+        match end_stream {
+            true => Ok(self.received(id)),
+            false => Ok(None),
+        }
+    }
+
+    // The client has sent all of the request on stream `id`
+    fn received(&mut self, id: u32) -> Option<(u32, Request)> {
+        let stream = self.streams.get_mut(&id)?;
+        stream.received = true;
+        if stream.oversized {
+            let response = super::message_too_large(self.max_body_size);
+            self.respond(id, response);
+            return None;
+        }
+        let request = Request {
+            headers: std::mem::take(&mut stream.headers),
+            body: std::mem::take(&mut stream.body),
+        };
+        Some((id, request))
+    }
+
+    fn settings(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, ConnectionError> {
+        if frame.stream_id != 0 {
+            return Err(ConnectionError::new(PROTOCOL_ERROR, "SETTINGS on a stream"));
+        }
+        if frame.flags & ACK != 0 {
+            return Ok(None);
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(ConnectionError::new(FRAME_SIZE_ERROR, "SETTINGS frame of the wrong size"));
+        }
+        for setting in frame.payload.chunks_exact(6) {
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match u16::from_be_bytes([setting[0], setting[1]]) {
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let window = value as i64;
+                    if window > MAX_WINDOW {
+                        return Err(ConnectionError::new(FLOW_CONTROL_ERROR, "initial window too large"));
+                    }
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += window - self.initial_window;
+                    }
+                    self.initial_window = window;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    let size = value as usize;
+                    if !(DEFAULT_MAX_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(&size) {
+                        return Err(ConnectionError::new(PROTOCOL_ERROR, "invalid maximum frame size"));
+                    }
+                    self.max_frame_size = size;
+                }
+                // Responses aren't compressed against the client's table, and nothing is pushed
+                _ => {}
+            }
+        }
+        self.frame(SETTINGS, ACK, 0, &[]);
+        self.send_pending();
+        Ok(None)
+    }
+
+    fn window_update(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, ConnectionError> {
+        let Ok(increment) = <[u8; 4]>::try_from(frame.payload.as_slice()) else {
+            return Err(ConnectionError::new(FRAME_SIZE_ERROR, "WINDOW_UPDATE frame of the wrong size"));
+        };
+        let increment = (u32::from_be_bytes(increment) & 0x7fff_ffff) as i64;
+        if frame.stream_id == 0 {
+            self.send_window += increment;
+            if increment == 0 || self.send_window > MAX_WINDOW {
+                return Err(ConnectionError::new(FLOW_CONTROL_ERROR, "invalid connection window update"));
+            }
+        } else if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+            stream.send_window += increment;
+            if increment == 0 || stream.send_window > MAX_WINDOW {
+                self.streams.remove(&frame.stream_id);
+                self.reset(frame.stream_id, FLOW_CONTROL_ERROR);
+            }
+        }
+        self.send_pending();
+        Ok(None)
+    }
+
+    // Start answering stream `id`; a stream the client reset meanwhile is left alone
+    fn respond(&mut self, id: u32, response: Response) {
+        if !self.streams.contains_key(&id) {
+            return;
+        }
+        let mut headers = vec![(":status", response.status.to_string())];
+        headers.extend(response.headers);
+        if response.body.is_empty() {
+            headers.extend(response.trailers);
+            self.headers(id, &headers, true);
+            self.streams.remove(&id);
+            return;
+        }
+        self.headers(id, &headers, false);
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.pending = Some((response.body, response.trailers));
+        }
+        self.send_pending();
+    }
+
+    // Send as much waiting response data as the windows allow, finishing streams whose data is all sent
+    fn send_pending(&mut self) {
+        let mut ids: Vec<u32> = self.streams.iter().filter(|(_, stream)| stream.pending.is_some()).map(|(id, _)| *id).collect();
+        // Oldest streams first
+        ids.sort_unstable();
+        for id in ids {
+            let mut finished = None;
+            while let Some(stream) = self.streams.get_mut(&id) {
+                let Some((data, _)) = stream.pending.as_mut() else { break };
+                if data.is_empty() {
+                    finished = stream.pending.take().map(|(_, trailers)| trailers);
+                    break;
+                }
+                let size = (data.len() as i64).min(self.send_window).min(stream.send_window).min(self.max_frame_size as i64);
+                if size <= 0 {
+                    break;
+                }
+                let chunk: Vec<u8> = data.drain(..size as usize).collect();
+                stream.send_window -= size;
+                self.send_window -= size;
+                self.frame(DATA, 0, id, &chunk);
+            }
+            if let Some(trailers) = finished {
+                match trailers.is_empty() {
+                    true => self.frame(DATA, END_STREAM, id, &[]),
+                    false => self.headers(id, &trailers, true),
+                }
+                self.streams.remove(&id);
+            }
+        }
+    }
+
+    // A header block, split into CONTINUATION frames when it's larger than a frame
+    fn headers(&mut self, id: u32, fields: &[(&str, String)], end_stream: bool) {
+        let fields: Vec<(&str, &str)> = fields.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        let block = hpack::encode(&fields);
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.frame(kind, flags, id, chunk);
+            if flags & END_HEADERS != 0 {
+                return;
+            }
+            (kind, flags) = (CONTINUATION, 0);
+        }
+    }
+
+    fn reset(&mut self, id: u32, code: u32) {
+        self.frame(RST_STREAM, 0, id, &code.to_be_bytes());
+    }
+
+    fn goaway(&mut self, code: u32) {
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.frame(GOAWAY, 0, 0, &payload);
+    }
+
+    fn frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        self.out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        self.out.extend_from_slice(&[kind, flags]);
+        self.out.extend_from_slice(&stream_id.to_be_bytes());
+        self.out.extend_from_slice(payload);
+    }
+}
+
+// A DATA or HEADERS frame's payload without its padding
+fn unpad(frame: &Frame) -> Result<&[u8], ConnectionError> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let (&padding, rest) = frame.payload.split_first().ok_or_else(|| ConnectionError::new(FRAME_SIZE_ERROR, "empty padded frame"))?;
+    rest.len()
+        .checked_sub(padding as usize)
+        .map(|length| &rest[..length])
+        .ok_or_else(|| ConnectionError::new(PROTOCOL_ERROR, "padding longer than the frame"))
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn, Instrument};
+
+use crate::config::Config;
+use crate::forwarded::{self, Client};
+use crate::http::{self, Response};
+use crate::query::Query;
+use crate::ratelimit::RateLimiter;
+use crate::repository::Stores;
+use crate::{audit, auth, request_id, tenancy};
+
+mod hpack;
+mod http2;
+mod protobuf;
+mod users;
+
+// Length-prefixed message framing: a compressed flag, then the length as four bytes
+const MESSAGE_HEADER_SIZE: usize = 5;
+
+// gRPC status codes (https://grpc.github.io/grpc/core/md_doc_statuscodes.html)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+// Why a call failed, as sent in its grpc-status and grpc-message trailers
+pub struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    pub fn new(code: Code, message: impl Into<String>) -> Status {
+        Status { code, message: message.into() }
+    }
+}
+
+// The status matching an error the REST API would have answered with, carrying its message and any
+// invalid fields
+impl From<Response> for Status {
+    fn from(response: Response) -> Status {
+        let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap_or_default();
+        let error = &body["error"];
+        let code = match (response.status, error["code"].as_str()) {
+            (400 | 415 | 422, _) => Code::InvalidArgument,
+            (401, _) => Code::Unauthenticated,
+            (403, _) => Code::PermissionDenied,
+            (404, _) => Code::NotFound,
+            (409, Some("email_taken")) => Code::AlreadyExists,
+            (409 | 412, _) => Code::FailedPrecondition,
+            (413 | 429, _) => Code::ResourceExhausted,
+            (503, _) => Code::Unavailable,
+            (504, _) => Code::DeadlineExceeded,
+            _ => Code::Internal,
+        };
+        let mut message = error["message"].as_str().unwrap_or("Internal server error").to_string();
+        if let Some(fields) = error["details"]["fields"].as_object() {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, problems)| {
+                    let problems: Vec<&str> = problems.as_array().into_iter().flatten().filter_map(|problem| problem.as_str()).collect();
+                    format!("{}: {}", field, problems.join(", "))
+                })
+                .collect();
+            message = format!("{} ({})", message, fields.join("; "));
+        }
+        Status::new(code, message)
+    }
+}
+
+// A call in progress: who made it, and what it runs against
+pub struct Call {
+    // Metadata sent with the call; pseudo-headers aren't included
+    metadata: HashMap<String, String>,
+    peer: IpAddr,
+    config: Arc<Config>,
+    stores: Stores,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Call {
+    pub fn stores(&self) -> &Stores {
+        &self.stores
+    }
+
+    // Let a read go ahead as the REST request `method path` would: counted against the client's
+    // rate limit, and authorized only when that request needs credentials
+    pub async fn read(&self, method: &str, path: &str) -> Result<(), Status> {
+        self.authorize(&self.rest_request(method, path)).await.map(|_| ())
+    }
+
+    // Make a change as the REST request `method path` would: rate limited, authorized, and audited
+    // whatever the outcome, with `status` as the successful response's
+    pub async fn change<T: Serialize>(
+        &self,
+        method: &str,
+        path: &str,
+        status: u16,
+        change: impl Future<Output = Result<T, Response>>,
+    ) -> Result<T, Status> {
+        let request = self.rest_request(method, path);
+        let actor = self.authorize(&request).await?;
+        let pending = audit::begin(&self.stores, &request, actor).await;
+        match change.await {
+            Ok(value) => {
+                let response = match status {
+                    204 => Response::new(204),
+                    status => Response::json(status, &value),
+                };
+                pending.finish(&self.stores, &response).await;
+                Ok(value)
+            }
+            Err(response) => {
+                pending.finish(&self.stores, &response).await;
+                Err(response.into())
+            }
+        }
+    }
+
+    // The actor for the audit log, or None for a read that takes no credentials
+    async fn authorize(&self, request: &http::Request) -> Result<Option<String>, Status> {
+        if let Some(limiter) = &self.limiter {
+            let client = forwarded::client(request, Client { ip: self.peer, scheme: "http" }, &self.config);
+            if let Some(rejection) = limiter.acquire(client.ip).rejection() {
+                return Err(rejection.into());
+            }
+        }
+        if !auth::requires_token(request) {
+            return Ok(None);
+        }
+        match auth::authorize(&self.config.jwt_secret, &self.stores, request).await {
+            Ok(caller) => Ok(Some(caller.actor())),
+            Err(response) => Err(response.into()),
+        }
+    }
+
+    // The REST request a method stands for, carrying the call's metadata as headers, so credentials
+    // and permissions work just as they do over HTTP/1.1
+    fn rest_request(&self, method: &str, path: &str) -> http::Request {
+        http::Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: Query::default(),
+            version: "HTTP/2".to_string(),
+            headers: self.metadata.clone(),
+            body: Vec::new(),
+            params: HashMap::new(),
+        }
+    }
+}
+
+// Accept gRPC connections until shutdown, each running on its own task
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<Config>,
+    stores: Stores,
+    limiter: Option<Arc<RateLimiter>>,
+    shutdown: CancellationToken,
+    connections: TaskTracker,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => return,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                let _ = stream.set_nodelay(true);
+                let config = Arc::clone(&config);
+                let stores = stores.clone();
+                let limiter = limiter.clone();
+                let shutdown = shutdown.clone();
+                connections.spawn(async move {
+                    let handler = |request| handle_call(request, peer.ip(), Arc::clone(&config), stores.clone(), limiter.clone());
+                    if let Err(e) = http2::serve(stream, &config, &shutdown, handler).await {
+                        warn!("gRPC connection from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Error handling gRPC client: {}", e);
+            }
+        }
+    }
+}
+
+// Answer one call, logging it as HTTP requests are
+async fn handle_call(
+    request: http2::Request,
+    peer: IpAddr,
+    config: Arc<Config>,
+    stores: Stores,
+    limiter: Option<Arc<RateLimiter>>,
+) -> http2::Response {
+    let mut metadata: HashMap<String, String> =
+        request.headers.iter().filter(|(name, _)| !name.starts_with(':')).map(|(name, value)| (name.clone(), value.clone())).collect();
+    // What HTTP/1.1 calls Host, for picking the tenant by subdomain
+    if let Some(authority) = request.header(":authority") {
+        metadata.insert("host".to_string(), authority.to_string());
+    }
+    let call = Call {
+        metadata,
+        peer,
+        config,
+        stores,
+        limiter,
+    };
+    let method = request.header(":path").unwrap_or_default().to_string();
+    let span = tracing::info_span!(
+        "grpc",
+        request_id = %request_id::for_request(&call.rest_request("POST", &method)),
+        method = %method,
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    );
+    let started = Instant::now();
+    let response = async {
+        if request.header(":method") != Some("POST") {
+            return http_error(405);
+        }
+        let is_grpc = request.header("content-type").and_then(|content_type| content_type.split(';').next()).map(str::trim);
+        if !matches!(is_grpc, Some("application/grpc" | "application/grpc+proto")) {
+            return http_error(415);
+        }
+        let deadline = request.header("grpc-timeout").and_then(parse_timeout);
+        // Run on a task of its own, so a panic fails just this call
+        let answered = tokio::spawn(dispatch(request, call).in_current_span());
+        let result = match deadline {
+            Some(deadline) => match tokio::time::timeout(deadline, answered).await {
+                Ok(answered) => answered,
+                Err(_) => Ok(Err(Status::new(Code::DeadlineExceeded, "Deadline exceeded"))),
+            },
+            None => answered.await,
+        };
+        let result = result.unwrap_or_else(|e| {
+            error!("gRPC call panicked: {}", e);
+            Err(Status::new(Code::Internal, "Internal server error"))
+        });
+        let code = result.as_ref().map_or_else(|status| status.code, |_| Code::Ok);
+        tracing::Span::current().record("status", format!("{:?}", code));
+        grpc_response(result)
+    }
+    .instrument(span.clone())
+    .await;
+    span.record("latency_ms", started.elapsed().as_micros() as f64 / 1000.0);
+    span.in_scope(|| info!("Call completed"));
+    response
+}
+
+// Unwrap the request message and run the method its path names, on the stores of the tenant the
+// x-tenant-id metadata names
+async fn dispatch(request: http2::Request, mut call: Call) -> Result<Vec<u8>, Status> {
+    if request.header("grpc-encoding").is_some_and(|encoding| encoding != "identity") {
+        return Err(Status::new(Code::Unimplemented, "Compressed messages are not supported"));
+    }
+    let message = match request.body.split_at_checked(MESSAGE_HEADER_SIZE) {
+        Some(([0, length @ ..], message)) if u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize == message.len() => {
+            message
+        }
+        Some(([1, ..], _)) => return Err(Status::new(Code::Internal, "Compressed message without grpc-encoding")),
+        _ => return Err(Status::new(Code::Internal, "Expected exactly one length-prefixed message")),
+    };
+    let path = request.header(":path").unwrap_or_default();
+    call.stores = tenancy::stores_for(&call.config, &call.stores, &call.rest_request("POST", path)).await?;
+    match path.strip_prefix('/').and_then(|path| path.split_once('/')) {
+        Some((users::SERVICE, method)) => users::call(method, &call, message).await,
+        _ => Err(Status::new(Code::Unimplemented, format!("Unknown method {}", path))),
+    }
+}
+
+// Status 200 with the reply message and an OK status, or a trailers-only response for an error
+fn grpc_response(result: Result<Vec<u8>, Status>) -> http2::Response {
+    let headers = vec![("content-type", "application/grpc".to_string())];
+    match result {
+        Ok(message) => {
+            let mut body = Vec::with_capacity(MESSAGE_HEADER_SIZE + message.len());
+            body.push(0);
+            body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            body.extend_from_slice(&message);
+            http2::Response {
+                status: 200,
+                headers,
+                body,
+                trailers: vec![("grpc-status", (Code::Ok as u8).to_string())],
+            }
+        }
+        Err(status) => http2::Response {
+            status: 200,
+            headers,
+            body: Vec::new(),
+            trailers: vec![("grpc-status", (status.code as u8).to_string()), ("grpc-message", percent_encode(&status.message))],
+        },
+    }
+}
+
+// The answer to a request with a body over the server's limit
+fn message_too_large(limit: usize) -> http2::Response {
+    let message = format!("Request messages may be at most {} bytes", limit);
+    grpc_response(Err(Status::new(Code::ResourceExhausted, message)))
+}
+
+// For requests that aren't gRPC calls at all
+fn http_error(status: u16) -> http2::Response {
+    http2::Response {
+        status,
+        headers: Vec::new(),
+        body: Vec::new(),
+        trailers: Vec::new(),
+    }
+}
+
+// grpc-timeout: up to eight digits and a unit, e.g. `100m` or `5S`
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let (amount, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    let amount: u64 = amount.parse().ok().filter(|_| amount.len() <= 8)?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+// grpc-message is percent-encoded UTF-8, leaving printable ASCII other than `%` as is
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
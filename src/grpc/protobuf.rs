@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+
+// Protocol Buffers wire format, for the messages of proto/users.proto. Proto3 leaves fields at
+// their default value off the wire, so writers skip them and readers start from defaults
+
+// A field's payload, by wire type
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+impl Value<'_> {
+    pub fn int32(&self) -> Result<i32, String> {
+        match self {
+            // Negative int32s are sign-extended to ten bytes
+            Value::Varint(value) => Ok(*value as i64 as i32),
+            _ => Err("expected a varint".to_string()),
+        }
+    }
+
+    pub fn int64(&self) -> Result<i64, String> {
+        match self {
+            Value::Varint(value) => Ok(*value as i64),
+            _ => Err("expected a varint".to_string()),
+        }
+    }
+
+    pub fn bool(&self) -> Result<bool, String> {
+        self.int64().map(|value| value != 0)
+    }
+
+    pub fn string(&self) -> Result<String, String> {
+        match self {
+            Value::Bytes(bytes) => String::from_utf8(bytes.to_vec()).map_err(|_| "string field is not UTF-8".to_string()),
+            _ => Err("expected a length-delimited field".to_string()),
+        }
+    }
+}
+
+// Reads a message's fields in wire order; unknown ones are for the caller to skip
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf }
+    }
+
+    pub fn next(&mut self) -> Result<Option<(u32, Value<'a>)>, String> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).ok().filter(|field| *field > 0).ok_or("invalid field number")?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let length = usize::try_from(self.varint()?).map_err(|_| "field too long")?;
+                Value::Bytes(self.take(length)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed32
+            }
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        Ok(Some((field, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.buf.split_first().ok_or("truncated message")?;
+            self.buf = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint too long".to_string())
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let (taken, rest) = self.buf.split_at_checked(length).ok_or("truncated message")?;
+        self.buf = rest;
+        Ok(taken)
+    }
+}
+
+// Builds a message field by field
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub fn int32(&mut self, field: u32, value: i32) -> &mut Writer {
+        if value != 0 {
+            self.key(field, 0);
+            self.varint(value as i64 as u64);
+        }
+        self
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Writer {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+        self
+    }
+
+    // An embedded message, written even when empty since its presence means something
+    pub fn message(&mut self, field: u32, message: Writer) -> &mut Writer {
+        self.bytes(field, &message.buf);
+        self
+    }
+
+    // A google.protobuf.Timestamp; None leaves the field unset
+    pub fn timestamp(&mut self, field: u32, value: Option<DateTime<Utc>>) -> &mut Writer {
+        if let Some(value) = value {
+            let mut timestamp = Writer::new();
+            if value.timestamp() != 0 {
+                timestamp.key(1, 0);
+                timestamp.varint(value.timestamp() as u64);
+            }
+            timestamp.int32(2, value.timestamp_subsec_nanos() as i32);
+            self.message(field, timestamp);
+        }
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, 2);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::context;
+use crate::error::AppError;
+use crate::handlers::users::{decode_cursor, encode_cursor};
+use crate::handlers::{DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::http::Response;
+use crate::models::User;
+use crate::repository::UserListQuery;
+use crate::validation;
+
+use super::protobuf::{Reader, Value, Writer};
+use super::{Call, Code, Status};
+
+// users.v1.UserService from proto/users.proto
+pub const SERVICE: &str = "users.v1.UserService";
+
+// Run one of the service's methods on its request message, returning the reply message
+pub async fn call(method: &str, call: &Call, message: &[u8]) -> Result<Vec<u8>, Status> {
+    match method {
+        "CreateUser" => create(call, message).await,
+        "GetUser" => context::read_only(get(call, message)).await,
+        "ListUsers" => context::read_only(list(call, message)).await,
+        "UpdateUser" => update(call, message).await,
+        "DeleteUser" => delete(call, message).await,
+        _ => Err(Status::new(Code::Unimplemented, format!("Unknown method {}/{}", SERVICE, method))),
+    }
+}
+
+async fn create(call: &Call, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let (mut name, mut email) = (String::new(), String::new());
+    read_fields(message, |field, value| {
+        match field {
+            1 => name = value.string()?,
+            2 => email = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    let user = call
+        .change("POST", "/users", 201, async {
+            validation::validate_user(&name, &email)?;
+            Ok(call.stores().users.create(&name, &email).await?)
+        })
+        .await?;
+    Ok(user_message(&user).into_bytes())
+}
+
+async fn get(call: &Call, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let (mut id, mut include_deleted) = (0, false);
+    read_fields(message, |field, value| {
+        match field {
+            1 => id = value.int32()?,
+            2 => include_deleted = value.bool()?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    call.read("GET", &format!("/users/{}", id)).await?;
+    match call.stores().users.get(id, include_deleted).await {
+        Ok(Some(user)) => Ok(user_message(&user).into_bytes()),
+        Ok(None) => Err(Response::from(AppError::NotFound("User not found")).into()),
+        Err(e) => Err(Response::from(e).into()),
+    }
+}
+
+async fn list(call: &Call, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let mut query = UserListQuery {
+        limit: DEFAULT_PAGE_LIMIT,
+        offset: 0,
+        after: None,
+        sort: "id",
+        order: "ASC",
+        name: None,
+        email_contains: None,
+        include_deleted: false,
+        fields: None,
+    };
+    read_fields(message, |field, value| {
+        match field {
+            1 => {
+                query.limit = match value.int32()? {
+                    0 => DEFAULT_PAGE_LIMIT,
+                    size if size < 0 => return Err("page_size must not be negative".to_string()),
+                    size => (size as i64).min(MAX_PAGE_LIMIT),
+                }
+            }
+            2 => {
+                query.after = match value.string()? {
+                    token if token.is_empty() => None,
+                    token => Some(decode_cursor(&token).ok_or("invalid page_token")?),
+                }
+            }
+            3 => query.name = Some(value.string()?).filter(|name| !name.is_empty()),
+            4 => query.email_contains = Some(value.string()?).filter(|email| !email.is_empty()),
+            5 => query.include_deleted = value.bool()?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    call.read("GET", "/users").await?;
+    let page = call.stores().users.list(&query).await.map_err(|e| Status::from(Response::from(e)))?;
+    let mut reply = Writer::new();
+    for user in &page.users {
+        reply.message(1, user_message(user));
+    }
+    if let Some(User { id: Some(id), .. }) = page.users.last().filter(|_| page.has_more) {
+        reply.string(2, &encode_cursor(*id));
+    }
+    Ok(reply.into_bytes())
+}
+
+async fn update(call: &Call, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let (mut id, mut name, mut email) = (0, String::new(), String::new());
+    read_fields(message, |field, value| {
+        match field {
+            1 => id = value.int32()?,
+            2 => name = value.string()?,
+            3 => email = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    let user = call
+        .change("PUT", &format!("/users/{}", id), 200, async {
+            validation::validate_user(&name, &email)?;
+            match call.stores().users.update(id, &name, &email).await? {
+                Some(user) => Ok(user),
+                None => Err(AppError::NotFound("User not found").into()),
+            }
+        })
+        .await?;
+    Ok(user_message(&user).into_bytes())
+}
+
+async fn delete(call: &Call, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let mut id = 0;
+    read_fields(message, |field, value| {
+        if field == 1 {
+            id = value.int32()?;
+        }
+        Ok(())
+    })?;
+
+    call.change("DELETE", &format!("/users/{}", id), 204, async {
+        match call.stores().users.delete(id).await? {
+            true => Ok(()),
+            false => Err(AppError::NotFound("User not found").into()),
+        }
+    })
+    .await?;
+    Ok(Vec::new())
+}
+
+// Hand each field of a request message to `read`; a malformed message or field is INVALID_ARGUMENT
+fn read_fields(message: &[u8], mut read: impl FnMut(u32, Value) -> Result<(), String>) -> Result<(), Status> {
+    let mut reader = Reader::new(message);
+    loop {
+        match reader.next() {
+            Ok(Some((field, value))) => read(field, value),
+            Ok(None) => return Ok(()),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| Status::new(Code::InvalidArgument, format!("Invalid request message: {}", e)))?;
+    }
+}
+
+fn user_message(user: &User) -> Writer {
+    let mut message = Writer::new();
+    message
+        .int32(1, user.id.unwrap_or_default())
+        .string(2, &user.name)
+        .string(3, &user.email)
+        .timestamp(4, user.created_at)
+        .timestamp(5, user.updated_at)
+        .timestamp(6, user.deleted_at)
+        .int32(7, user.version);
+    message
+}
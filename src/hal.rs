@@ -0,0 +1,97 @@
+use std::fmt::Display;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::http::{Request, Response};
+use crate::query::Query;
+use crate::versioning;
+
+// A link in a HAL document (draft-kelly-json-hal)
+#[derive(Serialize, JsonSchema)]
+pub struct Link {
+    pub href: String,
+}
+
+// What a client can do from a single resource: fetch it again, go to its collection, or change or
+// remove it (with PUT or PATCH, and DELETE, at the same URL)
+#[derive(Serialize, JsonSchema)]
+pub struct ItemLinks {
+    #[serde(rename = "self")]
+    pub own: Link,
+    pub collection: Link,
+    pub update: Link,
+    pub delete: Link,
+}
+
+// A resource with its `_links`. They're left out when there is no id to link to, as when `?fields=`
+// doesn't ask for it
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "Linked{T}")]
+pub struct Linked<T> {
+    #[serde(flatten)]
+    pub resource: T,
+    #[serde(rename = "_links", skip_serializing_if = "Option::is_none")]
+    pub links: Option<ItemLinks>,
+}
+
+impl ItemLinks {
+    // Links from the item `id` of `collection` (e.g. `/users`), under the version prefix the request
+    // was made with. Unprefixed requests get prefixed links, which is where their routes have moved
+    pub fn new(request: &Request, collection: &str, id: impl Display) -> ItemLinks {
+        let collection = format!("{}{}", versioning::prefix(&request.path), collection);
+        let item = format!("{}/{}", collection, id);
+        ItemLinks {
+            own: Link { href: item.clone() },
+            collection: Link { href: collection },
+            update: Link { href: item.clone() },
+            delete: Link { href: item },
+        }
+    }
+}
+
+// One page of a listing, as far as linking to the others goes. Offset pages know their total;
+// cursor pages only the cursor of the page after them
+pub struct Page<'a> {
+    pub total: Option<i64>,
+    pub limit: i64,
+    pub offset: Option<i64>,
+    pub next_cursor: Option<&'a str>,
+}
+
+impl Page<'_> {
+    // first, prev, next and last for an offset page, skipping the ones past either end; next alone
+    // for a cursor page with more after it. `path` and `query` are where this page was fetched
+    pub fn links(&self, path: &str, query: &Query) -> Vec<(&'static str, String)> {
+        let mut links = Vec::new();
+        match (self.total, self.offset) {
+            (Some(total), Some(offset)) if self.limit > 0 => {
+                let at = |offset: i64| query.link(path, &[("offset", Some(offset.to_string())), ("after", None)]);
+                links.push(("first", at(0)));
+                if offset > 0 {
+                    links.push(("prev", at((offset - self.limit).max(0))));
+                }
+                if offset + self.limit < total {
+                    links.push(("next", at(offset + self.limit)));
+                }
+                links.push(("last", at((total - 1).max(0) / self.limit * self.limit)));
+            }
+            _ => {
+                if let Some(cursor) = self.next_cursor {
+                    links.push(("next", query.link(path, &[("after", Some(cursor.to_string())), ("offset", None)])));
+                }
+            }
+        }
+        links
+    }
+
+    // The page's links as a Link header (RFC 8288), so clients can page through without building URLs
+    pub fn apply(&self, request: &Request, response: Response) -> Response {
+        let links: Vec<String> =
+            self.links(&request.path, &request.query).into_iter().map(|(rel, href)| format!("<{}>; rel=\"{}\"", href, rel)).collect();
+        match links.is_empty() {
+            true => response,
+            false => response.with_header("Link", links.join(", ")),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::repository::Stores;
+use crate::seed::{self, SeedPlan};
+use crate::validation::ValidationErrors;
+
+use super::json_body;
+
+// Upper bounds on one seeding request; the `seed` command has none
+const MAX_SEED_USERS: usize = 10_000;
+const MAX_SEED_POSTS_PER_USER: usize = 10;
+const MAX_SEED_GROUPS: usize = 100;
+
+// Fill the store with generated users, posts and groups for a demo or a load test
+pub async fn handle_seed_request(request: Request, stores: Stores) -> Result<Response, AppError> {
+    let plan: SeedPlan = json_body(&request, "Invalid seed JSON")?;
+    let mut errors = ValidationErrors::default();
+    if !(1..=MAX_SEED_USERS).contains(&plan.users) {
+        errors.add("users", format!("must be between 1 and {}", MAX_SEED_USERS));
+    }
+    if plan.posts_per_user > MAX_SEED_POSTS_PER_USER {
+        errors.add("posts_per_user", format!("must be at most {}", MAX_SEED_POSTS_PER_USER));
+    }
+    if plan.groups > MAX_SEED_GROUPS {
+        errors.add("groups", format!("must be at most {}", MAX_SEED_GROUPS));
+    }
+    if !errors.is_empty() {
+        return Err(errors.into_response().into());
+    }
+
+    let report = seed::run(&stores, &plan).await?;
+    Ok(Response::json(201, &report))
+}
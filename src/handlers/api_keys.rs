@@ -0,0 +1,50 @@
+use schemars::JsonSchema;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::{ApiKey, ApiKeyInput};
+use crate::repository::Stores;
+use crate::validation;
+
+use super::json_body;
+
+// Body of a successful POST /api-keys: the key's listing plus the key itself
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    key: String,
+}
+
+// Body of GET /api-keys
+#[derive(Serialize, JsonSchema)]
+pub struct ApiKeyList {
+    api_keys: Vec<ApiKey>,
+}
+
+// Create an API key for a service caller. The response is the only place the key itself appears;
+// only its digest is stored
+pub async fn handle_create_api_key_request(request: Request, Stores { api_keys, .. }: Stores) -> Result<Response, AppError> {
+    let input: ApiKeyInput = json_body(&request, "Invalid API key JSON")?;
+    validation::validate_name(&input.name)?;
+
+    let key = auth::generate_api_key();
+    let api_key = api_keys.create(&input.name, input.role, &auth::hash_secret(&key)).await?;
+    Ok(Response::json(201, &CreatedApiKey { api_key, key }).with_header("Cache-Control", "no-store"))
+}
+
+pub async fn handle_get_api_keys_request(_request: Request, Stores { api_keys, .. }: Stores) -> Result<Response, AppError> {
+    let api_keys = api_keys.list().await?;
+    Ok(Response::json(200, &ApiKeyList { api_keys }))
+}
+
+// Revoke a key; requests made with it are rejected from then on
+pub async fn handle_revoke_api_key_request(request: Request, Stores { api_keys, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    match api_keys.revoke(id).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("API key not found or already revoked")),
+    }
+}
@@ -0,0 +1,38 @@
+use schemars::JsonSchema;
+
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::AuditEntry;
+use crate::repository::{AuditQuery, Stores};
+
+use super::get_page;
+
+// One page of the audit log, newest first
+#[derive(Serialize, JsonSchema)]
+pub struct AuditPage {
+    entries: Vec<AuditEntry>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+// GET /audit: recorded mutations, newest first, optionally only those by `?actor=`
+pub async fn handle_get_audit_request(request: Request, Stores { audit, .. }: Stores) -> Result<Response, AppError> {
+    let (limit, offset) = get_page(&request)?;
+    let query = AuditQuery {
+        actor: request.query.get("actor").map(str::to_string),
+        limit,
+        offset,
+    };
+
+    let list = audit.list(&query).await?;
+    Ok(Response::json(
+        200,
+        &AuditPage {
+            entries: list.entries,
+            total: list.total,
+            limit,
+            offset,
+        },
+    ))
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+use tracing::{error, warn, Instrument};
+
+use crate::error::AppError;
+use crate::events::Event;
+use crate::http::{Request, Response};
+use crate::repository::Stores;
+use crate::websocket::{self, Session};
+
+// How long a quiet stream goes before a comment is sent, so proxies don't time it out and a
+// client that went away is noticed
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+// Events buffered for a client that reads slower than they happen
+const STREAM_BUFFER_EVENTS: usize = 64;
+
+// Stream user changes as Server-Sent Events until the client disconnects or the server shuts down.
+// Only changes made after connecting are sent; there's no history to resume from with Last-Event-ID
+pub async fn handle_user_events_request(_request: Request, Stores { events, .. }: Stores) -> Result<Response, AppError> {
+    let mut subscription = events.subscribe();
+    let (body, stream) = mpsc::channel(STREAM_BUFFER_EVENTS);
+    let forward = async move {
+        // Sent at once, so the client knows the stream is open before anything happens
+        if body.send(Ok(b": connected\n\n".to_vec())).await.is_err() {
+            return;
+        }
+        let mut heartbeat = time::interval_at(Instant::now() + HEARTBEAT, HEARTBEAT);
+        loop {
+            let chunk = tokio::select! {
+                _ = events.closed() => return,
+                _ = body.closed() => return,
+                _ = heartbeat.tick() => b": keep-alive\n\n".to_vec(),
+                event = subscription.recv() => match event {
+                    Ok(event) => match encode(&event) {
+                        Some(chunk) => chunk,
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Event stream fell behind; {} events were not sent", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+            };
+            if body.send(Ok(chunk)).await.is_err() {
+                return;
+            }
+            heartbeat.reset();
+        }
+    };
+    tokio::spawn(forward.in_current_span());
+    Ok(Response::stream(200, "text/event-stream", stream)
+        .with_header("Cache-Control", "no-cache")
+        // Tells nginx not to buffer the stream
+        .with_header("X-Accel-Buffering", "no"))
+}
+
+// Upgrade to a WebSocket that sends the same events as JSON messages. Clients narrow them to some
+// users with `{"type": "subscribe", "user_ids": [...]}`
+pub async fn handle_websocket_request(request: Request, Stores { events, .. }: Stores) -> Result<Response, AppError> {
+    let accept = websocket::handshake(&request)?;
+    let mut response = Response::new(101).with_header("Upgrade", "websocket").with_header("Sec-WebSocket-Accept", accept);
+    response.upgrade = Some(Session::new(&events));
+    Ok(response)
+}
+
+// One SSE message: the event's id and type, with the whole event as JSON on a single data line
+fn encode(event: &Event) -> Option<Vec<u8>> {
+    match serde_json::to_string(event) {
+        Ok(json) => Some(format!("id: {}\nevent: {}\ndata: {}\n\n", event.id, event.event_type.as_str(), json).into_bytes()),
+        Err(e) => {
+            error!("Error serializing event {}: {}", event.id, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use schemars::JsonSchema;
+
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::{Group, GroupInput, User};
+use crate::repository::{AddMember, Stores};
+use crate::validation;
+
+use super::{get_page, json_body};
+
+// One page of groups, by name
+#[derive(Serialize, JsonSchema)]
+pub struct GroupPage {
+    groups: Vec<Group>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+// One page of a group's members, by id
+#[derive(Serialize, JsonSchema)]
+pub struct MemberPage {
+    users: Vec<User>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+// Body of GET /users/{id}/groups
+#[derive(Serialize, JsonSchema)]
+pub struct UserGroups {
+    groups: Vec<Group>,
+}
+
+// The groups a live user belongs to, by name
+pub async fn handle_get_user_groups_request(request: Request, Stores { users, groups, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    if users.get(id, false).await?.is_none() {
+        return Err(AppError::NotFound("User not found"));
+    }
+
+    let groups = groups.user_groups(id).await?;
+    Ok(Response::json(200, &UserGroups { groups }))
+}
+
+pub async fn handle_create_group_request(request: Request, Stores { groups, .. }: Stores) -> Result<Response, AppError> {
+    let group: GroupInput = json_body(&request, "Invalid group JSON")?;
+    validation::validate_name(&group.name)?;
+
+    let group = groups.create(&group.name).await?;
+    Ok(Response::json(201, &group).with_header("Location", format!("/groups/{}", group.id)))
+}
+
+pub async fn handle_get_groups_request(request: Request, Stores { groups, .. }: Stores) -> Result<Response, AppError> {
+    let (limit, offset) = get_page(&request)?;
+
+    let list = groups.list(limit, offset).await?;
+    Ok(Response::json(
+        200,
+        &GroupPage {
+            groups: list.groups,
+            total: list.total,
+            limit,
+            offset,
+        },
+    ))
+}
+
+pub async fn handle_get_group_request(request: Request, Stores { groups, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    match groups.get(id).await? {
+        Some(group) => Ok(Response::json(200, &group)),
+        None => Err(AppError::NotFound("Group not found")),
+    }
+}
+
+// A group's live members, by id
+pub async fn handle_get_members_request(request: Request, Stores { groups, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let (limit, offset) = get_page(&request)?;
+    if groups.get(id).await?.is_none() {
+        return Err(AppError::NotFound("Group not found"));
+    }
+
+    let members = groups.members(id, limit, offset).await?;
+    Ok(Response::json(
+        200,
+        &MemberPage {
+            users: members.users,
+            total: members.total,
+            limit,
+            offset,
+        },
+    ))
+}
+
+// Add a user to a group: 201 when they join, 204 when they were already a member
+pub async fn handle_add_member_request(request: Request, Stores { groups, .. }: Stores) -> Result<Response, AppError> {
+    let (id, user_id) = (request.param::<i32>("id")?, request.param::<i32>("user_id")?);
+
+    match groups.add_member(id, user_id).await? {
+        AddMember::Added => Ok(Response::new(201)),
+        AddMember::AlreadyMember => Ok(Response::new(204)),
+        AddMember::NoSuchGroup => Err(AppError::NotFound("Group not found")),
+        AddMember::NoSuchUser => Err(AppError::NotFound("User not found")),
+    }
+}
+
+pub async fn handle_remove_member_request(request: Request, Stores { groups, .. }: Stores) -> Result<Response, AppError> {
+    let (id, user_id) = (request.param::<i32>("id")?, request.param::<i32>("user_id")?);
+
+    match groups.remove_member(id, user_id).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("The user is not a member of this group")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::handlers::testing::{error_code, request, respond, stores, RequestExt};
+
+    #[tokio::test]
+    async fn group_names_are_unique_ignoring_case() {
+        let stores = stores();
+        let create = |name: &str| request("POST", "/groups").with_json(json!({ "name": name }));
+        assert_eq!(respond(handle_create_group_request(create("Editors"), stores.clone()).await).0, 201);
+        let (status, body) = respond(handle_create_group_request(create("EDITORS"), stores.clone()).await);
+        assert_eq!((status, error_code(&body)), (409, "group_name_taken"));
+
+        handle_create_group_request(create("authors"), stores.clone()).await.unwrap();
+        let (_, page) = respond(handle_get_groups_request(request("GET", "/groups"), stores).await);
+        let names: Vec<&str> = page["groups"].as_array().unwrap().iter().map(|group| group["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["authors", "Editors"]);
+    }
+
+    #[tokio::test]
+    async fn membership() {
+        let stores = stores();
+        let group = stores.groups.create("Editors").await.unwrap().id;
+        let user = stores.users.create("Ada", "ada@example.com").await.unwrap().id.unwrap();
+        let member = |group: i32, user: i32| request("PUT", "/groups/1/members/1").with_param("id", group).with_param("user_id", user);
+
+        assert_eq!(respond(handle_add_member_request(member(group, user), stores.clone()).await).0, 201);
+        assert_eq!(respond(handle_add_member_request(member(group, user), stores.clone()).await).0, 204);
+        let (_, body) = respond(handle_add_member_request(member(group, 99), stores.clone()).await);
+        assert_eq!(body["error"]["message"], "User not found");
+        let (_, body) = respond(handle_add_member_request(member(99, user), stores.clone()).await);
+        assert_eq!(body["error"]["message"], "Group not found");
+
+        let user_groups = request("GET", "/users/1/groups").with_param("id", user);
+        let (_, groups) = respond(handle_get_user_groups_request(user_groups, stores.clone()).await);
+        assert_eq!(groups["groups"][0]["id"], group);
+        // A deleted user keeps the membership but drops out of the member list
+        stores.users.delete(user).await.unwrap();
+        let list = request("GET", "/groups/1/members").with_param("id", group);
+        let (_, members) = respond(handle_get_members_request(list, stores.clone()).await);
+        assert_eq!(members["total"], 0);
+        assert_eq!(respond(handle_remove_member_request(member(group, user), stores.clone()).await).0, 204);
+        assert_eq!(respond(handle_remove_member_request(member(group, user), stores).await).0, 404);
+    }
+}
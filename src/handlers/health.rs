@@ -0,0 +1,74 @@
+use schemars::JsonSchema;
+use tracing::warn;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::build_info;
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::metrics::Metrics;
+use crate::repository::Stores;
+
+// Body of GET /healthz and GET /readyz
+#[derive(Serialize, JsonSchema)]
+pub struct Health {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<HealthChecks>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HealthChecks {
+    database: DependencyCheck,
+}
+
+// Whether a dependency answered, and how long it took
+#[derive(Serialize, JsonSchema)]
+pub struct DependencyCheck {
+    status: &'static str,
+    latency_ms: f64,
+}
+
+// GET /healthz: the process is up and serving; nothing else is checked
+pub async fn handle_health_request(_: Request, _: Stores) -> Result<Response, AppError> {
+    Ok(Response::json(200, &Health { status: "ok", checks: None }))
+}
+
+// GET /readyz: ready for traffic once a pooled database connection answers `SELECT 1`; 503 otherwise
+pub async fn handle_ready_request(_: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let started = Instant::now();
+    let pinged = users.ping().await;
+    let latency_ms = started.elapsed().as_micros() as f64 / 1000.0;
+    let (status, ready, database) = match pinged {
+        Ok(()) => (200, "ready", "up"),
+        Err(e) => {
+            warn!("Readiness check failed: {}", e);
+            (503, "not_ready", "down")
+        }
+    };
+    let checks = HealthChecks {
+        database: DependencyCheck {
+            status: database,
+            latency_ms,
+        },
+    };
+    Ok(Response::json(
+        status,
+        &Health {
+            status: ready,
+            checks: Some(checks),
+        },
+    ))
+}
+
+// GET /version: the version, commit, build time and features of the running build
+pub async fn handle_version_request(_: Request, _: Stores) -> Result<Response, AppError> {
+    Ok(Response::json(200, &build_info::current()))
+}
+
+// GET /metrics: request, connection, database pool and user cache metrics for Prometheus to scrape
+pub async fn handle_metrics_request(Stores { users, .. }: Stores, metrics: Arc<Metrics>) -> Result<Response, AppError> {
+    let mut response = Response::new(200).with_header("Content-Type", "text/plain; version=0.0.4");
+    response.body = metrics.render(users.pool_status(), users.cache_status()).into_bytes();
+    Ok(response)
+}
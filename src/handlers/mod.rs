@@ -0,0 +1,191 @@
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::http::{ContentType, Request, Response};
+use crate::json_schema::Schema;
+use crate::models::{
+    ApiKeyInput, GroupInput, Login, PasswordChange, PostInput, RefreshRequest, RoleChange, TenantInput, User, UserPatch, WebhookInput,
+};
+use crate::repository::UserRepository;
+use crate::router::Router;
+use crate::seed::SeedPlan;
+use crate::state::AppState;
+use crate::static_files;
+use crate::{auth, dashboard, docs, openapi, versioning};
+
+pub mod admin;
+pub mod api_keys;
+pub mod audit;
+pub mod events;
+pub mod groups;
+pub mod health;
+pub mod posts;
+pub mod sessions;
+pub mod tenants;
+#[cfg(test)]
+mod testing;
+pub mod users;
+pub mod webhooks;
+
+// The user store, as handlers that only deal with users pass it around
+type Repository = Arc<dyn UserRepository>;
+
+// Pagination defaults for the user list
+pub(crate) const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub(crate) const MAX_PAGE_LIMIT: i64 = 500;
+
+// Register every API route, each version's under its prefix. A new version starts from the routes of
+// the one before, replacing only those that change
+pub fn build_router(config: &Config) -> Router<AppState> {
+    Router::new().nest(versioning::V1, v1_routes(config))
+}
+
+fn v1_routes(config: &Config) -> Router<AppState> {
+    let router = Router::new()
+        .route("POST", auth::LOGIN_PATH, |request, state: AppState| {
+            sessions::handle_login_request(request, state.stores, state.tokens)
+        })
+        .route("POST", auth::REFRESH_PATH, |request, state: AppState| {
+            sessions::handle_refresh_request(request, state.stores, state.tokens)
+        })
+        .route("POST", "/auth/logout", |request, state: AppState| {
+            sessions::handle_logout_request(request, state.stores, state.tokens)
+        })
+        .route("POST", "/users", users::handle_post_request)
+        .route("POST", "/users/batch", users::handle_batch_create_request)
+        .route("POST", "/users/import", users::handle_import_request)
+        .route("PATCH", "/users/batch", users::handle_batch_patch_request)
+        .route("GET", "/users", users::handle_get_all_requests)
+        .route("DELETE", "/users", users::handle_batch_delete_request)
+        .route("GET", "/users/all", users::handle_stream_all_request)
+        .route("GET", "/users/events", events::handle_user_events_request)
+        .route("GET", "/users/count", users::handle_count_request)
+        .route("GET", "/users/stats", users::handle_stats_request)
+        .route("GET", "/users/search", users::handle_search_request)
+        .route("GET", "/users/export", users::handle_export_request)
+        .route("GET", "/users/{id}", users::handle_get_request)
+        .route("PUT", "/users/{id}", |request, state: AppState| {
+            users::handle_put_request(request, state.stores, state.config.put_upsert)
+        })
+        .route("PATCH", "/users/{id}", users::handle_patch_request)
+        .route("DELETE", "/users/{id}", users::handle_delete_request)
+        .route("POST", "/users/{id}/restore", users::handle_restore_request)
+        .route("PUT", "/users/{id}/password", users::handle_set_password_request)
+        .route("PUT", "/users/{id}/role", users::handle_set_role_request)
+        .route("GET", "/users/{id}/posts", posts::handle_get_user_posts_request)
+        .route("POST", "/users/{id}/posts", posts::handle_create_user_post_request)
+        .route("GET", "/posts", posts::handle_get_posts_request)
+        .route("POST", "/posts", posts::handle_create_post_request)
+        .route("GET", "/posts/{id}", posts::handle_get_post_request)
+        .route("PUT", "/posts/{id}", posts::handle_put_post_request)
+        .route("DELETE", "/posts/{id}", posts::handle_delete_post_request)
+        .route("GET", "/users/{id}/groups", groups::handle_get_user_groups_request)
+        .route("POST", "/groups", groups::handle_create_group_request)
+        .route("GET", "/groups", groups::handle_get_groups_request)
+        .route("GET", "/groups/{id}", groups::handle_get_group_request)
+        .route("GET", "/groups/{id}/members", groups::handle_get_members_request)
+        .route("PUT", "/groups/{id}/members/{user_id}", groups::handle_add_member_request)
+        .route("DELETE", "/groups/{id}/members/{user_id}", groups::handle_remove_member_request)
+        .route("POST", "/api-keys", api_keys::handle_create_api_key_request)
+        .route("GET", "/api-keys", api_keys::handle_get_api_keys_request)
+        .route("DELETE", "/api-keys/{id}", api_keys::handle_revoke_api_key_request)
+        .route("POST", "/webhooks", webhooks::handle_create_webhook_request)
+        .route("GET", "/webhooks", webhooks::handle_get_webhooks_request)
+        .route("GET", "/webhooks/{id}", webhooks::handle_get_webhook_request)
+        .route("PUT", "/webhooks/{id}", webhooks::handle_put_webhook_request)
+        .route("DELETE", "/webhooks/{id}", webhooks::handle_delete_webhook_request)
+        .route("POST", "/tenants", tenants::handle_create_tenant_request)
+        .route("GET", "/tenants", tenants::handle_get_tenants_request)
+        .route("GET", "/tenants/{id}", tenants::handle_get_tenant_request)
+        .route("DELETE", "/tenants/{id}", tenants::handle_delete_tenant_request)
+        .route("GET", "/ws", events::handle_websocket_request)
+        .route("GET", "/audit", audit::handle_get_audit_request)
+        .route("POST", "/admin/seed", admin::handle_seed_request)
+        .route("GET", "/healthz", health::handle_health_request)
+        .route("GET", "/readyz", health::handle_ready_request)
+        .route("GET", "/version", health::handle_version_request)
+        .route("GET", "/metrics", |_, state: AppState| health::handle_metrics_request(state.stores, state.metrics))
+        // JSON bodies are checked against the schema of the type their handler reads, so every
+        // mismatch is reported at once with where it is. Batch items are checked one by one by their
+        // handlers, which report each item's problems in its result
+        .body_schema("POST", auth::LOGIN_PATH, Schema::of::<Login>())
+        .body_schema("POST", auth::REFRESH_PATH, Schema::of::<RefreshRequest>())
+        .body_schema("POST", "/users", Schema::of::<User>())
+        .body_schema("POST", "/users/batch", Schema::new(serde_json::json!({ "type": "array" })))
+        .body_schema("PATCH", "/users/batch", Schema::new(serde_json::json!({ "type": "array" })))
+        .body_schema("PUT", "/users/{id}", Schema::of::<User>())
+        .body_schema("PATCH", "/users/{id}", Schema::of::<UserPatch>())
+        .body_schema("PUT", "/users/{id}/password", Schema::of::<PasswordChange>())
+        .body_schema("PUT", "/users/{id}/role", Schema::of::<RoleChange>())
+        .body_schema("POST", "/users/{id}/posts", Schema::of::<PostInput>())
+        .body_schema("POST", "/posts", Schema::of::<PostInput>())
+        .body_schema("PUT", "/posts/{id}", Schema::of::<PostInput>())
+        .body_schema("POST", "/groups", Schema::of::<GroupInput>())
+        .body_schema("POST", "/api-keys", Schema::of::<ApiKeyInput>())
+        .body_schema("POST", "/webhooks", Schema::of::<WebhookInput>())
+        .body_schema("PUT", "/webhooks/{id}", Schema::of::<WebhookInput>())
+        .body_schema("POST", "/tenants", Schema::of::<TenantInput>())
+        .body_schema("POST", "/admin/seed", Schema::of::<SeedPlan>());
+
+    // The document describes the routes above, so it is built once they are all registered. The
+    // pages after it are for browsers rather than API clients
+    let spec = Arc::new(openapi::document(&router));
+    let router = router
+        .route("GET", openapi::SPEC_PATH, move |_, _: AppState| {
+            let spec = Arc::clone(&spec);
+            async move { Ok(Response::json(200, &*spec)) }
+        })
+        .route("GET", docs::PATH, |_, state: AppState| docs::handle_index_request(state.files))
+        .route("GET", "/docs/{file}", |request, state: AppState| docs::handle_asset_request(request, state.files))
+        .route("GET", dashboard::PATH, |_, state: AppState| dashboard::handle_index_request(state.files))
+        .route("GET", "/admin/{file}", |request, state: AppState| dashboard::handle_asset_request(request, state.files));
+    match config.static_files {
+        Some(_) => router.route("GET", "/static/{*path}", |request, state: AppState| static_files::handle_request(request, state.files)),
+        None => router,
+    }
+}
+
+// Read `limit` and `offset` for a listing; limit is capped at MAX_PAGE_LIMIT
+fn get_page(request: &Request) -> Result<(i64, i64), AppError> {
+    let limit = request.query.parse_value::<i64>("limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = request.query.parse_value::<i64>("offset")?.unwrap_or(0);
+    if limit < 1 || offset < 0 {
+        return Err(Response::error(400, "invalid_pagination", "limit must be positive and offset non-negative").into());
+    }
+    Ok((limit.min(MAX_PAGE_LIMIT), offset))
+}
+
+// Deserialize a JSON body; `what` starts the message when it doesn't parse
+fn json_body<T: DeserializeOwned>(request: &Request, what: &str) -> Result<T, AppError> {
+    check_media_type(request, &["application/json"])?;
+    serde_json::from_slice(&request.body).map_err(|e| AppError::InvalidJson(format!("{}: {}", what, e)))
+}
+
+// Fail with 415 unless the body is declared as one of `accepted`, in UTF-8 (a missing charset
+// counts as UTF-8, which is all JSON may be sent in)
+fn check_media_type(request: &Request, accepted: &[&str]) -> Result<ContentType, AppError> {
+    let expected = || match accepted {
+        [only] => only.to_string(),
+        [rest @ .., last] => format!("{} or {}", rest.join(", "), last),
+        [] => String::new(),
+    };
+    let Some(content_type) = request.content_type() else {
+        return Err(AppError::UnsupportedMediaType(format!("Content-Type is missing; send {}", expected())));
+    };
+    if !accepted.contains(&content_type.media_type.as_str()) {
+        return Err(AppError::UnsupportedMediaType(format!(
+            "Content-Type {} is not supported here; send {}",
+            content_type.media_type,
+            expected()
+        )));
+    }
+    if !content_type.is_utf8() {
+        return Err(AppError::UnsupportedMediaType(format!(
+            "charset {} is not supported; send UTF-8",
+            content_type.charset.unwrap_or_default()
+        )));
+    }
+    Ok(content_type)
+}
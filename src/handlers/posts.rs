@@ -0,0 +1,191 @@
+use schemars::JsonSchema;
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::{Post, PostInput};
+use crate::repository::{PostListQuery, PostRepository, Stores};
+use crate::validation;
+
+use super::{get_page, json_body};
+
+// One page of posts, oldest first
+#[derive(Serialize, JsonSchema)]
+pub struct PostPage {
+    posts: Vec<Post>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+// A user's posts, oldest first; 404 if the user doesn't exist or is deleted
+pub async fn handle_get_user_posts_request(request: Request, Stores { users, posts, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    if users.get(id, false).await?.is_none() {
+        return Err(AppError::NotFound("User not found"));
+    }
+    list_posts(posts, get_post_list_query(&request, Some(id))?).await
+}
+
+pub async fn handle_create_user_post_request(request: Request, Stores { posts, .. }: Stores) -> Result<Response, AppError> {
+    let user_id = request.param::<i32>("id")?;
+    let post = get_post_request_body(&request)?;
+    if post.user_id.is_some_and(|id| id != user_id) {
+        return Err(field_error("user_id", "must match the user in the URL"));
+    }
+
+    match posts.create(user_id, &post.title, &post.body).await? {
+        Some(post) => Ok(post_created(&post)),
+        None => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// Every post of a live user, optionally only those of `?user_id=`
+pub async fn handle_get_posts_request(request: Request, Stores { posts, .. }: Stores) -> Result<Response, AppError> {
+    let user_id = request.query.parse_value::<i32>("user_id")?;
+    list_posts(posts, get_post_list_query(&request, user_id)?).await
+}
+
+pub async fn handle_create_post_request(request: Request, Stores { posts, .. }: Stores) -> Result<Response, AppError> {
+    let post = get_post_request_body(&request)?;
+    let user_id = post.user_id.ok_or_else(|| field_error("user_id", "is required"))?;
+
+    match posts.create(user_id, &post.title, &post.body).await? {
+        Some(post) => Ok(post_created(&post)),
+        None => Err(field_error("user_id", "must be the id of an existing user")),
+    }
+}
+
+pub async fn handle_get_post_request(request: Request, Stores { posts, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    match posts.get(id).await? {
+        Some(post) => Ok(Response::json(200, &post)),
+        None => Err(AppError::NotFound("Post not found")),
+    }
+}
+
+// Replace a post's title and body; a `user_id` in the body must be the post's current user
+pub async fn handle_put_post_request(request: Request, Stores { posts, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let post = get_post_request_body(&request)?;
+    // A post's user never changes, so checking it ahead of the update can't race
+    if let Some(user_id) = post.user_id {
+        match posts.get(id).await? {
+            Some(current) if current.user_id != user_id => return Err(field_error("user_id", "cannot be changed")),
+            Some(_) => {}
+            None => return Err(AppError::NotFound("Post not found")),
+        }
+    }
+
+    match posts.update(id, &post.title, &post.body).await? {
+        Some(post) => Ok(Response::json(200, &post)),
+        None => Err(AppError::NotFound("Post not found")),
+    }
+}
+
+pub async fn handle_delete_post_request(request: Request, Stores { posts, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    match posts.delete(id).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("Post not found")),
+    }
+}
+
+async fn list_posts(posts: Arc<dyn PostRepository>, list: PostListQuery) -> Result<Response, AppError> {
+    let page = posts.list(&list).await?;
+    Ok(Response::json(
+        200,
+        &PostPage {
+            posts: page.posts,
+            total: page.total,
+            limit: list.limit,
+            offset: list.offset,
+        },
+    ))
+}
+
+fn post_created(post: &Post) -> Response {
+    Response::json(201, post).with_header("Location", format!("/posts/{}", post.id))
+}
+
+// Read a post body and validate its title and body
+fn get_post_request_body(request: &Request) -> Result<PostInput, AppError> {
+    let post: PostInput = json_body(request, "Invalid post JSON")?;
+    validation::validate_post(&post.title, &post.body)?;
+    Ok(post)
+}
+
+// A 422 naming one invalid field, shaped like the other validation failures
+fn field_error(field: &'static str, message: &str) -> AppError {
+    let mut errors = validation::ValidationErrors::default();
+    errors.add(field, message);
+    errors.into_response().into()
+}
+
+fn get_post_list_query(request: &Request, user_id: Option<i32>) -> Result<PostListQuery, AppError> {
+    let (limit, offset) = get_page(request)?;
+    Ok(PostListQuery { user_id, limit, offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::OnUserDelete;
+    use crate::handlers::testing::{error_code, request, respond, stores, RequestExt};
+    use crate::handlers::users;
+
+    async fn user_with_post(stores: &Stores) -> (i32, i64) {
+        let user = stores.users.create("Ada", "ada@example.com").await.unwrap().id.unwrap();
+        let post = request("POST", "/posts").with_json(json!({ "user_id": user, "title": "Hello", "body": "World" }));
+        let (status, body) = respond(handle_create_post_request(post, stores.clone()).await);
+        assert_eq!(status, 201, "{}", body);
+        (user, body["id"].as_i64().unwrap())
+    }
+
+    #[tokio::test]
+    async fn create_needs_a_live_user() {
+        let stores = stores();
+        let orphan = request("POST", "/posts").with_json(json!({ "user_id": 42, "title": "Hello", "body": "World" }));
+        let (status, body) = respond(handle_create_post_request(orphan, stores.clone()).await);
+        assert_eq!(status, 422);
+        assert!(body["error"]["details"]["fields"].get("user_id").is_some());
+
+        let (user, _) = user_with_post(&stores).await;
+        let body = json!({ "user_id": 42, "title": "T", "body": "B" });
+        let mismatch = request("POST", "/users/1/posts").with_param("id", user).with_json(body);
+        assert_eq!(respond(handle_create_user_post_request(mismatch, stores).await).0, 422);
+    }
+
+    #[tokio::test]
+    async fn posts_of_deleted_users_are_hidden() {
+        let stores = stores();
+        let (user, post) = user_with_post(&stores).await;
+        users::handle_delete_request(request("DELETE", "/users/1").with_param("id", user), stores.clone()).await.unwrap();
+
+        assert_eq!(respond(handle_get_post_request(request("GET", "/posts/1").with_param("id", post), stores.clone()).await).0, 404);
+        let (_, page) = respond(handle_get_posts_request(request("GET", "/posts"), stores.clone()).await);
+        assert_eq!(page["total"], 0);
+
+        stores.users.restore(user).await.unwrap();
+        assert_eq!(respond(handle_get_post_request(request("GET", "/posts/1").with_param("id", post), stores).await).0, 200);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_user_follows_the_posts_policy() {
+        let stores = Stores::in_memory(OnUserDelete::Restrict);
+        let (user, _) = user_with_post(&stores).await;
+        let delete = || request("DELETE", "/users/1").with_param("id", user);
+        let (status, body) = respond(users::handle_delete_request(delete(), stores.clone()).await);
+        assert_eq!((status, error_code(&body)), (409, "user_has_posts"));
+
+        let stores = Stores::in_memory(OnUserDelete::Cascade);
+        let (user, post) = user_with_post(&stores).await;
+        assert_eq!(respond(users::handle_delete_request(delete(), stores.clone()).await).0, 204);
+        stores.users.restore(user).await.unwrap();
+        assert!(stores.posts.get(post as i32).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use std::sync::Arc;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::{Login, RefreshRequest, Role};
+use crate::repository::Stores;
+
+use super::json_body;
+
+// Body of a successful POST /auth/login or /auth/refresh (RFC 6749 section 5.1)
+#[derive(Serialize, JsonSchema)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    // Seconds until the access token expires
+    expires_in: u64,
+    // Single use: refreshing returns a new one
+    refresh_token: String,
+}
+
+// Exchange an email and password for an access token and a refresh token, starting a session.
+// Every failure looks the same, so the response doesn't reveal which emails have accounts
+pub async fn handle_login_request(
+    request: Request,
+    Stores { users, tokens, tenant, .. }: Stores,
+    settings: Arc<auth::TokenSettings>,
+) -> Result<Response, AppError> {
+    let login: Login = json_body(&request, "Invalid login JSON")?;
+    let credentials = users.credentials(&login.email).await?;
+
+    // Argon2 is deliberately slow, so it runs off the async workers
+    let hash = credentials.as_ref().map(|credentials| credentials.password_hash.clone());
+    let verified = tokio::task::spawn_blocking(move || auth::verify_login(&login.password, hash.as_deref())).await;
+    let credentials = match (verified, credentials) {
+        (Ok(true), Some(credentials)) => credentials,
+        (Ok(_), _) => {
+            return Err(Response::error(401, "invalid_credentials", "Invalid email or password")
+                .with_header("WWW-Authenticate", "Bearer")
+                .into())
+        }
+        (Err(e), _) => return Err(AppError::Internal(e.to_string())),
+    };
+
+    let refresh_token = auth::generate_refresh_token();
+    let expires_at = Utc::now() + settings.refresh_ttl;
+    let session_id = tokens.create_session(credentials.user_id, &auth::hash_secret(&refresh_token), expires_at).await?;
+    token_response(&settings, credentials.user_id, credentials.role, tenant.as_deref(), session_id, refresh_token)
+}
+
+// Exchange a refresh token for a new access token and refresh token. The old refresh token stops
+// working, and the role is read afresh
+pub async fn handle_refresh_request(
+    request: Request,
+    Stores { tokens, tenant, .. }: Stores,
+    settings: Arc<auth::TokenSettings>,
+) -> Result<Response, AppError> {
+    let refresh: RefreshRequest = json_body(&request, "Invalid refresh JSON")?;
+
+    let refresh_token = auth::generate_refresh_token();
+    let expires_at = Utc::now() + settings.refresh_ttl;
+    let old_hash = auth::hash_secret(&refresh.refresh_token);
+    match tokens.refresh_session(&old_hash, &auth::hash_secret(&refresh_token), expires_at).await? {
+        Some(session) => token_response(&settings, session.user_id, session.role, tenant.as_deref(), session.session_id, refresh_token),
+        None => Err(Response::error(401, "invalid_refresh_token", "The refresh token is unknown, expired or revoked").into()),
+    }
+}
+
+// End the session of the request's access token: its refresh token and the access token itself
+// stop working at once
+pub async fn handle_logout_request(
+    request: Request,
+    Stores { tokens, .. }: Stores,
+    settings: Arc<auth::TokenSettings>,
+) -> Result<Response, AppError> {
+    let claims = auth::authenticate(&settings.secret, &request)?;
+    let user_id = claims
+        .sub
+        .parse::<i32>()
+        .map_err(|_| Response::error(401, "invalid_token", "Invalid token: bad subject"))?;
+
+    tokens.revoke_session(user_id, claims.sid).await?;
+    let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+    tokens.revoke_access(&claims.jti, expires_at).await?;
+    Ok(Response::new(204))
+}
+
+fn token_response(
+    settings: &auth::TokenSettings,
+    user_id: i32,
+    role: Role,
+    tenant: Option<&str>,
+    session_id: i32,
+    refresh_token: String,
+) -> Result<Response, AppError> {
+    let access_token =
+        auth::issue_token(&settings.secret, user_id, role, tenant, session_id, settings.access_ttl).map_err(AppError::Internal)?;
+    Ok(Response::json(
+        200,
+        &TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: settings.access_ttl.as_secs(),
+            refresh_token,
+        },
+    )
+    .with_header("Cache-Control", "no-store"))
+}
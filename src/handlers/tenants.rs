@@ -0,0 +1,85 @@
+use schemars::JsonSchema;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::{ApiKey, Role, Tenant, TenantInput};
+use crate::repository::{Stores, Tenants};
+use crate::validation;
+
+use super::json_body;
+
+// Name of the admin API key each new tenant starts with
+const BOOTSTRAP_KEY_NAME: &str = "bootstrap";
+
+// Body of a successful POST /tenants: the tenant, and an admin API key for managing its data
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedTenant {
+    #[serde(flatten)]
+    tenant: Tenant,
+    api_key: CreatedKey,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedKey {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    key: String,
+}
+
+// Body of GET /tenants
+#[derive(Serialize, JsonSchema)]
+pub struct TenantList {
+    tenants: Vec<Tenant>,
+}
+
+// Create a tenant with an empty dataset of its own. It has no users yet, so it comes with an admin
+// API key, which the response is the only place to find
+pub async fn handle_create_tenant_request(request: Request, stores: Stores) -> Result<Response, AppError> {
+    let tenants = tenants(&stores)?;
+    let input: TenantInput = json_body(&request, "Invalid tenant JSON")?;
+    validation::validate_tenant_id(&input.id)?;
+
+    let Some(tenant) = tenants.create(&input.id).await? else {
+        return Err(Response::error(409, "tenant_exists", format!("There is already a tenant {}", input.id)).into());
+    };
+    let Some(tenant_stores) = tenants.stores(&tenant.id).await? else {
+        return Err(AppError::Internal(format!("Tenant {} disappeared as it was created", tenant.id)));
+    };
+    let key = auth::generate_api_key();
+    let api_key = tenant_stores.api_keys.create(BOOTSTRAP_KEY_NAME, Role::Admin, &auth::hash_secret(&key)).await?;
+    let location = format!("/tenants/{}", tenant.id);
+    Ok(Response::json(201, &CreatedTenant { tenant, api_key: CreatedKey { api_key, key } })
+        .with_header("Location", location)
+        .with_header("Cache-Control", "no-store"))
+}
+
+pub async fn handle_get_tenants_request(_request: Request, stores: Stores) -> Result<Response, AppError> {
+    let tenants = tenants(&stores)?.list().await?;
+    Ok(Response::json(200, &TenantList { tenants }))
+}
+
+pub async fn handle_get_tenant_request(request: Request, stores: Stores) -> Result<Response, AppError> {
+    let id = request.param::<String>("id")?;
+    match tenants(&stores)?.get(&id).await? {
+        Some(tenant) => Ok(Response::json(200, &tenant)),
+        None => Err(AppError::NotFound("Tenant not found")),
+    }
+}
+
+// Delete the tenant and all of its data
+pub async fn handle_delete_tenant_request(request: Request, stores: Stores) -> Result<Response, AppError> {
+    let id = request.param::<String>("id")?;
+    if !validation::is_valid_tenant_id(&id) {
+        return Err(AppError::NotFound("Tenant not found"));
+    }
+    match tenants(&stores)?.delete(&id).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("Tenant not found")),
+    }
+}
+
+// Tenants are managed from the default dataset, on servers with tenancy on
+fn tenants(stores: &Stores) -> Result<&Tenants, AppError> {
+    stores.tenants.as_deref().ok_or(AppError::NotFound("Tenancy is not enabled"))
+}
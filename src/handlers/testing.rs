@@ -0,0 +1,71 @@
+// Requests, stores and response helpers for the handler unit tests, which call handlers directly
+// against the in-memory store
+use std::collections::HashMap;
+
+use crate::config::OnUserDelete;
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::query::Query;
+use crate::repository::Stores;
+
+pub fn stores() -> Stores {
+    Stores::in_memory(OnUserDelete::Keep)
+}
+
+// A request for `target`, which may carry a query string; path parameters are added with `with_param`
+pub fn request(method: &str, target: &str) -> Request {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: Query::parse(query),
+        version: "HTTP/1.1".to_string(),
+        headers: HashMap::new(),
+        body: Vec::new(),
+        params: HashMap::new(),
+    }
+}
+
+pub trait RequestExt {
+    fn with_param(self, name: &str, value: impl ToString) -> Request;
+    fn with_header(self, name: &str, value: &str) -> Request;
+    fn with_json(self, body: serde_json::Value) -> Request;
+    fn with_body(self, content_type: &str, body: &str) -> Request;
+}
+
+impl RequestExt for Request {
+    fn with_param(mut self, name: &str, value: impl ToString) -> Request {
+        self.params.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // Header names are stored lowercased, as the server does
+    fn with_header(mut self, name: &str, value: &str) -> Request {
+        self.headers.insert(name.to_ascii_lowercase(), value.to_string());
+        self
+    }
+
+    fn with_json(self, body: serde_json::Value) -> Request {
+        self.with_body("application/json", &body.to_string())
+    }
+
+    fn with_body(mut self, content_type: &str, body: &str) -> Request {
+        self.body = body.as_bytes().to_vec();
+        self.with_header("content-type", content_type)
+    }
+}
+
+// The status and JSON body (Null when empty) of a handler's result, error or not
+pub fn respond(result: Result<Response, AppError>) -> (u16, serde_json::Value) {
+    let response = result.unwrap_or_else(Response::from);
+    let body = match response.body.is_empty() {
+        true => serde_json::Value::Null,
+        false => serde_json::from_slice(&response.body).expect("a JSON body"),
+    };
+    (response.status, body)
+}
+
+// The `code` of an error body
+pub fn error_code(body: &serde_json::Value) -> &str {
+    body["error"]["code"].as_str().unwrap_or_default()
+}
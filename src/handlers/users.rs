@@ -0,0 +1,1086 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use schemars::JsonSchema;
+use tokio::sync::mpsc;
+use tracing::{error, Instrument};
+use std::io;
+
+use crate::error::AppError;
+use crate::etag::{self, IfMatch};
+use crate::hal::{self, ItemLinks, Linked};
+use crate::http::{self, Request, Response};
+use crate::models::{PasswordChange, RoleChange, SearchHit, User, UserPatch};
+use crate::repository::{PostListQuery, Stores, Upserted, UserChange, UserListQuery, UserSearch, SORTABLE_COLUMNS, USER_FIELDS};
+use crate::{auth, csv, export, multipart, validation};
+
+use super::{check_media_type, json_body, Repository, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+
+// One page of users plus the metadata needed to fetch the rest;
+// `total` and `offset` are only reported for offset pagination
+#[derive(Serialize, JsonSchema)]
+pub struct UserPage {
+    users: Vec<Linked<User>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<i64>,
+    limit: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<i64>,
+    // Pass as `?after=` to fetch the following page; null on the last page
+    next_cursor: Option<String>,
+}
+
+// Outcome of a batch request: one entry per submitted item, in request order
+#[derive(Serialize, JsonSchema)]
+pub struct BatchResult {
+    succeeded: usize,
+    failed: usize,
+    results: Vec<BatchItemResult>,
+}
+
+// `status` is what the item would have received as a single request;
+// `error` holds the `{ code, message, details }` body of a failure
+#[derive(Serialize, JsonSchema)]
+pub struct BatchItemResult {
+    index: usize,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<serde_json::Value>,
+}
+
+impl BatchItemResult {
+    fn success(index: usize, status: u16, user: User) -> BatchItemResult {
+        BatchItemResult { index, status, user: Some(user), error: None }
+    }
+
+    // Reuse the error a single request would get, unwrapped from its envelope
+    fn failure(index: usize, response: Response) -> BatchItemResult {
+        let error = error_body(&response);
+        BatchItemResult { index, status: response.status, user: None, error }
+    }
+}
+
+// The `{ code, message, details }` object inside an error response's envelope
+fn error_body(response: &Response) -> Option<serde_json::Value> {
+    serde_json::from_slice::<serde_json::Value>(&response.body)
+        .ok()
+        .and_then(|mut body| body.get_mut("error").map(serde_json::Value::take))
+}
+
+// Outcome of a CSV import: the users created and the lines that were rejected, by line number
+#[derive(Serialize, JsonSchema)]
+pub struct ImportReport {
+    created: usize,
+    failed: usize,
+    users: Vec<ImportedRow>,
+    errors: Vec<ImportFailure>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ImportedRow {
+    line: usize,
+    user: User,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ImportFailure {
+    line: usize,
+    status: u16,
+    error: Option<serde_json::Value>,
+}
+
+impl ImportFailure {
+    fn new(line: usize, response: Response) -> ImportFailure {
+        ImportFailure { line, status: response.status, error: error_body(&response) }
+    }
+}
+
+// Upper bound on data rows in one CSV import
+const MAX_IMPORT_ROWS: usize = 10_000;
+
+// Outcome of DELETE /users?ids=: the users soft-deleted and the ids that matched no live user
+#[derive(Serialize, JsonSchema)]
+pub struct DeleteSummary {
+    deleted: usize,
+    ids: Vec<i32>,
+    not_found: Vec<i32>,
+}
+
+// Upper bound on items in one batch request
+const MAX_BATCH_SIZE: usize = 1000;
+
+// One page of search hits, best match first
+#[derive(Serialize, JsonSchema)]
+pub struct SearchPage {
+    users: Vec<Linked<SearchHit>>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+// Longest accepted search text, in characters
+const MAX_SEARCH_LENGTH: usize = 100;
+
+// Body of GET /users/count
+#[derive(Serialize, JsonSchema)]
+pub struct UserCount {
+    count: i64,
+}
+
+// Window of GET /users/stats' per-day creations, in days
+const DEFAULT_STATS_DAYS: i32 = 30;
+const MAX_STATS_DAYS: i32 = 366;
+
+// Users buffered between the store and the encoder, and bytes gathered per chunk, when streaming
+const STREAM_BUFFER_USERS: usize = 256;
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+// Related records GET /users/{id} can embed with `?include=`
+const INCLUDES: [&str; 1] = ["posts"];
+
+// Most posts embedded in a user, oldest first; `posts_total` tells whether there are more to page through
+const MAX_INCLUDED_POSTS: i64 = MAX_PAGE_LIMIT;
+
+pub async fn handle_post_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let user = get_user_request_body(&request)?;
+    validation::validate_user(&user.name, &user.email)?;
+
+    let user = users.create(&user.name, &user.email).await?;
+    let location = format!("/users/{}", user.id.unwrap_or_default());
+    Ok(user_response(&request, 201, &user).with_header("Location", location))
+}
+
+// Create every valid user in one transaction; invalid or conflicting items are reported per item
+pub async fn handle_batch_create_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let items: Vec<serde_json::Value> = json_body(&request, "Expected a JSON array of users")?;
+    if items.len() > MAX_BATCH_SIZE {
+        return Err(batch_too_large());
+    }
+
+    let mut results = Vec::with_capacity(items.len());
+    let mut pending = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let user = match serde_json::from_value::<User>(item) {
+            Ok(user) => user,
+            Err(e) => {
+                let response = Response::error(400, "invalid_json", format!("Invalid user JSON: {}", e));
+                results.push(BatchItemResult::failure(index, response));
+                continue;
+            }
+        };
+        match validation::validate_user(&user.name, &user.email) {
+            Ok(()) => pending.push((index, user)),
+            Err(response) => results.push(BatchItemResult::failure(index, response)),
+        }
+    }
+
+    if !pending.is_empty() {
+        let (indexes, batch): (Vec<usize>, Vec<User>) = pending.into_iter().unzip();
+        let created = users.create_many(batch).await?;
+        for (index, outcome) in indexes.into_iter().zip(created) {
+            results.push(match outcome {
+                Ok(user) => BatchItemResult::success(index, 201, user),
+                Err(e) => BatchItemResult::failure(index, e.into()),
+            });
+        }
+        results.sort_by_key(|result| result.index);
+    }
+
+    Ok(batch_response(201, results))
+}
+
+// Create users from an uploaded CSV (multipart/form-data, or a bare text/csv body) with a header
+// row naming at least `name` and `email`; other columns are ignored, so exports import as-is
+pub async fn handle_import_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let csv = get_import_csv(&request)?;
+    let mut records = csv::parse(&csv)
+        .map_err(|message| Response::error(400, "invalid_csv", format!("Invalid CSV: {}", message)))?
+        .into_iter();
+    let header = match records.next() {
+        Some(header) => header.fields,
+        None => return Err(Response::error(400, "invalid_csv", "The CSV is empty; expected a header row").into()),
+    };
+    if records.len() > MAX_IMPORT_ROWS {
+        return Err(Response::error_with_details(
+            400,
+            "import_too_large",
+            format!("An import may contain at most {} rows", MAX_IMPORT_ROWS),
+            serde_json::json!({ "max": MAX_IMPORT_ROWS }),
+        )
+        .into());
+    }
+
+    let column = |name: &str| header.iter().position(|field| field.trim().eq_ignore_ascii_case(name));
+    let (name_column, email_column) = match (column("name"), column("email")) {
+        (Some(name), Some(email)) => (name, email),
+        _ => {
+            return Err(Response::error_with_details(
+                400,
+                "invalid_csv",
+                "The header row must include name and email columns",
+                serde_json::json!({ "header": header }),
+            )
+            .into())
+        }
+    };
+
+    let mut errors = Vec::new();
+    let mut lines = Vec::new();
+    let mut pending = Vec::new();
+    for record in records {
+        if record.fields.len() != header.len() {
+            let message = format!("Expected {} fields, found {}", header.len(), record.fields.len());
+            errors.push(ImportFailure::new(record.line, Response::error(400, "invalid_row", message)));
+            continue;
+        }
+        let mut fields = record.fields;
+        let (name, email) = (std::mem::take(&mut fields[name_column]), std::mem::take(&mut fields[email_column]));
+        if let Err(response) = validation::validate_user(&name, &email) {
+            errors.push(ImportFailure::new(record.line, response));
+            continue;
+        }
+        lines.push(record.line);
+        pending.push(User {
+            id: None,
+            name,
+            email,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        });
+    }
+
+    // Every valid row goes in one transaction; a taken email only fails its own line
+    let mut created = Vec::new();
+    if !pending.is_empty() {
+        let results = users.create_many(pending).await?;
+        for (line, result) in lines.into_iter().zip(results) {
+            match result {
+                Ok(user) => created.push(ImportedRow { line, user }),
+                Err(e) => errors.push(ImportFailure::new(line, e.into())),
+            }
+        }
+        errors.sort_by_key(|failure| failure.line);
+    }
+
+    let status = if errors.is_empty() { 201 } else { 207 };
+    Ok(Response::json(
+        status,
+        &ImportReport {
+            created: created.len(),
+            failed: errors.len(),
+            users: created,
+            errors,
+        },
+    ))
+}
+
+// The CSV text of an import: the `file` part of a form upload (or its first file), or the body itself
+fn get_import_csv(request: &Request) -> Result<String, Response> {
+    let content_type = check_media_type(request, &["multipart/form-data", "text/csv"]).map_err(Response::from)?;
+    let bytes = match content_type.media_type.as_str() {
+        "text/csv" => request.body.clone(),
+        _ => {
+            let boundary = multipart::boundary(request.header("content-type").unwrap_or_default())
+                .ok_or_else(|| Response::error(400, "invalid_multipart", "The multipart Content-Type has no boundary"))?;
+            let parts = multipart::parse(&request.body, &boundary)
+                .map_err(|e| Response::error(400, "invalid_multipart", format!("Invalid multipart body: {}", e)))?;
+            let file = parts
+                .iter()
+                .position(|part| part.name.as_deref() == Some("file"))
+                .or_else(|| parts.iter().position(|part| part.filename.is_some()));
+            match file {
+                Some(index) => parts.into_iter().nth(index).map(|part| part.body).unwrap_or_default(),
+                None => return Err(Response::error(400, "missing_file", "Upload the CSV as a form field named file")),
+            }
+        }
+    };
+    String::from_utf8(bytes).map_err(|_| Response::error(400, "invalid_csv", "The CSV must be UTF-8"))
+}
+
+// Apply partial updates (`{ "id": 1, "name": ... }`) to many users in one transaction
+pub async fn handle_batch_patch_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let items: Vec<serde_json::Value> = json_body(&request, "Expected a JSON array of patches")?;
+    if items.len() > MAX_BATCH_SIZE {
+        return Err(batch_too_large());
+    }
+
+    let mut results = Vec::with_capacity(items.len());
+    let mut pending = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let (id, patch) = match get_batch_patch(item) {
+            Ok(item) => item,
+            Err(message) => {
+                let response = Response::error(400, "invalid_json", format!("Invalid patch JSON: {}", message));
+                results.push(BatchItemResult::failure(index, response));
+                continue;
+            }
+        };
+        match validation::validate_fields(patch.name.as_deref(), patch.email.as_deref()) {
+            Ok(()) => pending.push((index, (id, patch))),
+            Err(response) => results.push(BatchItemResult::failure(index, response)),
+        }
+    }
+
+    if !pending.is_empty() {
+        let (indexes, batch): (Vec<usize>, Vec<(i32, UserPatch)>) = pending.into_iter().unzip();
+        let patched = users.patch_many(batch).await?;
+        for (index, outcome) in indexes.into_iter().zip(patched) {
+            results.push(match outcome {
+                Ok(Some(user)) => BatchItemResult::success(index, 200, user),
+                Ok(None) => BatchItemResult::failure(index, Response::error(404, "not_found", "User not found")),
+                Err(e) => BatchItemResult::failure(index, e.into()),
+            });
+        }
+        results.sort_by_key(|result| result.index);
+    }
+
+    Ok(batch_response(200, results))
+}
+
+// Soft-delete the users listed in `?ids=1,2,3` in one go
+pub async fn handle_batch_delete_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let ids = match request.query.get("ids") {
+        Some(ids) => ids,
+        None => {
+            return Err(Response::error_with_details(
+                400,
+                "invalid_query_parameter",
+                "ids is required, e.g. ?ids=1,2,3",
+                serde_json::json!({ "parameter": "ids" }),
+            )
+            .into())
+        }
+    };
+    let mut requested = Vec::new();
+    for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        match id.parse::<i32>() {
+            Ok(id) if !requested.contains(&id) => requested.push(id),
+            Ok(_) => {}
+            Err(_) => {
+                return Err(Response::error_with_details(
+                    400,
+                    "invalid_query_parameter",
+                    "ids must be a comma-separated list of user ids",
+                    serde_json::json!({ "parameter": "ids", "value": id }),
+                )
+                .into())
+            }
+        }
+    }
+    if requested.is_empty() || requested.len() > MAX_BATCH_SIZE {
+        return Err(Response::error_with_details(
+            400,
+            "invalid_query_parameter",
+            format!("ids must list between 1 and {} user ids", MAX_BATCH_SIZE),
+            serde_json::json!({ "parameter": "ids", "max": MAX_BATCH_SIZE }),
+        )
+        .into());
+    }
+
+    let deleted = users.delete_many(requested.clone()).await?;
+    // Reported in request order, whatever order the store returned them in
+    let (ids, not_found): (Vec<i32>, Vec<i32>) = requested.into_iter().partition(|id| deleted.contains(id));
+    Ok(Response::json(200, &DeleteSummary { deleted: ids.len(), ids, not_found }))
+}
+
+// Split a batch patch item into its target id and the fields to change
+fn get_batch_patch(item: serde_json::Value) -> Result<(i32, UserPatch), String> {
+    let mut item = match item {
+        serde_json::Value::Object(item) => item,
+        _ => return Err("expected an object".to_string()),
+    };
+    let id = match item.remove("id") {
+        Some(id) => serde_json::from_value::<i32>(id).map_err(|e| format!("id: {}", e))?,
+        None => return Err("missing field `id`".to_string()),
+    };
+    let patch = serde_json::from_value(serde_json::Value::Object(item)).map_err(|e| e.to_string())?;
+    Ok((id, patch))
+}
+
+// 400 for a batch with more than MAX_BATCH_SIZE items
+fn batch_too_large() -> AppError {
+    Response::error_with_details(
+        400,
+        "batch_too_large",
+        format!("A batch may contain at most {} users", MAX_BATCH_SIZE),
+        serde_json::json!({ "max": MAX_BATCH_SIZE }),
+    )
+    .into()
+}
+
+// `status` when every item succeeded, otherwise 207 so clients check each result
+fn batch_response(status: u16, results: Vec<BatchItemResult>) -> Response {
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    let status = if failed == 0 { status } else { 207 };
+    Response::json(
+        status,
+        &BatchResult {
+            succeeded: results.len() - failed,
+            failed,
+            results,
+        },
+    )
+}
+
+// The user, or just the fields `?fields=` asks for, with the related records `?include=` asks for
+// embedded. The whole user is read all the same, as that is what the caches in front of the store hold
+pub async fn handle_get_request(request: Request, Stores { users, posts, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let include_deleted = request.query.parse_value::<bool>("include_deleted")?.unwrap_or(false);
+    let fields = get_fields(&request)?;
+    let includes = get_includes(&request)?;
+
+    let user = users.get(id, include_deleted).await?.ok_or(AppError::NotFound("User not found"))?;
+    if fields.is_none() && includes.is_empty() {
+        return Ok(user_response(&request, 200, &user));
+    }
+    let mut body = serde_json::to_value(linked(&request, user.id, &user)).map_err(|e| AppError::Internal(e.to_string()))?;
+    if let Some(fields) = &fields {
+        select_fields(&mut body, fields);
+    }
+    if includes.is_empty() {
+        let mut response = user_response(&request, 200, &user);
+        response.body = serde_json::to_vec(&body).map_err(|e| AppError::Internal(e.to_string()))?;
+        return Ok(response);
+    }
+
+    if includes.contains(&"posts") {
+        let page = posts.list(&PostListQuery { user_id: Some(id), limit: MAX_INCLUDED_POSTS, offset: 0 }).await?;
+        body["posts"] = serde_json::to_value(page.posts).map_err(|e| AppError::Internal(e.to_string()))?;
+        body["posts_total"] = page.total.into();
+    }
+    // Embedded records change without the user's version changing, so the response is left to get
+    // an entity tag from its body rather than the user's
+    Ok(Response::json(200, &body))
+}
+
+// A page of users; `?fields=` narrows both what is read and what is sent
+pub async fn handle_get_all_requests(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let mut list = get_list_query(&request)?;
+    list.fields = get_fields(&request)?;
+    let page = users.list(&list).await?;
+
+    // Cursors follow the id order, so they are only offered when sorting by id
+    let next_cursor = match page.users.last() {
+        Some(User { id: Some(id), .. }) if page.has_more && list.sort == "id" => Some(encode_cursor(*id)),
+        _ => None,
+    };
+    let page = UserPage {
+        users: page.users.into_iter().map(|user| linked(&request, user.id, user)).collect(),
+        total: page.total,
+        limit: list.limit,
+        offset: list.after.is_none().then_some(list.offset),
+        next_cursor,
+    };
+    let links = hal::Page { total: page.total, limit: page.limit, offset: page.offset, next_cursor: page.next_cursor.as_deref() };
+    let Some(fields) = &list.fields else {
+        return Ok(links.apply(&request, Response::json(200, &page)));
+    };
+    let mut body = serde_json::to_value(&page).map_err(|e| AppError::Internal(e.to_string()))?;
+    for user in body["users"].as_array_mut().into_iter().flatten() {
+        select_fields(user, fields);
+    }
+    Ok(links.apply(&request, Response::json(200, &body)))
+}
+
+// Every user matching the list filters as one JSON array, streamed so memory use stays flat
+pub async fn handle_stream_all_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let list = get_list_query(&request)?;
+    stream_users(users, list, export::Format::Json).await
+}
+
+// Download every user matching the list filters as CSV, or JSON Lines when Accept asks for it
+pub async fn handle_export_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let list = get_list_query(&request)?;
+    let format = match export::Format::from_accept(&request) {
+        Some(format) => format,
+        None => {
+            return Err(Response::error_with_details(
+                406,
+                "not_acceptable",
+                "Exports are available as text/csv or application/x-ndjson",
+                serde_json::json!({ "available": ["text/csv", "application/x-ndjson"] }),
+            )
+            .into())
+        }
+    };
+
+    let disposition = format!("attachment; filename=\"users.{}\"", format.extension());
+    Ok(stream_users(users, list, format).await?.with_header("Content-Disposition", disposition))
+}
+
+// Stream the listed users in `format`, chunk by chunk as the store produces them
+async fn stream_users(users: Repository, list: UserListQuery, format: export::Format) -> Result<Response, AppError> {
+    let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER_USERS);
+    let producer = tokio::spawn(async move { users.stream(&list, sender).await }.in_current_span());
+
+    // Hold the status back until the first user arrives, so failing to query is still a proper error
+    let first = match receiver.recv().await {
+        Some(user) => user,
+        None => {
+            return match producer.await {
+                Ok(Ok(())) => {
+                    let mut response = Response::new(200).with_header("Content-Type", format.content_type());
+                    response.body = format.start();
+                    response.body.extend_from_slice(format.end());
+                    Ok(response)
+                }
+                Ok(Err(e)) => Err(e.into()),
+                Err(e) => Err(AppError::Internal(e.to_string())),
+            }
+        }
+    };
+
+    let (body, stream) = mpsc::channel(1);
+    // Logged errors stay within the request's span
+    let encoder = async move {
+        let mut chunk = format.start();
+        let mut next = Some(first);
+        let mut first = true;
+        while let Some(user) = next {
+            if let Err(e) = format.encode(&user, first, &mut chunk) {
+                let _ = body.send(Err(io::Error::other(e))).await;
+                return;
+            }
+            first = false;
+            if chunk.len() >= STREAM_CHUNK_SIZE && body.send(Ok(std::mem::take(&mut chunk))).await.is_err() {
+                // The client went away; dropping the receiver stops the query
+                return;
+            }
+            next = receiver.recv().await;
+        }
+
+        let end = match producer.await {
+            Ok(Ok(())) => {
+                chunk.extend_from_slice(format.end());
+                Ok(chunk)
+            }
+            Ok(Err(e)) => Err(io::Error::other(e.to_string())),
+            Err(e) => Err(io::Error::other(e)),
+        };
+        if let Err(e) = &end {
+            error!("Error streaming users: {}", e);
+        }
+        let _ = body.send(end).await;
+    };
+    tokio::spawn(encoder.in_current_span());
+    Ok(Response::stream(200, format.content_type(), stream))
+}
+
+// Count users with the same filters as the list, without fetching them
+pub async fn handle_count_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let list = get_list_query(&request)?;
+    let count = users.count(&list).await?;
+    Ok(Response::json(200, &UserCount { count }))
+}
+
+// Find users whose name or email resembles `q`, ranked by how well they match
+pub async fn handle_search_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let text = request.query.get("q").unwrap_or_default().trim();
+    if text.is_empty() || text.chars().count() > MAX_SEARCH_LENGTH {
+        return Err(Response::error_with_details(
+            400,
+            "invalid_query_parameter",
+            format!("q must be between 1 and {} characters", MAX_SEARCH_LENGTH),
+            serde_json::json!({ "parameter": "q" }),
+        )
+        .into());
+    }
+    let limit = request.query.parse_value::<i64>("limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = request.query.parse_value::<i64>("offset")?.unwrap_or(0);
+    if limit < 1 || offset < 0 {
+        return Err(Response::error(400, "invalid_pagination", "limit must be positive and offset non-negative").into());
+    }
+
+    let search = UserSearch {
+        text: text.to_string(),
+        limit: limit.min(MAX_PAGE_LIMIT),
+        offset,
+    };
+    let results = users.search(&search).await?;
+    let page = SearchPage {
+        users: results.hits.into_iter().map(|hit| linked(&request, hit.user.id, hit)).collect(),
+        total: results.total,
+        limit: search.limit,
+        offset,
+    };
+    let links = hal::Page { total: Some(page.total), limit: page.limit, offset: Some(offset), next_cursor: None };
+    Ok(links.apply(&request, Response::json(200, &page)))
+}
+
+pub async fn handle_stats_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let days = request.query.parse_value::<i32>("days")?.unwrap_or(DEFAULT_STATS_DAYS);
+    if !(1..=MAX_STATS_DAYS).contains(&days) {
+        return Err(Response::error_with_details(
+            400,
+            "invalid_query_parameter",
+            format!("days must be between 1 and {}", MAX_STATS_DAYS),
+            serde_json::json!({ "parameter": "days", "max": MAX_STATS_DAYS }),
+        )
+        .into());
+    }
+
+    let stats = users.stats(days).await?;
+    Ok(Response::json(200, &stats))
+}
+
+// Replace a user; with `upsert` a PUT to a missing id creates it there (201) instead of a 404
+pub async fn handle_put_request(request: Request, Stores { users, .. }: Stores, upsert: bool) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let user = get_user_request_body(&request)?;
+    validation::validate_user(&user.name, &user.email)?;
+
+    let result = match IfMatch::from_request(&request) {
+        None if upsert => {
+            return match users.upsert(id, &user.name, &user.email).await? {
+                Some(Upserted::Created(user)) => Ok(user_response(&request, 201, &user).with_header("Location", format!("/users/{}", id))),
+                Some(Upserted::Updated(user)) => Ok(user_response(&request, 200, &user)),
+                None => Err(Response::error(409, "user_deleted", "User is deleted; restore it before replacing it").into()),
+            }
+        }
+        None => users.update(id, &user.name, &user.email).await?,
+        // Compare versions under the row lock so a concurrent update can't slip in between
+        Some(if_match) => {
+            let change: UserChange = Box::new(move |current| {
+                if_match.check(current.version)?;
+                Ok(User {
+                    name: user.name,
+                    email: user.email,
+                    ..current
+                })
+            });
+            users.modify(id, change).await?
+        }
+    };
+    match result {
+        Some(user) => Ok(user_response(&request, 200, &user)),
+        None => Err(AppError::NotFound("User not found")),
+    }
+}
+
+pub async fn handle_patch_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    // The patch format is chosen by Content-Type; plain JSON is a partial user object
+    let content_type =
+        check_media_type(&request, &["application/json", "application/merge-patch+json", "application/json-patch+json"])?;
+    match content_type.media_type.as_str() {
+        "application/merge-patch+json" => return handle_document_patch(id, &request, &users, PatchFormat::Merge).await,
+        "application/json-patch+json" => return handle_document_patch(id, &request, &users, PatchFormat::Json).await,
+        _ => {}
+    }
+
+    let patch: UserPatch =
+        serde_json::from_slice(&request.body).map_err(|e| AppError::InvalidJson(format!("Invalid user JSON: {}", e)))?;
+    validation::validate_fields(patch.name.as_deref(), patch.email.as_deref())?;
+
+    let result = match IfMatch::from_request(&request) {
+        None => users.patch(id, &patch).await?,
+        Some(if_match) => {
+            let change: UserChange = Box::new(move |current| {
+                if_match.check(current.version)?;
+                Ok(User {
+                    name: patch.name.unwrap_or(current.name),
+                    email: patch.email.unwrap_or(current.email),
+                    ..current
+                })
+            });
+            users.modify(id, change).await?
+        }
+    };
+    match result {
+        Some(user) => Ok(user_response(&request, 200, &user)),
+        None => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// Patch documents applied to the stored user as JSON
+enum PatchFormat {
+    // RFC 7396 JSON Merge Patch
+    Merge,
+    // RFC 6902 JSON Patch
+    Json,
+}
+
+// Apply a merge patch or JSON patch to the current user atomically
+async fn handle_document_patch(id: i32, request: &Request, users: &Repository, format: PatchFormat) -> Result<Response, AppError> {
+    let patch: serde_json::Value =
+        serde_json::from_slice(&request.body).map_err(|e| AppError::InvalidJson(format!("Invalid patch JSON: {}", e)))?;
+
+    let if_match = IfMatch::from_request(request);
+    let change: UserChange = Box::new(move |current| {
+        if let Some(if_match) = if_match {
+            if_match.check(current.version)?;
+        }
+        let patched = apply_patch(id, &current, patch, format)?;
+        validation::validate_user(&patched.name, &patched.email)?;
+        Ok(patched)
+    });
+    match users.modify(id, change).await? {
+        Some(user) => Ok(user_response(request, 200, &user)),
+        None => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// Apply a patch document to the user's JSON form and read the result back as a user
+fn apply_patch(id: i32, current: &User, patch: serde_json::Value, format: PatchFormat) -> Result<User, Response> {
+    let mut document = serde_json::to_value(current)
+        .map_err(|e| Response::error(500, "internal_error", format!("Error serializing user: {}", e)))?;
+    match format {
+        PatchFormat::Merge => json_patch::merge(&mut document, &patch),
+        PatchFormat::Json => {
+            let operations: json_patch::Patch = serde_json::from_value(patch)
+                .map_err(|e| Response::error(400, "invalid_patch", format!("Invalid JSON Patch: {}", e)))?;
+            json_patch::patch(&mut document, &operations)
+                .map_err(|e| Response::error(422, "patch_failed", format!("Patch could not be applied: {}", e)))?;
+        }
+    }
+
+    // The version is read-only; use If-Match (or a JSON Patch `test`) to guard on it
+    if document.get("version") != Some(&serde_json::json!(current.version)) {
+        return Err(Response::error(422, "patch_failed", "The user version cannot be changed"));
+    }
+    if document.get("created_at").unwrap_or(&serde_json::Value::Null) != &serde_json::json!(current.created_at) {
+        return Err(Response::error(422, "patch_failed", "The user creation time cannot be changed"));
+    }
+    if document.get("updated_at").unwrap_or(&serde_json::Value::Null) != &serde_json::json!(current.updated_at) {
+        return Err(Response::error(422, "patch_failed", "The user modification time cannot be changed"));
+    }
+    match user_from_document(document) {
+        Ok(user) if user.id == Some(id) => Ok(user),
+        Ok(_) => Err(Response::error(422, "patch_failed", "The user id cannot be changed")),
+        Err(message) => Err(Response::error(422, "patch_failed", message)),
+    }
+}
+
+// Turn a patched JSON document back into a user, rejecting fields the model doesn't have
+fn user_from_document(document: serde_json::Value) -> Result<User, String> {
+    if let Some(object) = document.as_object() {
+        if let Some(field) = object.keys().find(|k| !["id", "name", "email", "created_at", "updated_at", "version"].contains(&k.as_str())) {
+            return Err(format!("Unknown user field: {}", field));
+        }
+    }
+    serde_json::from_value(document).map_err(|e| format!("Patched user is invalid: {}", e))
+}
+
+pub async fn handle_delete_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    match users.delete(id).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// Undo a soft delete; the user must not have been replaced by one with the same email
+pub async fn handle_restore_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+
+    match users.restore(id).await? {
+        Some(user) => Ok(user_response(&request, 200, &user)),
+        None => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// Set a live user's password; it is stored as an Argon2 hash and never returned
+pub async fn handle_set_password_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let change: PasswordChange = json_body(&request, "Invalid password JSON")?;
+    validation::validate_password(&change.password)?;
+
+    let hash = match tokio::task::spawn_blocking(move || auth::hash_password(&change.password)).await {
+        Ok(Ok(hash)) => hash,
+        Ok(Err(e)) => return Err(AppError::Internal(e)),
+        Err(e) => return Err(AppError::Internal(e.to_string())),
+    };
+    match users.set_password(id, &hash).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// Change a live user's role; it applies to tokens issued from then on
+pub async fn handle_set_role_request(request: Request, Stores { users, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let change: RoleChange = json_body(&request, "Invalid role JSON")?;
+
+    match users.set_role(id, change.role).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("User not found")),
+    }
+}
+
+// A single user as JSON with its links, tagged with its version for If-Match
+fn user_response(request: &Request, status: u16, user: &User) -> Response {
+    let response = Response::json(status, &linked(request, user.id, user)).with_header("ETag", etag::for_version(user.version));
+    match user.updated_at {
+        Some(updated_at) => response.with_header("Last-Modified", http::http_date(updated_at)),
+        None => response,
+    }
+}
+
+// Read listing parameters from the query string; limit is capped at MAX_PAGE_LIMIT
+fn get_list_query(request: &Request) -> Result<UserListQuery, Response> {
+    let query = &request.query;
+    let limit = query.parse_value::<i64>("limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = query.parse_value::<i64>("offset")?.unwrap_or(0);
+    if limit < 1 || offset < 0 {
+        return Err(Response::error(400, "invalid_pagination", "limit must be positive and offset non-negative"));
+    }
+
+    let sort = match query.get("sort") {
+        Some(column) => SORTABLE_COLUMNS
+            .iter()
+            .find(|c| **c == column)
+            .copied()
+            .ok_or_else(|| {
+                Response::error_with_details(
+                    400,
+                    "invalid_query_parameter",
+                    format!("sort must be one of: {}", SORTABLE_COLUMNS.join(", ")),
+                    serde_json::json!({ "parameter": "sort", "allowed": SORTABLE_COLUMNS }),
+                )
+            })?,
+        None => "id",
+    };
+    let order = match query.get("order") {
+        Some(order) if order.eq_ignore_ascii_case("asc") => "ASC",
+        Some(order) if order.eq_ignore_ascii_case("desc") => "DESC",
+        Some(_) => return Err(Response::error(400, "invalid_query_parameter", "order must be asc or desc")),
+        None => "ASC",
+    };
+
+    let after = match query.get("after") {
+        Some(_) if offset > 0 => return Err(Response::error(400, "invalid_pagination", "after and offset cannot be combined")),
+        Some(_) if sort != "id" => return Err(Response::error(400, "invalid_pagination", "after can only be used when sorting by id")),
+        Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| Response::error(400, "invalid_cursor", "Invalid cursor"))?),
+        None => None,
+    };
+
+    Ok(UserListQuery {
+        limit: limit.min(MAX_PAGE_LIMIT),
+        offset,
+        after,
+        sort,
+        order,
+        name: query.get("name").map(str::to_string),
+        email_contains: query.get("email_contains").map(str::to_string),
+        include_deleted: query.parse_value::<bool>("include_deleted")?.unwrap_or(false),
+        fields: None,
+    })
+}
+
+// Read `?fields=`, the comma-separated user fields to return (e.g. `id,email`); None without it
+fn get_fields(request: &Request) -> Result<Option<Vec<&'static str>>, Response> {
+    let Some(names) = request.query.get("fields") else {
+        return Ok(None);
+    };
+    let invalid = || {
+        Response::error_with_details(
+            400,
+            "invalid_query_parameter",
+            format!("fields must list some of: {}", USER_FIELDS.join(", ")),
+            serde_json::json!({ "parameter": "fields", "allowed": USER_FIELDS }),
+        )
+    };
+    let mut fields = Vec::new();
+    for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let field = USER_FIELDS.iter().find(|field| **field == name).ok_or_else(invalid)?;
+        if !fields.contains(field) {
+            fields.push(*field);
+        }
+    }
+    match fields.is_empty() {
+        true => Err(invalid()),
+        false => Ok(Some(fields)),
+    }
+}
+
+// Read `?include=`, the comma-separated related records to embed (e.g. `posts`)
+fn get_includes(request: &Request) -> Result<Vec<&'static str>, Response> {
+    let Some(names) = request.query.get("include") else {
+        return Ok(Vec::new());
+    };
+    let mut includes = Vec::new();
+    for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let include = INCLUDES.iter().find(|include| **include == name).ok_or_else(|| {
+            Response::error_with_details(
+                400,
+                "invalid_query_parameter",
+                format!("include must list some of: {}", INCLUDES.join(", ")),
+                serde_json::json!({ "parameter": "include", "allowed": INCLUDES }),
+            )
+        })?;
+        if !includes.contains(include) {
+            includes.push(*include);
+        }
+    }
+    Ok(includes)
+}
+
+// Drop every member of a serialized user but `fields`
+fn select_fields(user: &mut serde_json::Value, fields: &[&str]) {
+    if let Some(user) = user.as_object_mut() {
+        user.retain(|name, _| name == "_links" || fields.contains(&name.as_str()));
+    }
+}
+
+// A user, or a search hit, with the links HAL clients follow from the user `id`, when there is one
+fn linked<T>(request: &Request, id: Option<i32>, user: T) -> Linked<T> {
+    Linked { resource: user, links: id.map(|id| ItemLinks::new(request, "/users", id)) }
+}
+
+// Cursors are opaque to clients: the last seen id, base64url-encoded
+pub(crate) fn encode_cursor(id: i32) -> String {
+    URL_SAFE_NO_PAD.encode(format!("id:{}", id))
+}
+
+pub(crate) fn decode_cursor(cursor: &str) -> Option<i32> {
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    String::from_utf8(decoded).ok()?.strip_prefix("id:")?.parse().ok()
+}
+
+// Deserialize the user from the request body
+fn get_user_request_body(request: &Request) -> Result<User, AppError> {
+    json_body(request, "Invalid user JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::handlers::testing::{error_code, request, respond, stores, RequestExt};
+
+    async fn create(stores: &Stores, name: &str, email: &str) -> i64 {
+        let post = request("POST", "/users").with_json(json!({ "name": name, "email": email }));
+        let (status, body) = respond(handle_post_request(post, stores.clone()).await);
+        assert_eq!(status, 201, "{}", body);
+        body["id"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn create_validates_and_rejects_taken_emails() {
+        let stores = stores();
+        create(&stores, "Ada", "ada@example.com").await;
+
+        let post = request("POST", "/users").with_json(json!({ "name": "", "email": "nope" }));
+        let (status, body) = respond(handle_post_request(post, stores.clone()).await);
+        assert_eq!((status, error_code(&body)), (422, "validation_failed"));
+
+        let post = request("POST", "/users").with_json(json!({ "name": "Other Ada", "email": "ADA@example.com" }));
+        let (status, body) = respond(handle_post_request(post, stores.clone()).await);
+        assert_eq!((status, error_code(&body)), (409, "email_taken"));
+    }
+
+    #[tokio::test]
+    async fn bodies_must_be_utf8_json() {
+        let stores = stores();
+        let user = r#"{"name":"Ada","email":"ada@example.com"}"#;
+        let post = |content_type: &str| request("POST", "/users").with_body(content_type, user);
+
+        for content_type in ["text/plain", "application/x-www-form-urlencoded", "application/json; charset=latin1"] {
+            let (status, body) = respond(handle_post_request(post(content_type), stores.clone()).await);
+            assert_eq!((status, error_code(&body)), (415, "unsupported_media_type"), "{}", content_type);
+        }
+        let mut missing = post("application/json");
+        missing.headers.clear();
+        assert_eq!(respond(handle_post_request(missing, stores.clone()).await).0, 415);
+
+        let (status, _) = respond(handle_post_request(post("Application/JSON; Charset=\"UTF-8\""), stores).await);
+        assert_eq!(status, 201);
+    }
+
+    #[tokio::test]
+    async fn list_pages_with_a_cursor() {
+        let stores = stores();
+        for n in 1..=5 {
+            create(&stores, "User", &format!("user{}@example.com", n)).await;
+        }
+
+        let (status, page) = respond(handle_get_all_requests(request("GET", "/users?limit=2"), stores.clone()).await);
+        assert_eq!((status, page["total"].as_i64()), (200, Some(5)));
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+        let (_, next) = respond(handle_get_all_requests(request("GET", &format!("/users?limit=2&after={}", cursor)), stores.clone()).await);
+        let ids: Vec<i64> = next["users"].as_array().unwrap().iter().map(|user| user["id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, [3, 4]);
+
+        let (status, body) = respond(handle_get_all_requests(request("GET", "/users?sort=password"), stores).await);
+        assert_eq!((status, error_code(&body)), (400, "invalid_query_parameter"));
+    }
+
+    #[tokio::test]
+    async fn put_checks_if_match_and_upserts() {
+        let stores = stores();
+        let id = create(&stores, "Ada", "ada@example.com").await;
+        let body = json!({ "name": "Ada L", "email": "ada@example.com" });
+
+        let stale = request("PUT", "/users/1").with_param("id", id).with_header("If-Match", "\"7\"").with_json(body.clone());
+        let (status, error) = respond(handle_put_request(stale, stores.clone(), false).await);
+        assert_eq!((status, error_code(&error)), (412, "precondition_failed"));
+
+        let fresh = request("PUT", "/users/1").with_param("id", id).with_header("If-Match", "\"1\"").with_json(body.clone());
+        let (status, user) = respond(handle_put_request(fresh, stores.clone(), false).await);
+        assert_eq!((status, user["version"].as_i64()), (200, Some(2)));
+
+        let missing = json!({ "name": "New", "email": "new@example.com" });
+        let put = || request("PUT", "/users/9").with_param("id", 9).with_json(missing.clone());
+        assert_eq!(respond(handle_put_request(put(), stores.clone(), false).await).0, 404);
+        let (status, user) = respond(handle_put_request(put(), stores.clone(), true).await);
+        assert_eq!((status, user["id"].as_i64()), (201, Some(9)));
+        // Ids handed out later skip past the upserted one
+        assert_eq!(create(&stores, "Next", "next@example.com").await, 10);
+    }
+
+    #[tokio::test]
+    async fn patch_formats() {
+        let stores = stores();
+        let id = create(&stores, "Ada", "ada@example.com").await;
+        let patch = |content_type: &str, body: &str| request("PATCH", "/users/1").with_param("id", id).with_body(content_type, body);
+
+        let merge = patch("application/merge-patch+json", r#"{"name":"Merged"}"#);
+        let (status, user) = respond(handle_patch_request(merge, stores.clone()).await);
+        assert_eq!((status, user["name"].as_str()), (200, Some("Merged")));
+        let ops = r#"[{"op":"replace","path":"/email","value":"lovelace@example.com"}]"#;
+        let (status, user) = respond(handle_patch_request(patch("application/json-patch+json", ops), stores.clone()).await);
+        assert_eq!((status, user["email"].as_str()), (200, Some("lovelace@example.com")));
+        let (status, _) = respond(handle_patch_request(patch("text/plain", "name=x"), stores).await);
+        assert_eq!(status, 415);
+    }
+
+    #[tokio::test]
+    async fn delete_hides_the_user_until_restored() {
+        let stores = stores();
+        let id = create(&stores, "Ada", "ada@example.com").await;
+        let target = || request("DELETE", "/users/1").with_param("id", id);
+
+        assert_eq!(respond(handle_delete_request(target(), stores.clone()).await).0, 204);
+        assert_eq!(respond(handle_delete_request(target(), stores.clone()).await).0, 404);
+        assert_eq!(respond(handle_get_request(target(), stores.clone()).await).0, 404);
+        // The email is free again while the user is deleted, so restoring would clash
+        let taker = create(&stores, "Ada Again", "ada@example.com").await;
+        let (status, body) = respond(handle_restore_request(target(), stores.clone()).await);
+        assert_eq!((status, error_code(&body)), (409, "email_taken"));
+
+        assert_eq!(respond(handle_delete_request(request("DELETE", "/").with_param("id", taker), stores.clone()).await).0, 204);
+        let (status, user) = respond(handle_restore_request(target(), stores).await);
+        assert_eq!((status, user["version"].as_i64()), (200, Some(3)));
+    }
+
+    #[tokio::test]
+    async fn batch_create_reports_each_item() {
+        let stores = stores();
+        let items = json!([
+            { "name": "Ada", "email": "ada@example.com" },
+            { "name": "", "email": "blank@example.com" },
+            { "name": "Ada Again", "email": "ada@example.com" },
+        ]);
+        let (status, result) = respond(handle_batch_create_request(request("POST", "/users/batch").with_json(items), stores).await);
+        assert_eq!(status, 207);
+        let statuses: Vec<i64> = result["results"].as_array().unwrap().iter().map(|item| item["status"].as_i64().unwrap()).collect();
+        assert_eq!(statuses, [201, 422, 409]);
+    }
+}
@@ -0,0 +1,81 @@
+use schemars::JsonSchema;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::models::{Webhook, WebhookInput};
+use crate::repository::Stores;
+use crate::validation;
+
+use super::json_body;
+
+// Body of a successful POST /webhooks: the webhook plus the secret its deliveries are signed with
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+// Body of GET /webhooks
+#[derive(Serialize, JsonSchema)]
+pub struct WebhookList {
+    webhooks: Vec<Webhook>,
+}
+
+// Subscribe a URL to user events. The response is the only place the secret appears
+pub async fn handle_create_webhook_request(request: Request, Stores { webhooks, .. }: Stores) -> Result<Response, AppError> {
+    let input: WebhookInput = json_body(&request, "Invalid webhook JSON")?;
+    validation::validate_webhook(&input.url, &input.events, input.secret.as_deref())?;
+
+    let secret = input.secret.unwrap_or_else(auth::generate_webhook_secret);
+    let webhook = webhooks.create(&input.url, &secret, &distinct(input.events)).await?;
+    let location = format!("/webhooks/{}", webhook.id);
+    Ok(Response::json(201, &CreatedWebhook { webhook, secret })
+        .with_header("Location", location)
+        .with_header("Cache-Control", "no-store"))
+}
+
+pub async fn handle_get_webhooks_request(_request: Request, Stores { webhooks, .. }: Stores) -> Result<Response, AppError> {
+    let webhooks = webhooks.list().await?;
+    Ok(Response::json(200, &WebhookList { webhooks }))
+}
+
+pub async fn handle_get_webhook_request(request: Request, Stores { webhooks, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    match webhooks.get(id).await? {
+        Some(webhook) => Ok(Response::json(200, &webhook)),
+        None => Err(AppError::NotFound("Webhook not found")),
+    }
+}
+
+// Replace the URL and events; the secret changes only when a new one is given
+pub async fn handle_put_webhook_request(request: Request, Stores { webhooks, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    let input: WebhookInput = json_body(&request, "Invalid webhook JSON")?;
+    validation::validate_webhook(&input.url, &input.events, input.secret.as_deref())?;
+
+    match webhooks.update(id, &input.url, input.secret.as_deref(), &distinct(input.events)).await? {
+        Some(webhook) => Ok(Response::json(200, &webhook)),
+        None => Err(AppError::NotFound("Webhook not found")),
+    }
+}
+
+pub async fn handle_delete_webhook_request(request: Request, Stores { webhooks, .. }: Stores) -> Result<Response, AppError> {
+    let id = request.param::<i32>("id")?;
+    match webhooks.delete(id).await? {
+        true => Ok(Response::new(204)),
+        false => Err(AppError::NotFound("Webhook not found")),
+    }
+}
+
+// Each event type once, in the order first given
+fn distinct(events: Vec<String>) -> Vec<String> {
+    let mut distinct = Vec::with_capacity(events.len());
+    for event in events {
+        if !distinct.contains(&event) {
+            distinct.push(event);
+        }
+    }
+    distinct
+}
@@ -0,0 +1,682 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::query::Query;
+use crate::websocket;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::error;
+
+// Upper bound on the request line plus headers
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+// Sent as the Server header of every response
+const SERVER: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// Headers write_response sets itself; a handler's own values for them are dropped
+const MANAGED_HEADERS: [&str; 5] = ["content-length", "transfer-encoding", "connection", "date", "server"];
+
+// Parsed HTTP request: request line, headers and body
+pub struct Request {
+    pub method: String,
+    // Path without the query string
+    pub path: String,
+    pub query: Query,
+    pub version: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    // Path parameters captured by the router, e.g. `id` for `/users/{id}`
+    pub params: HashMap<String, String>,
+}
+
+impl Request {
+    // Look up a header by name (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(|v| v.as_str())
+    }
+
+    // The parsed Content-Type header, if the request has one
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header("content-type").map(ContentType::parse)
+    }
+
+    // Parse a path parameter into the requested type, responding 400 when it doesn't fit
+    pub fn param<T: FromStr>(&self, name: &str) -> Result<T, Response> {
+        self.params
+            .get(name)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| {
+                Response::error_with_details(
+                    400,
+                    "invalid_parameter",
+                    format!("Invalid {} format", name),
+                    serde_json::json!({ "parameter": name }),
+                )
+            })
+    }
+
+    // Whether the client wants the connection kept open after this request;
+    // HTTP/1.1 defaults to persistent connections, HTTP/1.0 must opt in
+    pub fn keep_alive(&self) -> bool {
+        match self.version.as_str() {
+            "HTTP/1.0" => self.has_token("connection", "keep-alive"),
+            _ => !self.has_token("connection", "close"),
+        }
+    }
+
+    // Whether a comma-separated header such as Connection lists `token`, in any case
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name).is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    }
+}
+
+// A Content-Type such as `application/json; charset="UTF-8"`
+#[derive(Debug, PartialEq)]
+pub struct ContentType {
+    // Type and subtype, lowercased
+    pub media_type: String,
+    // Lowercased, without quotes
+    pub charset: Option<String>,
+}
+
+impl ContentType {
+    pub fn parse(value: &str) -> ContentType {
+        let mut params = value.split(';');
+        let media_type = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let charset = params.find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+        });
+        ContentType { media_type, charset }
+    }
+
+    // UTF-8 is assumed when no charset is given
+    pub fn is_utf8(&self) -> bool {
+        matches!(self.charset.as_deref(), None | Some("utf-8" | "utf8"))
+    }
+}
+
+// Body chunks produced while the response is being written; an Err aborts the response
+pub type BodyStream = mpsc::Receiver<io::Result<Vec<u8>>>;
+
+// HTTP response built by handlers; framing headers are added when it is written
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // When set, the body is sent chunked from this stream instead of `body`
+    pub stream: Option<BodyStream>,
+    // When set on a 101, the connection is handed to this WebSocket session once the head is written
+    pub upgrade: Option<websocket::Session>,
+    // Answering HEAD: the head is written as it would be for GET, Content-Length included, and the
+    // body isn't
+    pub omit_body: bool,
+    // Why a 5xx was answered, as logged, for error reporting; never sent
+    pub cause: Option<String>,
+}
+
+impl Response {
+    // Response with no body
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            stream: None,
+            upgrade: None,
+            omit_body: false,
+            cause: None,
+        }
+    }
+
+    // Response whose body is written chunk by chunk as the stream yields it
+    pub fn stream(status: u16, content_type: &str, stream: BodyStream) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: Vec::new(),
+            stream: Some(stream),
+            upgrade: None,
+            omit_body: false,
+            cause: None,
+        }
+    }
+
+    // JSON response serialized from any serializable value
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response {
+                status,
+                headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+                body,
+                stream: None,
+                upgrade: None,
+                omit_body: false,
+                cause: None,
+            },
+            Err(e) => {
+                error!("Error serializing response: {}", e);
+                Response::error(500, "internal_error", "Internal server error")
+                    .with_cause(format!("Error serializing response: {}", e))
+            }
+        }
+    }
+
+    // Error response using the shared `{ "error": { code, message, details } }` envelope
+    pub fn error(status: u16, code: &str, message: impl Into<String>) -> Response {
+        Response::error_with_details(status, code, message, serde_json::Value::Null)
+    }
+
+    // Error response carrying structured details, e.g. the offending parameter
+    pub fn error_with_details(
+        status: u16,
+        code: &str,
+        message: impl Into<String>,
+        details: serde_json::Value,
+    ) -> Response {
+        let envelope = ErrorEnvelope {
+            error: ErrorBody {
+                code: code.to_string(),
+                message: message.into(),
+                details,
+            },
+        };
+        // Serialized directly: Response::json falls back to Response::error on failure
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: serde_json::to_vec(&envelope).unwrap_or_default(),
+            stream: None,
+            upgrade: None,
+            omit_body: false,
+            cause: None,
+        }
+    }
+
+    // Record why the request failed, for the error reporter
+    pub fn with_cause(mut self, cause: impl Into<String>) -> Response {
+        self.cause = Some(cause.into());
+        self
+    }
+
+    // Add a header, keeping any existing values
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+// Body of every error response
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorBody {
+    code: String,
+    message: String,
+    details: serde_json::Value,
+}
+
+// An HTTP-date (RFC 9110 5.6.7), as in Last-Modified: `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// Parse an HTTP-date in the preferred format; the obsolete RFC 850 and asctime forms aren't accepted
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    let time = NaiveDateTime::parse_from_str(value.trim(), "%a, %d %b %Y %H:%M:%S GMT").ok()?;
+    Some(time.and_utc())
+}
+
+// Canonical reason phrase for the status codes this server produces
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        207 => "Multi-Status",
+        301 => "Moved Permanently",
+        304 => "Not Modified",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+// Errors produced while reading or parsing a request
+#[derive(Debug)]
+pub enum RequestError {
+    Io(io::Error),
+    MissingRequestLine,
+    InvalidRequestLine(String),
+    InvalidHeader(String),
+    InvalidContentLength(String),
+    InvalidChunk(String),
+    UnsupportedTransferEncoding(String),
+    HeadersTooLarge,
+    PayloadTooLarge(usize),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::Io(e) => write!(f, "io error: {}", e),
+            RequestError::MissingRequestLine => write!(f, "missing request line"),
+            RequestError::InvalidRequestLine(line) => write!(f, "invalid request line: {}", line),
+            RequestError::InvalidHeader(line) => write!(f, "invalid header: {}", line),
+            RequestError::InvalidContentLength(value) => write!(f, "invalid content-length: {}", value),
+            RequestError::InvalidChunk(line) => write!(f, "invalid chunk: {}", line),
+            RequestError::UnsupportedTransferEncoding(value) => {
+                write!(f, "unsupported transfer-encoding: {}", value)
+            }
+            RequestError::HeadersTooLarge => write!(f, "request headers exceed {} bytes", MAX_HEAD_SIZE),
+            RequestError::PayloadTooLarge(len) => write!(f, "request body of {} bytes is too large", len),
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+// Read a full request from the stream, honoring Content-Length and chunked framing
+pub async fn read_request<R>(reader: &mut R, max_body_size: usize) -> Result<Request, RequestError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    // Read until the blank line that ends the headers
+    let mut head = Vec::new();
+    loop {
+        let remaining = (MAX_HEAD_SIZE + 1).saturating_sub(head.len()) as u64;
+        let start = head.len();
+        (&mut *reader).take(remaining).read_until(b'\n', &mut head).await?;
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(RequestError::HeadersTooLarge);
+        }
+        match &head[start..] {
+            b"" => return Err(unexpected_eof()),
+            b"\r\n" | b"\n" if start > 0 => break,
+            // Tolerate stray empty lines before the request line
+            b"\r\n" | b"\n" => head.clear(),
+            _ => {}
+        }
+    }
+
+    let mut request = parse_head(&head)?;
+
+    request.body = match request.header("transfer-encoding") {
+        Some(encoding) if is_chunked(encoding) => read_chunked_body(reader, max_body_size).await?,
+        Some(encoding) => return Err(RequestError::UnsupportedTransferEncoding(encoding.to_string())),
+        None => {
+            let content_length = match request.header("content-length") {
+                Some(value) => value
+                    .parse::<usize>()
+                    .map_err(|_| RequestError::InvalidContentLength(value.to_string()))?,
+                None => 0,
+            };
+            if content_length > max_body_size {
+                return Err(RequestError::PayloadTooLarge(content_length));
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await?;
+            body
+        }
+    };
+
+    Ok(request)
+}
+
+// Write a response with the framing headers needed for keep-alive, returning how many body bytes
+// were sent. A buffered body must be written within `timeout`; a streamed body is sent chunked, with
+// `timeout` applying to each chunk
+pub async fn write_response<W>(writer: &mut W, response: Response, keep_alive: bool, timeout: Duration) -> io::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    let head = head(&response, keep_alive);
+    if response.omit_body {
+        let write = async {
+            writer.write_all(head.as_bytes()).await?;
+            writer.flush().await
+        };
+        return with_timeout(timeout, write).await.map(|()| 0);
+    }
+    let Some(mut stream) = response.stream else {
+        let write = async {
+            writer.write_all(head.as_bytes()).await?;
+            writer.write_all(&response.body).await?;
+            writer.flush().await
+        };
+        return with_timeout(timeout, write).await.map(|()| response.body.len() as u64);
+    };
+
+    with_timeout(timeout, writer.write_all(head.as_bytes())).await?;
+    let mut sent = 0;
+    while let Some(chunk) = stream.recv().await {
+        // Stopping without the final chunk tells the client the body is incomplete
+        let chunk = chunk?;
+        if chunk.is_empty() {
+            continue;
+        }
+        let write = async {
+            writer.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+            writer.write_all(&chunk).await?;
+            writer.write_all(b"\r\n").await?;
+            writer.flush().await
+        };
+        with_timeout(timeout, write).await?;
+        sent += chunk.len() as u64;
+    }
+    with_timeout(timeout, async {
+        writer.write_all(b"0\r\n\r\n").await?;
+        writer.flush().await
+    })
+    .await
+    .map(|()| sent)
+}
+
+// The status line and headers, ending with the blank line. Every response gets Date, Server and
+// Connection, plus Content-Length for a buffered body or chunked Transfer-Encoding for a stream
+fn head(response: &Response, keep_alive: bool) -> String {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason_phrase(response.status));
+    let mut header = |name: &str, value: &str| {
+        // A line break in a value would start a header, or the body, of its own
+        let value: String = value.chars().map(|c| if c == '\r' || c == '\n' { ' ' } else { c }).collect();
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    };
+    header("Date", &http_date(Utc::now()));
+    header("Server", SERVER);
+    for (name, value) in &response.headers {
+        if !MANAGED_HEADERS.iter().any(|managed| name.eq_ignore_ascii_case(managed)) {
+            header(name, value);
+        }
+    }
+    match response.stream {
+        Some(_) => header("Transfer-Encoding", "chunked"),
+        // 101, 204 and 304 never have a body, and a 304's Content-Length would describe the 200 it stands for
+        None if matches!(response.status, 101 | 204 | 304) => {}
+        None => header("Content-Length", &response.body.len().to_string()),
+    }
+    let connection = match response.status {
+        101 => "Upgrade",
+        _ if keep_alive => "keep-alive",
+        _ => "close",
+    };
+    header("Connection", connection);
+    head.push_str("\r\n");
+    head
+}
+
+// Fail with TimedOut when `write` doesn't finish in time
+async fn with_timeout<F>(timeout: Duration, write: F) -> io::Result<()>
+where
+    F: std::future::Future<Output = io::Result<()>>,
+{
+    tokio::time::timeout(timeout, write)
+        .await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out writing response")))
+}
+
+// Errors that just mean the client went away or stayed idle too long
+pub fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+// Only "chunked" (optionally alone) is supported as a transfer coding
+fn is_chunked(encoding: &str) -> bool {
+    encoding.trim().eq_ignore_ascii_case("chunked")
+}
+
+// Decode a chunked body: hex size lines, chunk data, and optional trailers
+async fn read_chunked_body<R>(reader: &mut R, max_body_size: usize) -> Result<Vec<u8>, RequestError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader).await?;
+        // Only hex digits: from_str_radix would also take a sign, which a proxy in front might read differently
+        let size_str = line.split(';').next().unwrap_or_default().trim_end_matches([' ', '\t']);
+        if size_str.is_empty() || !size_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(RequestError::InvalidChunk(line));
+        }
+        let size = usize::from_str_radix(size_str, 16).map_err(|_| RequestError::InvalidChunk(line.clone()))?;
+
+        if size == 0 {
+            break;
+        }
+        // Saturating, since a hostile size line can be up to usize::MAX
+        let total = body.len().saturating_add(size);
+        if total > max_body_size {
+            return Err(RequestError::PayloadTooLarge(total));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+
+        // Every chunk is terminated by CRLF
+        let terminator = read_line(reader).await?;
+        if !terminator.is_empty() {
+            return Err(RequestError::InvalidChunk(terminator));
+        }
+    }
+
+    // Skip trailer fields up to the final empty line
+    while !read_line(reader).await?.is_empty() {}
+
+    Ok(body)
+}
+
+// Read one CRLF-terminated line (bounded by MAX_HEAD_SIZE) without the terminator
+async fn read_line<R>(reader: &mut R) -> Result<String, RequestError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    (&mut *reader).take(MAX_HEAD_SIZE as u64).read_until(b'\n', &mut line).await?;
+    if !line.ends_with(b"\n") {
+        return Err(if line.len() >= MAX_HEAD_SIZE {
+            RequestError::HeadersTooLarge
+        } else {
+            unexpected_eof()
+        });
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn unexpected_eof() -> RequestError {
+    RequestError::Io(io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+// Parse the request line and headers; the body is filled in by the reader
+fn parse_head(raw: &[u8]) -> Result<Request, RequestError> {
+    let head = String::from_utf8_lossy(raw);
+
+    let mut lines = head.lines();
+    let request_line = lines.next().filter(|l| !l.is_empty()).ok_or(RequestError::MissingRequestLine)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) if version.starts_with("HTTP/") => {
+            (method, target, version)
+        }
+        _ => return Err(RequestError::InvalidRequestLine(request_line.to_string())),
+    };
+
+    let mut headers = HashMap::new();
+    for line in lines.filter(|l| !l.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+            .ok_or_else(|| RequestError::InvalidHeader(line.to_string()))?;
+        // Repeated headers are combined into a comma-separated list
+        headers
+            .entry(name.to_ascii_lowercase())
+            .and_modify(|existing: &mut String| {
+                existing.push_str(", ");
+                existing.push_str(value.trim());
+            })
+            .or_insert_with(|| value.trim().to_string());
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    Ok(Request {
+        method: method.to_string(),
+        path: normalize_path(path),
+        query: Query::parse(query),
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+        params: HashMap::new(),
+    })
+}
+
+// Normalize a request path (RFC 3986, section 6.2.2) so equivalent spellings route alike: escapes of
+// characters a segment may hold as they are get decoded, e.g. `/users/%31` to `/users/1`, and other
+// escapes are kept with uppercase hex; repeated slashes are collapsed, and `.` and `..` segments
+// resolved without climbing above the root. A trailing slash is kept. Targets that aren't paths,
+// such as `*`, are left alone
+fn normalize_path(path: &str) -> String {
+    if !path.starts_with('/') {
+        return path.to_string();
+    }
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit) => {
+                std::str::from_utf8(hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&byte) => decoded.push(byte),
+            Some(byte) => decoded.extend_from_slice(format!("%{:02X}", byte).as_bytes()),
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+                continue;
+            }
+        }
+        i += 3;
+    }
+    let decoded = String::from_utf8_lossy(&decoded);
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing = false;
+    for segment in decoded.split('/') {
+        trailing = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    match (segments.is_empty(), trailing) {
+        (true, _) => "/".to_string(),
+        (false, true) => format!("/{}/", segments.join("/")),
+        (false, false) => format!("/{}", segments.join("/")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn status_line(response: Response) -> String {
+        let mut written = Vec::new();
+        write_response(&mut written, response, false, Duration::from_secs(1)).await.unwrap();
+        let written = String::from_utf8(written).unwrap();
+        written.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn status_lines_carry_the_reason_phrase() {
+        let timed_out = Response::error(504, "timeout", "The request took too long");
+        assert_eq!(status_line(timed_out).await, "HTTP/1.1 504 Gateway Timeout");
+        let redirect = Response::new(308).with_header("Location", "/v1/users");
+        assert_eq!(status_line(redirect).await, "HTTP/1.1 308 Permanent Redirect");
+    }
+
+    async fn read_chunked(chunks: &str, max_body_size: usize) -> Result<Request, RequestError> {
+        let raw = format!("POST /v1/users HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n{}", chunks);
+        read_request(&mut raw.as_bytes(), max_body_size).await
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_are_decoded() {
+        let request = read_chunked("4\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\n\r\n", 1024).await.unwrap();
+        assert_eq!(request.body, b"Wikipedia in\r\n\r\nchunks.");
+
+        // Extensions are ignored, as are trailer fields
+        let request = read_chunked("3;name=value\r\nabc\r\n2 ; last\r\nde\r\n0\r\nExpires: never\r\nX-Sum: 5\r\n\r\n", 1024).await;
+        assert_eq!(request.unwrap().body, b"abcde");
+    }
+
+    #[tokio::test]
+    async fn malformed_chunks_are_rejected() {
+        // Data running past its size, with no CRLF where the chunk should end
+        let result = read_chunked("3\r\nabcdef\r\n0\r\n\r\n", 1024).await;
+        assert!(matches!(result, Err(RequestError::InvalidChunk(_))), "{:?}", result.err());
+
+        for size in ["g", "+a", "-1", "0x3", " 3", ""] {
+            let result = read_chunked(&format!("{}\r\nabc\r\n0\r\n\r\n", size), 1024).await;
+            assert!(matches!(result, Err(RequestError::InvalidChunk(_))), "{:?}: {:?}", size, result.err());
+        }
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_over_the_limit_are_too_large() {
+        let result = read_chunked("8\r\n12345678\r\n8\r\n12345678\r\n0\r\n\r\n", 10).await;
+        assert!(matches!(result, Err(RequestError::PayloadTooLarge(16))), "{:?}", result.err());
+
+        // Refused from the size line, before reading what it announces
+        let result = read_chunked("ffffffffffffffff\r\n", 10).await;
+        assert!(matches!(result, Err(RequestError::PayloadTooLarge(_))), "{:?}", result.err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::auth::hash_secret;
+use crate::http::{Request, Response};
+use crate::repository::{IdempotencyClaim, Stores, StoredResponse};
+use crate::versioning;
+
+// Requests that honour an Idempotency-Key header: ones that create something
+const IDEMPOTENT_ROUTES: [(&str, &str); 1] = [("POST", "/users")];
+
+const MAX_KEY_LENGTH: usize = 255;
+
+// How long a running request holds its key. Finishing extends it to the configured TTL, so this
+// only matters for claims left behind by a request that never finished, like one cut off by a crash
+const LEASE: Duration = Duration::from_secs(60);
+
+// A request that claimed its key and runs once; its response is stored when it finishes
+pub struct Claim {
+    key_hash: String,
+    ttl: Duration,
+}
+
+// Claim the request's Idempotency-Key for the caller. Ok(None) when the request has no key or
+// its route ignores keys; Err with the response to send instead of running the request, which
+// is the stored response when the same request was already handled
+pub async fn begin(stores: &Stores, request: &Request, actor: Option<&str>, ttl: Duration) -> Result<Option<Claim>, Response> {
+    let Some(key) = request.header("idempotency-key") else {
+        return Ok(None);
+    };
+    if !IDEMPOTENT_ROUTES.contains(&(request.method.as_str(), versioning::unversioned(&request.path))) {
+        return Ok(None);
+    }
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH || !key.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return Err(Response::error(
+            400,
+            "invalid_idempotency_key",
+            format!("Idempotency-Key must be 1 to {} printable ASCII characters", MAX_KEY_LENGTH),
+        ));
+    }
+
+    // Keys are scoped to the caller, so one client can't replay another's response
+    let key_hash = hash_secret(&format!("{}\n{}", actor.unwrap_or("anonymous"), key));
+    let expires_at = Utc::now() + LEASE;
+    match stores.idempotency.claim(&key_hash, &fingerprint(request), expires_at).await {
+        Ok(IdempotencyClaim::Claimed) => Ok(Some(Claim { key_hash, ttl })),
+        Ok(IdempotencyClaim::Completed(stored)) => Err(replay(stored)),
+        Ok(IdempotencyClaim::InProgress) => Err(Response::error(
+            409,
+            "idempotency_key_in_use",
+            "A request with this Idempotency-Key is still being processed; retry later",
+        )),
+        Ok(IdempotencyClaim::Mismatch) => Err(Response::error(
+            422,
+            "idempotency_key_reused",
+            "This Idempotency-Key was already used for a different request",
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Claim {
+    // Keep the response for replaying. Server errors aren't kept, so the request can be retried
+    // with the same key; neither are streamed bodies. A failure is logged but never fails the request
+    pub async fn finish(self, stores: &Stores, response: &Response) {
+        let result = if response.status >= 500 || response.stream.is_some() {
+            stores.idempotency.release(&self.key_hash).await
+        } else {
+            let stored = StoredResponse {
+                status: response.status,
+                headers: response.headers.clone(),
+                body: response.body.clone(),
+            };
+            stores.idempotency.complete(&self.key_hash, &stored, Utc::now() + self.ttl).await
+        };
+        if let Err(e) = result {
+            error!("Failed to store the response for an Idempotency-Key: {}", e);
+        }
+    }
+}
+
+// What makes two requests the same: the route and the exact body
+fn fingerprint(request: &Request) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{} {}\n", request.method, request.path));
+    hasher.update(&request.body);
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let response = Response {
+        status: stored.status,
+        headers: stored.headers,
+        body: stored.body,
+        stream: None,
+        upgrade: None,
+        omit_body: false,
+        cause: None,
+    };
+    response.with_header("Idempotent-Replayed", "true")
+}
@@ -0,0 +1,289 @@
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::http::{Request, Response};
+
+// Most violations reported for one body; a long array of bad items would otherwise make a huge response
+const MAX_VIOLATIONS: usize = 50;
+
+// Deepest the checker follows nested schemas, so a `$ref` to itself can't recurse forever
+const MAX_DEPTH: usize = 64;
+
+// A JSON Schema (draft 7) request bodies are checked against. The subset schemars generates is
+// understood: types, enums, properties, items, length and range bounds, integer formats, the
+// combinators and `$ref`s into the schema's own document. Other keywords are ignored
+pub struct Schema {
+    root: Value,
+}
+
+// Where in the body a schema isn't met, as a JSON pointer (RFC 6901), and how
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Violation {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl Schema {
+    // The schema of the type a handler deserializes its body into
+    pub fn of<T: JsonSchema>() -> Schema {
+        let root = SchemaSettings::draft07().into_generator().into_root_schema_for::<T>();
+        Schema { root: serde_json::to_value(root).unwrap_or(Value::Bool(true)) }
+    }
+
+    // A schema written by hand
+    pub fn new(root: Value) -> Schema {
+        Schema { root }
+    }
+
+    // Every way `value` fails the schema, up to MAX_VIOLATIONS
+    pub fn validate(&self, value: &Value) -> Vec<Violation> {
+        let mut checker = Checker { root: &self.root, violations: Vec::new() };
+        checker.check(&self.root, value, &mut String::new(), 0);
+        checker.violations
+    }
+
+    // Check a JSON body against the schema before its handler deserializes it: 400 with every
+    // violation when it doesn't match. Bodies of other media types, and ones that aren't JSON at
+    // all, are left to the handler to reject as it always has
+    pub fn check_request(&self, request: &Request) -> Result<(), Response> {
+        if request.content_type().is_none_or(|content_type| content_type.media_type != "application/json") {
+            return Ok(());
+        }
+        let Ok(body) = serde_json::from_slice::<Value>(&request.body) else {
+            return Ok(());
+        };
+        let violations = self.validate(&body);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(Response::error_with_details(
+            400,
+            "invalid_json",
+            "The body doesn't match the schema of this route",
+            json!({ "violations": violations }),
+        ))
+    }
+}
+
+struct Checker<'a> {
+    root: &'a Value,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Checker<'a> {
+    fn fail(&mut self, pointer: &str, message: impl Into<String>) {
+        if self.violations.len() < MAX_VIOLATIONS {
+            self.violations.push(Violation { pointer: pointer.to_string(), message: message.into() });
+        }
+    }
+
+    // Whether `value` meets `schema`, without recording why not
+    fn matches(&self, schema: &Value, value: &Value, depth: usize) -> bool {
+        let mut checker = Checker { root: self.root, violations: Vec::new() };
+        checker.check(schema, value, &mut String::new(), depth);
+        checker.violations.is_empty()
+    }
+
+    // Check `value`, found at `pointer` in the body, against `schema`, recording each violation
+    fn check(&mut self, schema: &'a Value, value: &Value, pointer: &mut String, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return self.fail(pointer, "is not allowed"),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference.strip_prefix('#').and_then(|path| self.root.pointer(path)) {
+                Some(target) => self.check(target, value, pointer, depth + 1),
+                None => self.fail(pointer, format!("refers to {}, which the schema doesn't define", reference)),
+            }
+        }
+        for part in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+            self.check(part, value, pointer, depth + 1);
+        }
+        if let Some(choices) = schema.get("anyOf").and_then(Value::as_array) {
+            if !choices.iter().any(|choice| self.matches(choice, value, depth + 1)) {
+                self.fail(pointer, "matches none of the schemas it may match");
+            }
+        }
+        if let Some(choices) = schema.get("oneOf").and_then(Value::as_array) {
+            let matched = choices.iter().filter(|choice| self.matches(choice, value, depth + 1)).count();
+            if matched != 1 {
+                self.fail(pointer, format!("must match exactly one of its schemas, not {}", matched));
+            }
+        }
+        if let Some(excluded) = schema.get("not") {
+            if self.matches(excluded, value, depth + 1) {
+                self.fail(pointer, "matches a schema it must not");
+            }
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                return self.fail(pointer, format!("must be {}", expected));
+            }
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+                return self.fail(pointer, format!("must be one of {}", allowed.join(", ")));
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::String(kind) => vec![kind.as_str()],
+                Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !types.iter().any(|kind| has_type(value, kind)) {
+                let names: Vec<&str> = types.iter().map(|kind| type_name(kind)).collect();
+                return self.fail(pointer, format!("must be {}", names.join(" or ")));
+            }
+        }
+
+        match value {
+            Value::String(text) => self.check_string(schema, text, pointer),
+            Value::Number(_) => self.check_number(schema, value, pointer),
+            Value::Array(items) => self.check_array(schema, items, pointer, depth),
+            Value::Object(members) => self.check_object(schema, members, pointer, depth),
+            Value::Null | Value::Bool(_) => {}
+        }
+    }
+
+    fn check_string(&mut self, schema: &Map<String, Value>, text: &str, pointer: &str) {
+        let length = text.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64).filter(|min| length < *min) {
+            self.fail(pointer, format!("must be at least {} characters", min));
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64).filter(|max| length > *max) {
+            self.fail(pointer, format!("must be at most {} characters", max));
+        }
+    }
+
+    fn check_number(&mut self, schema: &Map<String, Value>, value: &Value, pointer: &str) {
+        let Some(number) = value.as_f64() else { return };
+        let reported = self.violations.len();
+        let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum").filter(|min| number < *min) {
+            self.fail(pointer, format!("must be at least {}", min));
+        }
+        if let Some(max) = bound("maximum").filter(|max| number > *max) {
+            self.fail(pointer, format!("must be at most {}", max));
+        }
+        if let Some(min) = bound("exclusiveMinimum").filter(|min| number <= *min) {
+            self.fail(pointer, format!("must be more than {}", min));
+        }
+        if let Some(max) = bound("exclusiveMaximum").filter(|max| number >= *max) {
+            self.fail(pointer, format!("must be less than {}", max));
+        }
+        // schemars gives the width of integer fields as their format, and the minimum of unsigned ones,
+        // which has been reported already if it wasn't met
+        if self.violations.len() > reported {
+            return;
+        }
+        let range = match schema.get("format").and_then(Value::as_str) {
+            Some("int8") => Some((i8::MIN as f64, i8::MAX as f64)),
+            Some("int16") => Some((i16::MIN as f64, i16::MAX as f64)),
+            Some("int32") => Some((i32::MIN as f64, i32::MAX as f64)),
+            Some("int64") => Some((i64::MIN as f64, i64::MAX as f64)),
+            Some("uint8") => Some((0.0, u8::MAX as f64)),
+            Some("uint16") => Some((0.0, u16::MAX as f64)),
+            Some("uint32") => Some((0.0, u32::MAX as f64)),
+            Some("uint64" | "uint") => Some((0.0, u64::MAX as f64)),
+            _ => None,
+        };
+        if let Some((min, max)) = range.filter(|(min, max)| number < *min || number > *max) {
+            self.fail(pointer, format!("must be from {} to {}", min, max));
+        }
+    }
+
+    fn check_array(&mut self, schema: &'a Map<String, Value>, items: &[Value], pointer: &mut String, depth: usize) {
+        let count = items.len() as u64;
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64).filter(|min| count < *min) {
+            self.fail(pointer, format!("must have at least {} items", min));
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64).filter(|max| count > *max) {
+            self.fail(pointer, format!("must have at most {} items", max));
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            let repeated = items.iter().enumerate().any(|(index, item)| items[..index].contains(item));
+            if repeated {
+                self.fail(pointer, "must not repeat items");
+            }
+        }
+        for (index, item) in items.iter().enumerate() {
+            // Either one schema for every item, or one for each position followed by additionalItems
+            let item_schema = match schema.get("items") {
+                Some(Value::Array(positions)) => positions.get(index).or_else(|| schema.get("additionalItems")),
+                other => other,
+            };
+            if let Some(item_schema) = item_schema {
+                let length = pointer.len();
+                pointer.push_str(&format!("/{}", index));
+                self.check(item_schema, item, pointer, depth + 1);
+                pointer.truncate(length);
+            }
+        }
+    }
+
+    fn check_object(&mut self, schema: &'a Map<String, Value>, members: &Map<String, Value>, pointer: &mut String, depth: usize) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+            if !members.contains_key(name) {
+                self.fail(&child(pointer, name), "is required");
+            }
+        }
+        for (name, member) in members {
+            let length = pointer.len();
+            pointer.push_str(&child("", name));
+            match properties.and_then(|properties| properties.get(name)) {
+                // Read-only properties are ignored when the body is deserialized, so whatever they hold is let through
+                Some(property) if property.get("readOnly") == Some(&Value::Bool(true)) => {}
+                Some(property) => self.check(property, member, pointer, depth + 1),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => self.fail(pointer, "is not a known field"),
+                    Some(additional) => self.check(additional, member, pointer, depth + 1),
+                    None => {}
+                },
+            }
+            pointer.truncate(length);
+        }
+    }
+}
+
+// `pointer` extended by the member `name`, escaped as RFC 6901 says
+fn child(pointer: &str, name: &str) -> String {
+    format!("{}/{}", pointer, name.replace('~', "~0").replace('/', "~1"))
+}
+
+fn has_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(kind: &str) -> &str {
+    match kind {
+        "null" => "null",
+        "boolean" => "a boolean",
+        "string" => "a string",
+        "number" => "a number",
+        "integer" => "an integer",
+        "array" => "an array",
+        "object" => "an object",
+        other => other,
+    }
+}
@@ -0,0 +1,251 @@
+use serde_json::{json, Map, Value};
+
+use crate::hal::Page;
+use crate::http::Request;
+use crate::query::Query;
+
+// Media type of JSON:API documents (https://jsonapi.org/format/1.1/)
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+// Resource type of each collection, by the path segment or member naming it. Members of a group are
+// users, and pages of the audit log call their entries `entries`
+const TYPES: [(&str, &str); 10] = [
+    ("users", "users"),
+    ("members", "users"),
+    ("posts", "posts"),
+    ("groups", "groups"),
+    ("api-keys", "api-keys"),
+    ("api_keys", "api-keys"),
+    ("webhooks", "webhooks"),
+    ("tenants", "tenants"),
+    ("audit", "audit-entries"),
+    ("entries", "audit-entries"),
+];
+
+// Members holding the id of another resource, which become relationships: (member, relationship, type)
+const REFERENCES: [(&str, &str, &str); 1] = [("user_id", "user", "users")];
+
+// Where a document was asked for: what its resources are called, and where its links point
+pub struct Location {
+    path: String,
+    query: Query,
+}
+
+impl Location {
+    pub fn of(request: &Request) -> Location {
+        Location { path: request.path.clone(), query: request.query.clone() }
+    }
+
+    // The type of the resources at this path, named by its last collection segment; None for paths
+    // that don't serve resources, like health checks and tokens
+    fn resource_type(&self) -> Option<&'static str> {
+        self.path.rsplit('/').find_map(type_named)
+    }
+
+    fn link(&self) -> String {
+        self.query.link(&self.path, &[])
+    }
+}
+
+// Reshape a JSON body as a JSON:API document: a resource, or a page of them, as `data`, with their
+// references as relationships and embedded resources as `included`; an error as `errors`; anything
+// else as `meta`. None when the path doesn't serve resources, so the body is left as it is
+pub fn document(body: Value, status: u16, location: &Location) -> Option<Value> {
+    let kind = location.resource_type()?;
+    if status >= 400 {
+        return Some(errors(body, status));
+    }
+    let mut included = Vec::new();
+    let mut document = match body {
+        Value::Object(object) if is_resource_object(&object) => {
+            json!({ "data": resource(kind, object, &mut included), "links": { "self": location.link() } })
+        }
+        Value::Array(items) if items.iter().all(is_resource) => {
+            let data: Vec<Value> = items.into_iter().map(|item| into_resource(kind, item, &mut included)).collect();
+            json!({ "data": data, "links": { "self": location.link() } })
+        }
+        Value::Object(mut object) => match object.iter().find(|(name, value)| is_collection(name, value)) {
+            Some((name, _)) => {
+                let name = name.clone();
+                let kind = type_named(&name).unwrap_or(kind);
+                let items = match object.remove(&name) {
+                    Some(Value::Array(items)) => items,
+                    _ => Vec::new(),
+                };
+                let data: Vec<Value> = items.into_iter().map(|item| into_resource(kind, item, &mut included)).collect();
+                json!({ "data": data, "links": page_links(&object, location), "meta": object })
+            }
+            None => json!({ "meta": object }),
+        },
+        // `meta` must be an object
+        other => json!({ "meta": { "value": other } }),
+    };
+    if !included.is_empty() {
+        document["included"] = Value::Array(included);
+    }
+    Some(document)
+}
+
+// `{ "error": { code, message, details } }` as error objects: one per invalid field or schema
+// violation, each pointing at it in the request body, or else one for the whole error. Other
+// details are kept as meta
+fn errors(body: Value, status: u16) -> Value {
+    let error = body.get("error").cloned().unwrap_or(Value::Null);
+    let mut details = match error.get("details") {
+        Some(Value::Object(details)) => details.clone(),
+        _ => Map::new(),
+    };
+    let base = || {
+        let mut object = Map::new();
+        object.insert("status".to_string(), Value::String(status.to_string()));
+        for (member, field) in [("code", "code"), ("title", "message")] {
+            if let Some(value) = error.get(field).filter(|value| !value.is_null()) {
+                object.insert(member.to_string(), value.clone());
+            }
+        }
+        object
+    };
+    let pointed = |pointer: String, detail: Value| {
+        let mut object = base();
+        object.insert("detail".to_string(), detail);
+        object.insert("source".to_string(), json!({ "pointer": pointer }));
+        Value::Object(object)
+    };
+
+    let mut errors = Vec::new();
+    if let Some(Value::Object(fields)) = details.remove("fields") {
+        for (field, messages) in fields {
+            let messages = match messages {
+                Value::Array(messages) => messages,
+                message => vec![message],
+            };
+            errors.extend(messages.into_iter().map(|message| pointed(format!("/{}", field), message)));
+        }
+    }
+    if let Some(Value::Array(violations)) = details.remove("violations") {
+        for violation in violations {
+            let pointer = violation.get("pointer").and_then(Value::as_str).unwrap_or_default().to_string();
+            errors.push(pointed(pointer, violation.get("message").cloned().unwrap_or(Value::Null)));
+        }
+    }
+    if errors.is_empty() {
+        errors.push(Value::Object(base()));
+    }
+    if !details.is_empty() {
+        let details = Value::Object(details);
+        for error in &mut errors {
+            error["meta"] = details.clone();
+        }
+    }
+    json!({ "errors": errors })
+}
+
+// A resource object: the id as a string, references as relationships, embedded resources linked by
+// relationships and added to `included`, and everything else as attributes
+fn resource(kind: &str, mut attributes: Map<String, Value>, included: &mut Vec<Value>) -> Value {
+    let id = attributes.remove("id").map(id_string).unwrap_or_default();
+    // HAL links, which JSON:API has its own member for
+    let own = attributes.remove("_links").and_then(|links| links.pointer("/self/href").cloned());
+    let mut relationships = Map::new();
+    for (member, relationship, related) in REFERENCES {
+        match attributes.remove(member) {
+            Some(Value::Null) => {
+                relationships.insert(relationship.to_string(), json!({ "data": null }));
+            }
+            Some(value) => {
+                relationships.insert(relationship.to_string(), json!({ "data": { "type": related, "id": id_string(value) } }));
+            }
+            None => {}
+        }
+    }
+    // As `?include=` embeds them, with their count beside them as `<name>_total`
+    let embedded: Vec<String> =
+        attributes.iter().filter(|(name, value)| is_collection(name, value)).map(|(name, _)| name.clone()).collect();
+    for name in embedded {
+        let related = type_named(&name).unwrap_or("resources");
+        let items = match attributes.remove(&name) {
+            Some(Value::Array(items)) => items,
+            _ => Vec::new(),
+        };
+        let mut linkage = Vec::new();
+        for item in items {
+            let item = into_resource(related, item, &mut Vec::new());
+            linkage.push(json!({ "type": item["type"], "id": item["id"] }));
+            if !included.iter().any(|other| other["type"] == item["type"] && other["id"] == item["id"]) {
+                included.push(item);
+            }
+        }
+        let mut relationship = json!({ "data": linkage });
+        if let Some(total) = attributes.remove(&format!("{}_total", name)) {
+            relationship["meta"] = json!({ "total": total });
+        }
+        relationships.insert(name, relationship);
+    }
+
+    let mut object = json!({ "type": kind, "id": id, "attributes": attributes });
+    if !relationships.is_empty() {
+        object["relationships"] = Value::Object(relationships);
+    }
+    if let Some(own) = own {
+        object["links"] = json!({ "self": own });
+    }
+    object
+}
+
+fn into_resource(kind: &str, item: Value, included: &mut Vec<Value>) -> Value {
+    match item {
+        Value::Object(object) => resource(kind, object, included),
+        other => other,
+    }
+}
+
+// self, and for an offset page first, prev, next and last, or for a cursor page next; prev and next
+// are null past the ends
+fn page_links(page: &Map<String, Value>, location: &Location) -> Value {
+    let number = |name: &str| page.get(name).and_then(Value::as_i64);
+    let page = Page {
+        total: number("total"),
+        limit: number("limit").unwrap_or_default(),
+        offset: number("offset"),
+        next_cursor: page.get("next_cursor").and_then(Value::as_str),
+    };
+    let mut links = Map::new();
+    links.insert("self".to_string(), Value::String(location.link()));
+    let ends: &[&str] = match (page.total, page.offset, page.next_cursor) {
+        (Some(_), Some(_), _) => &["prev", "next"],
+        (_, _, Some(_)) => &["next"],
+        _ => &[],
+    };
+    for rel in ends {
+        links.insert(rel.to_string(), Value::Null);
+    }
+    for (rel, href) in page.links(&location.path, &location.query) {
+        links.insert(rel.to_string(), Value::String(href));
+    }
+    Value::Object(links)
+}
+
+fn type_named(name: &str) -> Option<&'static str> {
+    TYPES.iter().find(|(segment, _)| *segment == name).map(|(_, kind)| *kind)
+}
+
+// A member holding resources of a known type, which may be none
+fn is_collection(name: &str, value: &Value) -> bool {
+    type_named(name).is_some() && value.as_array().is_some_and(|items| items.iter().all(is_resource))
+}
+
+fn is_resource(value: &Value) -> bool {
+    value.as_object().is_some_and(is_resource_object)
+}
+
+fn is_resource_object(object: &Map<String, Value>) -> bool {
+    object.get("id").is_some_and(|id| id.is_number() || id.is_string())
+}
+
+// Resource ids are always strings
+fn id_string(id: Value) -> String {
+    match id {
+        Value::String(id) => id,
+        other => other.to_string(),
+    }
+}
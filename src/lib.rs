@@ -0,0 +1,67 @@
+// The API as a library: main.rs runs the server, and tests can build the router, call handlers or
+// drive a connection directly
+mod access_log;
+mod audit;
+pub mod auth;
+pub mod build_info;
+mod circuit_breaker;
+pub mod cli;
+mod compression;
+pub mod config;
+mod context;
+mod cors;
+mod csv;
+mod dashboard;
+pub mod db;
+pub mod dotenv;
+mod docs;
+pub mod error;
+mod error_reporting;
+mod etag;
+mod events;
+mod export;
+mod forwarded;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod grpc;
+mod hal;
+pub mod handlers;
+pub mod http;
+mod idempotency;
+pub mod json_schema;
+mod jsonapi;
+mod load_shed;
+pub mod logging;
+pub mod metrics;
+mod middleware;
+mod migrations;
+pub mod models;
+mod msgpack;
+mod multipart;
+mod openapi;
+mod outbound;
+mod query;
+mod ratelimit;
+#[cfg(feature = "redis")]
+mod redis;
+pub mod repository;
+mod representation;
+mod request_id;
+mod retry;
+pub mod router;
+pub mod seed;
+pub mod server;
+mod state;
+mod static_files;
+mod tenancy;
+mod tls;
+mod toml;
+pub mod validation;
+mod versioning;
+mod webhooks;
+mod websocket;
+mod xml;
+
+#[macro_use]
+extern crate serde_derive;
@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::config::LoadSheddingConfig;
+use crate::http::{Request, Response};
+use crate::metrics::Metrics;
+use crate::versioning;
+
+// How long a shed request is told to wait before trying again
+const RETRY_AFTER_SECS: u64 = 1;
+
+// Paths never shed, so liveness probes and scrapes still get through to an overloaded server
+const EXEMPT_PATHS: [&str; 2] = ["/healthz", "/metrics"];
+
+// Hands out turns to handle a request: `max_concurrent` at a time, with up to `max_queued`
+// requests waiting for one. Requests beyond those are shed at once, rather than left to wait on
+// a server that is already behind
+pub struct LoadShedder {
+    turns: Semaphore,
+    max_queued: usize,
+    queued: AtomicUsize,
+    metrics: Arc<Metrics>,
+}
+
+// A request waiting for a turn; leaves the queue when dropped
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadShedder {
+    pub fn new(config: &LoadSheddingConfig, metrics: Arc<Metrics>) -> LoadShedder {
+        LoadShedder {
+            turns: Semaphore::new(config.max_concurrent),
+            max_queued: config.max_queued,
+            queued: AtomicUsize::new(0),
+            metrics,
+        }
+    }
+
+    // A turn for the request, held until it is answered, once one is free; the 503 to answer with
+    // when all are taken and the queue is full. Exempt paths don't take a turn
+    pub async fn admit(&self, request: &Request) -> Result<Option<SemaphorePermit<'_>>, Response> {
+        if matches!(request.method.as_str(), "GET" | "HEAD") && EXEMPT_PATHS.contains(&versioning::unversioned(&request.path)) {
+            return Ok(None);
+        }
+        if let Ok(turn) = self.turns.try_acquire() {
+            return Ok(Some(turn));
+        }
+        let position = self.queued.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.queued);
+        if position >= self.max_queued {
+            self.metrics.request_shed();
+            return Err(Response::error_with_details(
+                503,
+                "overloaded",
+                "The server is overloaded; try again shortly",
+                serde_json::json!({ "retry_after": RETRY_AFTER_SECS }),
+            )
+            .with_header("Retry-After", RETRY_AFTER_SECS.to_string()));
+        }
+        let _queued = self.metrics.request_queued();
+        // The semaphore is never closed
+        Ok(self.turns.acquire().await.ok())
+    }
+}
@@ -0,0 +1,17 @@
+use std::io::{self, IsTerminal};
+
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LogFormat;
+
+// Send log events to stdout in the configured format, filtered by RUST_LOG or else the configured level
+pub fn init(format: LogFormat, level: &str) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    // Colors only for a terminal; they would garble files and log collectors
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(io::stdout().is_terminal());
+    match format {
+        LogFormat::Text => builder.init(),
+        // The request span's fields (request id, method, path, ...) go on every line logged in it
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).init(),
+    }
+}
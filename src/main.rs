@@ -1,197 +1,179 @@
-use postgres::{Client, NoTls};
-use postgres::Error as PostgresError;
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
-use std::env;
-// use serde::{Serialize, Deserialize};
-
-#[macro_use]
-extern crate serde_derive;
-
-// Model: User struct with id, name, email
-#[derive(Serialize, Deserialize)]
-struct User {
-    id: Option<i32>,
-    name: String,
-    email: String,
-}
-
-// Constants
-const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n";
-const NOT_FOUND: &str = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
-const INTERNAL_SERVER_ERROR: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n";
+use rust_crud_api::cli::{self, Command};
+use rust_crud_api::config::Config;
+use rust_crud_api::models::Role;
+use rust_crud_api::seed::SeedPlan;
+use rust_crud_api::{auth, build_info, dotenv, logging, repository, seed, server, validation};
+use tracing::{error, info};
+use std::io;
+use std::sync::Arc;
 
 // Main function
 fn main() {
-    // Get the database URL
-    let db_url = get_db_url();
-
-    // Set up the database
-    if let Err(e) = set_database(&db_url) {
-        println!("Error setting up database: {}", e);
-        return;
+    let cli = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            println!("{}\nRun rust-crud-api --help for usage", e);
+            std::process::exit(2);
+        }
+    };
+    match cli.command {
+        Command::Help => return println!("{}", cli::USAGE),
+        Command::Version => {
+            let build = build_info::current();
+            return println!("rust-crud-api {} ({})", build.version, build.commit);
+        }
+        _ => {}
     }
 
-    // Start server
-    let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
-    println!("Server started at port 8080");
-
-    // Handle client connections
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                handle_client(stream, &db_url);
-            }
-            Err(e) => {
-                println!("Error handling client: {}", e);
-            }
+    // Local development settings, read before anything looks at the environment
+    if cli.dotenv && dotenv::enabled() {
+        if let Err(e) = dotenv::load(dotenv::FILE) {
+            println!("{}", e);
+            std::process::exit(2);
         }
     }
-}
-
-// Handle client request
-fn handle_client(mut stream: TcpStream, db_url: &str) {
-    let mut buffer = [0; 1024];
-    let mut request = String::new();
-    match stream.read(&mut buffer) {
-        Ok(size) => {
-            request.push_str(String::from_utf8_lossy(&buffer[..size]).as_ref());
-
-            let (status_line, content) = match &*request {
-                r if request.starts_with("POST /users") => handle_post_request(r, db_url),
-                r if request.starts_with("GET /users") => handle_get_request(r, db_url),
-                r if request.starts_with("GET /users/all") => handle_get_all_requests(r, db_url),
-                r if request.starts_with("PUT /users") => handle_put_request(r, db_url),
-                r if request.starts_with("DELETE /users") => handle_delete_request(r, db_url),
-                _ => (NOT_FOUND.to_string(), "Not found".to_string()),
-            };
-            stream.write_all(format!("{}{}", status_line, content).as_bytes()).unwrap();
+    let config = match Config::load(cli.config_file.as_deref()) {
+        Ok(config) => config,
+        Err(errors) => {
+            println!("Invalid configuration:");
+            for error in errors {
+                println!("  {}", error);
+            }
+            std::process::exit(2);
         }
-        Err(e) => {
-            println!("Error reading from stream: {}", e);
+    };
+    if let Command::CheckConfig = cli.command {
+        match &config.file {
+            Some(file) => println!("Configuration in {} and the environment is valid", file),
+            None => println!("Configuration in the environment is valid"),
         }
+        return;
     }
-}
-
-// Controllers for HTTP requests
-
-fn handle_post_request(request: &str, db_url: &str) -> (String, String) {
-    match (get_user_request_body(&request), Client::connect(db_url, NoTls)) {
-        (Ok(user), Ok(mut client)) => {
-            client
-                .execute(
-                    "INSERT INTO users (name, email) VALUES ($1,$2)",
-                    &[&user.name, &user.email],
-                )
-                .unwrap();
-            (OK_RESPONSE.to_string(), "User created".to_string())
-        }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error occurred".to_string()),
+    logging::init(config.log_format, &config.log_level);
+
+    // Build the async runtime with the configured number of worker threads
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .enable_all()
+        .build()
+        .expect("Failed to build the tokio runtime");
+
+    match cli.command {
+        Command::Migrate => runtime.block_on(migrate(&config)),
+        Command::Seed(plan) => runtime.block_on(seed(&config, plan)),
+        Command::SetPassword { id } => runtime.block_on(set_password(&config, id)),
+        Command::SetRole { id, role } => runtime.block_on(set_role(&config, id, role)),
+        _ => runtime.block_on(server::run(Arc::new(config))),
     }
 }
 
-fn handle_get_request(request: &str, db_url: &str) -> (String, String) {
-    match (get_id(&request).parse::<i32>(), Client::connect(db_url, NoTls)) {
-        (Ok(id), Ok(mut client)) => match client.query_one("SELECT * FROM users WHERE id = $1", &[&id]) {
-            Ok(row) => {
-                let user = User {
-                    id: row.get(0),
-                    name: row.get(1),
-                    email: row.get(2),
-                };
-                (OK_RESPONSE.to_string(), serde_json::to_string(&user).unwrap())
-            }
-            Err(e) => {
-                // Log the error if the user is not found or any other query error occurs
-                println!("Database query error: {}", e);
-                (NOT_FOUND.to_string(), "User not found".to_string())
-            }
-        },
-        (Err(e), _) => {
-            // Handle the case where parsing the ID fails
-            println!("Error parsing ID: {}", e);
-            (INTERNAL_SERVER_ERROR.to_string(), "Invalid ID format".to_string())
+// Apply pending database migrations without starting the server
+async fn migrate(config: &Config) {
+    match repository::connect(config).await {
+        Ok(stores) => {
+            stores.users.close();
+            info!("Database is up to date");
         }
-        (_, Err(e)) => {
-            // Handle database connection failure
-            println!("Database connection error: {}", e);
-            (INTERNAL_SERVER_ERROR.to_string(), "Database connection error".to_string())
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
         }
     }
 }
 
-fn handle_get_all_requests(request: &str, db_url: &str) -> (String, String) {
-    match Client::connect(db_url, NoTls) {
-        Ok(mut client) => {
-            let mut users = Vec::new();
-            for row in client.query("SELECT * FROM users", &[]).unwrap() {
-                users.push(User {
-                    id: row.get(0),
-                    name: row.get(1),
-                    email: row.get(2),
-                });
+// Insert generated users, posts and groups for demos and load tests
+async fn seed(config: &Config, plan: SeedPlan) {
+    let stores = match repository::connect(config).await {
+        Ok(stores) => stores,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = seed::run(&stores, &plan).await;
+    stores.users.close();
+    match result {
+        Ok(report) => {
+            info!(
+                "Created {} users ({} already existed), {} posts, {} groups ({} already existed) and {} memberships",
+                report.users_created,
+                report.users_skipped,
+                report.posts_created,
+                report.groups_created,
+                report.groups_skipped,
+                report.memberships_added
+            );
+            if let Some(seed) = report.seed {
+                info!("Generated with --seed {}", seed);
             }
-            (OK_RESPONSE.to_string(), serde_json::to_string(&users).unwrap())
         }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error occurred".to_string()),
+        Err(e) => {
+            error!("Error seeding users: {}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn handle_put_request(request: &str, db_url: &str) -> (String, String) {
-    match (
-        get_id(&request).parse::<i32>(),
-        get_user_request_body(&request),
-        Client::connect(db_url, NoTls),
-    ) {
-        (Ok(id), Ok(user), Ok(mut client)) => {
-            client
-                .execute("UPDATE users SET name = $1, email = $2 WHERE id = $3", &[&user.name, &user.email, &id])
-                .unwrap();
-            (OK_RESPONSE.to_string(), "User updated".to_string())
-        }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error occurred".to_string()),
+// Set a user's password from the first line of stdin, which keeps it out of the shell history.
+// This is how the first user gets a password, since the API only lets logged-in users set them
+async fn set_password(config: &Config, id: i32) {
+    let mut password = String::new();
+    if let Err(e) = io::stdin().read_line(&mut password) {
+        println!("Error reading the password: {}", e);
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if let Err(response) = validation::validate_password(password) {
+        println!("Invalid password: {}", String::from_utf8_lossy(&response.body));
+        std::process::exit(1);
     }
-}
 
-fn handle_delete_request(request: &str, db_url: &str) -> (String, String) {
-    match (get_id(&request).parse::<i32>(), Client::connect(db_url, NoTls)) {
-        (Ok(id), Ok(mut client)) => {
-            let rows_affected = client.execute("DELETE FROM users WHERE id = $1", &[&id]).unwrap();
-            if rows_affected == 0 {
-                return (NOT_FOUND.to_string(), "User not found".to_string());
-            }
-            (OK_RESPONSE.to_string(), "User deleted".to_string())
+    let stores = match repository::connect(config).await {
+        Ok(stores) => stores,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = match auth::hash_password(password) {
+        Ok(hash) => stores.users.set_password(id, &hash).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    stores.users.close();
+    match result {
+        Ok(true) => println!("Password set for user {}", id),
+        Ok(false) => {
+            println!("No user with id {}", id);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
         }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error occurred".to_string()),
     }
 }
 
-// Set up the database (initialize if needed)
-fn set_database(db_url: &str) -> Result<(), PostgresError> {
-    let mut client = Client::connect(db_url, NoTls)?;
-
-    client.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id SERIAL PRIMARY KEY,
-            name VARCHAR NOT NULL,
-            email VARCHAR NOT NULL
-        )",
-        &[],
-    )?;
-    Ok(())
-}
-
-// Get ID from request URL
-fn get_id(request: &str) -> &str {
-    request.split("/").nth(2).unwrap_or_default()
-}
-
-// Deserialize the user from the request body
-fn get_user_request_body(request: &str) -> Result<User, serde_json::Error> {
-    serde_json::from_str(request.split("\r\n\r\n").last().unwrap_or_default())
-}
-
-// Retrieve the database URL from the environment
-fn get_db_url() -> String {
-    env::var("DATABASE_URL").expect("DATABASE_URL environment variable not set")
+// Set a user's role. Only admins can change roles through the API, so this is how the first admin
+// is made
+async fn set_role(config: &Config, id: i32, role: Role) {
+    let stores = match repository::connect(config).await {
+        Ok(stores) => stores,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = stores.users.set_role(id, role).await;
+    stores.users.close();
+    match result {
+        Ok(true) => println!("User {} is now {}", id, role.as_str()),
+        Ok(false) => {
+            println!("No user with id {}", id);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::repository::{CacheStatus, PoolStatus};
+
+// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Methods reported by name; anything else is counted as OTHER so clients can't invent new series
+const KNOWN_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+// Route label for requests that matched no route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Counters and histograms served at GET /metrics in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    connections_open: AtomicU64,
+    connections_total: AtomicU64,
+    // Keyed by method and route pattern
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+    // Responses with a 5xx status
+    server_errors: AtomicU64,
+    // Requests that couldn't be read or parsed
+    malformed_requests: AtomicU64,
+    // Connections that failed mid-request or during the TLS handshake
+    connection_errors: AtomicU64,
+    // Requests waiting for a turn to be handled, and those refused because none was coming
+    requests_queued: AtomicU64,
+    requests_shed: AtomicU64,
+}
+
+#[derive(Default)]
+struct RouteStats {
+    // Responses by status code
+    statuses: BTreeMap<u16, u64>,
+    // Requests at or under each bucket's bound; the count is the +Inf bucket
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    seconds: f64,
+}
+
+// Counts a connection as open until dropped
+pub struct OpenConnection<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.metrics.connections_open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Counts a request as queued until dropped
+pub struct QueuedRequest<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        self.metrics.requests_queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn connection_opened(&self) -> OpenConnection<'_> {
+        self.connections_open.fetch_add(1, Ordering::Relaxed);
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+        OpenConnection { metrics: self }
+    }
+
+    // Record an answered request; `route` is the matched pattern, like `/users/{id}`
+    pub fn observe(&self, method: &str, route: Option<&str>, status: u16, elapsed: Duration) {
+        let method = KNOWN_METHODS.iter().find(|known| **known == method).copied().unwrap_or("OTHER");
+        let route = route.unwrap_or(UNMATCHED_ROUTE);
+        let seconds = elapsed.as_secs_f64();
+        {
+            let mut routes = self.routes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let stats = routes.entry((method.to_string(), route.to_string())).or_default();
+            *stats.statuses.entry(status).or_default() += 1;
+            for (bucket, bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                if seconds <= bound {
+                    *bucket += 1;
+                }
+            }
+            stats.count += 1;
+            stats.seconds += seconds;
+        }
+        if status >= 500 {
+            self.server_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn request_queued(&self) -> QueuedRequest<'_> {
+        self.requests_queued.fetch_add(1, Ordering::Relaxed);
+        QueuedRequest { metrics: self }
+    }
+
+    pub fn request_shed(&self) {
+        self.requests_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn malformed_request(&self) {
+        self.malformed_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_error(&self) {
+        self.connection_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Everything in the Prometheus text exposition format (version 0.0.4)
+    pub fn render(&self, pool: Option<PoolStatus>, cache: Option<CacheStatus>) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "http_requests_total", "counter", "Requests answered, by method, route and status");
+        let routes = self.routes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for ((method, route), stats) in routes.iter() {
+            for (status, count) in &stats.statuses {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                    method,
+                    escape(route),
+                    status,
+                    count
+                );
+            }
+        }
+
+        header(&mut out, "http_request_duration_seconds", "histogram", "Time from reading a request to writing its response");
+        for ((method, route), stats) in routes.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+            for (count, bound) in stats.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+            }
+            let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, stats.count);
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{}}} {}", labels, stats.seconds);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{}}} {}", labels, stats.count);
+        }
+        drop(routes);
+
+        header(&mut out, "http_errors_total", "counter", "Failed requests and connections, by kind");
+        let errors = [
+            ("server", &self.server_errors),
+            ("malformed_request", &self.malformed_requests),
+            ("connection", &self.connection_errors),
+        ];
+        for (kind, count) in errors {
+            let _ = writeln!(out, "http_errors_total{{kind=\"{}\"}} {}", kind, count.load(Ordering::Relaxed));
+        }
+
+        header(&mut out, "http_connections_open", "gauge", "Client connections currently open");
+        let _ = writeln!(out, "http_connections_open {}", self.connections_open.load(Ordering::Relaxed));
+        header(&mut out, "http_connections_total", "counter", "Client connections accepted");
+        let _ = writeln!(out, "http_connections_total {}", self.connections_total.load(Ordering::Relaxed));
+        header(&mut out, "http_requests_queued", "gauge", "Requests waiting for a turn to be handled");
+        let _ = writeln!(out, "http_requests_queued {}", self.requests_queued.load(Ordering::Relaxed));
+        header(&mut out, "http_requests_shed_total", "counter", "Requests refused with 503 because the server was overloaded");
+        let _ = writeln!(out, "http_requests_shed_total {}", self.requests_shed.load(Ordering::Relaxed));
+
+        if let Some(pool) = pool {
+            header(&mut out, "db_pool_connections", "gauge", "Database connections in the pool, by state");
+            let _ = writeln!(out, "db_pool_connections{{state=\"idle\"}} {}", pool.available);
+            let _ = writeln!(out, "db_pool_connections{{state=\"in_use\"}} {}", pool.size.saturating_sub(pool.available));
+            header(&mut out, "db_pool_max_connections", "gauge", "Most connections the pool will open");
+            let _ = writeln!(out, "db_pool_max_connections {}", pool.max_size);
+            header(&mut out, "db_pool_waiting", "gauge", "Requests waiting for a database connection");
+            let _ = writeln!(out, "db_pool_waiting {}", pool.waiting);
+        }
+        if let Some(cache) = cache {
+            header(&mut out, "user_cache_requests_total", "counter", "Users looked up by id in the in-memory cache, by result");
+            let _ = writeln!(out, "user_cache_requests_total{{result=\"hit\"}} {}", cache.hits);
+            let _ = writeln!(out, "user_cache_requests_total{{result=\"miss\"}} {}", cache.misses);
+            header(&mut out, "user_cache_entries", "gauge", "Users in the in-memory cache");
+            let _ = writeln!(out, "user_cache_entries {}", cache.size);
+            header(&mut out, "user_cache_capacity", "gauge", "Most users the in-memory cache holds");
+            let _ = writeln!(out, "user_cache_capacity {}", cache.capacity);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Escape a label value: backslashes, quotes and newlines
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,332 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use crate::config::TrailingSlash;
+use crate::context::Context;
+use crate::error_reporting::{ErrorEvent, ErrorReporter};
+use crate::http::{Request, Response};
+use crate::jsonapi::Location;
+use crate::load_shed::LoadShedder;
+use crate::ratelimit::RateLimiter;
+use crate::representation::Format;
+use crate::router::Router;
+use crate::state::AppState;
+use crate::{audit, auth, compression, cors, etag, idempotency, representation, request_id, tenancy, versioning};
+
+// A request on its way through the pipeline, with what the layers it has passed worked out about it
+pub struct Call {
+    pub request: Request,
+    // The address it came from, behind trusted proxies the client they forwarded for
+    pub client: IpAddr,
+    // What handlers share, with the stores of the request's tenant once tenancy has picked them
+    pub state: AppState,
+    // Who is making the request, once it has been authorized; None for anonymous reads
+    pub actor: Option<String>,
+}
+
+// Something done around every request: a layer gets the call and the rest of the pipeline, and
+// answers either by running the rest, with whatever it does before and after, or by itself
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response;
+}
+
+// The rest of the pipeline after a layer: the layers inside it, then the router
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    layers: &'a [Box<dyn Middleware>],
+    router: &'a Router<AppState>,
+}
+
+impl<'a> Next<'a> {
+    // The routes the pipeline ends in, for layers that depend on which one a request matches
+    pub fn router(&self) -> &'a Router<AppState> {
+        self.router
+    }
+
+    pub async fn run(self, call: Call) -> Response {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(call, Next { layers, router: self.router }).await,
+            None => self.router.dispatch(call.request, call.state).await,
+        }
+    }
+}
+
+// The layers every request passes through on its way to the router, outermost first
+pub struct Pipeline {
+    layers: Vec<Box<dyn Middleware>>,
+    router: Arc<Router<AppState>>,
+    state: AppState,
+}
+
+impl Pipeline {
+    // Start a pipeline ending in `router`, with the state each request starts out with
+    pub fn builder(router: Arc<Router<AppState>>, state: AppState) -> PipelineBuilder {
+        PipelineBuilder { pipeline: Pipeline { layers: Vec::new(), router, state } }
+    }
+
+    // The routes requests end up at, for what is worked out about a request before it enters
+    pub fn router(&self) -> &Router<AppState> {
+        &self.router
+    }
+
+    // What every request starts out with
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    pub async fn handle(&self, request: Request, client: IpAddr) -> Response {
+        let call = Call { request, client, state: self.state.clone(), actor: None };
+        Next { layers: &self.layers, router: &self.router }.run(call).await
+    }
+}
+
+pub struct PipelineBuilder {
+    pipeline: Pipeline,
+}
+
+impl PipelineBuilder {
+    // Add a layer inside those added before it
+    pub fn layer(mut self, middleware: impl Middleware + 'static) -> PipelineBuilder {
+        self.pipeline.layers.push(Box::new(middleware));
+        self
+    }
+
+    // Add a layer when it is configured, e.g. the rate limiter when there is a limit
+    pub fn optional_layer(self, middleware: Option<impl Middleware + 'static>) -> PipelineBuilder {
+        match middleware {
+            Some(middleware) => self.layer(middleware),
+            None => self,
+        }
+    }
+
+    pub fn build(self) -> Pipeline {
+        self.pipeline
+    }
+}
+
+// Tell the error reporter about every 5xx, with the request's id and route and what went wrong
+pub struct ErrorReporting(pub Arc<dyn ErrorReporter>);
+
+#[async_trait]
+impl Middleware for ErrorReporting {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let (method, path) = (call.request.method.clone(), call.request.path.clone());
+        let request_id = call.request.header(request_id::HEADER).map(str::to_string);
+        let response = next.run(call).await;
+        if response.status >= 500 {
+            self.0.report(ErrorEvent {
+                request_id,
+                route: next.router().pattern(&method, &path).map(str::to_string),
+                method,
+                path,
+                status: response.status,
+                cause: response.cause.clone(),
+            });
+        }
+        response
+    }
+}
+
+// Compress the response body in an encoding the client accepts
+pub struct Compression;
+
+#[async_trait]
+impl Middleware for Compression {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let accept_encoding = call.request.header("accept-encoding").map(str::to_string);
+        compression::apply(next.run(call).await, accept_encoding.as_deref())
+    }
+}
+
+// Write the response's JSON in the format the client asked for, or the configured one
+pub struct Representation;
+
+#[async_trait]
+impl Middleware for Representation {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let format = representation::choose(call.request.header("accept"), call.state.config.response_format);
+        // JSON:API links point back at the path and query, which the handler takes
+        let location = (format == Format::JsonApi).then(|| Location::of(&call.request));
+        representation::apply(next.run(call).await, format, location.as_ref())
+    }
+}
+
+// Answer If-None-Match and If-Modified-Since with a 304 when the response hasn't changed
+pub struct ConditionalRequests;
+
+#[async_trait]
+impl Middleware for ConditionalRequests {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        match etag::Conditional::from_request(&call.request) {
+            Some(conditional) => conditional.apply(next.run(call).await),
+            None => next.run(call).await,
+        }
+    }
+}
+
+// Tag JSON responses that have no entity tag of their own by their body
+pub struct EntityTags;
+
+#[async_trait]
+impl Middleware for EntityTags {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        etag::tag_body(next.run(call).await)
+    }
+}
+
+// Answer preflight requests, and let the allowed origins read the responses to the rest
+pub struct Cors;
+
+#[async_trait]
+impl Middleware for Cors {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let config = Arc::clone(&call.state.config);
+        let Some(cors) = &config.cors else {
+            return next.run(call).await;
+        };
+        if let Some(response) = cors::preflight(cors, &call.request) {
+            return response;
+        }
+        let origin = call.request.header("origin").map(str::to_string);
+        cors::apply_headers(cors, origin.as_deref(), next.run(call).await)
+    }
+}
+
+// With TRAILING_SLASH=redirect, a path a route would match without its trailing slash is sent
+// there with a 308, which keeps the method and body; its query string goes along
+pub struct TrailingSlashRedirect;
+
+#[async_trait]
+impl Middleware for TrailingSlashRedirect {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let request = &call.request;
+        if call.state.config.trailing_slash == TrailingSlash::Redirect && request.path != "/" {
+            if let Some(path) = request.path.strip_suffix('/').filter(|path| next.router().recognizes(path)) {
+                return Response::new(308).with_header("Location", request.query.link(path, &[]));
+            }
+        }
+        next.run(call).await
+    }
+}
+
+// Wait for a turn to handle the request, unless the server is too far behind to take it
+pub struct LoadShedding(pub Arc<LoadShedder>);
+
+#[async_trait]
+impl Middleware for LoadShedding {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let _turn = match self.0.admit(&call.request).await {
+            Ok(turn) => turn,
+            Err(response) => return response,
+        };
+        next.run(call).await
+    }
+}
+
+// Run the rest of the request in its context: GET and HEAD requests are read from a replica, when
+// there are any, while everything a mutation does, including its reads, is on the primary. The
+// route's budget starts here, so time spent waiting for a turn doesn't count against it
+pub struct RouteBudgets;
+
+#[async_trait]
+impl Middleware for RouteBudgets {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let request = &call.request;
+        let budget = next
+            .router()
+            .pattern(&request.method, &request.path)
+            .and_then(|pattern| call.state.config.route_timeout(&request.method, versioning::unversioned(pattern)));
+        let context = Context {
+            read_only: matches!(request.method.as_str(), "GET" | "HEAD"),
+            deadline: budget.map(|budget| Instant::now() + budget),
+        };
+        context.scope(next.run(call)).await
+    }
+}
+
+// Count the request against its client's rate limit; whatever answers it, the response tells the
+// client how much of the limit is left
+pub struct RateLimit(pub Arc<RateLimiter>);
+
+#[async_trait]
+impl Middleware for RateLimit {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let quota = self.0.acquire(call.client);
+        if let Some(rejection) = quota.rejection() {
+            return rejection;
+        }
+        quota.apply_headers(next.run(call).await)
+    }
+}
+
+// Handle the request with its tenant's stores
+pub struct Tenancy;
+
+#[async_trait]
+impl Middleware for Tenancy {
+    async fn handle(&self, mut call: Call, next: Next<'_>) -> Response {
+        call.state.stores = match tenancy::stores_for(&call.state.config, &call.state.stores, &call.request).await {
+            Ok(stores) => stores,
+            Err(response) => return response,
+        };
+        next.run(call).await
+    }
+}
+
+// Check the credentials of anything that writes, recording who the caller is
+pub struct Authorization;
+
+#[async_trait]
+impl Middleware for Authorization {
+    async fn handle(&self, mut call: Call, next: Next<'_>) -> Response {
+        if auth::requires_token(&call.request) {
+            call.actor = match auth::authorize(&call.state.config.jwt_secret, &call.state.stores, &call.request).await {
+                Ok(caller) => Some(caller.actor()),
+                Err(response) => return response,
+            };
+        }
+        next.run(call).await
+    }
+}
+
+// A request repeating an Idempotency-Key gets the stored response instead of running again
+pub struct Idempotency;
+
+#[async_trait]
+impl Middleware for Idempotency {
+    async fn handle(&self, call: Call, next: Next<'_>) -> Response {
+        let ttl = call.state.config.idempotency_ttl;
+        let claim = match idempotency::begin(&call.state.stores, &call.request, call.actor.as_deref(), ttl).await {
+            Ok(claim) => claim,
+            Err(response) => return response,
+        };
+        let Some(claim) = claim else {
+            return next.run(call).await;
+        };
+        let stores = call.state.stores.clone();
+        let response = next.run(call).await;
+        claim.finish(&stores, &response).await;
+        response
+    }
+}
+
+// Mutations that get past authorization are recorded in the audit log, whatever their outcome
+pub struct Audit;
+
+#[async_trait]
+impl Middleware for Audit {
+    async fn handle(&self, mut call: Call, next: Next<'_>) -> Response {
+        if !audit::is_audited(&call.request) {
+            return next.run(call).await;
+        }
+        let pending = audit::begin(&call.state.stores, &call.request, call.actor.take()).await;
+        let stores = call.state.stores.clone();
+        let response = next.run(call).await;
+        pending.finish(&stores, &response).await;
+        response
+    }
+}
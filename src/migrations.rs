@@ -0,0 +1,323 @@
+use deadpool_postgres::{Client, Pool, PoolError};
+
+// A schema change; versions are applied in ascending order and never edited once released
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// Every Postgres migration, embedded from the migrations/ directory at build time
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../migrations/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "unique_user_email",
+        sql: include_str!("../migrations/0002_unique_user_email.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "soft_delete_users",
+        sql: include_str!("../migrations/0003_soft_delete_users.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "add_user_version",
+        sql: include_str!("../migrations/0004_add_user_version.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "add_user_created_at",
+        sql: include_str!("../migrations/0005_add_user_created_at.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "user_search_indexes",
+        sql: include_str!("../migrations/0006_user_search_indexes.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_posts",
+        sql: include_str!("../migrations/0007_create_posts.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "create_groups",
+        sql: include_str!("../migrations/0008_create_groups.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "add_user_password",
+        sql: include_str!("../migrations/0009_add_user_password.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "create_api_keys",
+        sql: include_str!("../migrations/0010_create_api_keys.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "add_roles",
+        sql: include_str!("../migrations/0011_add_roles.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "create_tokens",
+        sql: include_str!("../migrations/0012_create_tokens.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "create_audit_log",
+        sql: include_str!("../migrations/0013_create_audit_log.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "add_user_updated_at",
+        sql: include_str!("../migrations/0014_add_user_updated_at.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "create_idempotency_keys",
+        sql: include_str!("../migrations/0015_create_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "create_webhooks",
+        sql: include_str!("../migrations/0016_create_webhooks.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "notify_user_changes",
+        sql: include_str!("../migrations/0017_notify_user_changes.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "notify_user_change_schema",
+        sql: include_str!("../migrations/0018_notify_user_change_schema.sql"),
+    },
+];
+
+// The same schema history in SQLite's dialect, tracked with PRAGMA user_version
+#[cfg(feature = "sqlite")]
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../migrations/sqlite/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "soft_delete_users",
+        sql: include_str!("../migrations/sqlite/0002_soft_delete_users.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "add_user_version",
+        sql: include_str!("../migrations/sqlite/0003_add_user_version.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "add_user_created_at",
+        sql: include_str!("../migrations/sqlite/0004_add_user_created_at.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "create_posts",
+        sql: include_str!("../migrations/sqlite/0005_create_posts.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_groups",
+        sql: include_str!("../migrations/sqlite/0006_create_groups.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "add_user_password",
+        sql: include_str!("../migrations/sqlite/0007_add_user_password.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "create_api_keys",
+        sql: include_str!("../migrations/sqlite/0008_create_api_keys.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "add_roles",
+        sql: include_str!("../migrations/sqlite/0009_add_roles.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "create_tokens",
+        sql: include_str!("../migrations/sqlite/0010_create_tokens.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "create_audit_log",
+        sql: include_str!("../migrations/sqlite/0011_create_audit_log.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "add_user_updated_at",
+        sql: include_str!("../migrations/sqlite/0012_add_user_updated_at.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "create_idempotency_keys",
+        sql: include_str!("../migrations/sqlite/0013_create_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "create_webhooks",
+        sql: include_str!("../migrations/sqlite/0014_create_webhooks.sql"),
+    },
+];
+
+// The same schema history in MySQL's dialect; each file holds a single statement
+#[cfg(feature = "mysql")]
+pub const MYSQL_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../migrations/mysql/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "soft_delete_users",
+        sql: include_str!("../migrations/mysql/0002_soft_delete_users.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "add_user_version",
+        sql: include_str!("../migrations/mysql/0003_add_user_version.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "add_user_created_at",
+        sql: include_str!("../migrations/mysql/0004_add_user_created_at.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "create_posts",
+        sql: include_str!("../migrations/mysql/0005_create_posts.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_groups",
+        sql: include_str!("../migrations/mysql/0006_create_groups.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_user_groups",
+        sql: include_str!("../migrations/mysql/0007_create_user_groups.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "add_user_password",
+        sql: include_str!("../migrations/mysql/0008_add_user_password.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "create_api_keys",
+        sql: include_str!("../migrations/mysql/0009_create_api_keys.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "add_user_role",
+        sql: include_str!("../migrations/mysql/0010_add_user_role.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "add_api_key_role",
+        sql: include_str!("../migrations/mysql/0011_add_api_key_role.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "create_refresh_tokens",
+        sql: include_str!("../migrations/mysql/0012_create_refresh_tokens.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "create_revoked_tokens",
+        sql: include_str!("../migrations/mysql/0013_create_revoked_tokens.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "create_audit_log",
+        sql: include_str!("../migrations/mysql/0014_create_audit_log.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "add_user_updated_at",
+        sql: include_str!("../migrations/mysql/0015_add_user_updated_at.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "backfill_user_updated_at",
+        sql: include_str!("../migrations/mysql/0016_backfill_user_updated_at.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "create_idempotency_keys",
+        sql: include_str!("../migrations/mysql/0017_create_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "create_webhooks",
+        sql: include_str!("../migrations/mysql/0018_create_webhooks.sql"),
+    },
+];
+
+// Advisory lock key so concurrent instances don't migrate at the same time
+const MIGRATION_LOCK_ID: i64 = 0x7275_7374_6372_7564;
+
+// Apply every pending migration, each in its own transaction; returns the ones applied
+pub async fn run(pool: &Pool) -> Result<Vec<&'static Migration>, PoolError> {
+    migrate(&mut pool.get().await?).await
+}
+
+// Bring the first schema on the client's search_path up to date, as `run` does; a tenant's schema
+// is migrated this way
+pub async fn migrate(client: &mut Client) -> Result<Vec<&'static Migration>, PoolError> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name VARCHAR NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        )
+        .await?;
+
+    client.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+    let result = apply_pending(client).await;
+    client.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_ID]).await?;
+    result
+}
+
+async fn apply_pending(client: &mut Client) -> Result<Vec<&'static Migration>, PoolError> {
+    let applied: Vec<i64> = client
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let mut newly_applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+        newly_applied.push(migration);
+    }
+    Ok(newly_applied)
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+
+// Model: User struct with id, name, email
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct User {
+    pub id: Option<i32>,
+    pub name: String,
+    pub email: String,
+    // Set when the user is inserted; never read from request bodies
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    // Set on insert and by every change, for Last-Modified; never read from request bodies
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    // Set once the user is soft-deleted; never read from request bodies
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Incremented by every change; exposed as the ETag for If-Match
+    #[serde(default, skip_deserializing)]
+    pub version: i32,
+}
+
+// Partial update for PATCH: only the fields present are changed
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+// A post written by a user
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Post {
+    pub id: i32,
+    pub user_id: i32,
+    pub title: String,
+    pub body: String,
+}
+
+// Body of a post create or replace. `user_id` is required by POST /posts; the nested
+// /users/{id}/posts routes take it from the URL. A post can't move to another user
+#[derive(Deserialize, JsonSchema)]
+pub struct PostInput {
+    pub user_id: Option<i32>,
+    pub title: String,
+    pub body: String,
+}
+
+// A named group of users
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Group {
+    pub id: i32,
+    pub name: String,
+}
+
+// Body of POST /groups
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GroupInput {
+    pub name: String,
+}
+
+// Body of POST /auth/login
+#[derive(Deserialize, JsonSchema)]
+pub struct Login {
+    pub email: String,
+    pub password: String,
+}
+
+// Body of POST /auth/refresh
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+// Body of PUT /users/{id}/password
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordChange {
+    pub password: String,
+}
+
+// What a caller may do: viewers only read, editors also write, and admins also delete users and
+// manage roles and API keys. Ordered from least to most privileged
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Viewer,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+// Body of PUT /users/{id}/role
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RoleChange {
+    pub role: Role,
+}
+
+// An API key as listed; the key itself is only shown once, when it is created
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// Body of POST /api-keys; the name only says what the key is for. Keys are editors by default
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyInput {
+    pub name: String,
+    #[serde(default = "editor")]
+    pub role: Role,
+}
+
+fn editor() -> Role {
+    Role::Editor
+}
+
+// A subscription to user events; the signing secret is only shown once, when it is created
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    // Event types delivered to the URL, e.g. `user.created`
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Body of POST /webhooks and PUT /webhooks/{id}. A secret is generated when none is given;
+// PUT keeps the current one unless a new one is given
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookInput {
+    pub url: String,
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+// A customer with a dataset of its own, picked by the X-Tenant-Id header
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Tenant {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Body of POST /tenants. The id is lowercase letters, digits and hyphens, starting with a letter
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TenantInput {
+    pub id: String,
+}
+
+// A recorded mutation request, as listed by GET /audit
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    // `user:<id>` or `api_key:<id>`; None for requests made without credentials
+    pub actor: Option<String>,
+    // The HTTP method
+    pub action: String,
+    pub path: String,
+    pub entity_id: Option<i32>,
+    pub status: u16,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+// A search result: the user plus how well it matched, from 0 to 1
+#[derive(Serialize, JsonSchema)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub user: User,
+    pub score: f64,
+}
+
+// Aggregates for GET /users/stats, computed by the store rather than from loaded rows
+#[derive(Serialize, JsonSchema)]
+pub struct UserStats {
+    pub total: i64,
+    pub active: i64,
+    pub deleted: i64,
+    // Live users created on each UTC day of the window, oldest first; empty days are omitted
+    pub created_per_day: Vec<DailyCount>,
+    pub distinct_email_domains: i64,
+    // The most common domains among live users, most common first
+    pub top_email_domains: Vec<DomainCount>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DailyCount {
+    pub date: NaiveDate,
+    pub count: i64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DomainCount {
+    pub domain: String,
+    pub count: i64,
+}
@@ -0,0 +1,103 @@
+use serde_json::{Number, Value};
+
+// Encode a JSON value as MessagePack, using the smallest format for each value
+pub fn from_json(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_value(value, &mut out);
+    out
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(number) => write_number(number, out),
+        Value::String(text) => {
+            let len = text.len();
+            match len {
+                0..=31 => out.push(0xa0 | len as u8),
+                32..=0xff => out.extend([0xd9, len as u8]),
+                0x100..=0xffff => {
+                    out.push(0xda);
+                    out.extend((len as u16).to_be_bytes());
+                }
+                _ => {
+                    out.push(0xdb);
+                    out.extend((len as u32).to_be_bytes());
+                }
+            }
+            out.extend(text.as_bytes());
+        }
+        Value::Array(items) => {
+            write_length(items.len(), 0x90, 0xdc, out);
+            for item in items {
+                write_value(item, out);
+            }
+        }
+        Value::Object(fields) => {
+            write_length(fields.len(), 0x80, 0xde, out);
+            for (key, value) in fields {
+                write_value(&Value::String(key.clone()), out);
+                write_value(value, out);
+            }
+        }
+    }
+}
+
+// The header of an array or map: a fix format for up to 15 entries, then 16 and 32-bit lengths
+fn write_length(len: usize, fix: u8, marker16: u8, out: &mut Vec<u8>) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => {
+            out.push(marker16);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(marker16 + 1);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn write_number(number: &Number, out: &mut Vec<u8>) {
+    if let Some(n) = number.as_u64() {
+        match n {
+            0..=0x7f => out.push(n as u8),
+            0x80..=0xff => out.extend([0xcc, n as u8]),
+            0x100..=0xffff => {
+                out.push(0xcd);
+                out.extend((n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(0xce);
+                out.extend((n as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(0xcf);
+                out.extend(n.to_be_bytes());
+            }
+        }
+    } else if let Some(n) = number.as_i64() {
+        // Only negative numbers get here
+        match n {
+            -32..=-1 => out.push(n as u8),
+            -0x80..=-33 => out.extend([0xd0, n as u8]),
+            -0x8000..=-0x81 => {
+                out.push(0xd1);
+                out.extend((n as i16).to_be_bytes());
+            }
+            -0x8000_0000..=-0x8001 => {
+                out.push(0xd2);
+                out.extend((n as i32).to_be_bytes());
+            }
+            _ => {
+                out.push(0xd3);
+                out.extend(n.to_be_bytes());
+            }
+        }
+    } else {
+        out.push(0xcb);
+        out.extend(number.as_f64().unwrap_or_default().to_be_bytes());
+    }
+}
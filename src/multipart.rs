@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+// One part of a multipart/form-data body
+pub struct Part {
+    // Field name from Content-Disposition
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub body: Vec<u8>,
+}
+
+// The boundary parameter of a multipart/form-data Content-Type, if that is the type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+// Split a multipart body (RFC 7578) into its parts
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut rest = match find(body, &delimiter) {
+        Some(start) => &body[start + delimiter.len()..],
+        None => return Err("missing opening boundary".to_string()),
+    };
+
+    // Every delimiter after the first is preceded by CRLF, which belongs to it rather than the part
+    let delimiter = [b"\r\n".as_slice(), &delimiter].concat();
+    let mut parts = Vec::new();
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest.strip_prefix(b"\r\n").ok_or("malformed boundary line")?;
+        let end = find(rest, &delimiter).ok_or("missing closing boundary")?;
+        parts.push(parse_part(&rest[..end])?);
+        rest = &rest[end + delimiter.len()..];
+    }
+}
+
+fn parse_part(part: &[u8]) -> Result<Part, String> {
+    let head_end = find(part, b"\r\n\r\n").ok_or("part has no header terminator")?;
+    let head = std::str::from_utf8(&part[..head_end]).map_err(|_| "part headers are not UTF-8")?;
+
+    let mut headers = HashMap::new();
+    for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or_else(|| format!("invalid part header: {}", line))?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let disposition = headers.get("content-disposition").map(String::as_str).unwrap_or_default();
+    Ok(Part {
+        name: disposition_param(disposition, "name"),
+        filename: disposition_param(disposition, "filename"),
+        body: part[head_end + 4..].to_vec(),
+    })
+}
+
+// A parameter of a Content-Disposition value such as `form-data; name="file"`
+fn disposition_param(disposition: &str, name: &str) -> Option<String> {
+    disposition.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
@@ -0,0 +1,373 @@
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::auth;
+use crate::build_info::BuildInfo;
+use crate::hal::Linked;
+use crate::http::ErrorEnvelope;
+use crate::models::{
+    ApiKeyInput, Group, GroupInput, Login, PasswordChange, Post, PostInput, RefreshRequest, RoleChange, Tenant, TenantInput, User, UserPatch,
+    UserStats, Webhook, WebhookInput,
+};
+use crate::router::Router;
+use crate::state::AppState;
+use crate::seed::{SeedPlan, SeedReport};
+use crate::versioning;
+use crate::handlers::api_keys::{ApiKeyList, CreatedApiKey};
+use crate::handlers::audit::AuditPage;
+use crate::handlers::groups::{GroupPage, MemberPage, UserGroups};
+use crate::handlers::health::Health;
+use crate::handlers::posts::PostPage;
+use crate::handlers::sessions::TokenResponse;
+use crate::handlers::tenants::{CreatedTenant, TenantList};
+use crate::handlers::users::{BatchResult, DeleteSummary, ImportReport, SearchPage, UserCount, UserPage};
+use crate::handlers::webhooks::{CreatedWebhook, WebhookList};
+
+pub const SPEC_PATH: &str = "/openapi.json";
+
+// A query parameter: name, JSON type and description
+type Param = (&'static str, &'static str, &'static str);
+
+const PAGE: &[Param] = &[
+    ("limit", "integer", "Most items to return"),
+    ("offset", "integer", "Items to skip"),
+];
+
+const USER_LIST: &[Param] = &[
+    ("limit", "integer", "Most users to return"),
+    ("offset", "integer", "Users to skip; cannot be combined with after"),
+    ("after", "string", "Cursor from a previous page's next_cursor; only when sorting by id"),
+    ("sort", "string", "Column to sort by: id, name or email"),
+    ("order", "string", "asc or desc"),
+    ("name", "string", "Only users with exactly this name"),
+    ("email_contains", "string", "Only users whose email contains this text"),
+    ("include_deleted", "boolean", "Include soft-deleted users"),
+];
+
+const INCLUDE_DELETED: &[Param] = &[("include_deleted", "boolean", "Return the user even if soft-deleted")];
+
+const INCLUDE: &[Param] = &[("include", "string", "Related records to embed: posts, with posts_total counting them all")];
+
+const FIELDS: &[Param] = &[("fields", "string", "Comma-separated user fields to return, like id,email; all of them by default")];
+
+const BATCH_DELETE: &[Param] = &[("ids", "string", "Comma-separated ids of the users to delete")];
+
+const STATS: &[Param] = &[("days", "integer", "Days of daily creation counts to return")];
+
+const SEARCH: &[Param] = &[
+    ("q", "string", "Text to search names and emails for"),
+    ("limit", "integer", "Most hits to return"),
+    ("offset", "integer", "Hits to skip"),
+];
+
+const POST_LIST: &[Param] = &[
+    ("user_id", "integer", "Only posts by this user"),
+    ("limit", "integer", "Most posts to return"),
+    ("offset", "integer", "Posts to skip"),
+];
+
+const AUDIT_LIST: &[Param] = &[
+    ("actor", "string", "Only entries by this actor, like user:1 or api_key:2"),
+    ("limit", "integer", "Most entries to return"),
+    ("offset", "integer", "Entries to skip"),
+];
+
+// One item of PATCH /users/batch: the user to change and the fields to set. Only described, never deserialized
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+struct UserPatchItem {
+    id: i32,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+// Request or response bodies by media type
+type Content = Vec<(&'static str, Value)>;
+
+const NO_BODY: Content = Vec::new();
+
+// What the document says about a route beyond its method and path
+struct Operation {
+    summary: &'static str,
+    query: Vec<Param>,
+    request: Option<Content>,
+    responses: Vec<(u16, &'static str, Content)>,
+}
+
+impl Operation {
+    fn new(summary: &'static str) -> Operation {
+        Operation {
+            summary,
+            query: Vec::new(),
+            request: None,
+            responses: Vec::new(),
+        }
+    }
+
+    fn query(mut self, query: &'static [Param]) -> Operation {
+        self.query.extend_from_slice(query);
+        self
+    }
+
+    fn request(mut self, content: Content) -> Operation {
+        self.request = Some(content);
+        self
+    }
+
+    fn respond(mut self, status: u16, description: &'static str, content: Content) -> Operation {
+        self.responses.push((status, description, content));
+        self
+    }
+}
+
+// The OpenAPI 3.0 document for every route registered on the router. Schemas come from the
+// types handlers read and write, so the document can't drift from what is served
+pub fn document(router: &Router<AppState>) -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let error = schema::<ErrorEnvelope>(&mut gen);
+
+    let mut paths = Map::new();
+    for (method, pattern) in router.routes().chain([("GET", SPEC_PATH)]) {
+        let operation = describe(method, pattern, &mut gen);
+        let item = paths.entry(pattern.to_string()).or_insert_with(|| json!({}));
+        item[method.to_ascii_lowercase()] = render(method, pattern, operation, &error);
+    }
+
+    // Apply the OpenAPI settings' fix-ups (like spelling out `true` schemas, which 3.0 lacks) to the definitions
+    let mut schemas = gen.take_definitions();
+    for visitor in gen.visitors_mut() {
+        for schema in schemas.values_mut() {
+            visitor.visit_schema(schema);
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        // Paths are written without the version prefix they are served under
+        "servers": [{ "url": versioning::V1 }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                "apiKey": { "type": "apiKey", "in": "header", "name": auth::API_KEY_HEADER },
+            },
+        },
+    })
+}
+
+fn render(method: &str, pattern: &str, operation: Operation, error: &Value) -> Value {
+    // Every path parameter is an id
+    let path_params = pattern.split('/').filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}')).map(|name| {
+        json!({ "name": name, "in": "path", "required": true, "schema": { "type": "integer", "format": "int32" } })
+    });
+    let query_params = operation.query.iter().map(|(name, kind, description)| {
+        json!({ "name": name, "in": "query", "required": false, "description": description, "schema": { "type": kind } })
+    });
+
+    let mut responses = Map::new();
+    for (status, description, content) in operation.responses {
+        let mut response = json!({ "description": description });
+        if !content.is_empty() {
+            response["content"] = content_object(content);
+        }
+        responses.insert(status.to_string(), response);
+    }
+    responses.insert(
+        "default".to_string(),
+        json!({ "description": "Error", "content": { "application/json": { "schema": error } } }),
+    );
+
+    let mut object = json!({
+        "summary": operation.summary,
+        "parameters": path_params.chain(query_params).collect::<Vec<_>>(),
+        "responses": responses,
+    });
+    if let Some(content) = operation.request {
+        object["requestBody"] = json!({ "required": true, "content": content_object(content) });
+    }
+    if auth::requires_credentials(method, pattern) {
+        object["security"] = json!([{ "bearerAuth": [] }, { "apiKey": [] }]);
+    }
+    object
+}
+
+fn content_object(content: Content) -> Value {
+    content.into_iter().map(|(media_type, schema)| (media_type.to_string(), json!({ "schema": schema }))).collect::<Map<_, _>>().into()
+}
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap_or_default()
+}
+
+fn json_body<T: JsonSchema>(gen: &mut SchemaGenerator) -> Content {
+    vec![("application/json", schema::<T>(gen))]
+}
+
+fn text_body(media_type: &'static str) -> Content {
+    vec![(media_type, json!({ "type": "string" }))]
+}
+
+fn describe(method: &str, pattern: &str, gen: &mut SchemaGenerator) -> Operation {
+    match (method, pattern) {
+        ("POST", "/auth/login") => Operation::new("Log in with an email and password")
+            .request(json_body::<Login>(gen))
+            .respond(200, "Access and refresh tokens", json_body::<TokenResponse>(gen)),
+        ("POST", "/auth/refresh") => Operation::new("Trade a refresh token for new tokens")
+            .request(json_body::<RefreshRequest>(gen))
+            .respond(200, "Access and refresh tokens", json_body::<TokenResponse>(gen)),
+        ("POST", "/auth/logout") => Operation::new("Revoke the bearer token's session").respond(204, "Logged out", NO_BODY),
+        ("POST", "/users") => Operation::new("Create a user")
+            .request(json_body::<User>(gen))
+            .respond(201, "The created user", json_body::<Linked<User>>(gen)),
+        ("POST", "/users/batch") => Operation::new("Create several users")
+            .request(json_body::<Vec<User>>(gen))
+            .respond(201, "Every user was created", json_body::<BatchResult>(gen))
+            .respond(207, "Some users were rejected", json_body::<BatchResult>(gen)),
+        ("POST", "/users/import") => Operation::new("Create users from a CSV file")
+            .request(vec![
+                (
+                    "multipart/form-data",
+                    json!({ "type": "object", "properties": { "file": { "type": "string", "format": "binary" } } }),
+                ),
+                ("text/csv", json!({ "type": "string" })),
+            ])
+            .respond(201, "Every row was imported", json_body::<ImportReport>(gen))
+            .respond(207, "Some rows were rejected", json_body::<ImportReport>(gen)),
+        ("PATCH", "/users/batch") => Operation::new("Update several users")
+            .request(json_body::<Vec<UserPatchItem>>(gen))
+            .respond(200, "Every user was updated", json_body::<BatchResult>(gen))
+            .respond(207, "Some updates were rejected", json_body::<BatchResult>(gen)),
+        ("GET", "/users") => Operation::new("List users")
+            .query(USER_LIST)
+            .query(FIELDS)
+            .respond(200, "A page of users", json_body::<UserPage>(gen)),
+        ("DELETE", "/users") => Operation::new("Delete several users")
+            .query(BATCH_DELETE)
+            .respond(200, "The users deleted and the ids not found", json_body::<DeleteSummary>(gen)),
+        ("GET", "/users/all") => Operation::new("Stream every matching user")
+            .query(USER_LIST)
+            .respond(200, "All matching users", json_body::<Vec<User>>(gen)),
+        ("GET", "/users/events") => Operation::new("Stream user changes as Server-Sent Events").respond(
+            200,
+            "`user.created`, `user.updated` and `user.deleted` events as they happen",
+            text_body("text/event-stream"),
+        ),
+        ("GET", "/users/count") => Operation::new("Count matching users")
+            .query(USER_LIST)
+            .respond(200, "The number of matching users", json_body::<UserCount>(gen)),
+        ("GET", "/users/stats") => Operation::new("User totals and daily creations")
+            .query(STATS)
+            .respond(200, "User statistics", json_body::<UserStats>(gen)),
+        ("GET", "/users/search") => Operation::new("Search users by name and email")
+            .query(SEARCH)
+            .respond(200, "Matching users, best first", json_body::<SearchPage>(gen)),
+        ("GET", "/users/export") => Operation::new("Export matching users as CSV or NDJSON")
+            .query(USER_LIST)
+            .respond(200, "The users, in the format the Accept header asks for", [text_body("text/csv"), text_body("application/x-ndjson")].concat()),
+        ("GET", "/users/{id}") => Operation::new("Get a user")
+            .query(INCLUDE_DELETED)
+            .query(FIELDS)
+            .query(INCLUDE)
+            .respond(200, "The user", json_body::<Linked<User>>(gen)),
+        ("PUT", "/users/{id}") => Operation::new("Replace a user, or create it when upserts are enabled")
+            .request(json_body::<User>(gen))
+            .respond(200, "The updated user", json_body::<Linked<User>>(gen))
+            .respond(201, "The created user", json_body::<Linked<User>>(gen)),
+        ("PATCH", "/users/{id}") => Operation::new("Update some of a user's fields")
+            .request(vec![
+                ("application/json", schema::<UserPatch>(gen)),
+                ("application/merge-patch+json", json!({ "type": "object" })),
+                ("application/json-patch+json", json!({ "type": "array", "items": { "type": "object" } })),
+            ])
+            .respond(200, "The updated user", json_body::<Linked<User>>(gen)),
+        ("DELETE", "/users/{id}") => Operation::new("Delete a user").respond(204, "Deleted", NO_BODY),
+        ("POST", "/users/{id}/restore") => Operation::new("Restore a soft-deleted user").respond(200, "The restored user", json_body::<Linked<User>>(gen)),
+        ("PUT", "/users/{id}/password") => Operation::new("Set a user's password")
+            .request(json_body::<PasswordChange>(gen))
+            .respond(204, "Password changed", NO_BODY),
+        ("PUT", "/users/{id}/role") => Operation::new("Set a user's role")
+            .request(json_body::<RoleChange>(gen))
+            .respond(204, "Role changed", NO_BODY),
+        ("GET", "/users/{id}/posts") => Operation::new("List a user's posts")
+            .query(PAGE)
+            .respond(200, "A page of posts", json_body::<PostPage>(gen)),
+        ("POST", "/users/{id}/posts") => Operation::new("Create a post by a user")
+            .request(json_body::<PostInput>(gen))
+            .respond(201, "The created post", json_body::<Post>(gen)),
+        ("GET", "/posts") => Operation::new("List posts")
+            .query(POST_LIST)
+            .respond(200, "A page of posts", json_body::<PostPage>(gen)),
+        ("POST", "/posts") => Operation::new("Create a post")
+            .request(json_body::<PostInput>(gen))
+            .respond(201, "The created post", json_body::<Post>(gen)),
+        ("GET", "/posts/{id}") => Operation::new("Get a post").respond(200, "The post", json_body::<Post>(gen)),
+        ("PUT", "/posts/{id}") => Operation::new("Replace a post")
+            .request(json_body::<PostInput>(gen))
+            .respond(200, "The updated post", json_body::<Post>(gen)),
+        ("DELETE", "/posts/{id}") => Operation::new("Delete a post").respond(204, "Deleted", NO_BODY),
+        ("GET", "/users/{id}/groups") => Operation::new("List the groups a user belongs to")
+            .respond(200, "The user's groups", json_body::<UserGroups>(gen)),
+        ("POST", "/groups") => Operation::new("Create a group")
+            .request(json_body::<GroupInput>(gen))
+            .respond(201, "The created group", json_body::<Group>(gen)),
+        ("GET", "/groups") => Operation::new("List groups")
+            .query(PAGE)
+            .respond(200, "A page of groups", json_body::<GroupPage>(gen)),
+        ("GET", "/groups/{id}") => Operation::new("Get a group").respond(200, "The group", json_body::<Group>(gen)),
+        ("GET", "/groups/{id}/members") => Operation::new("List a group's members")
+            .query(PAGE)
+            .respond(200, "A page of members", json_body::<MemberPage>(gen)),
+        ("PUT", "/groups/{id}/members/{user_id}") => Operation::new("Add a user to a group")
+            .respond(201, "Added", NO_BODY)
+            .respond(204, "Already a member", NO_BODY),
+        ("DELETE", "/groups/{id}/members/{user_id}") => Operation::new("Remove a user from a group").respond(204, "Removed", NO_BODY),
+        ("POST", "/api-keys") => Operation::new("Create an API key")
+            .request(json_body::<ApiKeyInput>(gen))
+            .respond(201, "The key; its secret is only ever shown here", json_body::<CreatedApiKey>(gen)),
+        ("GET", "/api-keys") => Operation::new("List API keys").respond(200, "The keys, without secrets", json_body::<ApiKeyList>(gen)),
+        ("DELETE", "/api-keys/{id}") => Operation::new("Revoke an API key").respond(204, "Revoked", NO_BODY),
+        ("POST", "/webhooks") => Operation::new("Subscribe a URL to user events")
+            .request(json_body::<WebhookInput>(gen))
+            .respond(201, "The webhook; its secret is only ever shown here", json_body::<CreatedWebhook>(gen)),
+        ("GET", "/webhooks") => Operation::new("List webhooks").respond(200, "The webhooks, without secrets", json_body::<WebhookList>(gen)),
+        ("GET", "/webhooks/{id}") => Operation::new("Get a webhook").respond(200, "The webhook", json_body::<Webhook>(gen)),
+        ("PUT", "/webhooks/{id}") => Operation::new("Replace a webhook's URL and events")
+            .request(json_body::<WebhookInput>(gen))
+            .respond(200, "The updated webhook", json_body::<Webhook>(gen)),
+        ("DELETE", "/webhooks/{id}") => Operation::new("Delete a webhook").respond(204, "Deleted", NO_BODY),
+        ("POST", "/tenants") => Operation::new("Create a tenant with a dataset of its own")
+            .request(json_body::<TenantInput>(gen))
+            .respond(201, "The tenant and its first admin API key, only ever shown here", json_body::<CreatedTenant>(gen)),
+        ("GET", "/tenants") => Operation::new("List tenants").respond(200, "The tenants", json_body::<TenantList>(gen)),
+        ("GET", "/tenants/{id}") => Operation::new("Get a tenant").respond(200, "The tenant", json_body::<Tenant>(gen)),
+        ("DELETE", "/tenants/{id}") => Operation::new("Delete a tenant and all of its data").respond(204, "Deleted", NO_BODY),
+        ("GET", "/ws") => Operation::new("Open a WebSocket that sends user changes as JSON messages").respond(
+            101,
+            "Upgraded; send `{\"type\": \"subscribe\", \"user_ids\": [...]}` to narrow the events to some users",
+            NO_BODY,
+        ),
+        ("GET", "/audit") => Operation::new("List recorded mutations, newest first")
+            .query(AUDIT_LIST)
+            .respond(200, "A page of audit entries", json_body::<AuditPage>(gen)),
+        ("POST", "/admin/seed") => Operation::new("Insert generated users, posts and groups")
+            .request(json_body::<SeedPlan>(gen))
+            .respond(201, "What was inserted", json_body::<SeedReport>(gen)),
+        ("GET", "/healthz") => Operation::new("Liveness check").respond(200, "The server is up", json_body::<Health>(gen)),
+        ("GET", "/readyz") => Operation::new("Readiness check, including the database")
+            .respond(200, "Ready for traffic", json_body::<Health>(gen))
+            .respond(503, "A dependency is down", json_body::<Health>(gen)),
+        ("GET", "/version") => Operation::new("What is running: version, commit, build time and features")
+            .respond(200, "The running build", json_body::<BuildInfo>(gen)),
+        ("GET", "/metrics") => {
+            Operation::new("Prometheus metrics").respond(200, "Metrics in the Prometheus text format", text_body("text/plain"))
+        }
+        ("GET", SPEC_PATH) => Operation::new("This document").respond(200, "The OpenAPI document", vec![("application/json", json!({ "type": "object" }))]),
+        _ => Operation::new(""),
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+// An http:// or https:// URL taken apart for connecting to it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    pub tls: bool,
+    // Without the brackets of an IPv6 literal
+    pub host: String,
+    pub port: u16,
+    // Path and query, starting with `/`
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, String> {
+        let (tls, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+            _ => return Err("must be an http:// or https:// URL".to_string()),
+        };
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(at) => rest.split_at(at),
+            None => (rest, ""),
+        };
+        if authority.contains('@') {
+            return Err("must not contain credentials".to_string());
+        }
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => match v6.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or("has a malformed port")?)),
+                None => return Err("has a malformed IPv6 address".to_string()),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, _)) if host.contains(':') => return Err("has an IPv6 address without brackets".to_string()),
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() || host.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("must name a host".to_string());
+        }
+        let port = match port {
+            Some(port) => port.parse().ok().filter(|port| *port > 0).ok_or("has a malformed port")?,
+            None if tls => 443,
+            None => 80,
+        };
+        if target.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("must not contain spaces".to_string());
+        }
+        let target = match target {
+            "" => "/".to_string(),
+            target if target.starts_with('?') => format!("/{}", target),
+            target => target.to_string(),
+        };
+        Ok(Url { tls, host: host.to_string(), port, target })
+    }
+
+    // The Host header: the port is left out when it's the scheme's default
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        match (self.tls, self.port) {
+            (false, 80) | (true, 443) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+// POST `body` and return the response status; the response itself is not read. `timeout` covers
+// connecting, the TLS handshake, sending and waiting for the status line
+pub async fn post(url: &Url, headers: &[(&str, String)], body: &[u8], timeout: Duration) -> Result<u16, String> {
+    let exchange = async {
+        let stream = TcpStream::connect((url.host.as_str(), url.port)).await.map_err(|e| format!("connecting: {}", e))?;
+        let _ = stream.set_nodelay(true);
+        if !url.tls {
+            return send(stream, url, headers, body).await;
+        }
+        let name = ServerName::try_from(url.host.clone()).map_err(|e| format!("invalid host name: {}", e))?;
+        let stream = TlsConnector::from(client_config()?).connect(name, stream).await.map_err(|e| format!("TLS handshake: {}", e))?;
+        send(stream, url, headers, body).await
+    };
+    tokio::time::timeout(timeout, exchange).await.unwrap_or_else(|_| Err(format!("timed out after {}s", timeout.as_secs())))
+}
+
+async fn send<S: AsyncRead + AsyncWrite + Unpin>(stream: S, url: &Url, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String> {
+    let mut stream = BufReader::new(stream);
+    let mut head = format!("POST {} HTTP/1.1\r\nHost: {}\r\n", url.target, url.authority());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()));
+    let write = async {
+        stream.get_mut().write_all(head.as_bytes()).await?;
+        stream.get_mut().write_all(body).await?;
+        stream.get_mut().flush().await
+    };
+    write.await.map_err(|e| format!("sending: {}", e))?;
+
+    let mut status_line = String::new();
+    stream.read_line(&mut status_line).await.map_err(|e| format!("reading the response: {}", e))?;
+    let status = match status_line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [version, status, ..] if version.starts_with("HTTP/") => status.parse().ok(),
+        _ => None,
+    };
+    status.ok_or_else(|| format!("malformed status line {:?}", status_line.trim_end()))
+}
+
+// Trusts the public web roots; built once
+fn client_config() -> Result<Arc<ClientConfig>, String> {
+    static CONFIG: OnceLock<Result<Arc<ClientConfig>, String>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Arc::new(config))
+    });
+    config.clone()
+}
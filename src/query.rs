@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::http::Response;
+
+// Parsed query string; a key may appear several times (`?id=1&id=2`)
+#[derive(Default, Clone)]
+pub struct Query {
+    params: HashMap<String, Vec<String>>,
+}
+
+#[allow(dead_code)]
+impl Query {
+    // Parse `a=1&b=two%20words&flag` into decoded key/value pairs
+    pub fn parse(raw: &str) -> Query {
+        let mut params: HashMap<String, Vec<String>> = HashMap::new();
+        for pair in raw.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            params.entry(percent_decode(key)).or_default().push(percent_decode(value));
+        }
+        Query { params }
+    }
+
+    // First value for a key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.get(key).and_then(|values| values.first()).map(|v| v.as_str())
+    }
+
+    // Every value for a key, in the order given
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.params.get(key).map(|values| values.as_slice()).unwrap_or_default()
+    }
+
+    // Every key/value pair, sorted by key and then in the order given
+    pub fn pairs(&self) -> Vec<(&str, &str)> {
+        let mut keys: Vec<&String> = self.params.keys().collect();
+        keys.sort();
+        keys.into_iter()
+            .flat_map(|key| self.params[key].iter().map(move |value| (key.as_str(), value.as_str())))
+            .collect()
+    }
+
+    // `path` with this query string, after `changes` to it: a None value removes the key, and a
+    // value replaces the ones it had. Keys come out sorted, so equal links compare equal
+    pub fn link(&self, path: &str, changes: &[(&str, Option<String>)]) -> String {
+        let mut pairs: Vec<(&str, &str)> =
+            self.pairs().into_iter().filter(|(key, _)| !changes.iter().any(|(changed, _)| changed == key)).collect();
+        pairs.extend(changes.iter().filter_map(|(key, value)| Some((*key, value.as_deref()?))));
+        pairs.sort_by_key(|(key, _)| *key);
+        if pairs.is_empty() {
+            return path.to_string();
+        }
+        let pairs: Vec<String> = pairs.iter().map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value))).collect();
+        format!("{}?{}", path, pairs.join("&"))
+    }
+
+    // Parse the first value for a key, responding 400 when it is present but malformed
+    pub fn parse_value<T: FromStr>(&self, key: &str) -> Result<Option<T>, Response> {
+        match self.get(key) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| {
+                    Response::error_with_details(
+                        400,
+                        "invalid_query_parameter",
+                        format!("Invalid value for query parameter {}", key),
+                        serde_json::json!({ "parameter": key }),
+                    )
+                }),
+            None => Ok(None),
+        }
+    }
+}
+
+// Decode %XX escapes and `+` as space; malformed escapes are kept verbatim
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match (hex_value(bytes.get(i + 1)), hex_value(bytes.get(i + 2))) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Escape everything but the unreserved characters (RFC 3986 2.3), for a query key or value
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex_value(byte: Option<&u8>) -> Option<u8> {
+    (*byte? as char).to_digit(16).map(|d| d as u8)
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+use crate::http::Response;
+
+// Idle clients' buckets are dropped at most this often, once they have refilled
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Token buckets per client IP: each request takes a token, and tokens come back at a steady
+// rate up to the burst size
+pub struct RateLimiter {
+    capacity: f64,
+    // Tokens regained per second
+    refill_rate: f64,
+    state: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<IpAddr, Bucket>,
+    next_prune: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// The outcome of a request against its client's bucket
+pub struct Quota {
+    limit: u32,
+    remaining: u32,
+    // Seconds until the bucket is full again
+    reset: u64,
+    // Seconds until a token is available, when the request was refused
+    retry_after: Option<u64>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            capacity: f64::from(config.burst),
+            refill_rate: f64::from(config.per_minute) / 60.0,
+            state: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                next_prune: Instant::now() + PRUNE_INTERVAL,
+            }),
+        }
+    }
+
+    // Take a token from the client's bucket if it has one
+    pub fn acquire(&self, client: IpAddr) -> Quota {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if now >= state.next_prune {
+            let (capacity, refill_rate) = (self.capacity, self.refill_rate);
+            state.buckets.retain(|_, bucket| bucket.tokens_at(now, capacity, refill_rate) < capacity);
+            state.next_prune = now + PRUNE_INTERVAL;
+        }
+
+        let bucket = state.buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = bucket.tokens_at(now, self.capacity, self.refill_rate);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Quota {
+            limit: self.capacity as u32,
+            remaining: bucket.tokens as u32,
+            reset: ((self.capacity - bucket.tokens) / self.refill_rate).ceil() as u64,
+            retry_after: (!allowed).then(|| ((1.0 - bucket.tokens) / self.refill_rate).ceil().max(1.0) as u64),
+        }
+    }
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant, capacity: f64, refill_rate: f64) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * refill_rate).min(capacity)
+    }
+}
+
+impl Quota {
+    // The 429 for a refused request, or None if it may go ahead
+    pub fn rejection(&self) -> Option<Response> {
+        let retry_after = self.retry_after?;
+        let response = Response::error_with_details(
+            429,
+            "rate_limited",
+            "Too many requests",
+            serde_json::json!({ "retry_after": retry_after }),
+        );
+        Some(self.apply_headers(response).with_header("Retry-After", retry_after.to_string()))
+    }
+
+    // Add the X-RateLimit-* headers describing the client's bucket
+    pub fn apply_headers(&self, response: Response) -> Response {
+        response
+            .with_header("X-RateLimit-Limit", self.limit.to_string())
+            .with_header("X-RateLimit-Remaining", self.remaining.to_string())
+            .with_header("X-RateLimit-Reset", self.reset.to_string())
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::query::percent_decode;
+
+// Idle connections kept for reuse; more are opened when needed and closed afterwards
+const MAX_IDLE: usize = 16;
+
+// Largest bulk reply read; cached values are single users
+const MAX_BULK_SIZE: usize = 1024 * 1024;
+
+// Just enough of a Redis client for a cache: GET, SET with an expiry and DEL, over RESP2 and
+// plain TCP. Every command, connecting included, has to finish within `timeout`
+pub struct Redis {
+    host: String,
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+    db: Option<u32>,
+    timeout: Duration,
+    idle: Mutex<Vec<BufReader<TcpStream>>>,
+}
+
+enum Reply {
+    Nil,
+    Status,
+    Integer,
+    Bulk(Vec<u8>),
+}
+
+impl Redis {
+    // From a `redis://[[username]:password@]host[:port][/db]` URL
+    pub fn new(url: &str, timeout: Duration) -> Result<Redis, String> {
+        let rest = match url.split_once("://") {
+            Some(("redis", rest)) => rest,
+            Some(("rediss", _)) => return Err("REDIS_URL: TLS (rediss://) isn't supported".to_string()),
+            _ => return Err("REDIS_URL must be a redis:// URL".to_string()),
+        };
+        let (authority, db) = match rest.split_once('/') {
+            Some((authority, "")) => (authority, None),
+            Some((authority, db)) => (authority, Some(db.parse().map_err(|_| format!("REDIS_URL: invalid database {:?}", db))?)),
+            None => (rest, None),
+        };
+        let (credentials, address) = match authority.rsplit_once('@') {
+            Some((credentials, address)) => (Some(credentials), address),
+            None => (None, authority),
+        };
+        let (username, password) = match credentials.map(|credentials| credentials.split_once(':')) {
+            Some(Some((username, password))) => {
+                (Some(percent_decode(username)).filter(|username| !username.is_empty()), Some(percent_decode(password)))
+            }
+            Some(None) => return Err("REDIS_URL: credentials must be `username:password` or `:password`".to_string()),
+            None => (None, None),
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("REDIS_URL: invalid port {:?}", port))?),
+            None => (address, 6379),
+        };
+        if host.is_empty() {
+            return Err("REDIS_URL must name a host".to_string());
+        }
+        Ok(Redis {
+            host: host.to_string(),
+            port,
+            username,
+            password,
+            db,
+            timeout,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(value) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    // Store `value` under `key` for `ttl`
+    pub async fn set(&self, key: &str, value: &[u8], ttl: Duration) -> Result<(), String> {
+        let millis = ttl.as_millis().max(1).to_string();
+        self.command(&[b"SET", key.as_bytes(), value, b"PX", millis.as_bytes()]).await.map(|_| ())
+    }
+
+    pub async fn del(&self, keys: &[String]) -> Result<(), String> {
+        let mut args: Vec<&[u8]> = vec![b"DEL"];
+        args.extend(keys.iter().map(|key| key.as_bytes()));
+        self.command(&args).await.map(|_| ())
+    }
+
+    // Run one command, on an idle connection when there is one. Every command used here is safe to
+    // repeat, so one that fails on a connection the server may have closed is retried on a new one
+    async fn command(&self, args: &[&[u8]]) -> Result<Reply, String> {
+        let run = async {
+            let reused = self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).pop();
+            let (connection, reply) = match reused {
+                Some(mut connection) => match exchange(&mut connection, args).await {
+                    Ok(reply) => (connection, reply),
+                    Err(_) => {
+                        let mut connection = self.connect().await?;
+                        let reply = exchange(&mut connection, args).await?;
+                        (connection, reply)
+                    }
+                },
+                None => {
+                    let mut connection = self.connect().await?;
+                    let reply = exchange(&mut connection, args).await?;
+                    (connection, reply)
+                }
+            };
+            let surplus = {
+                let mut idle = self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if idle.len() < MAX_IDLE {
+                    idle.push(connection);
+                    None
+                } else {
+                    Some(connection)
+                }
+            };
+            if let Some(mut connection) = surplus {
+                let _ = connection.get_mut().shutdown().await;
+            }
+            Ok(reply)
+        };
+        tokio::time::timeout(self.timeout, run)
+            .await
+            .unwrap_or_else(|_| Err(format!("timed out after {}ms", self.timeout.as_millis())))
+    }
+
+    async fn connect(&self) -> Result<BufReader<TcpStream>, String> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await.map_err(|e| format!("connecting: {}", e))?;
+        let _ = stream.set_nodelay(true);
+        let mut connection = BufReader::new(stream);
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => exchange(&mut connection, &[b"AUTH", username.as_bytes(), password.as_bytes()]).await?,
+            (None, Some(password)) => exchange(&mut connection, &[b"AUTH", password.as_bytes()]).await?,
+            _ => Reply::Nil,
+        };
+        if let Some(db) = self.db {
+            exchange(&mut connection, &[b"SELECT", db.to_string().as_bytes()]).await?;
+        }
+        Ok(connection)
+    }
+}
+
+// Send a command as an array of bulk strings and read its reply; an error reply is an Err
+async fn exchange(connection: &mut BufReader<TcpStream>, args: &[&[u8]]) -> Result<Reply, String> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend_from_slice(arg);
+        request.extend_from_slice(b"\r\n");
+    }
+    connection.get_mut().write_all(&request).await.map_err(|e| format!("sending: {}", e))?;
+
+    let mut line = String::new();
+    match connection.read_line(&mut line).await {
+        Ok(0) => return Err("the connection closed".to_string()),
+        Ok(_) => {}
+        Err(e) => return Err(format!("reading: {}", e)),
+    }
+    let line = line.trim_end();
+    match line.split_at_checked(1) {
+        Some(("+", _)) => Ok(Reply::Status),
+        Some(("-", error)) => Err(error.to_string()),
+        Some((":", _)) => Ok(Reply::Integer),
+        Some(("$", "-1")) => Ok(Reply::Nil),
+        Some(("$", length)) => {
+            let length: usize = length.parse().ok().filter(|length| *length <= MAX_BULK_SIZE).ok_or("malformed bulk reply")?;
+            let mut value = vec![0; length + 2];
+            connection.read_exact(&mut value).await.map_err(|e| format!("reading: {}", e))?;
+            value.truncate(length);
+            Ok(Reply::Bulk(value))
+        }
+        _ => Err(format!("unexpected reply {:?}", line)),
+    }
+}
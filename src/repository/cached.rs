@@ -0,0 +1,268 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use super::{
+    CacheStatus, Credentials, PoolStatus, RepositoryError, SearchResults, Upserted, UserChange, UserList, UserListQuery, UserRepository, UserSearch,
+};
+use crate::models::{Role, User, UserPatch, UserStats};
+use crate::redis::Redis;
+
+// How long Redis is left alone after failing, so an outage doesn't add its timeout to every read
+const RECHECK_AFTER: Duration = Duration::from_secs(5);
+
+const KEY_PREFIX: &str = concat!(env!("CARGO_PKG_NAME"), ":");
+
+// Wraps a user store to keep users fetched by id in Redis for `ttl`. Changes made through it drop
+// the users they touch from the cache; changes made any other way show once the entry expires.
+// Whenever Redis can't be reached, reads go straight to the store
+pub struct CachedUserRepository {
+    inner: Arc<dyn UserRepository>,
+    redis: Redis,
+    // Starts every key: `rust-crud-api:user:` for the default dataset, `rust-crud-api:tenant:acme:user:`
+    // for a tenant's, since tenants' ids overlap
+    prefix: String,
+    ttl: Duration,
+    // Set while Redis is treated as down, until it's next tried
+    down_until: Mutex<Option<Instant>>,
+}
+
+// A user as cached: unlike User's own serialization, this keeps every field
+#[derive(Serialize, Deserialize)]
+struct CachedUser {
+    id: Option<i32>,
+    name: String,
+    email: String,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    version: i32,
+}
+
+impl CachedUserRepository {
+    pub fn new(inner: Arc<dyn UserRepository>, redis: Redis, ttl: Duration, tenant: Option<&str>) -> Self {
+        let prefix = match tenant {
+            Some(tenant) => format!("{}tenant:{}:user:", KEY_PREFIX, tenant),
+            None => format!("{}user:", KEY_PREFIX),
+        };
+        CachedUserRepository {
+            inner,
+            redis,
+            prefix,
+            ttl,
+            down_until: Mutex::new(None),
+        }
+    }
+
+    async fn cached(&self, id: i32) -> Option<User> {
+        if self.is_down() {
+            return None;
+        }
+        let value = match self.redis.get(&self.key(id)).await {
+            Ok(value) => {
+                self.succeeded();
+                value?
+            }
+            Err(e) => {
+                self.failed(e);
+                return None;
+            }
+        };
+        match serde_json::from_slice::<CachedUser>(&value) {
+            Ok(user) => Some(User {
+                id: user.id,
+                name: user.name,
+                email: user.email,
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+                deleted_at: user.deleted_at,
+                version: user.version,
+            }),
+            // Left by an incompatible version; it's replaced once read from the store
+            Err(_) => None,
+        }
+    }
+
+    async fn store(&self, id: i32, user: &User) {
+        if self.is_down() {
+            return;
+        }
+        let cached = CachedUser {
+            id: user.id,
+            name: user.name.clone(),
+            email: user.email.clone(),
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            deleted_at: user.deleted_at,
+            version: user.version,
+        };
+        let Ok(value) = serde_json::to_vec(&cached) else { return };
+        match self.redis.set(&self.key(id), &value, self.ttl).await {
+            Ok(()) => self.succeeded(),
+            Err(e) => self.failed(e),
+        }
+    }
+
+    // Drop changed users from the cache. Tried even while Redis looks down, since a stale entry
+    // left behind would be served until it expires
+    async fn invalidate(&self, ids: &[i32]) {
+        if ids.is_empty() {
+            return;
+        }
+        let keys: Vec<String> = ids.iter().map(|id| self.key(*id)).collect();
+        match self.redis.del(&keys).await {
+            Ok(()) => self.succeeded(),
+            Err(e) => {
+                warn!("Users {:?} changed but stay in the Redis cache for up to {}s: {}", ids, self.ttl.as_secs(), e);
+                self.failed(e);
+            }
+        }
+    }
+
+    fn is_down(&self) -> bool {
+        let down_until = self.down_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        down_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    fn succeeded(&self) {
+        let mut down_until = self.down_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if down_until.take().is_some() {
+            info!("Redis is reachable again; caching users");
+        }
+    }
+
+    // Outages are logged when they start, not on every failed command
+    fn failed(&self, e: String) {
+        let mut down_until = self.down_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if down_until.is_none() {
+            warn!("Redis is unavailable, reading users from the database until it's back: {}", e);
+        }
+        *down_until = Some(Instant::now() + RECHECK_AFTER);
+    }
+
+    fn key(&self, id: i32) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+}
+
+#[async_trait]
+impl UserRepository for CachedUserRepository {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        self.inner.create(name, email).await
+    }
+
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        self.inner.create_many(users).await
+    }
+
+    // Only live users are cached, so one found there suits either kind of lookup
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        if let Some(user) = self.cached(id).await {
+            return Ok(Some(user));
+        }
+        let user = self.inner.get(id, include_deleted).await?;
+        if let Some(user) = user.as_ref().filter(|user| user.deleted_at.is_none()) {
+            self.store(id, user).await;
+        }
+        Ok(user)
+    }
+
+    async fn list(&self, query: &UserListQuery) -> Result<UserList, RepositoryError> {
+        self.inner.list(query).await
+    }
+
+    async fn stream(&self, query: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        self.inner.stream(query, sink).await
+    }
+
+    async fn count(&self, query: &UserListQuery) -> Result<i64, RepositoryError> {
+        self.inner.count(query).await
+    }
+
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        self.inner.search(search).await
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        self.inner.stats(days).await
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        let updated = self.inner.update(id, name, email).await?;
+        self.invalidate(&[id]).await;
+        Ok(updated)
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let upserted = self.inner.upsert(id, name, email).await?;
+        self.invalidate(&[id]).await;
+        Ok(upserted)
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        let patched = self.inner.patch(id, patch).await?;
+        self.invalidate(&[id]).await;
+        Ok(patched)
+    }
+
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        let modified = self.inner.modify(id, change).await?;
+        self.invalidate(&[id]).await;
+        Ok(modified)
+    }
+
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        let ids: Vec<i32> = patches.iter().map(|(id, _)| *id).collect();
+        let results = self.inner.patch_many(patches).await?;
+        self.invalidate(&ids).await;
+        Ok(results)
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let deleted = self.inner.delete(id).await?;
+        self.invalidate(&[id]).await;
+        Ok(deleted)
+    }
+
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let deleted = self.inner.delete_many(ids).await?;
+        self.invalidate(&deleted).await;
+        Ok(deleted)
+    }
+
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        self.inner.restore(id).await
+    }
+
+    // Neither passwords nor roles are part of a cached user
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        self.inner.set_password(id, password_hash).await
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        self.inner.set_role(id, role).await
+    }
+
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        self.inner.credentials(email).await
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.inner.ping().await
+    }
+
+    fn close(&self) {
+        self.inner.close()
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        self.inner.pool_status()
+    }
+
+    fn cache_status(&self) -> Option<CacheStatus> {
+        self.inner.cache_status()
+    }
+}
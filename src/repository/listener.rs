@@ -0,0 +1,119 @@
+use std::future;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, Notification};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tracing::{error, info, warn};
+
+use super::UserRepository;
+use crate::config::Config;
+use crate::db;
+use crate::events::{EventType, Events};
+
+// Where the users table's trigger announces changes (migrations 0017 and 0018)
+const CHANNEL: &str = "user_changes";
+
+// Payload of a notification on CHANNEL
+#[derive(Deserialize)]
+struct Change {
+    #[serde(rename = "type")]
+    event_type: String,
+    id: i32,
+    // The users table's schema; every tenant's trigger announces on the same channel
+    schema: Option<String>,
+}
+
+// Publish the changes announced on CHANNEL, whoever made them, until `events` is closed. Listens on
+// a connection of its own rather than one from the pool, and reconnects with the database retry
+// backoff when it's lost; changes made while reconnecting are missed
+pub fn spawn(config: &Config, users: Arc<dyn UserRepository>, events: Events) -> Result<(), String> {
+    let (pg_config, tls) = db::connection_config(config)?;
+    let retry = config.db_retry.clone();
+    tokio::spawn(async move {
+        let mut failures = 0;
+        loop {
+            let lost = tokio::select! {
+                _ = events.closed() => return,
+                lost = listen(&pg_config, tls.clone(), &users, &events, &mut failures) => lost,
+            };
+            failures += 1;
+            let delay = retry.delay(failures);
+            warn!("Not listening for user changes, reconnecting in {}ms: {}", delay.as_millis(), lost);
+            tokio::select! {
+                _ = events.closed() => return,
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+    });
+    Ok(())
+}
+
+// LISTEN and publish each change until the connection fails; returns why it did
+async fn listen(
+    pg_config: &tokio_postgres::Config,
+    tls: MakeRustlsConnect,
+    users: &Arc<dyn UserRepository>,
+    events: &Events,
+    failures: &mut u32,
+) -> String {
+    let (client, mut connection) = match pg_config.connect(tls).await {
+        Ok(connected) => connected,
+        Err(e) => return e.to_string(),
+    };
+    // Notifications arrive as the connection is polled, which also carries the client's queries.
+    // It ends once `client` is dropped
+    let (sender, mut notifications) = mpsc::unbounded_channel();
+    let driver = tokio::spawn(async move {
+        loop {
+            match future::poll_fn(|cx| connection.poll_message(cx)).await {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    let _ = sender.send(notification);
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return e.to_string(),
+                None => return "the connection closed".to_string(),
+            }
+        }
+    });
+    if let Err(e) = client.batch_execute(&format!("LISTEN {}", CHANNEL)).await {
+        return e.to_string();
+    }
+    // The schema `users` is in, to tell its changes from tenants'
+    let schema: String = match client.query_one("SELECT current_schema()", &[]).await {
+        Ok(row) => row.get(0),
+        Err(e) => return e.to_string(),
+    };
+    info!("Listening for user changes on {}", CHANNEL);
+    *failures = 0;
+
+    while let Some(notification) = notifications.recv().await {
+        publish(&notification, &schema, users, events).await;
+    }
+    driver.await.unwrap_or_else(|e| e.to_string())
+}
+
+async fn publish(notification: &Notification, schema: &str, users: &Arc<dyn UserRepository>, events: &Events) {
+    let change: Change = match serde_json::from_str(notification.payload()) {
+        Ok(change) => change,
+        Err(e) => {
+            warn!("Ignoring malformed user change {:?}: {}", notification.payload(), e);
+            return;
+        }
+    };
+    // A tenant's change; its own stores publish those
+    if change.schema.as_deref().is_some_and(|changed| changed != schema) {
+        return;
+    }
+    match EventType::parse(&change.event_type) {
+        Some(EventType::Deleted) => events.user_deleted(change.id),
+        // The user as it is now, which is newer than the change when others quickly followed it
+        Some(event_type) => match users.get(change.id, true).await {
+            Ok(Some(user)) => events.user_changed(event_type, &user),
+            // Deleted outright since
+            Ok(None) => {}
+            Err(e) => error!("Error loading user {} to publish its change: {}", change.id, e),
+        },
+        None => warn!("Ignoring user change of unknown type {:?}", change.event_type),
+    }
+}
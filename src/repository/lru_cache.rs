@@ -0,0 +1,225 @@
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use super::{
+    CacheStatus, Credentials, PoolStatus, RepositoryError, SearchResults, Upserted, UserChange, UserList, UserListQuery, UserRepository,
+    UserSearch,
+};
+use crate::events::{EventType, Events};
+use crate::models::{Role, User, UserPatch, UserStats};
+
+// Wraps a user store to keep the users most recently fetched by id in memory. Changes made through
+// it drop the users they touch at once; the rest, such as those another instance announces through
+// Postgres, are dropped as their events arrive
+pub struct LruCachedUserRepository {
+    inner: Arc<dyn UserRepository>,
+    cache: Arc<Mutex<Cache>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Cache {
+    users: LruCache<i32, User>,
+    // Bumped whenever users are dropped, so a read that raced a change doesn't cache what it read
+    generation: u64,
+}
+
+impl Cache {
+    fn invalidate(&mut self, ids: &[i32]) {
+        self.generation += 1;
+        for id in ids {
+            self.users.pop(id);
+        }
+    }
+}
+
+impl LruCachedUserRepository {
+    pub fn new(inner: Arc<dyn UserRepository>, capacity: NonZeroUsize, events: &Events) -> Self {
+        let cache = Arc::new(Mutex::new(Cache {
+            users: LruCache::new(capacity),
+            generation: 0,
+        }));
+        tokio::spawn(invalidate_on_events(Arc::clone(&cache), events.clone()));
+        LruCachedUserRepository {
+            inner,
+            cache,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn invalidate(&self, ids: &[i32]) {
+        lock(&self.cache).invalidate(ids);
+    }
+}
+
+fn lock(cache: &Mutex<Cache>) -> MutexGuard<'_, Cache> {
+    cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Drop each user an event is about until the server shuts down; everything, if events were missed
+async fn invalidate_on_events(cache: Arc<Mutex<Cache>>, events: Events) {
+    let mut subscription = events.subscribe();
+    loop {
+        let event = tokio::select! {
+            _ = events.closed() => return,
+            event = subscription.recv() => event,
+        };
+        match event {
+            // Missing users aren't cached, so there's nothing to drop for a new one
+            Ok(event) if event.event_type == EventType::Created => {}
+            Ok(event) => {
+                if let Some(id) = event.data["id"].as_i64().and_then(|id| i32::try_from(id).ok()) {
+                    lock(&cache).invalidate(&[id]);
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                warn!("User cache fell behind on {} events; emptying it", missed);
+                let mut cache = lock(&cache);
+                cache.generation += 1;
+                cache.users.clear();
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for LruCachedUserRepository {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        self.inner.create(name, email).await
+    }
+
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        self.inner.create_many(users).await
+    }
+
+    // Only live users are cached, so one found there suits either kind of lookup
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        let generation = {
+            let mut cache = lock(&self.cache);
+            if let Some(user) = cache.users.get(&id) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(user.clone()));
+            }
+            cache.generation
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let user = self.inner.get(id, include_deleted).await?;
+        if let Some(user) = user.as_ref().filter(|user| user.deleted_at.is_none()) {
+            let mut cache = lock(&self.cache);
+            if cache.generation == generation {
+                cache.users.put(id, user.clone());
+            }
+        }
+        Ok(user)
+    }
+
+    async fn list(&self, query: &UserListQuery) -> Result<UserList, RepositoryError> {
+        self.inner.list(query).await
+    }
+
+    async fn stream(&self, query: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        self.inner.stream(query, sink).await
+    }
+
+    async fn count(&self, query: &UserListQuery) -> Result<i64, RepositoryError> {
+        self.inner.count(query).await
+    }
+
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        self.inner.search(search).await
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        self.inner.stats(days).await
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        let updated = self.inner.update(id, name, email).await?;
+        self.invalidate(&[id]);
+        Ok(updated)
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let upserted = self.inner.upsert(id, name, email).await?;
+        self.invalidate(&[id]);
+        Ok(upserted)
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        let patched = self.inner.patch(id, patch).await?;
+        self.invalidate(&[id]);
+        Ok(patched)
+    }
+
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        let modified = self.inner.modify(id, change).await?;
+        self.invalidate(&[id]);
+        Ok(modified)
+    }
+
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        let ids: Vec<i32> = patches.iter().map(|(id, _)| *id).collect();
+        let results = self.inner.patch_many(patches).await?;
+        self.invalidate(&ids);
+        Ok(results)
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let deleted = self.inner.delete(id).await?;
+        self.invalidate(&[id]);
+        Ok(deleted)
+    }
+
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let deleted = self.inner.delete_many(ids).await?;
+        self.invalidate(&deleted);
+        Ok(deleted)
+    }
+
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        self.inner.restore(id).await
+    }
+
+    // Neither passwords nor roles are part of a cached user
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        self.inner.set_password(id, password_hash).await
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        self.inner.set_role(id, role).await
+    }
+
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        self.inner.credentials(email).await
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.inner.ping().await
+    }
+
+    fn close(&self) {
+        self.inner.close()
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        self.inner.pool_status()
+    }
+
+    fn cache_status(&self) -> Option<CacheStatus> {
+        let cache = lock(&self.cache);
+        Some(CacheStatus {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            size: cache.users.len(),
+            capacity: cache.users.cap().get(),
+        })
+    }
+}
@@ -0,0 +1,824 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::mpsc;
+
+use super::{
+    AddMember, ApiKeyRepository, AuditList, AuditQuery, AuditRecord, AuditRepository, Credentials, GroupList, Refreshed, TokenRepository,
+    GroupRepository, IdempotencyClaim, IdempotencyRepository, MemberList, PostList, PostListQuery, PostRepository, RepositoryError,
+    SearchResults, StoredResponse, Upserted, UserChange, UserList, UserListQuery, UserRepository, UserSearch, WebhookRepository, WebhookTarget,
+    TOP_EMAIL_DOMAINS,
+};
+use crate::config::OnUserDelete;
+use crate::models::{ApiKey, AuditEntry, DailyCount, DomainCount, Group, Post, Role, SearchHit, User, UserPatch, UserStats, Webhook};
+
+// Every store kept in HashMaps behind one lock, for handler unit tests that shouldn't need a
+// database. It follows the SQL backends' rules: emails are unique among live users and group
+// names unique, both ignoring case, and soft-deleted users' posts are hidden
+pub struct MemoryRepository {
+    state: Mutex<State>,
+    on_user_delete: OnUserDelete,
+}
+
+#[derive(Default)]
+struct State {
+    users: HashMap<i32, StoredUser>,
+    posts: HashMap<i32, Post>,
+    groups: HashMap<i32, Group>,
+    // (group id, user id)
+    memberships: HashSet<(i32, i32)>,
+    api_keys: HashMap<i32, StoredApiKey>,
+    sessions: HashMap<i32, Session>,
+    // Revoked access token jti to when the token expires
+    revoked: HashMap<String, DateTime<Utc>>,
+    audit: Vec<AuditEntry>,
+    idempotency_keys: HashMap<String, IdempotencyRecord>,
+    webhooks: HashMap<i32, StoredWebhook>,
+    // Last id handed out per table, like a sequence
+    last_user_id: i32,
+    last_post_id: i32,
+    last_group_id: i32,
+    last_api_key_id: i32,
+    last_session_id: i32,
+    last_webhook_id: i32,
+}
+
+struct StoredUser {
+    user: User,
+    password_hash: Option<String>,
+    role: Role,
+}
+
+struct StoredApiKey {
+    api_key: ApiKey,
+    key_hash: String,
+}
+
+struct Session {
+    user_id: i32,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+    revoked: bool,
+}
+
+struct StoredWebhook {
+    webhook: Webhook,
+    secret: String,
+}
+
+struct IdempotencyRecord {
+    request_hash: String,
+    // None while the request runs
+    response: Option<StoredResponse>,
+    expires_at: DateTime<Utc>,
+}
+
+impl MemoryRepository {
+    pub fn new(on_user_delete: OnUserDelete) -> Self {
+        MemoryRepository {
+            state: Mutex::new(State::default()),
+            on_user_delete,
+        }
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, State>, RepositoryError> {
+        self.state
+            .lock()
+            .map_err(|_| RepositoryError::Internal("In-memory store lock poisoned".to_string()))
+    }
+}
+
+impl State {
+    fn live_user(&self, id: i32) -> Option<&StoredUser> {
+        self.users.get(&id).filter(|stored| stored.user.deleted_at.is_none())
+    }
+
+    // Whether a live user other than `except` has this email, in any case
+    fn email_taken(&self, email: &str, except: Option<i32>) -> bool {
+        self.users.values().any(|stored| {
+            stored.user.deleted_at.is_none() && stored.user.id != except && stored.user.email.eq_ignore_ascii_case(email)
+        })
+    }
+
+    fn insert_user(&mut self, id: Option<i32>, name: &str, email: &str) -> Result<User, RepositoryError> {
+        if self.email_taken(email, None) {
+            return Err(RepositoryError::EmailTaken);
+        }
+        // An explicit id moves the sequence past it, so later inserts don't collide
+        let id = id.unwrap_or(self.last_user_id + 1);
+        self.last_user_id = self.last_user_id.max(id);
+        let now = Utc::now();
+        let user = User {
+            id: Some(id),
+            name: name.to_string(),
+            email: email.to_string(),
+            created_at: Some(now),
+            updated_at: Some(now),
+            deleted_at: None,
+            version: 1,
+        };
+        self.users.insert(
+            id,
+            StoredUser {
+                user: user.clone(),
+                password_hash: None,
+                role: Role::default(),
+            },
+        );
+        Ok(user)
+    }
+
+    // Replace the live user's fields, bumping the version; None if there is no live user
+    fn update_user(&mut self, id: i32, name: Option<String>, email: Option<String>) -> Result<Option<User>, RepositoryError> {
+        if self.live_user(id).is_none() {
+            return Ok(None);
+        }
+        if email.as_deref().is_some_and(|email| self.email_taken(email, Some(id))) {
+            return Err(RepositoryError::EmailTaken);
+        }
+        let Some(stored) = self.users.get_mut(&id) else {
+            return Ok(None);
+        };
+        if let Some(name) = name {
+            stored.user.name = name;
+        }
+        if let Some(email) = email {
+            stored.user.email = email;
+        }
+        stored.user.version += 1;
+        stored.user.updated_at = Some(Utc::now());
+        Ok(Some(stored.user.clone()))
+    }
+
+    // Users matching the query's filters (and cursor, when `with_cursor`), in its sort order
+    fn matching_users(&self, list: &UserListQuery, with_cursor: bool) -> Vec<User> {
+        let fragment = list.email_contains.as_ref().map(|fragment| fragment.to_lowercase());
+        let mut users: Vec<User> = self
+            .users
+            .values()
+            .map(|stored| &stored.user)
+            .filter(|user| list.include_deleted || user.deleted_at.is_none())
+            .filter(|user| list.name.as_ref().is_none_or(|name| user.name == *name))
+            .filter(|user| fragment.as_ref().is_none_or(|fragment| user.email.to_lowercase().contains(fragment)))
+            .filter(|user| match (with_cursor, list.after) {
+                (true, Some(after)) if list.order == "DESC" => user.id < Some(after),
+                (true, Some(after)) => user.id > Some(after),
+                _ => true,
+            })
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| {
+            let by_column = match list.sort {
+                "name" => a.name.cmp(&b.name),
+                "email" => a.email.cmp(&b.email),
+                _ => a.id.cmp(&b.id),
+            };
+            by_column.then(a.id.cmp(&b.id))
+        });
+        if list.order == "DESC" {
+            users.reverse();
+        }
+        users
+    }
+
+    // Posts whose author is live
+    fn visible_post(&self, id: i32) -> Option<&Post> {
+        self.posts.get(&id).filter(|post| self.live_user(post.user_id).is_some())
+    }
+
+    fn role(&self, user_id: i32) -> Role {
+        self.users.get(&user_id).map(|stored| stored.role).unwrap_or_default()
+    }
+}
+
+// A page of `items`, as LIMIT and OFFSET would cut it
+fn page<T>(items: Vec<T>, limit: i64, offset: i64) -> Vec<T> {
+    items.into_iter().skip(offset.max(0) as usize).take(limit.max(0) as usize).collect()
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        self.state()?.insert_user(None, name, email)
+    }
+
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        let mut state = self.state()?;
+        Ok(users.iter().map(|user| state.insert_user(None, &user.name, &user.email)).collect())
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        let state = self.state()?;
+        let user = state.users.get(&id).map(|stored| &stored.user);
+        Ok(user.filter(|user| include_deleted || user.deleted_at.is_none()).cloned())
+    }
+
+    async fn list(&self, list: &UserListQuery) -> Result<UserList, RepositoryError> {
+        let state = self.state()?;
+        // Offset pagination reports the filtered total; keyset pagination skips the count
+        let total = match list.after {
+            Some(_) => None,
+            None => Some(state.matching_users(list, false).len() as i64),
+        };
+        let offset = if list.after.is_some() { 0 } else { list.offset };
+        let mut users = page(state.matching_users(list, true), list.limit + 1, offset);
+        let has_more = users.len() as i64 > list.limit;
+        users.truncate(list.limit as usize);
+        Ok(UserList { users, total, has_more })
+    }
+
+    // The matching users are copied out first, so the lock isn't held while the receiver catches up
+    async fn stream(&self, list: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        let users = self.state()?.matching_users(list, false);
+        for user in users {
+            if sink.send(user).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn count(&self, list: &UserListQuery) -> Result<i64, RepositoryError> {
+        Ok(self.state()?.matching_users(list, false).len() as i64)
+    }
+
+    // Substring matches only, scored exact > prefix > anywhere like the SQLite backend
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        let state = self.state()?;
+        let text = search.text.to_lowercase();
+        let mut hits: Vec<SearchHit> = state
+            .users
+            .values()
+            .map(|stored| &stored.user)
+            .filter(|user| user.deleted_at.is_none())
+            .filter_map(|user| {
+                let (name, email) = (user.name.to_lowercase(), user.email.to_lowercase());
+                let score = match () {
+                    _ if name == text || email == text => 1.0,
+                    _ if name.starts_with(&text) || email.starts_with(&text) => 0.75,
+                    _ if name.contains(&text) || email.contains(&text) => 0.5,
+                    _ => return None,
+                };
+                Some(SearchHit { user: user.clone(), score })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.user.id.cmp(&b.user.id)));
+        let total = hits.len() as i64;
+        Ok(SearchResults { hits: page(hits, search.limit, search.offset), total })
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        let state = self.state()?;
+        let total = state.users.len() as i64;
+        let live: Vec<&User> = state.users.values().map(|stored| &stored.user).filter(|user| user.deleted_at.is_none()).collect();
+        let deleted = total - live.len() as i64;
+
+        let since = (Utc::now().date_naive() - Days::new(days.max(1) as u64 - 1)).and_time(NaiveTime::MIN).and_utc();
+        let mut per_day = HashMap::new();
+        for created_at in live.iter().filter_map(|user| user.created_at).filter(|created_at| *created_at >= since) {
+            *per_day.entry(created_at.date_naive()).or_insert(0) += 1;
+        }
+        let mut created_per_day: Vec<DailyCount> = per_day.into_iter().map(|(date, count)| DailyCount { date, count }).collect();
+        created_per_day.sort_by_key(|day| day.date);
+
+        let mut domains = HashMap::new();
+        for user in &live {
+            let domain = user.email.split_once('@').map(|(_, domain)| domain).unwrap_or_default();
+            *domains.entry(domain.to_lowercase()).or_insert(0) += 1;
+        }
+        let distinct_email_domains = domains.len() as i64;
+        let mut top_email_domains: Vec<DomainCount> = domains.into_iter().map(|(domain, count)| DomainCount { domain, count }).collect();
+        top_email_domains.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.domain.cmp(&b.domain)));
+        top_email_domains.truncate(TOP_EMAIL_DOMAINS as usize);
+
+        Ok(UserStats {
+            total,
+            active: total - deleted,
+            deleted,
+            created_per_day,
+            distinct_email_domains,
+            top_email_domains,
+        })
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        self.state()?.update_user(id, Some(name.to_string()), Some(email.to_string()))
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let mut state = self.state()?;
+        match state.users.get(&id).map(|stored| stored.user.deleted_at.is_some()) {
+            Some(true) => Ok(None),
+            Some(false) => Ok(state.update_user(id, Some(name.to_string()), Some(email.to_string()))?.map(Upserted::Updated)),
+            None => Ok(Some(Upserted::Created(state.insert_user(Some(id), name, email)?))),
+        }
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        self.state()?.update_user(id, patch.name.clone(), patch.email.clone())
+    }
+
+    // The lock is held across the change, so concurrent changes don't interleave
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        let mut state = self.state()?;
+        let Some(current) = state.live_user(id).map(|stored| stored.user.clone()) else {
+            return Ok(None);
+        };
+        let changed = change(current).map_err(RepositoryError::Rejected)?;
+        state.update_user(id, Some(changed.name), Some(changed.email))
+    }
+
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        let mut state = self.state()?;
+        Ok(patches.into_iter().map(|(id, patch)| state.update_user(id, patch.name, patch.email)).collect())
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        Ok(!self.delete_many(vec![id]).await?.is_empty())
+    }
+
+    // Under restrict nothing changes unless none of the users has posts
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let mut state = self.state()?;
+        let mut deleted: Vec<i32> = Vec::new();
+        for id in ids {
+            if state.live_user(id).is_some() && !deleted.contains(&id) {
+                deleted.push(id);
+            }
+        }
+        match self.on_user_delete {
+            OnUserDelete::Keep => {}
+            OnUserDelete::Cascade => state.posts.retain(|_, post| !deleted.contains(&post.user_id)),
+            OnUserDelete::Restrict => {
+                let mut owners: Vec<i32> =
+                    deleted.iter().copied().filter(|id| state.posts.values().any(|post| post.user_id == *id)).collect();
+                if !owners.is_empty() {
+                    owners.sort_unstable();
+                    return Err(RepositoryError::HasPosts(owners));
+                }
+            }
+        }
+        let now = Utc::now();
+        for id in &deleted {
+            if let Some(stored) = state.users.get_mut(id) {
+                stored.user.deleted_at = Some(now);
+                stored.user.updated_at = Some(now);
+                stored.user.version += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        let mut state = self.state()?;
+        let Some(email) = state.users.get(&id).map(|stored| stored.user.email.clone()) else {
+            return Ok(None);
+        };
+        if state.live_user(id).is_none() && state.email_taken(&email, Some(id)) {
+            return Err(RepositoryError::EmailTaken);
+        }
+        let Some(stored) = state.users.get_mut(&id) else {
+            return Ok(None);
+        };
+        // Restoring a live user changes nothing, so it keeps its version
+        if stored.user.deleted_at.take().is_some() {
+            stored.user.version += 1;
+            stored.user.updated_at = Some(Utc::now());
+        }
+        Ok(Some(stored.user.clone()))
+    }
+
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        let mut state = self.state()?;
+        match state.users.get_mut(&id).filter(|stored| stored.user.deleted_at.is_none()) {
+            Some(stored) => {
+                stored.password_hash = Some(password_hash.to_string());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        let mut state = self.state()?;
+        match state.users.get_mut(&id).filter(|stored| stored.user.deleted_at.is_none()) {
+            Some(stored) => {
+                stored.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        let state = self.state()?;
+        let credentials = state
+            .users
+            .values()
+            .filter(|stored| stored.user.deleted_at.is_none() && stored.user.email.eq_ignore_ascii_case(email))
+            .find_map(|stored| {
+                Some(Credentials {
+                    user_id: stored.user.id?,
+                    password_hash: stored.password_hash.clone()?,
+                    role: stored.role,
+                })
+            });
+        Ok(credentials)
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.state().map(|_| ())
+    }
+}
+
+#[async_trait]
+impl PostRepository for MemoryRepository {
+    async fn create(&self, user_id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let mut state = self.state()?;
+        if state.live_user(user_id).is_none() {
+            return Ok(None);
+        }
+        state.last_post_id += 1;
+        let post = Post {
+            id: state.last_post_id,
+            user_id,
+            title: title.to_string(),
+            body: body.to_string(),
+        };
+        state.posts.insert(post.id, post.clone());
+        Ok(Some(post))
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Post>, RepositoryError> {
+        Ok(self.state()?.visible_post(id).cloned())
+    }
+
+    async fn list(&self, query: &PostListQuery) -> Result<PostList, RepositoryError> {
+        let state = self.state()?;
+        let mut posts: Vec<Post> = state
+            .posts
+            .values()
+            .filter(|post| state.live_user(post.user_id).is_some())
+            .filter(|post| query.user_id.is_none_or(|user_id| post.user_id == user_id))
+            .cloned()
+            .collect();
+        posts.sort_by_key(|post| post.id);
+        let total = posts.len() as i64;
+        Ok(PostList { posts: page(posts, query.limit, query.offset), total })
+    }
+
+    async fn update(&self, id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let mut state = self.state()?;
+        if state.visible_post(id).is_none() {
+            return Ok(None);
+        }
+        let Some(post) = state.posts.get_mut(&id) else {
+            return Ok(None);
+        };
+        post.title = title.to_string();
+        post.body = body.to_string();
+        Ok(Some(post.clone()))
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let mut state = self.state()?;
+        if state.visible_post(id).is_none() {
+            return Ok(false);
+        }
+        Ok(state.posts.remove(&id).is_some())
+    }
+}
+
+#[async_trait]
+impl GroupRepository for MemoryRepository {
+    async fn create(&self, name: &str) -> Result<Group, RepositoryError> {
+        let mut state = self.state()?;
+        if state.groups.values().any(|group| group.name.to_lowercase() == name.to_lowercase()) {
+            return Err(RepositoryError::GroupNameTaken);
+        }
+        state.last_group_id += 1;
+        let group = Group {
+            id: state.last_group_id,
+            name: name.to_string(),
+        };
+        state.groups.insert(group.id, group.clone());
+        Ok(group)
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Group>, RepositoryError> {
+        Ok(self.state()?.groups.get(&id).cloned())
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<GroupList, RepositoryError> {
+        let state = self.state()?;
+        let mut groups: Vec<Group> = state.groups.values().cloned().collect();
+        groups.sort_by_key(|group| (group.name.to_lowercase(), group.id));
+        let total = groups.len() as i64;
+        Ok(GroupList { groups: page(groups, limit, offset), total })
+    }
+
+    async fn members(&self, group_id: i32, limit: i64, offset: i64) -> Result<MemberList, RepositoryError> {
+        let state = self.state()?;
+        let mut users: Vec<User> = state
+            .memberships
+            .iter()
+            .filter(|(group, _)| *group == group_id)
+            .filter_map(|(_, user_id)| state.live_user(*user_id))
+            .map(|stored| stored.user.clone())
+            .collect();
+        users.sort_by_key(|user| user.id);
+        let total = users.len() as i64;
+        Ok(MemberList { users: page(users, limit, offset), total })
+    }
+
+    async fn add_member(&self, group_id: i32, user_id: i32) -> Result<AddMember, RepositoryError> {
+        let mut state = self.state()?;
+        if !state.groups.contains_key(&group_id) {
+            return Ok(AddMember::NoSuchGroup);
+        }
+        if state.live_user(user_id).is_none() {
+            return Ok(AddMember::NoSuchUser);
+        }
+        Ok(match state.memberships.insert((group_id, user_id)) {
+            true => AddMember::Added,
+            false => AddMember::AlreadyMember,
+        })
+    }
+
+    async fn remove_member(&self, group_id: i32, user_id: i32) -> Result<bool, RepositoryError> {
+        Ok(self.state()?.memberships.remove(&(group_id, user_id)))
+    }
+
+    async fn user_groups(&self, user_id: i32) -> Result<Vec<Group>, RepositoryError> {
+        let state = self.state()?;
+        let mut groups: Vec<Group> = state
+            .memberships
+            .iter()
+            .filter(|(_, user)| *user == user_id)
+            .filter_map(|(group_id, _)| state.groups.get(group_id).cloned())
+            .collect();
+        groups.sort_by_key(|group| (group.name.to_lowercase(), group.id));
+        Ok(groups)
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryRepository {
+    async fn create(&self, name: &str, role: Role, key_hash: &str) -> Result<ApiKey, RepositoryError> {
+        let mut state = self.state()?;
+        state.last_api_key_id += 1;
+        let api_key = ApiKey {
+            id: state.last_api_key_id,
+            name: name.to_string(),
+            role,
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        let stored = StoredApiKey {
+            api_key: api_key.clone(),
+            key_hash: key_hash.to_string(),
+        };
+        state.api_keys.insert(api_key.id, stored);
+        Ok(api_key)
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        let state = self.state()?;
+        let mut keys: Vec<ApiKey> = state.api_keys.values().map(|stored| stored.api_key.clone()).collect();
+        keys.sort_by_key(|key| key.id);
+        Ok(keys)
+    }
+
+    async fn revoke(&self, id: i32) -> Result<bool, RepositoryError> {
+        let mut state = self.state()?;
+        match state.api_keys.get_mut(&id).filter(|stored| stored.api_key.revoked_at.is_none()) {
+            Some(stored) => {
+                stored.api_key.revoked_at = Some(Utc::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn find_active(&self, key_hash: &str) -> Result<Option<(i32, Role)>, RepositoryError> {
+        let state = self.state()?;
+        let key = state
+            .api_keys
+            .values()
+            .find(|stored| stored.key_hash == key_hash && stored.api_key.revoked_at.is_none())
+            .map(|stored| (stored.api_key.id, stored.api_key.role));
+        Ok(key)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MemoryRepository {
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<i32, RepositoryError> {
+        let mut state = self.state()?;
+        state.last_session_id += 1;
+        let session = Session {
+            user_id,
+            token_hash: token_hash.to_string(),
+            expires_at,
+            revoked: false,
+        };
+        let id = state.last_session_id;
+        state.sessions.insert(id, session);
+        Ok(id)
+    }
+
+    async fn refresh_session(
+        &self,
+        token_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Refreshed>, RepositoryError> {
+        let mut state = self.state()?;
+        let now = Utc::now();
+        let old = state
+            .sessions
+            .iter()
+            .find(|(_, session)| session.token_hash == token_hash && !session.revoked && session.expires_at > now)
+            .map(|(id, session)| (*id, session.user_id))
+            .filter(|(_, user_id)| state.live_user(*user_id).is_some());
+        let Some((old_id, user_id)) = old else {
+            return Ok(None);
+        };
+        if let Some(session) = state.sessions.get_mut(&old_id) {
+            session.revoked = true;
+        }
+
+        state.last_session_id += 1;
+        let session_id = state.last_session_id;
+        let session = Session {
+            user_id,
+            token_hash: new_hash.to_string(),
+            expires_at,
+            revoked: false,
+        };
+        state.sessions.insert(session_id, session);
+        Ok(Some(Refreshed {
+            session_id,
+            user_id,
+            role: state.role(user_id),
+        }))
+    }
+
+    async fn revoke_session(&self, user_id: i32, session_id: i32) -> Result<(), RepositoryError> {
+        let mut state = self.state()?;
+        if let Some(session) = state.sessions.get_mut(&session_id).filter(|session| session.user_id == user_id) {
+            session.revoked = true;
+        }
+        Ok(())
+    }
+
+    async fn revoke_access(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let mut state = self.state()?;
+        let now = Utc::now();
+        state.revoked.retain(|_, expires_at| *expires_at > now);
+        state.revoked.entry(jti.to_string()).or_insert(expires_at);
+        Ok(())
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, RepositoryError> {
+        Ok(self.state()?.revoked.contains_key(jti))
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MemoryRepository {
+    async fn record(&self, record: AuditRecord) -> Result<(), RepositoryError> {
+        let mut state = self.state()?;
+        let entry = AuditEntry {
+            id: state.audit.len() as i64 + 1,
+            actor: record.actor,
+            action: record.action,
+            path: record.path,
+            entity_id: record.entity_id,
+            status: record.status,
+            old_value: record.old_value,
+            new_value: record.new_value,
+            created_at: Utc::now(),
+        };
+        state.audit.push(entry);
+        Ok(())
+    }
+
+    async fn list(&self, query: &AuditQuery) -> Result<AuditList, RepositoryError> {
+        let state = self.state()?;
+        let entries: Vec<AuditEntry> = state
+            .audit
+            .iter()
+            .rev()
+            .filter(|entry| query.actor.is_none() || entry.actor == query.actor)
+            .cloned()
+            .collect();
+        let total = entries.len() as i64;
+        Ok(AuditList { entries: page(entries, query.limit, query.offset), total })
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for MemoryRepository {
+    async fn claim(&self, key_hash: &str, request_hash: &str, expires_at: DateTime<Utc>) -> Result<IdempotencyClaim, RepositoryError> {
+        let mut state = self.state()?;
+        let now = Utc::now();
+        state.idempotency_keys.retain(|_, record| record.expires_at > now);
+        if let Some(record) = state.idempotency_keys.get(key_hash) {
+            return Ok(IdempotencyClaim::of_record(request_hash, &record.request_hash, record.response.clone()));
+        }
+        let record = IdempotencyRecord {
+            request_hash: request_hash.to_string(),
+            response: None,
+            expires_at,
+        };
+        state.idempotency_keys.insert(key_hash.to_string(), record);
+        Ok(IdempotencyClaim::Claimed)
+    }
+
+    async fn complete(&self, key_hash: &str, response: &StoredResponse, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        if let Some(record) = self.state()?.idempotency_keys.get_mut(key_hash) {
+            record.response = Some(response.clone());
+            record.expires_at = expires_at;
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key_hash: &str) -> Result<(), RepositoryError> {
+        let mut state = self.state()?;
+        if state.idempotency_keys.get(key_hash).is_some_and(|record| record.response.is_none()) {
+            state.idempotency_keys.remove(key_hash);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MemoryRepository {
+    async fn create(&self, url: &str, secret: &str, events: &[String]) -> Result<Webhook, RepositoryError> {
+        let mut state = self.state()?;
+        state.last_webhook_id += 1;
+        let webhook = Webhook {
+            id: state.last_webhook_id,
+            url: url.to_string(),
+            events: events.to_vec(),
+            created_at: Utc::now(),
+        };
+        let stored = StoredWebhook {
+            webhook: webhook.clone(),
+            secret: secret.to_string(),
+        };
+        state.webhooks.insert(webhook.id, stored);
+        Ok(webhook)
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        let state = self.state()?;
+        let mut webhooks: Vec<Webhook> = state.webhooks.values().map(|stored| stored.webhook.clone()).collect();
+        webhooks.sort_by_key(|webhook| webhook.id);
+        Ok(webhooks)
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Webhook>, RepositoryError> {
+        Ok(self.state()?.webhooks.get(&id).map(|stored| stored.webhook.clone()))
+    }
+
+    async fn update(&self, id: i32, url: &str, secret: Option<&str>, events: &[String]) -> Result<Option<Webhook>, RepositoryError> {
+        let mut state = self.state()?;
+        let Some(stored) = state.webhooks.get_mut(&id) else {
+            return Ok(None);
+        };
+        stored.webhook.url = url.to_string();
+        stored.webhook.events = events.to_vec();
+        if let Some(secret) = secret {
+            stored.secret = secret.to_string();
+        }
+        Ok(Some(stored.webhook.clone()))
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        Ok(self.state()?.webhooks.remove(&id).is_some())
+    }
+
+    async fn subscribers(&self, event_type: &str) -> Result<Vec<WebhookTarget>, RepositoryError> {
+        let state = self.state()?;
+        let mut targets: Vec<WebhookTarget> = state
+            .webhooks
+            .values()
+            .filter(|stored| stored.webhook.events.iter().any(|event| event == event_type))
+            .map(|stored| WebhookTarget {
+                id: stored.webhook.id,
+                url: stored.webhook.url.clone(),
+                secret: stored.secret.clone(),
+            })
+            .collect();
+        targets.sort_by_key(|target| target.id);
+        Ok(targets)
+    }
+}
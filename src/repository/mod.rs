@@ -0,0 +1,694 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::config::{Config, OnUserDelete, RedisConfig};
+use crate::events::Events;
+use crate::http::Response;
+use crate::models::{ApiKey, AuditEntry, Group, Post, Role, SearchHit, User, UserPatch, UserStats, Webhook};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::{db, migrations};
+
+#[cfg(feature = "redis")]
+mod cached;
+mod listener;
+mod lru_cache;
+pub mod memory;
+#[cfg(feature = "mysql")]
+pub mod mysql;
+mod notifying;
+pub mod postgres;
+mod replicas;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod tenants;
+
+#[cfg(feature = "redis")]
+use cached::CachedUserRepository;
+use lru_cache::LruCachedUserRepository;
+pub use memory::MemoryRepository;
+#[cfg(feature = "mysql")]
+pub use self::mysql::MysqlUserRepository;
+use notifying::NotifyingUserRepository;
+pub use postgres::PostgresUserRepository;
+use replicas::Replicas;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteUserRepository;
+pub use tenants::Tenants;
+
+// How many email domains the stats report individually
+pub const TOP_EMAIL_DOMAINS: i64 = 10;
+
+// Columns clients may sort the user list by
+pub const SORTABLE_COLUMNS: [&str; 3] = ["id", "name", "email"];
+
+// Fields of a user that clients may pick with `?fields=`, named as in its JSON and in the users table
+pub const USER_FIELDS: [&str; 7] = ["id", "name", "email", "created_at", "updated_at", "deleted_at", "version"];
+
+#[derive(Clone)]
+// Listing options: page (`limit` plus `offset` or an `after` cursor), sort order and filters
+pub struct UserListQuery {
+    pub limit: i64,
+    pub offset: i64,
+    pub after: Option<i32>,
+    // Always one of SORTABLE_COLUMNS, so it is safe to splice into SQL
+    pub sort: &'static str,
+    pub order: &'static str,
+    pub name: Option<String>,
+    pub email_contains: Option<String>,
+    // Also list soft-deleted users
+    pub include_deleted: bool,
+    // The only fields read, each one of USER_FIELDS; None for all of them. The id is always read,
+    // and the fields left out come back empty
+    pub fields: Option<Vec<&'static str>>,
+}
+
+impl UserListQuery {
+    // The select list for the query's fields: each of `columns` in turn, or its stand-in (an empty
+    // value of the column's type) when it isn't wanted, so rows decode the same either way
+    pub(crate) fn select_list(&self, columns: &[(&str, &str)]) -> String {
+        let wanted = |column: &str| column == "id" || self.fields.as_ref().is_none_or(|fields| fields.contains(&column));
+        let columns: Vec<String> = columns
+            .iter()
+            .map(|(column, stand_in)| match wanted(column) {
+                true => format!("users.{}", column),
+                false => format!("{} AS {}", stand_in, column),
+            })
+            .collect();
+        columns.join(", ")
+    }
+}
+
+// One page of users; `total` is only counted for offset pagination
+pub struct UserList {
+    pub users: Vec<User>,
+    pub total: Option<i64>,
+    pub has_more: bool,
+}
+
+// Free-text search over live users' names and emails, one page at a time
+pub struct UserSearch {
+    pub text: String,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// One page of search hits, best match first, and how many users matched in all
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub total: i64,
+}
+
+// Listing options for posts: one page, optionally of a single user's posts
+pub struct PostListQuery {
+    pub user_id: Option<i32>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// One page of posts, oldest first, and how many posts matched in all
+pub struct PostList {
+    pub posts: Vec<Post>,
+    pub total: i64,
+}
+
+// One page of groups by name, and how many groups there are
+pub struct GroupList {
+    pub groups: Vec<Group>,
+    pub total: i64,
+}
+
+// One page of a group's live members by id, and how many there are
+pub struct MemberList {
+    pub users: Vec<User>,
+    pub total: i64,
+}
+
+// Outcome of adding a user to a group
+pub enum AddMember {
+    Added,
+    AlreadyMember,
+    NoSuchGroup,
+    // No live user with this id
+    NoSuchUser,
+}
+
+// Read-modify-write step applied to a locked user; an Err aborts the change
+pub type UserChange = Box<dyn FnOnce(User) -> Result<User, Response> + Send>;
+
+// Result of a PUT that may create the user at the requested id
+pub enum Upserted {
+    Created(User),
+    Updated(User),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepositoryError {
+    // Another user already has this email
+    #[error("email already taken")]
+    EmailTaken,
+    // Another group already has this name
+    #[error("group name already taken")]
+    GroupNameTaken,
+    // Some other uniqueness constraint was violated
+    #[error("conflicting user")]
+    Conflict,
+    // No connection to the store could be obtained
+    #[error("store unavailable: {0}")]
+    Unavailable(String),
+    // The store is down, and isn't tried again for this long
+    #[error("store down, circuit breaker open for {0:?}")]
+    CircuitOpen(Duration),
+    // The query ran past the request's deadline and was cancelled
+    #[error("query cancelled at the request's deadline")]
+    TimedOut,
+    // A UserChange rejected the update
+    #[error("change rejected with status {}", .0.status)]
+    Rejected(Response),
+    // These users still have posts and POSTS_ON_USER_DELETE is restrict
+    #[error("users {0:?} still have posts")]
+    HasPosts(Vec<i32>),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl RepositoryError {
+    // Errors caused by one row conflicting with another, which batches report per item
+    pub fn is_conflict(&self) -> bool {
+        matches!(
+            self,
+            RepositoryError::EmailTaken | RepositoryError::GroupNameTaken | RepositoryError::Conflict
+        )
+    }
+}
+
+// Storage for users; handlers only talk to this trait so backends can be swapped
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError>;
+
+    // Insert every user (ids are ignored) in one transaction. A conflicting email fails only
+    // its own item; any other error aborts the whole batch
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError>;
+
+    // Soft-deleted users are only found when `include_deleted` is set
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError>;
+
+    async fn list(&self, query: &UserListQuery) -> Result<UserList, RepositoryError>;
+
+    // Send every user matching the query's filters to `sink` in its sort order, a few rows at a
+    // time rather than all at once; paging is ignored. Stops early once the receiver is dropped
+    async fn stream(&self, query: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError>;
+
+    // Number of users matching the query's filters; paging and sorting are ignored
+    async fn count(&self, query: &UserListQuery) -> Result<i64, RepositoryError>;
+
+    // Rank live users by how closely their name or email matches the search text
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError>;
+
+    // Totals plus per-day creations over the last `days` days and email domain counts
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError>;
+
+    // Replace both fields; None if there is no live (not soft-deleted) user with this id
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError>;
+
+    // Replace the live user, or insert one with this exact id if there is none.
+    // None if the id belongs to a soft-deleted user, which must be restored first
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError>;
+
+    // Change only the fields present in the patch; None if there is no live user
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError>;
+
+    // Apply `change` to the live user atomically, so concurrent changes don't interleave
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError>;
+
+    // Apply each patch in one transaction; missing users come back as None, and a conflicting
+    // email fails only its own item while any other error aborts the whole batch
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError>;
+
+    // Soft delete by stamping deleted_at, dealing with the user's posts as POSTS_ON_USER_DELETE
+    // says; false if there is no live user with this id
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError>;
+
+    // Soft-delete every live user among `ids` in one transaction, returning those deleted.
+    // Under restrict, one user with posts fails the whole batch with HasPosts
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError>;
+
+    // Clear deleted_at; restoring a live user is a no-op. None if the user doesn't exist
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError>;
+
+    // Store the live user's password hash; false if there is no live user with this id.
+    // The password isn't part of the user's representation, so the version stays put
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError>;
+
+    // Set the live user's role; false if there is no live user with this id. Like the password,
+    // the role isn't part of the user's representation
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError>;
+
+    // Login details of the live user with this email (in any case), if they have a password
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError>;
+
+    // Run `SELECT 1` on a pooled connection, to check the database is reachable
+    async fn ping(&self) -> Result<(), RepositoryError>;
+
+    // Release connections once the server has drained
+    fn close(&self) {}
+
+    // How busy the connection pool is; None for stores without one
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+
+    // How the in-memory cache of users is doing; None for stores without one
+    fn cache_status(&self) -> Option<CacheStatus> {
+        None
+    }
+}
+
+// Connection pool occupancy, for monitoring
+pub struct PoolStatus {
+    pub max_size: usize,
+    // Connections open, whether in use or idle
+    pub size: usize,
+    // Idle connections ready to be handed out
+    pub available: usize,
+    // Requests waiting for a connection
+    pub waiting: usize,
+}
+
+// Use of the in-memory cache of users fetched by id, for monitoring
+pub struct CacheStatus {
+    pub hits: u64,
+    pub misses: u64,
+    // Users cached now, out of at most `capacity`
+    pub size: usize,
+    pub capacity: usize,
+}
+
+// Storage for posts. Posts of soft-deleted users are hidden until the user is restored
+#[async_trait]
+pub trait PostRepository: Send + Sync {
+    // None if there is no live user with this id
+    async fn create(&self, user_id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError>;
+
+    async fn get(&self, id: i32) -> Result<Option<Post>, RepositoryError>;
+
+    async fn list(&self, query: &PostListQuery) -> Result<PostList, RepositoryError>;
+
+    // Replace the title and body; None if there is no such (visible) post
+    async fn update(&self, id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError>;
+
+    // Delete permanently; false if there is no such (visible) post
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError>;
+}
+
+// Storage for groups and their memberships. Soft-deleted users keep their memberships,
+// but are left out of member lists until restored
+#[async_trait]
+pub trait GroupRepository: Send + Sync {
+    async fn create(&self, name: &str) -> Result<Group, RepositoryError>;
+
+    async fn get(&self, id: i32) -> Result<Option<Group>, RepositoryError>;
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<GroupList, RepositoryError>;
+
+    async fn members(&self, group_id: i32, limit: i64, offset: i64) -> Result<MemberList, RepositoryError>;
+
+    // Adding an existing member changes nothing
+    async fn add_member(&self, group_id: i32, user_id: i32) -> Result<AddMember, RepositoryError>;
+
+    // False if the user wasn't a member
+    async fn remove_member(&self, group_id: i32, user_id: i32) -> Result<bool, RepositoryError>;
+
+    // Every group the user belongs to, by name
+    async fn user_groups(&self, user_id: i32) -> Result<Vec<Group>, RepositoryError>;
+}
+
+// A live user's password hash and role, for checking a login
+pub struct Credentials {
+    pub user_id: i32,
+    pub password_hash: String,
+    pub role: Role,
+}
+
+// Storage for service API keys, looked up by the SHA-256 digest of the key
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    async fn create(&self, name: &str, role: Role, key_hash: &str) -> Result<ApiKey, RepositoryError>;
+
+    // Every key, revoked ones included, oldest first
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError>;
+
+    // False if there is no unrevoked key with this id
+    async fn revoke(&self, id: i32) -> Result<bool, RepositoryError>;
+
+    // The id and role of the unrevoked key with this digest
+    async fn find_active(&self, key_hash: &str) -> Result<Option<(i32, Role)>, RepositoryError>;
+}
+
+// A refresh token exchanged for a new one: the new session and who it belongs to
+pub struct Refreshed {
+    pub session_id: i32,
+    pub user_id: i32,
+    pub role: Role,
+}
+
+// Storage for login sessions (refresh tokens, looked up by SHA-256 digest) and for access tokens
+// revoked before they expire
+#[async_trait]
+pub trait TokenRepository: Send + Sync {
+    // Start a session for the user, returning its id
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<i32, RepositoryError>;
+
+    // Atomically revoke the unexpired, unrevoked session with `token_hash` and start a new one
+    // with `new_hash`. None if there is no such session or its user is no longer live
+    async fn refresh_session(
+        &self,
+        token_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Refreshed>, RepositoryError>;
+
+    // Revoke the user's session; revoking it twice changes nothing
+    async fn revoke_session(&self, user_id: i32, session_id: i32) -> Result<(), RepositoryError>;
+
+    // Reject the access token with this jti until it expires; expired entries are pruned
+    async fn revoke_access(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), RepositoryError>;
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, RepositoryError>;
+}
+
+// An audit log entry to record; the store assigns the id and timestamp
+pub struct AuditRecord {
+    pub actor: Option<String>,
+    pub action: String,
+    pub path: String,
+    pub entity_id: Option<i32>,
+    pub status: u16,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+}
+
+// A page of the audit log, optionally for one actor
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+pub struct AuditList {
+    pub entries: Vec<AuditEntry>,
+    pub total: i64,
+}
+
+// Storage for the audit log; entries are only ever added
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    async fn record(&self, record: AuditRecord) -> Result<(), RepositoryError>;
+
+    // Newest first
+    async fn list(&self, query: &AuditQuery) -> Result<AuditList, RepositoryError>;
+}
+
+// A response kept for an Idempotency-Key, to replay when the request is sent again
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+// What claiming an idempotency key found
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyClaim {
+    // The key was free, so the request should run
+    Claimed,
+    // Another request with this key hasn't finished yet
+    InProgress,
+    // The key was used for a request with a different fingerprint
+    Mismatch,
+    // The key's request already ran and got this response
+    Completed(StoredResponse),
+}
+
+impl IdempotencyClaim {
+    // What an unexpired record means for a request with `request_hash`; `response` is None while
+    // the record's request is still running
+    fn of_record(request_hash: &str, record_hash: &str, response: Option<StoredResponse>) -> IdempotencyClaim {
+        match response {
+            _ if request_hash != record_hash => IdempotencyClaim::Mismatch,
+            Some(response) => IdempotencyClaim::Completed(response),
+            None => IdempotencyClaim::InProgress,
+        }
+    }
+}
+
+// Storage for Idempotency-Key records, by a SHA-256 digest of the caller and the key. Records
+// expire; expired ones are pruned as keys are claimed
+#[async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    // Claim the key for a request with this fingerprint until `expires_at`, unless an unexpired
+    // record already holds it
+    async fn claim(&self, key_hash: &str, request_hash: &str, expires_at: DateTime<Utc>) -> Result<IdempotencyClaim, RepositoryError>;
+
+    // Store the response of the claimed key's request and keep it until `expires_at`
+    async fn complete(&self, key_hash: &str, response: &StoredResponse, expires_at: DateTime<Utc>) -> Result<(), RepositoryError>;
+
+    // Give up a claim, so the request can be retried with the same key
+    async fn release(&self, key_hash: &str) -> Result<(), RepositoryError>;
+}
+
+// Where and how to deliver one webhook's events
+pub struct WebhookTarget {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+// Storage for webhook subscriptions. Secrets are kept as given, since deliveries are signed with them
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn create(&self, url: &str, secret: &str, events: &[String]) -> Result<Webhook, RepositoryError>;
+
+    // Oldest first
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError>;
+
+    async fn get(&self, id: i32) -> Result<Option<Webhook>, RepositoryError>;
+
+    // Replace the URL and events, and the secret when one is given; None if there is no such webhook
+    async fn update(&self, id: i32, url: &str, secret: Option<&str>, events: &[String]) -> Result<Option<Webhook>, RepositoryError>;
+
+    // False if there is no such webhook
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError>;
+
+    // Every webhook subscribed to `event_type`
+    async fn subscribers(&self, event_type: &str) -> Result<Vec<WebhookTarget>, RepositoryError>;
+}
+
+// Event types are stored as one comma-separated column in every backend
+fn join_events(events: &[String]) -> String {
+    events.join(",")
+}
+
+fn split_events(events: &str) -> Vec<String> {
+    events.split(',').filter(|event| !event.is_empty()).map(str::to_string).collect()
+}
+
+// The stores handlers work with; every backend keeps users and posts in the same database
+#[derive(Clone)]
+pub struct Stores {
+    pub users: Arc<dyn UserRepository>,
+    pub posts: Arc<dyn PostRepository>,
+    pub groups: Arc<dyn GroupRepository>,
+    pub api_keys: Arc<dyn ApiKeyRepository>,
+    pub tokens: Arc<dyn TokenRepository>,
+    pub audit: Arc<dyn AuditRepository>,
+    pub idempotency: Arc<dyn IdempotencyRepository>,
+    pub webhooks: Arc<dyn WebhookRepository>,
+    // Changes to users, published by `users` as they are committed, or by a listener for Postgres
+    pub events: Events,
+    // The tenant whose data these are; None for the default dataset
+    pub tenant: Option<String>,
+    // Every tenant, when tenancy is on; only the default stores have it
+    pub tenants: Option<Arc<Tenants>>,
+}
+
+impl Stores {
+    // Fresh, empty stores kept in memory, for tests that shouldn't need a database
+    pub fn in_memory(on_user_delete: OnUserDelete) -> Stores {
+        Stores::shared(MemoryRepository::new(on_user_delete), true)
+    }
+
+    // `publish` wraps the user store so it publishes the changes it makes. Postgres leaves that to
+    // NOTIFY instead, which also hears about changes made outside the API
+    fn shared<R>(repository: R, publish: bool) -> Stores
+    where
+        R: UserRepository
+            + PostRepository
+            + GroupRepository
+            + ApiKeyRepository
+            + TokenRepository
+            + AuditRepository
+            + IdempotencyRepository
+            + WebhookRepository
+            + 'static,
+    {
+        let repository = Arc::new(repository);
+        let events = Events::new();
+        let users: Arc<dyn UserRepository> = match publish {
+            true => Arc::new(NotifyingUserRepository::new(repository.clone(), events.clone())),
+            false => repository.clone(),
+        };
+        Stores {
+            users,
+            posts: repository.clone(),
+            groups: repository.clone(),
+            api_keys: repository.clone(),
+            tokens: repository.clone(),
+            audit: repository.clone(),
+            idempotency: repository.clone(),
+            webhooks: repository,
+            events,
+            tenant: None,
+            tenants: None,
+        }
+    }
+}
+
+// Open the store named by DATABASE_URL's scheme, with users cached in Redis when REDIS_URL is set
+// and in memory when USER_CACHE_SIZE is
+pub async fn connect(config: &Config) -> Result<Stores, String> {
+    let stores = open(config).await?;
+    cache(stores, config.redis.as_ref(), config.user_cache_size)
+}
+
+// Wrap the user store in the caches configured, keeping a tenant's users apart from others' in Redis
+fn cache(mut stores: Stores, redis: Option<&RedisConfig>, user_cache_size: usize) -> Result<Stores, String> {
+    if let Some(redis) = redis {
+        stores.users = cache_users(stores.users, redis, stores.tenant.as_deref())?;
+    }
+    if let Some(capacity) = NonZeroUsize::new(user_cache_size) {
+        info!("Caching up to {} users in memory", capacity);
+        stores.users = Arc::new(LruCachedUserRepository::new(stores.users, capacity, &stores.events));
+    }
+    Ok(stores)
+}
+
+// `sqlite://` and `mysql://` (or `mariadb://`) need their cargo features, anything else is a
+// Postgres URL migrated to the latest schema. Only Postgres has schemas to keep tenants in, and
+// replicas to read from
+async fn open(config: &Config) -> Result<Stores, String> {
+    let postgres = sqlite_path(&config.db_url).is_none() && mysql_url(&config.db_url).is_none();
+    if config.tenancy.is_some() && !postgres {
+        return Err("TENANCY_ENABLED needs a Postgres DATABASE_URL".to_string());
+    }
+    if !config.db_replica_urls.is_empty() && !postgres {
+        return Err("DATABASE_REPLICA_URLS needs a Postgres DATABASE_URL".to_string());
+    }
+    if let Some(path) = sqlite_path(&config.db_url) {
+        return open_sqlite(path, config.posts_on_user_delete);
+    }
+    if let Some(url) = mysql_url(&config.db_url) {
+        return open_mysql(url, config).await;
+    }
+
+    let pool = db::create_pool(config).map_err(|e| format!("Error creating database pool: {}", e))?;
+    let applied = migrations::run(&pool)
+        .await
+        .map_err(|e| format!("Error running migrations: {}", e))?;
+    for migration in applied {
+        info!("Applied migration {:04} {}", migration.version, migration.name);
+    }
+    info!("Database pool ready with up to {} connections", config.db_pool_size);
+    let mut repository = PostgresUserRepository::new(pool, config.db_retry.clone(), config.posts_on_user_delete);
+    if let Some(replicas) = Replicas::open(config)? {
+        repository = repository.replicas(replicas);
+    }
+    if let Some(breaker) = &config.db_circuit_breaker {
+        repository = repository.circuit_breaker(Arc::new(CircuitBreaker::new("database", breaker.clone())));
+    }
+    if config.handler_timeout.is_some() || !config.route_timeouts.is_empty() {
+        repository = repository.deadlines();
+    }
+    if let Some(threshold) = config.db_slow_query {
+        repository = repository.slow_queries(threshold);
+    }
+    // Connections are shared with tenants, which change their search_path
+    let tenants = match config.tenancy {
+        Some(_) => {
+            repository = repository.on_checkout("RESET search_path".to_string());
+            let tenants = Tenants::open(repository.sharing_pool().on_checkout("RESET search_path".to_string()), config)
+                .await
+                .map_err(|e| format!("Error opening the tenant registry: {}", e))?;
+            Some(Arc::new(tenants))
+        }
+        None => None,
+    };
+    let mut stores = Stores::shared(repository, false);
+    stores.tenants = tenants;
+    listener::spawn(config, stores.users.clone(), stores.events.clone())?;
+    Ok(stores)
+}
+
+// The file path in a `sqlite://path` or `sqlite:path` URL
+fn sqlite_path(url: &str) -> Option<&str> {
+    let path = url.strip_prefix("sqlite:")?;
+    Some(path.strip_prefix("//").unwrap_or(path))
+}
+
+// A MySQL URL for the driver, which only understands the `mysql://` scheme
+fn mysql_url(url: &str) -> Option<String> {
+    if url.starts_with("mysql://") {
+        return Some(url.to_string());
+    }
+    url.strip_prefix("mariadb://").map(|rest| format!("mysql://{}", rest))
+}
+
+#[cfg(feature = "redis")]
+fn cache_users(users: Arc<dyn UserRepository>, config: &RedisConfig, tenant: Option<&str>) -> Result<Arc<dyn UserRepository>, String> {
+    let redis = crate::redis::Redis::new(&config.url, config.timeout)?;
+    info!("Caching users in Redis for {}s", config.cache_ttl.as_secs());
+    Ok(Arc::new(CachedUserRepository::new(users, redis, config.cache_ttl, tenant)))
+}
+
+#[cfg(not(feature = "redis"))]
+fn cache_users(_users: Arc<dyn UserRepository>, _config: &RedisConfig, _tenant: Option<&str>) -> Result<Arc<dyn UserRepository>, String> {
+    Err("REDIS_URL is set but the server was built without the `redis` feature".to_string())
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(path: &str, on_user_delete: OnUserDelete) -> Result<Stores, String> {
+    let repository = SqliteUserRepository::open(path, on_user_delete)?;
+    info!("Using SQLite database {}", path);
+    Ok(Stores::shared(repository, true))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite(_path: &str, _on_user_delete: OnUserDelete) -> Result<Stores, String> {
+    Err("DATABASE_URL is a SQLite URL but the server was built without the `sqlite` feature".to_string())
+}
+
+#[cfg(feature = "mysql")]
+async fn open_mysql(url: String, config: &Config) -> Result<Stores, String> {
+    let (pool_size, timeout, on_user_delete) = (config.db_pool_size, config.db_pool_timeout, config.posts_on_user_delete);
+    let repository =
+        tokio::task::spawn_blocking(move || MysqlUserRepository::open(&url, pool_size, timeout, on_user_delete))
+            .await
+            .map_err(|e| e.to_string())??;
+    info!("MySQL pool ready with up to {} connections", pool_size);
+    Ok(Stores::shared(repository, true))
+}
+
+#[cfg(not(feature = "mysql"))]
+async fn open_mysql(_url: String, _config: &Config) -> Result<Stores, String> {
+    Err("DATABASE_URL is a MySQL URL but the server was built without the `mysql` feature".to_string())
+}
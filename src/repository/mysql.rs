@@ -0,0 +1,1220 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, NaiveTime, SubsecRound, Utc};
+use mysql::prelude::Queryable;
+use mysql::{DriverError, Error as MysqlError, Opts, OptsBuilder, Params, Pool, PoolConstraints, PoolOpts, PooledConn, TxOpts, Value};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use super::{
+    AddMember, ApiKeyRepository, AuditList, AuditQuery, AuditRecord, AuditRepository, Credentials, GroupList, Refreshed, TokenRepository,
+    GroupRepository, IdempotencyClaim, IdempotencyRepository, MemberList, PostList, PostListQuery, PostRepository, RepositoryError,
+    SearchResults, StoredResponse, Upserted, UserChange, UserList, UserListQuery, UserRepository, UserSearch, WebhookRepository, WebhookTarget,
+    TOP_EMAIL_DOMAINS,
+};
+use crate::config::OnUserDelete;
+use crate::migrations::MYSQL_MIGRATIONS;
+use crate::models::{ApiKey, AuditEntry, DailyCount, DomainCount, Group, Post, Role, SearchHit, User, UserPatch, UserStats, Webhook};
+
+// Unique keys on email and group name, named like their Postgres counterparts
+const USERS_EMAIL_INDEX: &str = "users_email_key";
+const GROUPS_NAME_INDEX: &str = "groups_name_key";
+
+// ER_DUP_ENTRY: a unique key was violated
+const DUPLICATE_ENTRY: u16 = 1062;
+
+// Named lock held while migrating, so concurrent instances don't migrate at the same time
+const MIGRATION_LOCK: &str = "rust_crud_migrations";
+
+const USER_COLUMNS: &str = "id, name, email, created_at, deleted_at, version, updated_at";
+
+// USER_COLUMNS with the stand-ins listings read for fields left out; the timestamps UserRow
+// requires get the epoch
+const USER_SELECT: [(&str, &str); 7] = [
+    ("id", "id"),
+    ("name", "''"),
+    ("email", "''"),
+    ("created_at", "TIMESTAMP('1970-01-01')"),
+    ("deleted_at", "NULL"),
+    ("version", "0"),
+    ("updated_at", "TIMESTAMP('1970-01-01')"),
+];
+
+// created_at and deleted_at are DATETIMEs holding UTC
+type UserRow = (i32, String, String, NaiveDateTime, Option<NaiveDateTime>, i32, NaiveDateTime);
+
+const POST_COLUMNS: &str = "id, user_id, title, body";
+
+// Posts of soft-deleted users are hidden until the user is restored
+const LIVE_POSTS: &str = "user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)";
+
+type PostRow = (i32, i32, String, String);
+
+const API_KEY_COLUMNS: &str = "id, name, role, created_at, revoked_at";
+
+type ApiKeyRow = (i32, String, String, NaiveDateTime, Option<NaiveDateTime>);
+
+const WEBHOOK_COLUMNS: &str = "id, url, events, created_at";
+
+type WebhookRow = (i32, String, String, NaiveDateTime);
+
+type IdempotencyRow = (String, Option<u16>, Option<String>, Option<Vec<u8>>);
+
+type AuditRow = (i64, Option<String>, String, String, Option<i32>, u16, Option<String>, Option<String>, NaiveDateTime);
+
+// Users, and their posts, stored in MySQL or MariaDB; the driver is synchronous, so calls run on
+// the blocking thread pool
+pub struct MysqlUserRepository {
+    pool: Pool,
+    // How long a request waits for a pooled connection before failing
+    timeout: Duration,
+    on_user_delete: OnUserDelete,
+}
+
+impl MysqlUserRepository {
+    // Connect with at most `pool_size` connections and migrate the schema; blocks the calling thread
+    pub fn open(url: &str, pool_size: usize, timeout: Duration, on_user_delete: OnUserDelete) -> Result<Self, String> {
+        let opts = Opts::from_url(url).map_err(|e| format!("invalid DATABASE_URL: {}", e))?;
+        let constraints = PoolConstraints::new(0, pool_size).ok_or("DB_POOL_SIZE must be positive")?;
+        let opts = OptsBuilder::from_opts(opts).pool_opts(PoolOpts::default().with_constraints(constraints));
+        let pool = Pool::new(opts).map_err(|e| format!("Error creating database pool: {}", e))?;
+
+        let mut conn = pool
+            .try_get_conn(timeout)
+            .map_err(|e| format!("Error connecting to MySQL: {}", e))?;
+        migrate(&mut conn).map_err(|e| format!("Error running migrations: {}", e))?;
+        Ok(MysqlUserRepository {
+            pool,
+            timeout,
+            on_user_delete,
+        })
+    }
+
+    // Run `f` with a pooled connection on a blocking thread so the runtime isn't stalled
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PooledConn) -> Result<T, RepositoryError> + Send + 'static,
+    {
+        let pool = self.pool.clone();
+        let timeout = self.timeout;
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.try_get_conn(timeout)?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| RepositoryError::Internal(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl UserRepository for MysqlUserRepository {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        self.with_conn(move |conn| {
+            // No RETURNING in MySQL: read the generated id back from the connection
+            let created_at = now();
+            conn.exec_drop(
+                "INSERT INTO users (name, email, created_at, updated_at) VALUES (?, ?, ?, ?)",
+                (&name, &email, created_at.naive_utc(), created_at.naive_utc()),
+            )?;
+            Ok(User {
+                id: Some(conn.last_insert_id() as i32),
+                name,
+                email,
+                created_at: Some(created_at),
+                updated_at: Some(created_at),
+                deleted_at: None,
+                version: 1,
+            })
+        })
+        .await
+    }
+
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let mut transaction = conn.start_transaction(TxOpts::default())?;
+            let mut results = Vec::with_capacity(users.len());
+            let created_at = now();
+            for user in users {
+                // A duplicate key only rolls back its own statement, leaving the transaction usable
+                let inserted = transaction.exec_drop(
+                    "INSERT INTO users (name, email, created_at, updated_at) VALUES (?, ?, ?, ?)",
+                    (&user.name, &user.email, created_at.naive_utc(), created_at.naive_utc()),
+                );
+                match inserted {
+                    Ok(()) => results.push(Ok(User {
+                        id: transaction.last_insert_id().map(|id| id as i32),
+                        created_at: Some(created_at),
+                        updated_at: Some(created_at),
+                        deleted_at: None,
+                        version: 1,
+                        ..user
+                    })),
+                    Err(e) => {
+                        let e = RepositoryError::from(e);
+                        if !e.is_conflict() {
+                            return Err(e);
+                        }
+                        results.push(Err(e));
+                    }
+                }
+            }
+            transaction.commit()?;
+            Ok(results)
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let sql = format!("SELECT {} FROM users WHERE id = ? AND (? OR deleted_at IS NULL)", USER_COLUMNS);
+            let row: Option<UserRow> = conn.exec_first(sql, (id, include_deleted))?;
+            Ok(row.map(user_from_row))
+        })
+        .await
+    }
+
+    async fn list(&self, list: &UserListQuery) -> Result<UserList, RepositoryError> {
+        let list = list.clone();
+        self.with_conn(move |conn| {
+            // Offset pagination reports the filtered total; keyset pagination skips the count
+            let total = match list.after {
+                Some(_) => None,
+                None => {
+                    let (where_sql, params) = where_clause(&list, false);
+                    let sql = format!("SELECT COUNT(*) FROM users{}", where_sql);
+                    conn.exec_first::<i64, _, _>(sql, Params::Positional(params))?
+                }
+            };
+
+            // One extra row tells us whether another page follows
+            let (where_sql, mut params) = where_clause(&list, true);
+            params.push(Value::from(list.limit + 1));
+            let mut sql = format!(
+                "SELECT {} FROM users{} ORDER BY users.{} {}, users.id {} LIMIT ?",
+                list.select_list(&USER_SELECT),
+                where_sql,
+                list.sort,
+                list.order,
+                list.order
+            );
+            if list.after.is_none() {
+                params.push(Value::from(list.offset));
+                sql.push_str(" OFFSET ?");
+            }
+
+            let mut users = conn.exec_map(sql, Params::Positional(params), user_from_row)?;
+            let has_more = users.len() as i64 > list.limit;
+            users.truncate(list.limit as usize);
+            Ok(UserList { users, total, has_more })
+        })
+        .await
+    }
+
+    // The binary protocol hands rows over as they arrive instead of buffering the result
+    async fn stream(&self, list: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        let list = list.clone();
+        self.with_conn(move |conn| {
+            let (where_sql, params) = where_clause(&list, false);
+            let sql = format!(
+                "SELECT {} FROM users{} ORDER BY {} {}, id {}",
+                USER_COLUMNS, where_sql, list.sort, list.order, list.order
+            );
+            for row in conn.exec_iter(sql, Params::Positional(params))? {
+                let user = user_from_row(mysql::from_row(row?));
+                if sink.blocking_send(user).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn count(&self, list: &UserListQuery) -> Result<i64, RepositoryError> {
+        let list = list.clone();
+        self.with_conn(move |conn| {
+            let (where_sql, params) = where_clause(&list, false);
+            let sql = format!("SELECT COUNT(*) FROM users{}", where_sql);
+            Ok(conn.exec_first(sql, Params::Positional(params))?.unwrap_or(0))
+        })
+        .await
+    }
+
+    // No trigram support here: substring matches only, scored exact > prefix > anywhere.
+    // The case-insensitive collation makes the comparisons ignore case
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        let (text, limit, offset) = (search.text.clone(), search.limit, search.offset);
+        self.with_conn(move |conn| {
+            let (prefix, anywhere) = (format!("{}%", escape_like(&text)), format!("%{}%", escape_like(&text)));
+            let matches = "deleted_at IS NULL AND (name LIKE ? OR email LIKE ?)";
+
+            let total = conn
+                .exec_first(format!("SELECT COUNT(*) FROM users WHERE {}", matches), (&anywhere, &anywhere))?
+                .unwrap_or(0);
+            let sql = format!(
+                "SELECT {}, CASE WHEN name = ? OR email = ? THEN 1.0 \
+                 WHEN name LIKE ? OR email LIKE ? THEN 0.75 ELSE 0.5 END AS score \
+                 FROM users WHERE {} ORDER BY score DESC, id LIMIT ? OFFSET ?",
+                USER_COLUMNS, matches
+            );
+            let params: Vec<Value> = vec![
+                text.as_str().into(),
+                text.as_str().into(),
+                prefix.as_str().into(),
+                prefix.as_str().into(),
+                anywhere.as_str().into(),
+                anywhere.as_str().into(),
+                limit.into(),
+                offset.into(),
+            ];
+            let hits = conn.exec_map(sql, Params::Positional(params), |row: mysql::Row| {
+                let (id, name, email, created_at, deleted_at, version, updated_at, score) = mysql::from_row(row);
+                SearchHit {
+                    user: user_from_row((id, name, email, created_at, deleted_at, version, updated_at)),
+                    score,
+                }
+            })?;
+            Ok(SearchResults { hits, total })
+        })
+        .await
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        self.with_conn(move |conn| {
+            let (total, deleted): (i64, i64) =
+                conn.query_first("SELECT COUNT(*), COUNT(deleted_at) FROM users")?.unwrap_or((0, 0));
+
+            // The window starts at midnight UTC so its first day is counted in full
+            let since = (Utc::now().date_naive() - Days::new(days as u64 - 1)).and_time(NaiveTime::MIN);
+            let created_per_day = conn
+                .exec::<(NaiveDate, i64), _, _>(
+                    "SELECT DATE(created_at) AS day, COUNT(*) FROM users \
+                     WHERE deleted_at IS NULL AND created_at >= ? GROUP BY day ORDER BY day",
+                    (since,),
+                )?
+                .into_iter()
+                .map(|(date, count)| DailyCount { date, count })
+                .collect();
+
+            let domains = "SELECT LOWER(SUBSTRING_INDEX(email, '@', -1)) AS domain FROM users WHERE deleted_at IS NULL";
+            let distinct_email_domains = conn
+                .query_first(format!("SELECT COUNT(DISTINCT domain) FROM ({}) d", domains))?
+                .unwrap_or(0);
+            let top_email_domains = conn
+                .exec::<(String, i64), _, _>(
+                    format!(
+                        "SELECT domain, COUNT(*) FROM ({}) d GROUP BY domain ORDER BY COUNT(*) DESC, domain LIMIT ?",
+                        domains
+                    ),
+                    (TOP_EMAIL_DOMAINS,),
+                )?
+                .into_iter()
+                .map(|(domain, count)| DomainCount { domain, count })
+                .collect();
+
+            Ok(UserStats {
+                total,
+                active: total - deleted,
+                deleted,
+                created_per_day,
+                distinct_email_domains,
+                top_email_domains,
+            })
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        self.with_conn(move |conn| {
+            update_in_transaction(
+                conn,
+                id,
+                "UPDATE users SET name = ?, email = ?, version = version + 1, updated_at = UTC_TIMESTAMP(6) \
+                 WHERE id = ? AND deleted_at IS NULL",
+                (name, email, id),
+            )
+        })
+        .await
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        self.with_conn(move |conn| {
+            let mut transaction = conn.start_transaction(TxOpts::default())?;
+            let existing: Option<bool> =
+                transaction.exec_first("SELECT deleted_at IS NOT NULL FROM users WHERE id = ? FOR UPDATE", (id,))?;
+            let upserted = match existing {
+                Some(true) => None,
+                Some(false) => {
+                    transaction.exec_drop(
+                        "UPDATE users SET name = ?, email = ?, version = version + 1, updated_at = UTC_TIMESTAMP(6) WHERE id = ?",
+                        (&name, &email, id),
+                    )?;
+                    let sql = format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS);
+                    transaction.exec_first::<UserRow, _, _>(sql, (id,))?.map(|row| Upserted::Updated(user_from_row(row)))
+                }
+                // AUTO_INCREMENT moves past an explicitly inserted id on its own
+                None => {
+                    let created_at = now();
+                    transaction.exec_drop(
+                        "INSERT INTO users (id, name, email, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+                        (id, &name, &email, created_at.naive_utc(), created_at.naive_utc()),
+                    )?;
+                    Some(Upserted::Created(User {
+                        id: Some(id),
+                        name,
+                        email,
+                        created_at: Some(created_at),
+                        updated_at: Some(created_at),
+                        deleted_at: None,
+                        version: 1,
+                    }))
+                }
+            };
+            transaction.commit()?;
+            Ok(upserted)
+        })
+        .await
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        let (name, email) = (patch.name.clone(), patch.email.clone());
+        self.with_conn(move |conn| {
+            // Absent fields bind as NULL and keep their current value
+            update_in_transaction(
+                conn,
+                id,
+                "UPDATE users SET name = COALESCE(?, name), email = COALESCE(?, email), \
+                 version = version + 1, updated_at = UTC_TIMESTAMP(6) \
+                 WHERE id = ? AND deleted_at IS NULL",
+                (name, email, id),
+            )
+        })
+        .await
+    }
+
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        self.with_conn(move |conn| {
+            // Dropping the transaction without committing rolls it back
+            let mut transaction = conn.start_transaction(TxOpts::default())?;
+            // Lock the row so concurrent changes apply one after another
+            let sql = format!("SELECT {} FROM users WHERE id = ? AND deleted_at IS NULL FOR UPDATE", USER_COLUMNS);
+            let current = match transaction.exec_first::<UserRow, _, _>(sql, (id,))? {
+                Some(row) => user_from_row(row),
+                None => return Ok(None),
+            };
+
+            let changed = change(current).map_err(RepositoryError::Rejected)?;
+            transaction.exec_drop(
+                "UPDATE users SET name = ?, email = ?, version = version + 1, updated_at = UTC_TIMESTAMP(6) WHERE id = ?",
+                (&changed.name, &changed.email, id),
+            )?;
+            let sql = format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS);
+            let row: Option<UserRow> = transaction.exec_first(sql, (id,))?;
+            transaction.commit()?;
+            Ok(row.map(user_from_row))
+        })
+        .await
+    }
+
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let mut transaction = conn.start_transaction(TxOpts::default())?;
+            let select = format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS);
+            let mut results = Vec::with_capacity(patches.len());
+            for (id, patch) in patches {
+                // A duplicate key only rolls back its own statement, leaving the transaction usable
+                let updated = transaction.exec_drop(
+                    "UPDATE users SET name = COALESCE(?, name), email = COALESCE(?, email), \
+                     version = version + 1, updated_at = UTC_TIMESTAMP(6) \
+                     WHERE id = ? AND deleted_at IS NULL",
+                    (patch.name, patch.email, id),
+                );
+                match updated {
+                    Ok(()) if transaction.affected_rows() == 0 => results.push(Ok(None)),
+                    Ok(()) => {
+                        let row: Option<UserRow> = transaction.exec_first(&select, (id,))?;
+                        results.push(Ok(row.map(user_from_row)));
+                    }
+                    Err(e) => {
+                        let e = RepositoryError::from(e);
+                        if !e.is_conflict() {
+                            return Err(e);
+                        }
+                        results.push(Err(e));
+                    }
+                }
+            }
+            transaction.commit()?;
+            Ok(results)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let on_user_delete = self.on_user_delete;
+        self.with_conn(move |conn| Ok(!soft_delete(conn, &[id], on_user_delete)?.is_empty()))
+            .await
+    }
+
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let on_user_delete = self.on_user_delete;
+        self.with_conn(move |conn| soft_delete(conn, &ids, on_user_delete)).await
+    }
+
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let restored = update_in_transaction(
+                conn,
+                id,
+                "UPDATE users SET deleted_at = NULL, version = version + 1, updated_at = UTC_TIMESTAMP(6) \
+                 WHERE id = ? AND deleted_at IS NOT NULL",
+                (id,),
+            )?;
+            // Restoring a live user changes nothing, so it keeps its version
+            match restored {
+                Some(user) => Ok(Some(user)),
+                None => {
+                    let sql = format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS);
+                    Ok(conn.exec_first::<UserRow, _, _>(sql, (id,))?.map(user_from_row))
+                }
+            }
+        })
+        .await
+    }
+
+    // MySQL doesn't count a row whose value didn't change as affected, so a miss is double-checked
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        let password_hash = password_hash.to_string();
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "UPDATE users SET password_hash = ? WHERE id = ? AND deleted_at IS NULL",
+                (&password_hash, id),
+            )?;
+            if conn.affected_rows() > 0 {
+                return Ok(true);
+            }
+            let live: Option<i32> = conn.exec_first("SELECT id FROM users WHERE id = ? AND deleted_at IS NULL", (id,))?;
+            Ok(live.is_some())
+        })
+        .await
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "UPDATE users SET role = ? WHERE id = ? AND deleted_at IS NULL",
+                (role.as_str(), id),
+            )?;
+            // Setting the role a user already has affects no rows
+            if conn.affected_rows() > 0 {
+                return Ok(true);
+            }
+            let live: Option<i32> = conn.exec_first("SELECT id FROM users WHERE id = ? AND deleted_at IS NULL", (id,))?;
+            Ok(live.is_some())
+        })
+        .await
+    }
+
+    // The column's case-insensitive collation matches emails in any case
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        let email = email.to_string();
+        self.with_conn(move |conn| {
+            let row: Option<(i32, String, String)> = conn.exec_first(
+                "SELECT id, password_hash, role FROM users \
+                 WHERE email = ? AND deleted_at IS NULL AND password_hash IS NOT NULL",
+                (&email,),
+            )?;
+            Ok(row.map(|(user_id, password_hash, role)| Credentials {
+                user_id,
+                password_hash,
+                role: role_from_column(&role),
+            }))
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.with_conn(|conn| {
+            conn.query_drop("SELECT 1")?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl PostRepository for MysqlUserRepository {
+    // INSERT ... SELECT share-locks the user row, so a concurrent delete can't slip in between
+    async fn create(&self, user_id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let (title, body) = (title.to_string(), body.to_string());
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "INSERT INTO posts (user_id, title, body) SELECT id, ?, ? FROM users WHERE id = ? AND deleted_at IS NULL",
+                (&title, &body, user_id),
+            )?;
+            if conn.affected_rows() == 0 {
+                return Ok(None);
+            }
+            Ok(Some(Post {
+                id: conn.last_insert_id() as i32,
+                user_id,
+                title,
+                body,
+            }))
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Post>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let sql = format!("SELECT {} FROM posts WHERE id = ? AND {}", POST_COLUMNS, LIVE_POSTS);
+            Ok(conn.exec_first::<PostRow, _, _>(sql, (id,))?.map(post_from_row))
+        })
+        .await
+    }
+
+    async fn list(&self, query: &PostListQuery) -> Result<PostList, RepositoryError> {
+        let (user_id, limit, offset) = (query.user_id, query.limit, query.offset);
+        self.with_conn(move |conn| {
+            let filter = format!("WHERE {} AND (? IS NULL OR user_id = ?)", LIVE_POSTS);
+            let total = conn
+                .exec_first(format!("SELECT COUNT(*) FROM posts {}", filter), (user_id, user_id))?
+                .unwrap_or(0);
+            let sql = format!("SELECT {} FROM posts {} ORDER BY id LIMIT ? OFFSET ?", POST_COLUMNS, filter);
+            let posts = conn.exec_map(sql, (user_id, user_id, limit, offset), post_from_row)?;
+            Ok(PostList { posts, total })
+        })
+        .await
+    }
+
+    // MySQL reports only changed rows as affected, so the post is locked and checked first
+    async fn update(&self, id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let (title, body) = (title.to_string(), body.to_string());
+        self.with_conn(move |conn| {
+            let mut transaction = conn.start_transaction(TxOpts::default())?;
+            let sql = format!("SELECT user_id FROM posts WHERE id = ? AND {} FOR UPDATE", LIVE_POSTS);
+            let user_id = match transaction.exec_first::<i32, _, _>(sql, (id,))? {
+                Some(user_id) => user_id,
+                None => return Ok(None),
+            };
+            transaction.exec_drop("UPDATE posts SET title = ?, body = ? WHERE id = ?", (&title, &body, id))?;
+            transaction.commit()?;
+            Ok(Some(Post { id, user_id, title, body }))
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop(format!("DELETE FROM posts WHERE id = ? AND {}", LIVE_POSTS), (id,))?;
+            Ok(conn.affected_rows() > 0)
+        })
+        .await
+    }
+}
+
+// `groups` is a reserved word in MySQL 8, hence the backquotes
+#[async_trait]
+impl GroupRepository for MysqlUserRepository {
+    async fn create(&self, name: &str) -> Result<Group, RepositoryError> {
+        let name = name.to_string();
+        self.with_conn(move |conn| {
+            conn.exec_drop("INSERT INTO `groups` (name) VALUES (?)", (&name,))?;
+            Ok(Group {
+                id: conn.last_insert_id() as i32,
+                name,
+            })
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Group>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let row: Option<(i32, String)> = conn.exec_first("SELECT id, name FROM `groups` WHERE id = ?", (id,))?;
+            Ok(row.map(|(id, name)| Group { id, name }))
+        })
+        .await
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<GroupList, RepositoryError> {
+        self.with_conn(move |conn| {
+            let total = conn.query_first("SELECT COUNT(*) FROM `groups`")?.unwrap_or(0);
+            let groups = conn.exec_map(
+                "SELECT id, name FROM `groups` ORDER BY name, id LIMIT ? OFFSET ?",
+                (limit, offset),
+                |(id, name)| Group { id, name },
+            )?;
+            Ok(GroupList { groups, total })
+        })
+        .await
+    }
+
+    async fn members(&self, group_id: i32, limit: i64, offset: i64) -> Result<MemberList, RepositoryError> {
+        self.with_conn(move |conn| {
+            let from = "FROM users JOIN user_groups ON user_groups.user_id = users.id \
+                        WHERE user_groups.group_id = ? AND users.deleted_at IS NULL";
+            let total = conn.exec_first(format!("SELECT COUNT(*) {}", from), (group_id,))?.unwrap_or(0);
+            // user_groups has no columns named like the user's, so they need no qualifying
+            let sql = format!("SELECT {} {} ORDER BY users.id LIMIT ? OFFSET ?", USER_COLUMNS, from);
+            let users = conn.exec_map(sql, (group_id, limit, offset), user_from_row)?;
+            Ok(MemberList { users, total })
+        })
+        .await
+    }
+
+    async fn add_member(&self, group_id: i32, user_id: i32) -> Result<AddMember, RepositoryError> {
+        self.with_conn(move |conn| {
+            let (group_exists, user_exists): (bool, bool) = conn
+                .exec_first(
+                    "SELECT EXISTS (SELECT 1 FROM `groups` WHERE id = ?), \
+                     EXISTS (SELECT 1 FROM users WHERE id = ? AND deleted_at IS NULL)",
+                    (group_id, user_id),
+                )?
+                .unwrap_or_default();
+            if !group_exists {
+                return Ok(AddMember::NoSuchGroup);
+            }
+            if !user_exists {
+                return Ok(AddMember::NoSuchUser);
+            }
+            conn.exec_drop("INSERT IGNORE INTO user_groups (user_id, group_id) VALUES (?, ?)", (user_id, group_id))?;
+            Ok(if conn.affected_rows() > 0 { AddMember::Added } else { AddMember::AlreadyMember })
+        })
+        .await
+    }
+
+    async fn remove_member(&self, group_id: i32, user_id: i32) -> Result<bool, RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop("DELETE FROM user_groups WHERE group_id = ? AND user_id = ?", (group_id, user_id))?;
+            Ok(conn.affected_rows() > 0)
+        })
+        .await
+    }
+
+    async fn user_groups(&self, user_id: i32) -> Result<Vec<Group>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let groups = conn.exec_map(
+                "SELECT `groups`.id, `groups`.name FROM `groups` JOIN user_groups ON user_groups.group_id = `groups`.id \
+                 WHERE user_groups.user_id = ? ORDER BY `groups`.name, `groups`.id",
+                (user_id,),
+                |(id, name)| Group { id, name },
+            )?;
+            Ok(groups)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MysqlUserRepository {
+    async fn create(&self, name: &str, role: Role, key_hash: &str) -> Result<ApiKey, RepositoryError> {
+        let (name, key_hash) = (name.to_string(), key_hash.to_string());
+        self.with_conn(move |conn| {
+            let created_at = now();
+            conn.exec_drop(
+                "INSERT INTO api_keys (name, role, key_hash, created_at) VALUES (?, ?, ?, ?)",
+                (&name, role.as_str(), &key_hash, created_at.naive_utc()),
+            )?;
+            Ok(ApiKey {
+                id: conn.last_insert_id() as i32,
+                name,
+                role,
+                created_at,
+                revoked_at: None,
+            })
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let rows: Vec<ApiKeyRow> = conn.query(format!("SELECT {} FROM api_keys ORDER BY id", API_KEY_COLUMNS))?;
+            Ok(rows.into_iter().map(api_key_from_row).collect())
+        })
+        .await
+    }
+
+    async fn revoke(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "UPDATE api_keys SET revoked_at = UTC_TIMESTAMP(6) WHERE id = ? AND revoked_at IS NULL",
+                (id,),
+            )?;
+            Ok(conn.affected_rows() > 0)
+        })
+        .await
+    }
+
+    async fn find_active(&self, key_hash: &str) -> Result<Option<(i32, Role)>, RepositoryError> {
+        let key_hash = key_hash.to_string();
+        self.with_conn(move |conn| {
+            let key: Option<(i32, String)> =
+                conn.exec_first("SELECT id, role FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL", (&key_hash,))?;
+            Ok(key.map(|(id, role)| (id, role_from_column(&role))))
+        })
+        .await
+    }
+}
+
+// JSON columns travel as text
+#[async_trait]
+impl AuditRepository for MysqlUserRepository {
+    async fn record(&self, record: AuditRecord) -> Result<(), RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "INSERT INTO audit_log (actor, action, path, entity_id, status, old_value, new_value) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                (
+                    &record.actor,
+                    &record.action,
+                    &record.path,
+                    record.entity_id,
+                    record.status,
+                    record.old_value.map(|value| value.to_string()),
+                    record.new_value.map(|value| value.to_string()),
+                ),
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self, query: &AuditQuery) -> Result<AuditList, RepositoryError> {
+        let (actor, limit, offset) = (query.actor.clone(), query.limit, query.offset);
+        self.with_conn(move |conn| {
+            let filter = "WHERE ? IS NULL OR actor = ?";
+            let total = conn.exec_first(format!("SELECT COUNT(*) FROM audit_log {}", filter), (&actor, &actor))?.unwrap_or(0);
+            let rows: Vec<AuditRow> = conn.exec(
+                format!(
+                    "SELECT id, actor, action, path, entity_id, status, old_value, new_value, created_at \
+                     FROM audit_log {} ORDER BY id DESC LIMIT ? OFFSET ?",
+                    filter
+                ),
+                (&actor, &actor, limit, offset),
+            )?;
+            Ok(AuditList {
+                entries: rows.into_iter().map(audit_entry_from_row).collect(),
+                total,
+            })
+        })
+        .await
+    }
+}
+
+// Apply pending migrations in order, recording each in schema_migrations. MySQL commits
+// DDL implicitly, so a migration that fails halfway must be repaired by hand.
+fn migrate(conn: &mut PooledConn) -> Result<(), MysqlError> {
+    conn.query_drop(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )?;
+
+    conn.exec_drop("SELECT GET_LOCK(?, -1)", (MIGRATION_LOCK,))?;
+    let result = apply_pending(conn);
+    conn.exec_drop("SELECT RELEASE_LOCK(?)", (MIGRATION_LOCK,))?;
+    result
+}
+
+fn apply_pending(conn: &mut PooledConn) -> Result<(), MysqlError> {
+    let applied: Vec<i64> = conn.query("SELECT version FROM schema_migrations")?;
+    for migration in MYSQL_MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        conn.query_drop(migration.sql)?;
+        conn.exec_drop(
+            "INSERT INTO schema_migrations (version, name) VALUES (?, ?)",
+            (migration.version, migration.name),
+        )?;
+        info!("Applied migration {:04} {}", migration.version, migration.name);
+    }
+    Ok(())
+}
+
+// Run an UPDATE and read the row back in one transaction, since MySQL has no RETURNING.
+// Every update bumps the version, so a matched row always counts as affected.
+fn update_in_transaction<P: Into<Params>>(
+    conn: &mut PooledConn,
+    id: i32,
+    sql: &str,
+    params: P,
+) -> Result<Option<User>, RepositoryError> {
+    let mut transaction = conn.start_transaction(TxOpts::default())?;
+    transaction.exec_drop(sql, params)?;
+    if transaction.affected_rows() == 0 {
+        return Ok(None);
+    }
+    let select = format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS);
+    let row: Option<UserRow> = transaction.exec_first(select, (id,))?;
+    transaction.commit()?;
+    Ok(row.map(user_from_row))
+}
+
+// Soft-delete the live users among `ids` and deal with their posts in one transaction,
+// returning the ids deleted; under restrict, any user with posts rolls the whole lot back.
+// The row locks taken by the updates keep posts from being added to those users meanwhile
+fn soft_delete(conn: &mut PooledConn, ids: &[i32], on_user_delete: OnUserDelete) -> Result<Vec<i32>, RepositoryError> {
+    let mut transaction = conn.start_transaction(TxOpts::default())?;
+    let mut deleted = Vec::new();
+    let mut owners = Vec::new();
+    for &id in ids {
+        transaction.exec_drop(
+            "UPDATE users SET deleted_at = UTC_TIMESTAMP(6), version = version + 1, updated_at = UTC_TIMESTAMP(6) \
+             WHERE id = ? AND deleted_at IS NULL",
+            (id,),
+        )?;
+        if transaction.affected_rows() == 0 {
+            continue;
+        }
+        deleted.push(id);
+        match on_user_delete {
+            OnUserDelete::Keep => {}
+            OnUserDelete::Cascade => transaction.exec_drop("DELETE FROM posts WHERE user_id = ?", (id,))?,
+            OnUserDelete::Restrict => {
+                let has_posts: Option<bool> =
+                    transaction.exec_first("SELECT EXISTS (SELECT 1 FROM posts WHERE user_id = ?)", (id,))?;
+                if has_posts == Some(true) {
+                    owners.push(id);
+                }
+            }
+        }
+    }
+    if !owners.is_empty() {
+        owners.sort_unstable();
+        return Err(RepositoryError::HasPosts(owners));
+    }
+    transaction.commit()?;
+    Ok(deleted)
+}
+
+// The current time at the microsecond precision DATETIME(6) stores
+fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(6)
+}
+
+fn post_from_row((id, user_id, title, body): PostRow) -> Post {
+    Post { id, user_id, title, body }
+}
+
+#[async_trait]
+impl TokenRepository for MysqlUserRepository {
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<i32, RepositoryError> {
+        let token_hash = token_hash.to_string();
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) VALUES (?, ?, ?)",
+                (user_id, &token_hash, expires_at.naive_utc()),
+            )?;
+            Ok(conn.last_insert_id() as i32)
+        })
+        .await
+    }
+
+    // The old session is locked first, so a token can't be refreshed twice by racing requests
+    async fn refresh_session(
+        &self,
+        token_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Refreshed>, RepositoryError> {
+        let (token_hash, new_hash) = (token_hash.to_string(), new_hash.to_string());
+        self.with_conn(move |conn| {
+            let mut transaction = conn.start_transaction(TxOpts::default())?;
+            let old: Option<(i32, i32, String)> = transaction.exec_first(
+                "SELECT refresh_tokens.id, users.id, users.role FROM refresh_tokens \
+                 JOIN users ON users.id = refresh_tokens.user_id AND users.deleted_at IS NULL \
+                 WHERE token_hash = ? AND revoked_at IS NULL AND expires_at > UTC_TIMESTAMP(6) \
+                 FOR UPDATE",
+                (&token_hash,),
+            )?;
+            let Some((old_id, user_id, role)) = old else {
+                return Ok(None);
+            };
+            transaction.exec_drop("UPDATE refresh_tokens SET revoked_at = UTC_TIMESTAMP(6) WHERE id = ?", (old_id,))?;
+            transaction.exec_drop(
+                "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) VALUES (?, ?, ?)",
+                (user_id, &new_hash, expires_at.naive_utc()),
+            )?;
+            let session_id = transaction.last_insert_id().unwrap_or_default() as i32;
+            transaction.commit()?;
+            Ok(Some(Refreshed {
+                session_id,
+                user_id,
+                role: role_from_column(&role),
+            }))
+        })
+        .await
+    }
+
+    async fn revoke_session(&self, user_id: i32, session_id: i32) -> Result<(), RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "UPDATE refresh_tokens SET revoked_at = UTC_TIMESTAMP(6) \
+                 WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+                (session_id, user_id),
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_access(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let jti = jti.to_string();
+        self.with_conn(move |conn| {
+            conn.query_drop("DELETE FROM revoked_tokens WHERE expires_at <= UTC_TIMESTAMP(6)")?;
+            conn.exec_drop(
+                "INSERT IGNORE INTO revoked_tokens (jti, expires_at) VALUES (?, ?)",
+                (&jti, expires_at.naive_utc()),
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, RepositoryError> {
+        let jti = jti.to_string();
+        self.with_conn(move |conn| {
+            let revoked: Option<i32> = conn.exec_first("SELECT 1 FROM revoked_tokens WHERE jti = ?", (&jti,))?;
+            Ok(revoked.is_some())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MysqlUserRepository {
+    async fn create(&self, url: &str, secret: &str, events: &[String]) -> Result<Webhook, RepositoryError> {
+        let (url, secret, events) = (url.to_string(), secret.to_string(), events.to_vec());
+        self.with_conn(move |conn| {
+            let created_at = now();
+            conn.exec_drop(
+                "INSERT INTO webhooks (url, secret, events, created_at) VALUES (?, ?, ?, ?)",
+                (&url, &secret, super::join_events(&events), created_at.naive_utc()),
+            )?;
+            Ok(Webhook {
+                id: conn.last_insert_id() as i32,
+                url,
+                events,
+                created_at,
+            })
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let rows: Vec<WebhookRow> = conn.query(format!("SELECT {} FROM webhooks ORDER BY id", WEBHOOK_COLUMNS))?;
+            Ok(rows.into_iter().map(webhook_from_row).collect())
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Webhook>, RepositoryError> {
+        self.with_conn(move |conn| {
+            let row: Option<WebhookRow> = conn.exec_first(format!("SELECT {} FROM webhooks WHERE id = ?", WEBHOOK_COLUMNS), (id,))?;
+            Ok(row.map(webhook_from_row))
+        })
+        .await
+    }
+
+    // affected_rows counts only rows that changed, so the webhook is read back to tell whether it exists
+    async fn update(&self, id: i32, url: &str, secret: Option<&str>, events: &[String]) -> Result<Option<Webhook>, RepositoryError> {
+        let (url, secret, events) = (url.to_string(), secret.map(str::to_string), super::join_events(events));
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "UPDATE webhooks SET url = ?, secret = COALESCE(?, secret), events = ? WHERE id = ?",
+                (&url, &secret, &events, id),
+            )?;
+            let row: Option<WebhookRow> = conn.exec_first(format!("SELECT {} FROM webhooks WHERE id = ?", WEBHOOK_COLUMNS), (id,))?;
+            Ok(row.map(webhook_from_row))
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.with_conn(move |conn| {
+            conn.exec_drop("DELETE FROM webhooks WHERE id = ?", (id,))?;
+            Ok(conn.affected_rows() > 0)
+        })
+        .await
+    }
+
+    async fn subscribers(&self, event_type: &str) -> Result<Vec<WebhookTarget>, RepositoryError> {
+        let event_type = event_type.to_string();
+        self.with_conn(move |conn| {
+            let rows: Vec<(i32, String, String)> = conn.exec(
+                "SELECT id, url, secret FROM webhooks WHERE CONCAT(',', events, ',') LIKE CONCAT('%,', ?, ',%') ORDER BY id",
+                (&event_type,),
+            )?;
+            Ok(rows.into_iter().map(|(id, url, secret)| WebhookTarget { id, url, secret }).collect())
+        })
+        .await
+    }
+}
+
+// Headers travel as JSON text
+#[async_trait]
+impl IdempotencyRepository for MysqlUserRepository {
+    async fn claim(&self, key_hash: &str, request_hash: &str, expires_at: DateTime<Utc>) -> Result<IdempotencyClaim, RepositoryError> {
+        let (key_hash, request_hash) = (key_hash.to_string(), request_hash.to_string());
+        self.with_conn(move |conn| {
+            conn.query_drop("DELETE FROM idempotency_keys WHERE expires_at <= UTC_TIMESTAMP(6)")?;
+            conn.exec_drop(
+                "INSERT IGNORE INTO idempotency_keys (key_hash, request_hash, expires_at) VALUES (?, ?, ?)",
+                (&key_hash, &request_hash, expires_at.naive_utc()),
+            )?;
+            if conn.affected_rows() == 1 {
+                return Ok(IdempotencyClaim::Claimed);
+            }
+            let row: Option<IdempotencyRow> =
+                conn.exec_first("SELECT request_hash, status, headers, body FROM idempotency_keys WHERE key_hash = ?", (&key_hash,))?;
+            // Pruned between the insert and the select: whoever held it has given up
+            let Some((record_hash, status, headers, body)) = row else {
+                return Ok(IdempotencyClaim::InProgress);
+            };
+            let response = status.map(|status| StoredResponse {
+                status,
+                headers: headers.and_then(|headers| serde_json::from_str(&headers).ok()).unwrap_or_default(),
+                body: body.unwrap_or_default(),
+            });
+            Ok(IdempotencyClaim::of_record(&request_hash, &record_hash, response))
+        })
+        .await
+    }
+
+    async fn complete(&self, key_hash: &str, response: &StoredResponse, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let (key_hash, response) = (key_hash.to_string(), response.clone());
+        let headers = serde_json::to_string(&response.headers).map_err(|e| RepositoryError::Internal(e.to_string()))?;
+        self.with_conn(move |conn| {
+            conn.exec_drop(
+                "UPDATE idempotency_keys SET status = ?, headers = ?, body = ?, expires_at = ? WHERE key_hash = ?",
+                (response.status, headers, response.body, expires_at.naive_utc(), &key_hash),
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release(&self, key_hash: &str) -> Result<(), RepositoryError> {
+        let key_hash = key_hash.to_string();
+        self.with_conn(move |conn| {
+            conn.exec_drop("DELETE FROM idempotency_keys WHERE key_hash = ? AND status IS NULL", (&key_hash,))?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn webhook_from_row((id, url, events, created_at): WebhookRow) -> Webhook {
+    Webhook {
+        id,
+        url,
+        events: super::split_events(&events),
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(created_at, Utc),
+    }
+}
+
+fn api_key_from_row((id, name, role, created_at, revoked_at): ApiKeyRow) -> ApiKey {
+    ApiKey {
+        id,
+        name,
+        role: role_from_column(&role),
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(created_at, Utc),
+        revoked_at: revoked_at.map(|at| DateTime::<Utc>::from_naive_utc_and_offset(at, Utc)),
+    }
+}
+
+fn audit_entry_from_row((id, actor, action, path, entity_id, status, old_value, new_value, created_at): AuditRow) -> AuditEntry {
+    AuditEntry {
+        id,
+        actor,
+        action,
+        path,
+        entity_id,
+        status,
+        old_value: old_value.and_then(|value| serde_json::from_str(&value).ok()),
+        new_value: new_value.and_then(|value| serde_json::from_str(&value).ok()),
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(created_at, Utc),
+    }
+}
+
+// The ENUM column admits only known roles; anything else would get the least privilege
+fn role_from_column(role: &str) -> Role {
+    Role::parse(role).unwrap_or_default()
+}
+
+fn user_from_row((id, name, email, created_at, deleted_at, version, updated_at): UserRow) -> User {
+    User {
+        id: Some(id),
+        name,
+        email,
+        created_at: Some(DateTime::<Utc>::from_naive_utc_and_offset(created_at, Utc)),
+        updated_at: Some(DateTime::<Utc>::from_naive_utc_and_offset(updated_at, Utc)),
+        deleted_at: deleted_at.map(|at| DateTime::<Utc>::from_naive_utc_and_offset(at, Utc)),
+        version,
+    }
+}
+
+// Build the WHERE clause and its positional parameters, mirroring the Postgres filters
+fn where_clause(list: &UserListQuery, with_cursor: bool) -> (String, Vec<Value>) {
+    let mut conditions = Vec::new();
+    let mut params = Vec::new();
+
+    if !list.include_deleted {
+        conditions.push("deleted_at IS NULL");
+    }
+    if let Some(name) = &list.name {
+        params.push(Value::from(name));
+        conditions.push("name = ?");
+    }
+    if let Some(fragment) = &list.email_contains {
+        // LIKE follows the column's case-insensitive collation, matching ILIKE
+        params.push(Value::from(format!("%{}%", escape_like(fragment))));
+        conditions.push("email LIKE ?");
+    }
+    if let (true, Some(after)) = (with_cursor, list.after) {
+        params.push(Value::from(after));
+        conditions.push(if list.order == "DESC" { "id < ?" } else { "id > ?" });
+    }
+
+    if conditions.is_empty() {
+        (String::new(), params)
+    } else {
+        (format!(" WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+// Escape LIKE wildcards so user input only matches literally
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Duplicate keys become conflicts, connection failures mean the store is unavailable
+impl From<MysqlError> for RepositoryError {
+    fn from(e: MysqlError) -> Self {
+        match e {
+            MysqlError::MySqlError(e) if e.code == DUPLICATE_ENTRY => {
+                if e.message.contains(USERS_EMAIL_INDEX) {
+                    RepositoryError::EmailTaken
+                } else if e.message.contains(GROUPS_NAME_INDEX) {
+                    RepositoryError::GroupNameTaken
+                } else {
+                    RepositoryError::Conflict
+                }
+            }
+            MysqlError::DriverError(DriverError::Timeout | DriverError::CouldNotConnect(_)) | MysqlError::IoError(_) => {
+                RepositoryError::Unavailable(e.to_string())
+            }
+            e => RepositoryError::Internal(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use super::{
+    CacheStatus, Credentials, PoolStatus, RepositoryError, SearchResults, Upserted, UserChange, UserList, UserListQuery, UserRepository, UserSearch,
+};
+use crate::events::{EventType, Events};
+use crate::models::{Role, User, UserPatch, UserStats};
+
+// Wraps a user store to publish an event for every change it commits. Passwords and roles aren't
+// part of a user's representation, so changing them publishes nothing
+pub struct NotifyingUserRepository {
+    inner: Arc<dyn UserRepository>,
+    events: Events,
+}
+
+impl NotifyingUserRepository {
+    pub fn new(inner: Arc<dyn UserRepository>, events: Events) -> Self {
+        NotifyingUserRepository { inner, events }
+    }
+
+    fn updated(&self, user: Option<User>) -> Option<User> {
+        if let Some(user) = &user {
+            self.events.user_changed(EventType::Updated, user);
+        }
+        user
+    }
+}
+
+#[async_trait]
+impl UserRepository for NotifyingUserRepository {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        let user = self.inner.create(name, email).await?;
+        self.events.user_changed(EventType::Created, &user);
+        Ok(user)
+    }
+
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        let results = self.inner.create_many(users).await?;
+        for user in results.iter().flatten() {
+            self.events.user_changed(EventType::Created, user);
+        }
+        Ok(results)
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        self.inner.get(id, include_deleted).await
+    }
+
+    async fn list(&self, query: &UserListQuery) -> Result<UserList, RepositoryError> {
+        self.inner.list(query).await
+    }
+
+    async fn stream(&self, query: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        self.inner.stream(query, sink).await
+    }
+
+    async fn count(&self, query: &UserListQuery) -> Result<i64, RepositoryError> {
+        self.inner.count(query).await
+    }
+
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        self.inner.search(search).await
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        self.inner.stats(days).await
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        Ok(self.updated(self.inner.update(id, name, email).await?))
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let upserted = self.inner.upsert(id, name, email).await?;
+        match &upserted {
+            Some(Upserted::Created(user)) => self.events.user_changed(EventType::Created, user),
+            Some(Upserted::Updated(user)) => self.events.user_changed(EventType::Updated, user),
+            None => {}
+        }
+        Ok(upserted)
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        Ok(self.updated(self.inner.patch(id, patch).await?))
+    }
+
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        Ok(self.updated(self.inner.modify(id, change).await?))
+    }
+
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        let results = self.inner.patch_many(patches).await?;
+        for user in results.iter().flatten().flatten() {
+            self.events.user_changed(EventType::Updated, user);
+        }
+        Ok(results)
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let deleted = self.inner.delete(id).await?;
+        if deleted {
+            self.events.user_deleted(id);
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let deleted = self.inner.delete_many(ids).await?;
+        for id in &deleted {
+            self.events.user_deleted(*id);
+        }
+        Ok(deleted)
+    }
+
+    // Restoring brings the user back as it was, so it counts as an update
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        Ok(self.updated(self.inner.restore(id).await?))
+    }
+
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        self.inner.set_password(id, password_hash).await
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        self.inner.set_role(id, role).await
+    }
+
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        self.inner.credentials(email).await
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.inner.ping().await
+    }
+
+    fn close(&self) {
+        self.inner.close()
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        self.inner.pool_status()
+    }
+
+    fn cache_status(&self) -> Option<CacheStatus> {
+        self.inner.cache_status()
+    }
+}
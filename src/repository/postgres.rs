@@ -0,0 +1,1386 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Object, Pool, PoolError, TimeoutType};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Error as PostgresError, Row};
+
+use super::replicas::Replicas;
+use super::{
+    AddMember, ApiKeyRepository, AuditList, AuditQuery, AuditRecord, AuditRepository, Credentials, GroupList, Refreshed, TokenRepository,
+    GroupRepository, IdempotencyClaim, IdempotencyRepository, MemberList, PoolStatus, PostList, PostListQuery, PostRepository, RepositoryError,
+    SearchResults, StoredResponse, Upserted, UserChange, UserList, UserListQuery, UserRepository, UserSearch, WebhookRepository, WebhookTarget,
+    TOP_EMAIL_DOMAINS,
+};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::context::Context;
+use crate::config::{OnUserDelete, RetryConfig};
+use crate::db::{self, Traced};
+use crate::models::{ApiKey, AuditEntry, DailyCount, DomainCount, Group, Post, Role, SearchHit, User, UserPatch, UserStats, Webhook};
+use crate::retry::retry;
+
+const API_KEY_COLUMNS: &str = "id, name, role, created_at, revoked_at";
+
+const WEBHOOK_COLUMNS: &str = "id, url, events, created_at";
+
+// Matches rows whose comma-separated `events` include the event type in $1
+const SUBSCRIBED: &str = "',' || events || ',' LIKE '%,' || $1 || ',%'";
+
+// Unique index on lower(email), created by migration 0002
+const USERS_EMAIL_INDEX: &str = "users_email_key";
+
+// Unique index on lower(name), created by migration 0008
+const GROUPS_NAME_INDEX: &str = "groups_name_key";
+
+// Rows fetched per round trip when streaming users through a portal
+const STREAM_BATCH_SIZE: i32 = 500;
+
+// The users table's columns in user_from_row's order, with the stand-ins listings read for fields left out
+const USER_SELECT: [(&str, &str); 7] = [
+    ("id", "id"),
+    ("name", "''"),
+    ("email", "''"),
+    ("deleted_at", "NULL::timestamptz"),
+    ("version", "0"),
+    ("created_at", "NULL::timestamptz"),
+    ("updated_at", "NULL::timestamptz"),
+];
+
+// Users, and their posts, stored in Postgres through the shared connection pool
+pub struct PostgresUserRepository {
+    pool: Pool,
+    retry: RetryConfig,
+    on_user_delete: OnUserDelete,
+    // Run on every connection checked out, when tenants share the pool: the connection may still
+    // have the search_path another tenant's requests set
+    on_checkout: Option<String>,
+    // Where read-only work reads from, when there are replicas
+    replicas: Option<Arc<Replicas>>,
+    // Refuses work while the database is down, shared by every repository on the pool
+    breaker: Option<Arc<CircuitBreaker>>,
+    // Whether requests have deadlines, which their queries are cancelled at
+    deadlines: bool,
+    // Queries taking at least this long are logged
+    slow_query: Option<Duration>,
+}
+
+impl PostgresUserRepository {
+    pub fn new(pool: Pool, retry: RetryConfig, on_user_delete: OnUserDelete) -> Self {
+        PostgresUserRepository {
+            pool,
+            retry,
+            on_user_delete,
+            on_checkout: None,
+            replicas: None,
+            breaker: None,
+            deadlines: false,
+            slow_query: None,
+        }
+    }
+
+    // Fail at once with RepositoryError::CircuitOpen while `breaker` says the database is down
+    pub fn circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    // Send the queries of read-only work to `replicas` while one of them is up
+    pub fn replicas(mut self, replicas: Arc<Replicas>) -> Self {
+        self.replicas = Some(replicas);
+        self
+    }
+
+    // Cancel queries still running when the request they're for runs out of time, with RepositoryError::TimedOut
+    pub fn deadlines(mut self) -> Self {
+        self.deadlines = true;
+        self
+    }
+
+    // Log queries taking at least `threshold`, with their statement and redacted parameters
+    pub fn slow_queries(mut self, threshold: Duration) -> Self {
+        self.slow_query = Some(threshold);
+        self
+    }
+
+    // Work in the schemas `statement` puts on the search_path (or `RESET search_path` for the
+    // database's own), whatever the pooled connection was last used for
+    pub fn on_checkout(mut self, statement: String) -> Self {
+        self.on_checkout = Some(statement);
+        self
+    }
+
+    // Another repository on the same pool, replicas and circuit breaker, with nothing run on checkout yet
+    pub(super) fn sharing_pool(&self) -> Self {
+        PostgresUserRepository {
+            replicas: self.replicas.clone(),
+            breaker: self.breaker.clone(),
+            deadlines: self.deadlines,
+            slow_query: self.slow_query,
+            ..PostgresUserRepository::new(self.pool.clone(), self.retry.clone(), self.on_user_delete)
+        }
+    }
+
+    // Check out a connection, retrying while the database is unreachable
+    pub(super) async fn connect(&self) -> Result<Traced<Object>, RepositoryError> {
+        self.guarded(retry(&self.retry, is_transient, || self.checkout())).await
+    }
+
+    async fn checkout(&self) -> Result<Traced<Object>, PoolError> {
+        let replica = match &self.replicas {
+            Some(replicas) => replicas.checkout().await,
+            None => None,
+        };
+        let client = match replica {
+            Some(client) => client,
+            None => self.pool.get().await?,
+        };
+        // The statement_timeout is set on every checkout, as the connection may still have the one set
+        // for another request
+        let mut statement = self.on_checkout.as_deref().map(Cow::Borrowed);
+        if self.deadlines {
+            let millis = match Context::current().deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()).as_millis().max(1),
+                None => 0,
+            };
+            let timeout = format!("SET statement_timeout = {}", millis);
+            statement = Some(match statement {
+                Some(statement) => Cow::Owned(format!("{}; {}", statement, timeout)),
+                None => Cow::Owned(timeout),
+            });
+        }
+        if let Some(statement) = statement {
+            client.batch_execute(&statement).await?;
+        }
+        Ok(Traced::new(client, self.slow_query))
+    }
+
+    // Run reads, retrying them on a fresh connection after transient failures
+    async fn read<T, F, Fut>(&self, op: F) -> Result<T, RepositoryError>
+    where
+        F: Fn(Traced<Object>) -> Fut,
+        Fut: Future<Output = Result<T, PoolError>>,
+    {
+        let attempt = || async { op(self.checkout().await?).await };
+        self.guarded(retry(&self.retry, is_transient, attempt)).await
+    }
+
+    // Run a write, retrying it only after failures that mean it didn't happen: no connection could be
+    // had, or Postgres rolled it back as a serialization failure or deadlock. A connection lost once
+    // the write was sent may have been lost after it committed, and repeating it would, say, bump a
+    // version twice, so that failure is returned as it is
+    async fn write<T, F, Fut>(&self, op: F) -> Result<T, RepositoryError>
+    where
+        F: Fn(Traced<Object>) -> Fut,
+        Fut: Future<Output = Result<T, PoolError>>,
+    {
+        let attempt = || async {
+            let client = self.checkout().await.map_err(|error| Attempt { error, sent: false })?;
+            op(client).await.map_err(|error| Attempt { error, sent: true })
+        };
+        let is_retryable = |attempt: &Attempt| match attempt.sent {
+            true => is_rolled_back(&attempt.error),
+            false => is_transient(&attempt.error),
+        };
+        self.guarded(async { retry(&self.retry, is_retryable, attempt).await.map_err(|attempt| attempt.error) }).await
+    }
+
+    // Run `work` unless the circuit breaker is open, telling it whether the database was reached
+    async fn guarded<T>(&self, work: impl Future<Output = Result<T, PoolError>>) -> Result<T, RepositoryError> {
+        let Some(breaker) = &self.breaker else {
+            return Ok(work.await?);
+        };
+        breaker.admit().map_err(RepositoryError::CircuitOpen)?;
+        let result = work.await;
+        match &result {
+            Err(e) if is_unreachable(e) => breaker.failed(),
+            _ => breaker.succeeded(),
+        }
+        Ok(result?)
+    }
+
+    // Soft-delete the live users among `ids` and deal with their posts in one transaction,
+    // returning the ids deleted. The users are locked first, so no post can be added to them meanwhile
+    async fn soft_delete(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let on_user_delete = self.on_user_delete;
+        let outcome = self
+            .write(|mut client| {
+                let ids = ids.clone();
+                async move {
+                    db::transaction(&mut client, |transaction| {
+                        Box::pin(async move {
+                            let live: Vec<i32> = transaction
+                                .query(
+                                    "SELECT id FROM users WHERE id = ANY($1) AND deleted_at IS NULL ORDER BY id FOR UPDATE",
+                                    &[&ids],
+                                )
+                                .await?
+                                .iter()
+                                .map(|row| row.get(0))
+                                .collect();
+                            match on_user_delete {
+                                OnUserDelete::Keep => {}
+                                OnUserDelete::Cascade => {
+                                    transaction.execute("DELETE FROM posts WHERE user_id = ANY($1)", &[&live]).await?;
+                                }
+                                OnUserDelete::Restrict => {
+                                    let owners: Vec<i32> = transaction
+                                        .query(
+                                            "SELECT DISTINCT user_id FROM posts WHERE user_id = ANY($1) ORDER BY user_id",
+                                            &[&live],
+                                        )
+                                        .await?
+                                        .iter()
+                                        .map(|row| row.get(0))
+                                        .collect();
+                                    if !owners.is_empty() {
+                                        return Ok(Err(owners));
+                                    }
+                                }
+                            }
+                            transaction
+                                .execute(
+                                    "UPDATE users SET deleted_at = now(), version = version + 1, updated_at = now() WHERE id = ANY($1)",
+                                    &[&live],
+                                )
+                                .await?;
+                            Ok(Ok(live))
+                        })
+                    })
+                    .await
+                }
+            })
+            .await?;
+        outcome.map_err(RepositoryError::HasPosts)
+    }
+}
+
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    // Inserts aren't idempotent: only checking out the connection is retried
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one("INSERT INTO users (name, email) VALUES ($1,$2) RETURNING *", &[&name, &email])
+            .await?;
+        Ok(user_from_row(&row))
+    }
+
+    // Not retried either, as a retry after an ambiguous commit could insert the batch twice
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        let mut client = self.connect().await?;
+        db::transaction(&mut client, |transaction| {
+            Box::pin(async move {
+                let insert = transaction
+                    .prepare_cached("INSERT INTO users (name, email) VALUES ($1, $2) RETURNING *")
+                    .await?;
+                let mut results = Vec::with_capacity(users.len());
+                for user in &users {
+                    // A savepoint per row lets a conflicting row fail without aborting the others
+                    transaction.batch_execute("SAVEPOINT batch_item").await?;
+                    match transaction.query_one(&insert, &[&user.name, &user.email]).await {
+                        Ok(row) => {
+                            transaction.batch_execute("RELEASE SAVEPOINT batch_item").await?;
+                            results.push(Ok(user_from_row(&row)));
+                        }
+                        Err(e) => {
+                            let e = RepositoryError::from(e);
+                            if !e.is_conflict() {
+                                return Err(e);
+                            }
+                            transaction.batch_execute("ROLLBACK TO SAVEPOINT batch_item").await?;
+                            results.push(Err(e));
+                        }
+                    }
+                }
+                Ok(results)
+            })
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        self.read(|client| async move {
+            let row = client
+                .query_opt(
+                    "SELECT * FROM users WHERE id = $1 AND ($2 OR deleted_at IS NULL)",
+                    &[&id, &include_deleted],
+                )
+                .await?;
+            Ok(row.as_ref().map(user_from_row))
+        })
+        .await
+    }
+
+    async fn list(&self, list: &UserListQuery) -> Result<UserList, RepositoryError> {
+        self.read(|client| async move {
+            // Offset pagination reports the filtered total; keyset pagination skips the count
+            let total = match list.after {
+                Some(_) => None,
+                None => {
+                    let (where_sql, params) = where_clause(list, false);
+                    let sql = format!("SELECT COUNT(*) FROM users{}", where_sql);
+                    Some(client.query_one(&sql, &sql_params(&params)).await?.get::<_, i64>(0))
+                }
+            };
+
+            // One extra row tells us whether another page follows
+            let (where_sql, mut params) = where_clause(list, true);
+            params.push(Box::new(list.limit + 1));
+            let mut sql = format!(
+                "SELECT {} FROM users{} ORDER BY users.{} {}, users.id {} LIMIT ${}",
+                list.select_list(&USER_SELECT),
+                where_sql,
+                list.sort,
+                list.order,
+                list.order,
+                params.len()
+            );
+            if list.after.is_none() {
+                params.push(Box::new(list.offset));
+                sql.push_str(&format!(" OFFSET ${}", params.len()));
+            }
+
+            let rows = client.query(&sql, &sql_params(&params)).await?;
+            let mut users: Vec<User> = rows.iter().map(user_from_row).collect();
+            let has_more = users.len() as i64 > list.limit;
+            users.truncate(list.limit as usize);
+            Ok(UserList { users, total, has_more })
+        })
+        .await
+    }
+
+    // Not retried: rows may already have been sent. Portals only live inside a transaction
+    async fn stream(&self, list: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        let list = list.clone();
+        let mut client = self.connect().await?;
+        db::transaction(&mut client, |transaction| {
+            Box::pin(async move {
+                let (where_sql, params) = where_clause(&list, false);
+                let sql = format!(
+                    "SELECT * FROM users{} ORDER BY {} {}, id {}",
+                    where_sql, list.sort, list.order, list.order
+                );
+                let statement = transaction.prepare(&sql).await?;
+                let bound = transaction.bind(&statement, &sql_params(&params)).await?;
+                loop {
+                    let rows = transaction.query_portal(&bound, STREAM_BATCH_SIZE).await?;
+                    for row in &rows {
+                        if sink.send(user_from_row(row)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    if rows.len() < STREAM_BATCH_SIZE as usize {
+                        return Ok(());
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    async fn count(&self, list: &UserListQuery) -> Result<i64, RepositoryError> {
+        self.read(|client| async move {
+            let (where_sql, params) = where_clause(list, false);
+            let sql = format!("SELECT COUNT(*) FROM users{}", where_sql);
+            Ok(client.query_one(&sql, &sql_params(&params)).await?.get(0))
+        })
+        .await
+    }
+
+    // Substring matches and trigram word similarity (pg_trgm's <% operator), both served by
+    // the trigram indexes; the score is the better of the two fields' word similarity
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        self.read(|client| async move {
+            let text = search.text.to_lowercase();
+            let pattern = format!("%{}%", escape_like(&text));
+            let matches = "deleted_at IS NULL AND (lower(name) LIKE $2 OR lower(email) LIKE $2 \
+                           OR $1 <% lower(name) OR $1 <% lower(email))";
+
+            let total = client
+                .query_one(&format!("SELECT COUNT(*) FROM users WHERE {}", matches), &[&text, &pattern])
+                .await?
+                .get(0);
+            let rows = client
+                .query(
+                    &format!(
+                        "SELECT *, GREATEST(word_similarity($1, lower(name)), word_similarity($1, lower(email)))::float8 \
+                         AS score FROM users WHERE {} ORDER BY score DESC, id LIMIT $3 OFFSET $4",
+                        matches
+                    ),
+                    &[&text, &pattern, &search.limit, &search.offset],
+                )
+                .await?;
+            let hits = rows
+                .iter()
+                .map(|row| SearchHit { user: user_from_row(row), score: row.get("score") })
+                .collect();
+            Ok(SearchResults { hits, total })
+        })
+        .await
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        self.read(|client| async move {
+            let totals = client
+                .query_one("SELECT COUNT(*), COUNT(*) FILTER (WHERE deleted_at IS NULL) FROM users", &[])
+                .await?;
+            let (total, active): (i64, i64) = (totals.get(0), totals.get(1));
+
+            // The window starts at midnight UTC so its first day is counted in full
+            let created_per_day = client
+                .query(
+                    "SELECT (created_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) FROM users \
+                     WHERE deleted_at IS NULL \
+                     AND created_at >= date_trunc('day', now() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' \
+                         - make_interval(days => $1 - 1) \
+                     GROUP BY day ORDER BY day",
+                    &[&days],
+                )
+                .await?
+                .iter()
+                .map(|row| DailyCount { date: row.get(0), count: row.get(1) })
+                .collect();
+
+            let domains = "SELECT lower(split_part(email, '@', 2)) AS domain FROM users WHERE deleted_at IS NULL";
+            let distinct_email_domains = client
+                .query_one(&format!("SELECT COUNT(DISTINCT domain) FROM ({}) d", domains), &[])
+                .await?
+                .get(0);
+            let top_email_domains = client
+                .query(
+                    &format!(
+                        "SELECT domain, COUNT(*) FROM ({}) d GROUP BY domain ORDER BY COUNT(*) DESC, domain LIMIT $1",
+                        domains
+                    ),
+                    &[&TOP_EMAIL_DOMAINS],
+                )
+                .await?
+                .iter()
+                .map(|row| DomainCount { domain: row.get(0), count: row.get(1) })
+                .collect();
+
+            Ok(UserStats {
+                total,
+                active,
+                deleted: total - active,
+                created_per_day,
+                distinct_email_domains,
+                top_email_domains,
+            })
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        self.write(|client| async move {
+            let row = client
+                .query_opt(
+                    "UPDATE users SET name = $1, email = $2, version = version + 1, updated_at = now() \
+                     WHERE id = $3 AND deleted_at IS NULL RETURNING *",
+                    &[&name, &email, &id],
+                )
+                .await?;
+            Ok(row.as_ref().map(user_from_row))
+        })
+        .await
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        let mut client = self.connect().await?;
+        db::transaction(&mut client, |transaction| {
+            Box::pin(async move {
+                // xmax is 0 only for a freshly inserted row; deleted users are left alone
+                let row = transaction
+                    .query_opt(
+                        "INSERT INTO users (id, name, email) VALUES ($1, $2, $3) \
+                         ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, email = EXCLUDED.email, \
+                         version = users.version + 1, updated_at = now() WHERE users.deleted_at IS NULL \
+                         RETURNING *, xmax = 0 AS inserted",
+                        &[&id, &name, &email],
+                    )
+                    .await?;
+                let row = match row {
+                    Some(row) => row,
+                    None => return Ok(None),
+                };
+                if !row.get::<_, bool>("inserted") {
+                    return Ok(Some(Upserted::Updated(user_from_row(&row))));
+                }
+
+                // An explicit id doesn't advance the serial, so move it past this id
+                // before a later insert draws the same value
+                transaction
+                    .execute(
+                        "SELECT setval(pg_get_serial_sequence('users', 'id'), $1::bigint) \
+                         WHERE $1::bigint > COALESCE(pg_sequence_last_value(pg_get_serial_sequence('users', 'id')::regclass), 0)",
+                        &[&i64::from(id)],
+                    )
+                    .await?;
+                Ok(Some(Upserted::Created(user_from_row(&row))))
+            })
+        })
+        .await
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        self.write(|client| async move {
+            // Absent fields bind as NULL and keep their current value
+            let row = client
+                .query_opt(
+                    "UPDATE users SET name = COALESCE($1, name), email = COALESCE($2, email), version = version + 1, updated_at = now() \
+                     WHERE id = $3 AND deleted_at IS NULL RETURNING *",
+                    &[&patch.name, &patch.email, &id],
+                )
+                .await?;
+            Ok(row.as_ref().map(user_from_row))
+        })
+        .await
+    }
+
+    // The change is consumed by the first attempt, so only opening the transaction is retried
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        let mut client = self.connect().await?;
+        db::transaction(&mut client, |transaction| {
+            Box::pin(async move {
+                // Lock the row so concurrent changes apply one after another
+                let current = match transaction
+                    .query_opt("SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE", &[&id])
+                    .await?
+                {
+                    Some(row) => user_from_row(&row),
+                    None => return Ok(None),
+                };
+
+                let changed = change(current).map_err(RepositoryError::Rejected)?;
+                let row = transaction
+                    .query_one(
+                        "UPDATE users SET name = $1, email = $2, version = version + 1, updated_at = now() WHERE id = $3 RETURNING *",
+                        &[&changed.name, &changed.email, &id],
+                    )
+                    .await?;
+                Ok(Some(user_from_row(&row)))
+            })
+        })
+        .await
+    }
+
+    // Like modify, the batch is only retried up to opening its transaction
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        let mut client = self.connect().await?;
+        db::transaction(&mut client, |transaction| {
+            Box::pin(async move {
+                let update = transaction
+                    .prepare_cached(
+                        "UPDATE users SET name = COALESCE($1, name), email = COALESCE($2, email), version = version + 1, updated_at = now() \
+                         WHERE id = $3 AND deleted_at IS NULL RETURNING *",
+                    )
+                    .await?;
+                let mut results = Vec::with_capacity(patches.len());
+                for (id, patch) in &patches {
+                    // A savepoint per row lets a conflicting row fail without aborting the others
+                    transaction.batch_execute("SAVEPOINT batch_item").await?;
+                    match transaction.query_opt(&update, &[&patch.name, &patch.email, id]).await {
+                        Ok(row) => {
+                            transaction.batch_execute("RELEASE SAVEPOINT batch_item").await?;
+                            results.push(Ok(row.as_ref().map(user_from_row)));
+                        }
+                        Err(e) => {
+                            let e = RepositoryError::from(e);
+                            if !e.is_conflict() {
+                                return Err(e);
+                            }
+                            transaction.batch_execute("ROLLBACK TO SAVEPOINT batch_item").await?;
+                            results.push(Err(e));
+                        }
+                    }
+                }
+                Ok(results)
+            })
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        Ok(!self.soft_delete(vec![id]).await?.is_empty())
+    }
+
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        self.soft_delete(ids).await
+    }
+
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        self.write(|client| async move {
+            let restored = client
+                .query_opt(
+                    "UPDATE users SET deleted_at = NULL, version = version + 1, updated_at = now() \
+                     WHERE id = $1 AND deleted_at IS NOT NULL RETURNING *",
+                    &[&id],
+                )
+                .await?;
+            // Restoring a live user changes nothing, so it keeps its version
+            let row = match restored {
+                Some(row) => Some(row),
+                None => client.query_opt("SELECT * FROM users WHERE id = $1", &[&id]).await?,
+            };
+            Ok(row.as_ref().map(user_from_row))
+        })
+        .await
+    }
+
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        self.write(|client| async move {
+            let updated = client
+                .execute(
+                    "UPDATE users SET password_hash = $2 WHERE id = $1 AND deleted_at IS NULL",
+                    &[&id, &password_hash],
+                )
+                .await?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        self.write(|client| async move {
+            let updated = client
+                .execute(
+                    "UPDATE users SET role = $2 WHERE id = $1 AND deleted_at IS NULL",
+                    &[&id, &role.as_str()],
+                )
+                .await?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        self.read(|client| async move {
+            let row = client
+                .query_opt(
+                    "SELECT id, password_hash, role FROM users \
+                     WHERE lower(email) = lower($1) AND deleted_at IS NULL AND password_hash IS NOT NULL",
+                    &[&email],
+                )
+                .await?;
+            Ok(row.map(|row| Credentials {
+                user_id: row.get(0),
+                password_hash: row.get(1),
+                role: role_from_column(row.get(2)),
+            }))
+        })
+        .await
+    }
+
+    // Not retried: a probe should report a failing database straight away
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        let client = self.connect().await?;
+        client.query_one("SELECT 1", &[]).await?;
+        Ok(())
+    }
+
+    fn close(&self) {
+        self.pool.close();
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        let status = self.pool.status();
+        Some(PoolStatus {
+            max_size: status.max_size,
+            size: status.size,
+            available: status.available,
+            waiting: status.waiting,
+        })
+    }
+}
+
+// Posts are only visible while their user is live, hence the join in every query
+#[async_trait]
+impl PostRepository for PostgresUserRepository {
+    // Not retried, like user inserts. Locking the user row holds off a concurrent delete
+    async fn create(&self, user_id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_opt(
+                "INSERT INTO posts (user_id, title, body) \
+                 SELECT id, $2, $3 FROM users WHERE id = $1 AND deleted_at IS NULL FOR SHARE \
+                 RETURNING *",
+                &[&user_id, &title, &body],
+            )
+            .await?;
+        Ok(row.as_ref().map(post_from_row))
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Post>, RepositoryError> {
+        self.read(|client| async move {
+            let row = client
+                .query_opt(
+                    "SELECT posts.* FROM posts JOIN users ON users.id = posts.user_id \
+                     WHERE posts.id = $1 AND users.deleted_at IS NULL",
+                    &[&id],
+                )
+                .await?;
+            Ok(row.as_ref().map(post_from_row))
+        })
+        .await
+    }
+
+    async fn list(&self, query: &PostListQuery) -> Result<PostList, RepositoryError> {
+        self.read(|client| async move {
+            let from = "FROM posts JOIN users ON users.id = posts.user_id \
+                        WHERE users.deleted_at IS NULL AND ($1::integer IS NULL OR posts.user_id = $1)";
+            let total: i64 = client
+                .query_one(&format!("SELECT COUNT(*) {}", from), &[&query.user_id])
+                .await?
+                .get(0);
+            let rows = client
+                .query(
+                    &format!("SELECT posts.* {} ORDER BY posts.id LIMIT $2 OFFSET $3", from),
+                    &[&query.user_id, &query.limit, &query.offset],
+                )
+                .await?;
+            Ok(PostList {
+                posts: rows.iter().map(post_from_row).collect(),
+                total,
+            })
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        self.write(|client| async move {
+            let row = client
+                .query_opt(
+                    "UPDATE posts SET title = $2, body = $3 FROM users \
+                     WHERE posts.id = $1 AND users.id = posts.user_id AND users.deleted_at IS NULL \
+                     RETURNING posts.*",
+                    &[&id, &title, &body],
+                )
+                .await?;
+            Ok(row.as_ref().map(post_from_row))
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.write(|client| async move {
+            let deleted = client
+                .execute(
+                    "DELETE FROM posts USING users \
+                     WHERE posts.id = $1 AND users.id = posts.user_id AND users.deleted_at IS NULL",
+                    &[&id],
+                )
+                .await?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl GroupRepository for PostgresUserRepository {
+    async fn create(&self, name: &str) -> Result<Group, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one("INSERT INTO groups (name) VALUES ($1) RETURNING *", &[&name])
+            .await?;
+        Ok(group_from_row(&row))
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Group>, RepositoryError> {
+        self.read(|client| async move {
+            let row = client.query_opt("SELECT * FROM groups WHERE id = $1", &[&id]).await?;
+            Ok(row.as_ref().map(group_from_row))
+        })
+        .await
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<GroupList, RepositoryError> {
+        self.read(|client| async move {
+            let total: i64 = client.query_one("SELECT COUNT(*) FROM groups", &[]).await?.get(0);
+            let rows = client
+                .query("SELECT * FROM groups ORDER BY lower(name), id LIMIT $1 OFFSET $2", &[&limit, &offset])
+                .await?;
+            Ok(GroupList {
+                groups: rows.iter().map(group_from_row).collect(),
+                total,
+            })
+        })
+        .await
+    }
+
+    async fn members(&self, group_id: i32, limit: i64, offset: i64) -> Result<MemberList, RepositoryError> {
+        self.read(|client| async move {
+            let from = "FROM users JOIN user_groups ON user_groups.user_id = users.id \
+                        WHERE user_groups.group_id = $1 AND users.deleted_at IS NULL";
+            let total: i64 = client
+                .query_one(&format!("SELECT COUNT(*) {}", from), &[&group_id])
+                .await?
+                .get(0);
+            let rows = client
+                .query(
+                    &format!("SELECT users.* {} ORDER BY users.id LIMIT $2 OFFSET $3", from),
+                    &[&group_id, &limit, &offset],
+                )
+                .await?;
+            Ok(MemberList {
+                users: rows.iter().map(user_from_row).collect(),
+                total,
+            })
+        })
+        .await
+    }
+
+    async fn add_member(&self, group_id: i32, user_id: i32) -> Result<AddMember, RepositoryError> {
+        self.write(|client| async move {
+            let added = client
+                .execute(
+                    "INSERT INTO user_groups (user_id, group_id) \
+                     SELECT users.id, groups.id FROM users, groups \
+                     WHERE users.id = $1 AND users.deleted_at IS NULL AND groups.id = $2 \
+                     ON CONFLICT DO NOTHING",
+                    &[&user_id, &group_id],
+                )
+                .await?;
+            if added > 0 {
+                return Ok(AddMember::Added);
+            }
+            // Nothing inserted: work out which side was missing, or whether it was already there
+            let row = client
+                .query_one(
+                    "SELECT EXISTS (SELECT 1 FROM groups WHERE id = $2), \
+                     EXISTS (SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL)",
+                    &[&user_id, &group_id],
+                )
+                .await?;
+            Ok(match (row.get(0), row.get(1)) {
+                (false, _) => AddMember::NoSuchGroup,
+                (true, false) => AddMember::NoSuchUser,
+                (true, true) => AddMember::AlreadyMember,
+            })
+        })
+        .await
+    }
+
+    async fn remove_member(&self, group_id: i32, user_id: i32) -> Result<bool, RepositoryError> {
+        self.write(|client| async move {
+            let removed = client
+                .execute(
+                    "DELETE FROM user_groups WHERE group_id = $1 AND user_id = $2",
+                    &[&group_id, &user_id],
+                )
+                .await?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    async fn user_groups(&self, user_id: i32) -> Result<Vec<Group>, RepositoryError> {
+        self.read(|client| async move {
+            let rows = client
+                .query(
+                    "SELECT groups.* FROM groups JOIN user_groups ON user_groups.group_id = groups.id \
+                     WHERE user_groups.user_id = $1 ORDER BY lower(groups.name), groups.id",
+                    &[&user_id],
+                )
+                .await?;
+            Ok(rows.iter().map(group_from_row).collect())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for PostgresUserRepository {
+    async fn create(&self, name: &str, role: Role, key_hash: &str) -> Result<ApiKey, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one(
+                &format!("INSERT INTO api_keys (name, role, key_hash) VALUES ($1, $2, $3) RETURNING {}", API_KEY_COLUMNS),
+                &[&name, &role.as_str(), &key_hash],
+            )
+            .await?;
+        Ok(api_key_from_row(&row))
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        self.read(|client| async move {
+            let rows = client
+                .query(&format!("SELECT {} FROM api_keys ORDER BY id", API_KEY_COLUMNS), &[])
+                .await?;
+            Ok(rows.iter().map(api_key_from_row).collect())
+        })
+        .await
+    }
+
+    async fn revoke(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.write(|client| async move {
+            let revoked = client
+                .execute("UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL", &[&id])
+                .await?;
+            Ok(revoked > 0)
+        })
+        .await
+    }
+
+    async fn find_active(&self, key_hash: &str) -> Result<Option<(i32, Role)>, RepositoryError> {
+        self.read(|client| async move {
+            let row = client
+                .query_opt("SELECT id, role FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL", &[&key_hash])
+                .await?;
+            Ok(row.map(|row| (row.get(0), role_from_column(row.get(1)))))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl TokenRepository for PostgresUserRepository {
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<i32, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one(
+                "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3) RETURNING id",
+                &[&user_id, &token_hash, &expires_at],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    // One statement, so a token can't be refreshed twice by racing requests
+    async fn refresh_session(
+        &self,
+        token_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Refreshed>, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_opt(
+                "WITH old AS ( \
+                     UPDATE refresh_tokens SET revoked_at = now() \
+                     FROM users WHERE users.id = refresh_tokens.user_id AND users.deleted_at IS NULL \
+                     AND token_hash = $1 AND revoked_at IS NULL AND expires_at > now() \
+                     RETURNING refresh_tokens.user_id, users.role \
+                 ), new AS ( \
+                     INSERT INTO refresh_tokens (user_id, token_hash, expires_at) \
+                     SELECT user_id, $2, $3 FROM old RETURNING id, user_id \
+                 ) \
+                 SELECT new.id, new.user_id, old.role FROM new JOIN old USING (user_id)",
+                &[&token_hash, &new_hash, &expires_at],
+            )
+            .await?;
+        Ok(row.map(|row| Refreshed {
+            session_id: row.get(0),
+            user_id: row.get(1),
+            role: role_from_column(row.get(2)),
+        }))
+    }
+
+    async fn revoke_session(&self, user_id: i32, session_id: i32) -> Result<(), RepositoryError> {
+        self.write(|client| async move {
+            client
+                .execute(
+                    "UPDATE refresh_tokens SET revoked_at = now() \
+                     WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+                    &[&session_id, &user_id],
+                )
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_access(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        self.write(|client| async move {
+            client.execute("DELETE FROM revoked_tokens WHERE expires_at <= now()", &[]).await?;
+            client
+                .execute(
+                    "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                    &[&jti, &expires_at],
+                )
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, RepositoryError> {
+        self.read(|client| async move {
+            let row = client
+                .query_one("SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)", &[&jti])
+                .await?;
+            Ok(row.get(0))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl AuditRepository for PostgresUserRepository {
+    async fn record(&self, record: AuditRecord) -> Result<(), RepositoryError> {
+        let client = self.connect().await?;
+        client
+            .execute(
+                "INSERT INTO audit_log (actor, action, path, entity_id, status, old_value, new_value) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &record.actor,
+                    &record.action,
+                    &record.path,
+                    &record.entity_id,
+                    &(record.status as i16),
+                    &record.old_value,
+                    &record.new_value,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list(&self, query: &AuditQuery) -> Result<AuditList, RepositoryError> {
+        self.read(|client| async move {
+            let filter = "WHERE $1::varchar IS NULL OR actor = $1";
+            let total: i64 = client.query_one(&format!("SELECT COUNT(*) FROM audit_log {}", filter), &[&query.actor]).await?.get(0);
+            let rows = client
+                .query(
+                    &format!(
+                        "SELECT id, actor, action, path, entity_id, status, old_value, new_value, created_at \
+                         FROM audit_log {} ORDER BY id DESC LIMIT $2 OFFSET $3",
+                        filter
+                    ),
+                    &[&query.actor, &query.limit, &query.offset],
+                )
+                .await?;
+            Ok(AuditList {
+                entries: rows.iter().map(audit_entry_from_row).collect(),
+                total,
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for PostgresUserRepository {
+    // Not retried: a retry after the insert landed would find the claim taken
+    async fn claim(&self, key_hash: &str, request_hash: &str, expires_at: DateTime<Utc>) -> Result<IdempotencyClaim, RepositoryError> {
+        let client = self.connect().await?;
+        client.execute("DELETE FROM idempotency_keys WHERE expires_at <= now()", &[]).await?;
+        let inserted = client
+            .execute(
+                "INSERT INTO idempotency_keys (key_hash, request_hash, expires_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&key_hash, &request_hash, &expires_at],
+            )
+            .await?;
+        if inserted == 1 {
+            return Ok(IdempotencyClaim::Claimed);
+        }
+        let row = client
+            .query_opt("SELECT request_hash, status, headers, body FROM idempotency_keys WHERE key_hash = $1", &[&key_hash])
+            .await?;
+        // Pruned between the insert and the select: whoever held it has given up
+        let Some(row) = row else {
+            return Ok(IdempotencyClaim::InProgress);
+        };
+        let response = row.get::<_, Option<i16>>(1).map(|status| StoredResponse {
+            status: status as u16,
+            headers: row.get::<_, Option<serde_json::Value>>(2).and_then(|headers| serde_json::from_value(headers).ok()).unwrap_or_default(),
+            body: row.get::<_, Option<Vec<u8>>>(3).unwrap_or_default(),
+        });
+        Ok(IdempotencyClaim::of_record(request_hash, row.get(0), response))
+    }
+
+    async fn complete(&self, key_hash: &str, response: &StoredResponse, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let headers = serde_json::to_value(&response.headers).map_err(|e| RepositoryError::Internal(e.to_string()))?;
+        self.write(|client| {
+            let headers = &headers;
+            async move {
+                client
+                    .execute(
+                        "UPDATE idempotency_keys SET status = $2, headers = $3, body = $4, expires_at = $5 WHERE key_hash = $1",
+                        &[&key_hash, &(response.status as i16), headers, &response.body, &expires_at],
+                    )
+                    .await?;
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    async fn release(&self, key_hash: &str) -> Result<(), RepositoryError> {
+        self.write(|client| async move {
+            client.execute("DELETE FROM idempotency_keys WHERE key_hash = $1 AND status IS NULL", &[&key_hash]).await?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for PostgresUserRepository {
+    async fn create(&self, url: &str, secret: &str, events: &[String]) -> Result<Webhook, RepositoryError> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one(
+                &format!("INSERT INTO webhooks (url, secret, events) VALUES ($1, $2, $3) RETURNING {}", WEBHOOK_COLUMNS),
+                &[&url, &secret, &super::join_events(events)],
+            )
+            .await?;
+        Ok(webhook_from_row(&row))
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        self.read(|client| async move {
+            let rows = client.query(&format!("SELECT {} FROM webhooks ORDER BY id", WEBHOOK_COLUMNS), &[]).await?;
+            Ok(rows.iter().map(webhook_from_row).collect())
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Webhook>, RepositoryError> {
+        self.read(|client| async move {
+            let row = client.query_opt(&format!("SELECT {} FROM webhooks WHERE id = $1", WEBHOOK_COLUMNS), &[&id]).await?;
+            Ok(row.as_ref().map(webhook_from_row))
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, url: &str, secret: Option<&str>, events: &[String]) -> Result<Option<Webhook>, RepositoryError> {
+        let events = super::join_events(events);
+        self.write(|client| {
+            let events = &events;
+            async move {
+                let row = client
+                    .query_opt(
+                        &format!(
+                            "UPDATE webhooks SET url = $2, secret = COALESCE($3, secret), events = $4 WHERE id = $1 RETURNING {}",
+                            WEBHOOK_COLUMNS
+                        ),
+                        &[&id, &url, &secret, events],
+                    )
+                    .await?;
+                Ok(row.as_ref().map(webhook_from_row))
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.write(|client| async move { Ok(client.execute("DELETE FROM webhooks WHERE id = $1", &[&id]).await? > 0) })
+            .await
+    }
+
+    async fn subscribers(&self, event_type: &str) -> Result<Vec<WebhookTarget>, RepositoryError> {
+        self.read(|client| async move {
+            let rows = client
+                .query(&format!("SELECT id, url, secret FROM webhooks WHERE {} ORDER BY id", SUBSCRIBED), &[&event_type])
+                .await?;
+            Ok(rows
+                .iter()
+                .map(|row| WebhookTarget {
+                    id: row.get(0),
+                    url: row.get(1),
+                    secret: row.get(2),
+                })
+                .collect())
+        })
+        .await
+    }
+}
+
+fn webhook_from_row(row: &Row) -> Webhook {
+    Webhook {
+        id: row.get(0),
+        url: row.get(1),
+        events: super::split_events(row.get(2)),
+        created_at: row.get(3),
+    }
+}
+
+fn audit_entry_from_row(row: &Row) -> AuditEntry {
+    AuditEntry {
+        id: row.get(0),
+        actor: row.get(1),
+        action: row.get(2),
+        path: row.get(3),
+        entity_id: row.get(4),
+        status: row.get::<_, i16>(5) as u16,
+        old_value: row.get(6),
+        new_value: row.get(7),
+        created_at: row.get(8),
+    }
+}
+
+fn api_key_from_row(row: &Row) -> ApiKey {
+    ApiKey {
+        id: row.get(0),
+        name: row.get(1),
+        role: role_from_column(row.get(2)),
+        created_at: row.get(3),
+        revoked_at: row.get(4),
+    }
+}
+
+// The CHECK constraint admits only known roles; anything else would get the least privilege
+fn role_from_column(role: &str) -> Role {
+    Role::parse(role).unwrap_or_default()
+}
+
+fn group_from_row(row: &Row) -> Group {
+    Group {
+        id: row.get(0),
+        name: row.get(1),
+    }
+}
+
+fn post_from_row(row: &Row) -> Post {
+    Post {
+        id: row.get(0),
+        user_id: row.get(1),
+        title: row.get(2),
+        body: row.get(3),
+    }
+}
+
+fn user_from_row(row: &Row) -> User {
+    User {
+        id: row.get(0),
+        name: row.get(1),
+        email: row.get(2),
+        created_at: row.get(5),
+        updated_at: row.get("updated_at"),
+        deleted_at: row.get(3),
+        version: row.get(4),
+    }
+}
+
+// Build the WHERE clause and its parameters; the cursor condition is optional
+// so the same filters can be reused for the total count
+fn where_clause(list: &UserListQuery, with_cursor: bool) -> (String, Vec<Box<dyn ToSql + Sync + Send>>) {
+    let mut conditions = Vec::new();
+    let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+
+    if !list.include_deleted {
+        conditions.push("deleted_at IS NULL".to_string());
+    }
+    if let Some(name) = &list.name {
+        params.push(Box::new(name.clone()));
+        conditions.push(format!("name = ${}", params.len()));
+    }
+    if let Some(fragment) = &list.email_contains {
+        params.push(Box::new(format!("%{}%", escape_like(fragment))));
+        conditions.push(format!("email ILIKE ${}", params.len()));
+    }
+    if let (true, Some(after)) = (with_cursor, list.after) {
+        params.push(Box::new(after));
+        let op = if list.order == "DESC" { "<" } else { ">" };
+        conditions.push(format!("id {} ${}", op, params.len()));
+    }
+
+    if conditions.is_empty() {
+        (String::new(), params)
+    } else {
+        (format!(" WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+// Escape LIKE wildcards so user input only matches literally
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Borrow boxed parameters in the form tokio-postgres expects
+fn sql_params(params: &[Box<dyn ToSql + Sync + Send>]) -> Vec<&(dyn ToSql + Sync)> {
+    params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect()
+}
+
+// Errors worth retrying: lost or refused connections, server restarts, and serialization
+// failures. Waiting for a free pooled connection timing out is not retried, as that only
+// queues behind an exhausted pool for longer.
+fn is_transient(e: &PoolError) -> bool {
+    is_rolled_back(e) || is_unreachable(e)
+}
+
+// Serialization failures and deadlocks, after which Postgres has rolled the statement or its
+// transaction back, so trying again can't apply it twice
+fn is_rolled_back(e: &PoolError) -> bool {
+    match e {
+        PoolError::Backend(e) => e.code().is_some_and(|code| {
+            [SqlState::T_R_SERIALIZATION_FAILURE, SqlState::T_R_DEADLOCK_DETECTED].contains(code)
+        }),
+        _ => false,
+    }
+}
+
+// A write's failure, and whether the write had been sent when it failed
+struct Attempt {
+    error: PoolError,
+    sent: bool,
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+// Errors meaning the database couldn't be reached or is shutting down, which count against the
+// circuit breaker
+fn is_unreachable(e: &PoolError) -> bool {
+    match e {
+        PoolError::Timeout(TimeoutType::Create | TimeoutType::Recycle) => true,
+        PoolError::Backend(e) => match e.code() {
+            Some(code) => {
+                code.code().starts_with("08")
+                    || [SqlState::ADMIN_SHUTDOWN, SqlState::CRASH_SHUTDOWN, SqlState::CANNOT_CONNECT_NOW].contains(code)
+            }
+            // No SQLSTATE: the connection dropped or could not be opened
+            None => e.is_closed() || e.source().is_some_and(|source| source.is::<io::Error>()),
+        },
+        _ => false,
+    }
+}
+
+// Unique violations become conflicts and cancelled queries timeouts; everything else is an internal error
+impl From<PostgresError> for RepositoryError {
+    fn from(e: PostgresError) -> Self {
+        if e.code() == Some(&SqlState::QUERY_CANCELED) {
+            return RepositoryError::TimedOut;
+        }
+        if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+            let constraint = e.as_db_error().and_then(|db| db.constraint());
+            if constraint == Some(USERS_EMAIL_INDEX) {
+                return RepositoryError::EmailTaken;
+            }
+            if constraint == Some(GROUPS_NAME_INDEX) {
+                return RepositoryError::GroupNameTaken;
+            }
+            return RepositoryError::Conflict;
+        }
+        RepositoryError::Internal(e.to_string())
+    }
+}
+
+// Failing to check out a connection (e.g. the pool is exhausted) means the store is unavailable
+impl From<PoolError> for RepositoryError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Backend(e) => e.into(),
+            e => RepositoryError::Unavailable(e.to_string()),
+        }
+    }
+}
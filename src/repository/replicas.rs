@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use deadpool_postgres::{Object, Pool, PoolError, TimeoutType};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::context::Context;
+use crate::db;
+
+// Read replicas of the primary, taking turns to hand out connections. One that fails a check, or
+// to connect, is skipped until it passes a check again
+pub struct Replicas {
+    replicas: Vec<Replica>,
+    // Where the next turn starts
+    next: AtomicUsize,
+}
+
+struct Replica {
+    // Its place in DATABASE_REPLICA_URLS, from 1; logs don't show URLs, which may hold passwords
+    number: usize,
+    pool: Pool,
+    up: AtomicBool,
+}
+
+impl Replicas {
+    // Pools for DATABASE_REPLICA_URLS, or None when there are none. They are checked every
+    // DB_REPLICA_CHECK_INTERVAL for as long as the repositories using them are around
+    pub fn open(config: &Config) -> Result<Option<Arc<Replicas>>, String> {
+        if config.db_replica_urls.is_empty() {
+            return Ok(None);
+        }
+        let mut replicas = Vec::with_capacity(config.db_replica_urls.len());
+        for (index, url) in config.db_replica_urls.iter().enumerate() {
+            let pool = db::create_replica_pool(config, url)
+                .map_err(|e| format!("Error creating the pool of replica {}: {}", index + 1, e))?;
+            replicas.push(Replica {
+                number: index + 1,
+                pool,
+                up: AtomicBool::new(true),
+            });
+        }
+        info!("Reading from {} replicas, checked every {}s", replicas.len(), config.db_replica_check_interval.as_secs());
+        let replicas = Arc::new(Replicas {
+            replicas,
+            next: AtomicUsize::new(0),
+        });
+        tokio::spawn(check(Arc::downgrade(&replicas), config.db_replica_check_interval));
+        Ok(Some(replicas))
+    }
+
+    // A connection to the next replica that is up, for read-only work (see `Context`); None
+    // otherwise, or when none is up, and the primary is used instead
+    pub(super) async fn checkout(&self) -> Option<Object> {
+        if !Context::current().read_only {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..self.replicas.len() {
+            let replica = &self.replicas[(start + offset) % self.replicas.len()];
+            if !replica.up.load(Ordering::Relaxed) {
+                continue;
+            }
+            match replica.pool.get().await {
+                Ok(client) => return Some(client),
+                // Busy rather than down
+                Err(PoolError::Timeout(TimeoutType::Wait)) => {}
+                Err(e) => replica.mark_down(&e.to_string()),
+            }
+        }
+        None
+    }
+}
+
+impl Replica {
+    fn mark_up(&self) {
+        if !self.up.swap(true, Ordering::Relaxed) {
+            info!("Replica {} is back up", self.number);
+        }
+    }
+
+    fn mark_down(&self, error: &str) {
+        if self.up.swap(false, Ordering::Relaxed) {
+            warn!("Replica {} is down, reading from the others or the primary: {}", self.number, error);
+        }
+    }
+}
+
+// Check each replica every `interval`, marking it up or down, until the replicas are dropped. A
+// check that takes longer than the interval fails
+async fn check(replicas: Weak<Replicas>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let Some(replicas) = replicas.upgrade() else {
+            return;
+        };
+        for replica in &replicas.replicas {
+            let ping = async {
+                let client = replica.pool.get().await.map_err(|e| e.to_string())?;
+                client.simple_query("SELECT 1").await.map_err(|e| e.to_string())
+            };
+            match tokio::time::timeout(interval, ping).await {
+                Ok(Ok(_)) => replica.mark_up(),
+                Ok(Err(e)) => replica.mark_down(&e),
+                Err(_) => replica.mark_down("the check timed out"),
+            }
+        }
+    }
+}
@@ -0,0 +1,1193 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, ErrorCode, OptionalExtension, Row};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use super::{
+    AddMember, ApiKeyRepository, AuditList, AuditQuery, AuditRecord, AuditRepository, Credentials, GroupList, Refreshed, TokenRepository,
+    GroupRepository, IdempotencyClaim, IdempotencyRepository, MemberList, PostList, PostListQuery, PostRepository, RepositoryError,
+    SearchResults, StoredResponse, Upserted, UserChange, UserList, UserListQuery, UserRepository, UserSearch, WebhookRepository, WebhookTarget,
+    TOP_EMAIL_DOMAINS,
+};
+use crate::config::OnUserDelete;
+use crate::migrations::SQLITE_MIGRATIONS;
+use crate::models::{ApiKey, AuditEntry, DailyCount, DomainCount, Group, Post, Role, SearchHit, User, UserPatch, UserStats, Webhook};
+
+// Unique indexes on lower(email) and lower(name), named like their Postgres counterparts
+const USERS_EMAIL_INDEX: &str = "users_email_key";
+const GROUPS_NAME_INDEX: &str = "groups_name_key";
+
+const USER_COLUMNS: &str = "id, name, email, created_at, deleted_at, version, updated_at";
+
+// USER_COLUMNS with the stand-ins listings read for fields left out
+const USER_SELECT: [(&str, &str); 7] = [
+    ("id", "id"),
+    ("name", "''"),
+    ("email", "''"),
+    ("created_at", "NULL"),
+    ("deleted_at", "NULL"),
+    ("version", "0"),
+    ("updated_at", "NULL"),
+];
+
+// The current time in the text format rusqlite writes for DateTime<Utc> (see migration 0004)
+const NOW: &str = "strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')";
+
+const API_KEY_COLUMNS: &str = "id, name, role, created_at, revoked_at";
+
+const POST_COLUMNS: &str = "id, user_id, title, body";
+
+const WEBHOOK_COLUMNS: &str = "id, url, events, created_at";
+
+// Posts of soft-deleted users are hidden until the user is restored
+const LIVE_POSTS: &str = "user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)";
+
+// Users, and their posts, stored in a single SQLite connection; calls run on the blocking thread pool
+pub struct SqliteUserRepository {
+    connection: Arc<Mutex<Connection>>,
+    on_user_delete: OnUserDelete,
+}
+
+impl SqliteUserRepository {
+    // Open (or create) the database file and migrate it; ":memory:" keeps it in memory
+    pub fn open(path: &str, on_user_delete: OnUserDelete) -> Result<Self, String> {
+        let mut connection = match path {
+            ":memory:" => Connection::open_in_memory(),
+            path => Connection::open(path),
+        }
+        .map_err(|e| format!("Error opening SQLite database {}: {}", path, e))?;
+        // Foreign keys are off by default in SQLite and have to be enabled per connection
+        connection
+            .pragma_update(None, "foreign_keys", true)
+            .map_err(|e| format!("Error enabling foreign keys: {}", e))?;
+        migrate(&mut connection).map_err(|e| format!("Error running migrations: {}", e))?;
+        Ok(SqliteUserRepository {
+            connection: Arc::new(Mutex::new(connection)),
+            on_user_delete,
+        })
+    }
+
+    // Run `f` with the connection on a blocking thread so the runtime isn't stalled
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, RepositoryError> + Send + 'static,
+    {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| RepositoryError::Internal("SQLite connection lock poisoned".to_string()))?;
+            f(&mut connection)
+        })
+        .await
+        .map_err(|e| RepositoryError::Internal(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqliteUserRepository {
+    async fn create(&self, name: &str, email: &str) -> Result<User, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        self.with_connection(move |connection| {
+            // created_at and updated_at have no column default (see migration 0004), so they are always supplied
+            let user = connection.query_row(
+                &format!("INSERT INTO users (name, email, created_at, updated_at) VALUES (?1, ?2, ?3, ?3) RETURNING {}", USER_COLUMNS),
+                params![name, email, Utc::now()],
+                user_from_row,
+            )?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn create_many(&self, users: Vec<User>) -> Result<Vec<Result<User, RepositoryError>>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut results = Vec::with_capacity(users.len());
+            {
+                let sql = format!(
+                    "INSERT INTO users (name, email, created_at, updated_at) VALUES (?1, ?2, ?3, ?3) RETURNING {}",
+                    USER_COLUMNS
+                );
+                let mut insert = transaction.prepare(&sql)?;
+                let created_at = Utc::now();
+                for user in &users {
+                    // A failed statement is rolled back on its own, leaving the transaction usable
+                    match insert.query_row(params![user.name, user.email, created_at], user_from_row) {
+                        Ok(user) => results.push(Ok(user)),
+                        Err(e) => {
+                            let e = RepositoryError::from(e);
+                            if !e.is_conflict() {
+                                return Err(e);
+                            }
+                            results.push(Err(e));
+                        }
+                    }
+                }
+            }
+            transaction.commit()?;
+            Ok(results)
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<User>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let sql = format!(
+                "SELECT {} FROM users WHERE id = ?1 AND (?2 OR deleted_at IS NULL)",
+                USER_COLUMNS
+            );
+            let user = connection
+                .query_row(&sql, params![id, include_deleted], user_from_row)
+                .optional()?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn list(&self, list: &UserListQuery) -> Result<UserList, RepositoryError> {
+        let list = list.clone();
+        self.with_connection(move |connection| {
+            // Offset pagination reports the filtered total; keyset pagination skips the count
+            let total = match list.after {
+                Some(_) => None,
+                None => {
+                    let (where_sql, params) = where_clause(&list, false);
+                    let sql = format!("SELECT COUNT(*) FROM users{}", where_sql);
+                    Some(connection.query_row(&sql, params_from_iter(params), |row| row.get::<_, i64>(0))?)
+                }
+            };
+
+            // One extra row tells us whether another page follows
+            let (where_sql, mut params) = where_clause(&list, true);
+            params.push(Value::Integer(list.limit + 1));
+            let mut sql = format!(
+                "SELECT {} FROM users{} ORDER BY users.{} {}, users.id {} LIMIT ?",
+                list.select_list(&USER_SELECT),
+                where_sql,
+                list.sort,
+                list.order,
+                list.order
+            );
+            if list.after.is_none() {
+                params.push(Value::Integer(list.offset));
+                sql.push_str(" OFFSET ?");
+            }
+
+            let mut statement = connection.prepare(&sql)?;
+            let mut users = statement
+                .query_map(params_from_iter(params), user_from_row)?
+                .collect::<Result<Vec<User>, _>>()?;
+            let has_more = users.len() as i64 > list.limit;
+            users.truncate(list.limit as usize);
+            Ok(UserList { users, total, has_more })
+        })
+        .await
+    }
+
+    // Rows are read one at a time, but the connection stays locked until the stream ends
+    async fn stream(&self, list: &UserListQuery, sink: mpsc::Sender<User>) -> Result<(), RepositoryError> {
+        let list = list.clone();
+        self.with_connection(move |connection| {
+            let (where_sql, params) = where_clause(&list, false);
+            let sql = format!(
+                "SELECT {} FROM users{} ORDER BY {} {}, id {}",
+                USER_COLUMNS, where_sql, list.sort, list.order, list.order
+            );
+            let mut statement = connection.prepare(&sql)?;
+            let mut rows = statement.query(params_from_iter(params))?;
+            while let Some(row) = rows.next()? {
+                if sink.blocking_send(user_from_row(row)?).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn count(&self, list: &UserListQuery) -> Result<i64, RepositoryError> {
+        let list = list.clone();
+        self.with_connection(move |connection| {
+            let (where_sql, params) = where_clause(&list, false);
+            let sql = format!("SELECT COUNT(*) FROM users{}", where_sql);
+            Ok(connection.query_row(&sql, params_from_iter(params), |row| row.get(0))?)
+        })
+        .await
+    }
+
+    // No trigram support here: substring matches only, scored exact > prefix > anywhere
+    async fn search(&self, search: &UserSearch) -> Result<SearchResults, RepositoryError> {
+        let text = search.text.to_lowercase();
+        let (limit, offset) = (search.limit, search.offset);
+        self.with_connection(move |connection| {
+            let (prefix, anywhere) = (format!("{}%", escape_like(&text)), format!("%{}%", escape_like(&text)));
+            let matches = "deleted_at IS NULL AND (lower(name) LIKE ?1 ESCAPE '\\' OR lower(email) LIKE ?1 ESCAPE '\\')";
+
+            let total = connection.query_row(
+                &format!("SELECT COUNT(*) FROM users WHERE {}", matches),
+                [&anywhere],
+                |row| row.get(0),
+            )?;
+            let sql = format!(
+                "SELECT {}, CASE WHEN lower(name) = ?2 OR lower(email) = ?2 THEN 1.0 \
+                 WHEN lower(name) LIKE ?3 ESCAPE '\\' OR lower(email) LIKE ?3 ESCAPE '\\' THEN 0.75 ELSE 0.5 END AS score \
+                 FROM users WHERE {} ORDER BY score DESC, id LIMIT ?4 OFFSET ?5",
+                USER_COLUMNS, matches
+            );
+            let mut statement = connection.prepare(&sql)?;
+            let hits = statement
+                .query_map(params![anywhere, text, prefix, limit, offset], |row| {
+                    Ok(SearchHit { user: user_from_row(row)?, score: row.get("score")? })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(SearchResults { hits, total })
+        })
+        .await
+    }
+
+    async fn stats(&self, days: i32) -> Result<UserStats, RepositoryError> {
+        self.with_connection(move |connection| {
+            let (total, deleted): (i64, i64) = connection
+                .query_row("SELECT COUNT(*), COUNT(deleted_at) FROM users", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+            // created_at is text in rusqlite's UTC format, so the date is its first ten characters
+            // and it sorts in time order against a bound written the same way
+            let since = (Utc::now().date_naive() - Days::new(days as u64 - 1)).and_time(NaiveTime::MIN).and_utc();
+            let mut statement = connection.prepare(
+                "SELECT substr(created_at, 1, 10) AS day, COUNT(*) FROM users \
+                 WHERE deleted_at IS NULL AND created_at >= ?1 GROUP BY day ORDER BY day",
+            )?;
+            let created_per_day = statement
+                .query_map([since], |row| Ok(DailyCount { date: row.get(0)?, count: row.get(1)? }))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let domains = "SELECT lower(substr(email, instr(email, '@') + 1)) AS domain FROM users WHERE deleted_at IS NULL";
+            let distinct_email_domains = connection.query_row(
+                &format!("SELECT COUNT(DISTINCT domain) FROM ({})", domains),
+                [],
+                |row| row.get(0),
+            )?;
+            let mut statement = connection.prepare(&format!(
+                "SELECT domain, COUNT(*) FROM ({}) GROUP BY domain ORDER BY COUNT(*) DESC, domain LIMIT ?1",
+                domains
+            ))?;
+            let top_email_domains = statement
+                .query_map([TOP_EMAIL_DOMAINS], |row| Ok(DomainCount { domain: row.get(0)?, count: row.get(1)? }))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(UserStats {
+                total,
+                active: total - deleted,
+                deleted,
+                created_per_day,
+                distinct_email_domains,
+                top_email_domains,
+            })
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, name: &str, email: &str) -> Result<Option<User>, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        self.with_connection(move |connection| {
+            let user = connection
+                .query_row(
+                    &format!(
+                        "UPDATE users SET name = ?1, email = ?2, version = version + 1, updated_at = {} \
+                         WHERE id = ?3 AND deleted_at IS NULL RETURNING {}",
+                        NOW, USER_COLUMNS
+                    ),
+                    params![name, email, id],
+                    user_from_row,
+                )
+                .optional()?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn upsert(&self, id: i32, name: &str, email: &str) -> Result<Option<Upserted>, RepositoryError> {
+        let (name, email) = (name.to_string(), email.to_string());
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let existing = transaction
+                .query_row("SELECT deleted_at IS NOT NULL FROM users WHERE id = ?1", [id], |row| row.get::<_, bool>(0))
+                .optional()?;
+            let upserted = match existing {
+                Some(true) => None,
+                Some(false) => Some(Upserted::Updated(transaction.query_row(
+                    &format!(
+                        "UPDATE users SET name = ?1, email = ?2, version = version + 1, updated_at = {} WHERE id = ?3 RETURNING {}",
+                        NOW, USER_COLUMNS
+                    ),
+                    params![name, email, id],
+                    user_from_row,
+                )?)),
+                // AUTOINCREMENT keeps future ids above any explicitly inserted one
+                None => Some(Upserted::Created(transaction.query_row(
+                    &format!(
+                        "INSERT INTO users (id, name, email, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4) RETURNING {}",
+                        USER_COLUMNS
+                    ),
+                    params![id, name, email, Utc::now()],
+                    user_from_row,
+                )?)),
+            };
+            transaction.commit()?;
+            Ok(upserted)
+        })
+        .await
+    }
+
+    async fn patch(&self, id: i32, patch: &UserPatch) -> Result<Option<User>, RepositoryError> {
+        let (name, email) = (patch.name.clone(), patch.email.clone());
+        self.with_connection(move |connection| {
+            // Absent fields bind as NULL and keep their current value
+            let user = connection
+                .query_row(
+                    &format!(
+                        "UPDATE users SET name = COALESCE(?1, name), email = COALESCE(?2, email), \
+                         version = version + 1, updated_at = {} \
+                         WHERE id = ?3 AND deleted_at IS NULL RETURNING {}",
+                        NOW, USER_COLUMNS
+                    ),
+                    params![name, email, id],
+                    user_from_row,
+                )
+                .optional()?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn modify(&self, id: i32, change: UserChange) -> Result<Option<User>, RepositoryError> {
+        self.with_connection(move |connection| {
+            // The connection lock already serializes writers; the transaction keeps it all-or-nothing
+            let transaction = connection.transaction()?;
+            let sql = format!("SELECT {} FROM users WHERE id = ?1 AND deleted_at IS NULL", USER_COLUMNS);
+            let current = transaction.query_row(&sql, [id], user_from_row).optional()?;
+            let current = match current {
+                Some(user) => user,
+                None => return Ok(None),
+            };
+
+            let changed = change(current).map_err(RepositoryError::Rejected)?;
+            let user = transaction.query_row(
+                &format!(
+                    "UPDATE users SET name = ?1, email = ?2, version = version + 1, updated_at = {} WHERE id = ?3 RETURNING {}",
+                    NOW, USER_COLUMNS
+                ),
+                params![changed.name, changed.email, id],
+                user_from_row,
+            )?;
+            transaction.commit()?;
+            Ok(Some(user))
+        })
+        .await
+    }
+
+    async fn patch_many(&self, patches: Vec<(i32, UserPatch)>) -> Result<Vec<Result<Option<User>, RepositoryError>>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut results = Vec::with_capacity(patches.len());
+            {
+                let sql = format!(
+                    "UPDATE users SET name = COALESCE(?1, name), email = COALESCE(?2, email), \
+                     version = version + 1, updated_at = {} WHERE id = ?3 AND deleted_at IS NULL RETURNING {}",
+                    NOW, USER_COLUMNS
+                );
+                let mut update = transaction.prepare(&sql)?;
+                for (id, patch) in &patches {
+                    // A failed statement is rolled back on its own, leaving the transaction usable
+                    match update.query_row(params![patch.name, patch.email, id], user_from_row).optional() {
+                        Ok(user) => results.push(Ok(user)),
+                        Err(e) => {
+                            let e = RepositoryError::from(e);
+                            if !e.is_conflict() {
+                                return Err(e);
+                            }
+                            results.push(Err(e));
+                        }
+                    }
+                }
+            }
+            transaction.commit()?;
+            Ok(results)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        let on_user_delete = self.on_user_delete;
+        self.with_connection(move |connection| Ok(!soft_delete(connection, &[id], on_user_delete)?.is_empty()))
+            .await
+    }
+
+    async fn delete_many(&self, ids: Vec<i32>) -> Result<Vec<i32>, RepositoryError> {
+        let on_user_delete = self.on_user_delete;
+        self.with_connection(move |connection| soft_delete(connection, &ids, on_user_delete)).await
+    }
+
+    async fn restore(&self, id: i32) -> Result<Option<User>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let restore = format!(
+                "UPDATE users SET deleted_at = NULL, version = version + 1, updated_at = {} \
+                 WHERE id = ?1 AND deleted_at IS NOT NULL RETURNING {}",
+                NOW, USER_COLUMNS
+            );
+            let restored = connection.query_row(&restore, [id], user_from_row).optional()?;
+            // Restoring a live user changes nothing, so it keeps its version
+            let user = match restored {
+                Some(user) => Some(user),
+                None => connection
+                    .query_row(&format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS), [id], user_from_row)
+                    .optional()?,
+            };
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn set_password(&self, id: i32, password_hash: &str) -> Result<bool, RepositoryError> {
+        let password_hash = password_hash.to_string();
+        self.with_connection(move |connection| {
+            let updated = connection.execute(
+                "UPDATE users SET password_hash = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+                params![id, password_hash],
+            )?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn set_role(&self, id: i32, role: Role) -> Result<bool, RepositoryError> {
+        self.with_connection(move |connection| {
+            let updated = connection.execute(
+                "UPDATE users SET role = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+                params![id, role.as_str()],
+            )?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn credentials(&self, email: &str) -> Result<Option<Credentials>, RepositoryError> {
+        let email = email.to_string();
+        self.with_connection(move |connection| {
+            let credentials = connection
+                .query_row(
+                    "SELECT id, password_hash, role FROM users \
+                     WHERE lower(email) = lower(?1) AND deleted_at IS NULL AND password_hash IS NOT NULL",
+                    [email],
+                    |row| {
+                        Ok(Credentials {
+                            user_id: row.get(0)?,
+                            password_hash: row.get(1)?,
+                            role: role_from_column(&row.get::<_, String>(2)?),
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(credentials)
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.with_connection(|connection| {
+            connection.query_row("SELECT 1", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl PostRepository for SqliteUserRepository {
+    async fn create(&self, user_id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let (title, body) = (title.to_string(), body.to_string());
+        self.with_connection(move |connection| {
+            let post = connection
+                .query_row(
+                    &format!(
+                        "INSERT INTO posts (user_id, title, body) \
+                         SELECT id, ?2, ?3 FROM users WHERE id = ?1 AND deleted_at IS NULL RETURNING {}",
+                        POST_COLUMNS
+                    ),
+                    params![user_id, title, body],
+                    post_from_row,
+                )
+                .optional()?;
+            Ok(post)
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Post>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let post = connection
+                .query_row(
+                    &format!("SELECT {} FROM posts WHERE id = ?1 AND {}", POST_COLUMNS, LIVE_POSTS),
+                    [id],
+                    post_from_row,
+                )
+                .optional()?;
+            Ok(post)
+        })
+        .await
+    }
+
+    async fn list(&self, query: &PostListQuery) -> Result<PostList, RepositoryError> {
+        let (user_id, limit, offset) = (query.user_id, query.limit, query.offset);
+        self.with_connection(move |connection| {
+            let filter = format!("WHERE {} AND (?1 IS NULL OR user_id = ?1)", LIVE_POSTS);
+            let total = connection.query_row(&format!("SELECT COUNT(*) FROM posts {}", filter), [user_id], |row| {
+                row.get(0)
+            })?;
+            let sql = format!("SELECT {} FROM posts {} ORDER BY id LIMIT ?2 OFFSET ?3", POST_COLUMNS, filter);
+            let posts = connection
+                .prepare(&sql)?
+                .query_map(params![user_id, limit, offset], post_from_row)?
+                .collect::<rusqlite::Result<Vec<Post>>>()?;
+            Ok(PostList { posts, total })
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, title: &str, body: &str) -> Result<Option<Post>, RepositoryError> {
+        let (title, body) = (title.to_string(), body.to_string());
+        self.with_connection(move |connection| {
+            let post = connection
+                .query_row(
+                    &format!(
+                        "UPDATE posts SET title = ?2, body = ?3 WHERE id = ?1 AND {} RETURNING {}",
+                        LIVE_POSTS, POST_COLUMNS
+                    ),
+                    params![id, title, body],
+                    post_from_row,
+                )
+                .optional()?;
+            Ok(post)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.with_connection(move |connection| {
+            let deleted = connection.execute(&format!("DELETE FROM posts WHERE id = ?1 AND {}", LIVE_POSTS), [id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl GroupRepository for SqliteUserRepository {
+    async fn create(&self, name: &str) -> Result<Group, RepositoryError> {
+        let name = name.to_string();
+        self.with_connection(move |connection| {
+            let group = connection.query_row("INSERT INTO groups (name) VALUES (?1) RETURNING id, name", [name], group_from_row)?;
+            Ok(group)
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Group>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let group = connection
+                .query_row("SELECT id, name FROM groups WHERE id = ?1", [id], group_from_row)
+                .optional()?;
+            Ok(group)
+        })
+        .await
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<GroupList, RepositoryError> {
+        self.with_connection(move |connection| {
+            let total = connection.query_row("SELECT COUNT(*) FROM groups", [], |row| row.get(0))?;
+            let groups = connection
+                .prepare("SELECT id, name FROM groups ORDER BY lower(name), id LIMIT ?1 OFFSET ?2")?
+                .query_map([limit, offset], group_from_row)?
+                .collect::<rusqlite::Result<Vec<Group>>>()?;
+            Ok(GroupList { groups, total })
+        })
+        .await
+    }
+
+    async fn members(&self, group_id: i32, limit: i64, offset: i64) -> Result<MemberList, RepositoryError> {
+        self.with_connection(move |connection| {
+            let from = "FROM users JOIN user_groups ON user_groups.user_id = users.id \
+                        WHERE user_groups.group_id = ?1 AND users.deleted_at IS NULL";
+            let total = connection.query_row(&format!("SELECT COUNT(*) {}", from), [group_id], |row| row.get(0))?;
+            // user_groups has no columns named like the user's, so they need no qualifying
+            let sql = format!("SELECT {} {} ORDER BY users.id LIMIT ?2 OFFSET ?3", USER_COLUMNS, from);
+            let users = connection
+                .prepare(&sql)?
+                .query_map(params![group_id, limit, offset], user_from_row)?
+                .collect::<rusqlite::Result<Vec<User>>>()?;
+            Ok(MemberList { users, total })
+        })
+        .await
+    }
+
+    async fn add_member(&self, group_id: i32, user_id: i32) -> Result<AddMember, RepositoryError> {
+        self.with_connection(move |connection| {
+            let group_exists: bool =
+                connection.query_row("SELECT EXISTS (SELECT 1 FROM groups WHERE id = ?1)", [group_id], |row| row.get(0))?;
+            if !group_exists {
+                return Ok(AddMember::NoSuchGroup);
+            }
+            let user_exists: bool = connection.query_row(
+                "SELECT EXISTS (SELECT 1 FROM users WHERE id = ?1 AND deleted_at IS NULL)",
+                [user_id],
+                |row| row.get(0),
+            )?;
+            if !user_exists {
+                return Ok(AddMember::NoSuchUser);
+            }
+            let added = connection.execute(
+                "INSERT OR IGNORE INTO user_groups (user_id, group_id) VALUES (?1, ?2)",
+                [user_id, group_id],
+            )?;
+            Ok(if added > 0 { AddMember::Added } else { AddMember::AlreadyMember })
+        })
+        .await
+    }
+
+    async fn remove_member(&self, group_id: i32, user_id: i32) -> Result<bool, RepositoryError> {
+        self.with_connection(move |connection| {
+            let removed = connection.execute(
+                "DELETE FROM user_groups WHERE group_id = ?1 AND user_id = ?2",
+                [group_id, user_id],
+            )?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    async fn user_groups(&self, user_id: i32) -> Result<Vec<Group>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let groups = connection
+                .prepare(
+                    "SELECT groups.id, groups.name FROM groups JOIN user_groups ON user_groups.group_id = groups.id \
+                     WHERE user_groups.user_id = ?1 ORDER BY lower(groups.name), groups.id",
+                )?
+                .query_map([user_id], group_from_row)?
+                .collect::<rusqlite::Result<Vec<Group>>>()?;
+            Ok(groups)
+        })
+        .await
+    }
+}
+
+// Soft-delete the live users among `ids` and deal with their posts in one transaction,
+// returning the ids deleted; under restrict, any user with posts rolls the whole lot back
+fn soft_delete(connection: &mut Connection, ids: &[i32], on_user_delete: OnUserDelete) -> Result<Vec<i32>, RepositoryError> {
+    let transaction = connection.transaction()?;
+    let mut deleted = Vec::new();
+    let mut owners = Vec::new();
+    {
+        let mut delete = transaction
+            .prepare("UPDATE users SET deleted_at = ?1, version = version + 1, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NULL")?;
+        let mut has_posts = transaction.prepare("SELECT EXISTS (SELECT 1 FROM posts WHERE user_id = ?1)")?;
+        let mut delete_posts = transaction.prepare("DELETE FROM posts WHERE user_id = ?1")?;
+        let now = Utc::now();
+        for &id in ids {
+            if delete.execute(params![now, id])? == 0 {
+                continue;
+            }
+            deleted.push(id);
+            match on_user_delete {
+                OnUserDelete::Keep => {}
+                OnUserDelete::Cascade => {
+                    delete_posts.execute([id])?;
+                }
+                OnUserDelete::Restrict => {
+                    if has_posts.query_row([id], |row| row.get(0))? {
+                        owners.push(id);
+                    }
+                }
+            }
+        }
+    }
+    if !owners.is_empty() {
+        owners.sort_unstable();
+        return Err(RepositoryError::HasPosts(owners));
+    }
+    transaction.commit()?;
+    Ok(deleted)
+}
+
+// Apply pending migrations in order, recording the schema version in PRAGMA user_version
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for migration in SQLITE_MIGRATIONS.iter().filter(|m| m.version > version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration.sql)?;
+        transaction.pragma_update(None, "user_version", migration.version)?;
+        transaction.commit()?;
+        info!("Applied migration {:04} {}", migration.version, migration.name);
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ApiKeyRepository for SqliteUserRepository {
+    async fn create(&self, name: &str, role: Role, key_hash: &str) -> Result<ApiKey, RepositoryError> {
+        let (name, key_hash) = (name.to_string(), key_hash.to_string());
+        self.with_connection(move |connection| {
+            let key = connection.query_row(
+                &format!(
+                    "INSERT INTO api_keys (name, role, key_hash, created_at) VALUES (?1, ?2, ?3, ?4) RETURNING {}",
+                    API_KEY_COLUMNS
+                ),
+                params![name, role.as_str(), key_hash, Utc::now()],
+                api_key_from_row,
+            )?;
+            Ok(key)
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let keys = connection
+                .prepare(&format!("SELECT {} FROM api_keys ORDER BY id", API_KEY_COLUMNS))?
+                .query_map([], api_key_from_row)?
+                .collect::<rusqlite::Result<Vec<ApiKey>>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+
+    async fn revoke(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.with_connection(move |connection| {
+            let revoked = connection.execute(
+                "UPDATE api_keys SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                params![id, Utc::now()],
+            )?;
+            Ok(revoked > 0)
+        })
+        .await
+    }
+
+    async fn find_active(&self, key_hash: &str) -> Result<Option<(i32, Role)>, RepositoryError> {
+        let key_hash = key_hash.to_string();
+        self.with_connection(move |connection| {
+            let key = connection
+                .query_row(
+                    "SELECT id, role FROM api_keys WHERE key_hash = ?1 AND revoked_at IS NULL",
+                    [key_hash],
+                    |row| Ok((row.get(0)?, role_from_column(&row.get::<_, String>(1)?))),
+                )
+                .optional()?;
+            Ok(key)
+        })
+        .await
+    }
+}
+
+// Timestamps are written in one format (see migration 0004), so they compare as text
+#[async_trait]
+impl TokenRepository for SqliteUserRepository {
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<i32, RepositoryError> {
+        let token_hash = token_hash.to_string();
+        self.with_connection(move |connection| {
+            let id = connection.query_row(
+                "INSERT INTO refresh_tokens (user_id, token_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4) \
+                 RETURNING id",
+                params![user_id, token_hash, Utc::now(), expires_at],
+                |row| row.get(0),
+            )?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn refresh_session(
+        &self,
+        token_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Refreshed>, RepositoryError> {
+        let (token_hash, new_hash) = (token_hash.to_string(), new_hash.to_string());
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let now = Utc::now();
+            let old = transaction
+                .query_row(
+                    "UPDATE refresh_tokens SET revoked_at = ?2 \
+                     WHERE token_hash = ?1 AND revoked_at IS NULL AND expires_at > ?2 \
+                     AND user_id IN (SELECT id FROM users WHERE deleted_at IS NULL) \
+                     RETURNING user_id, (SELECT role FROM users WHERE users.id = refresh_tokens.user_id)",
+                    params![token_hash, now],
+                    |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?)),
+                )
+                .optional()?;
+            let Some((user_id, role)) = old else {
+                return Ok(None);
+            };
+            let session_id = transaction.query_row(
+                "INSERT INTO refresh_tokens (user_id, token_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4) \
+                 RETURNING id",
+                params![user_id, new_hash, now, expires_at],
+                |row| row.get(0),
+            )?;
+            transaction.commit()?;
+            Ok(Some(Refreshed {
+                session_id,
+                user_id,
+                role: role_from_column(&role),
+            }))
+        })
+        .await
+    }
+
+    async fn revoke_session(&self, user_id: i32, session_id: i32) -> Result<(), RepositoryError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "UPDATE refresh_tokens SET revoked_at = ?3 WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL",
+                params![session_id, user_id, Utc::now()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_access(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let jti = jti.to_string();
+        self.with_connection(move |connection| {
+            connection.execute("DELETE FROM revoked_tokens WHERE expires_at <= ?1", [Utc::now()])?;
+            connection.execute(
+                "INSERT OR IGNORE INTO revoked_tokens (jti, expires_at) VALUES (?1, ?2)",
+                params![jti, expires_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, RepositoryError> {
+        let jti = jti.to_string();
+        self.with_connection(move |connection| {
+            let revoked = connection.query_row(
+                "SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = ?1)",
+                [jti],
+                |row| row.get(0),
+            )?;
+            Ok(revoked)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl AuditRepository for SqliteUserRepository {
+    async fn record(&self, record: AuditRecord) -> Result<(), RepositoryError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO audit_log (actor, action, path, entity_id, status, old_value, new_value, created_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    record.actor,
+                    record.action,
+                    record.path,
+                    record.entity_id,
+                    record.status,
+                    record.old_value,
+                    record.new_value,
+                    Utc::now()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self, query: &AuditQuery) -> Result<AuditList, RepositoryError> {
+        let (actor, limit, offset) = (query.actor.clone(), query.limit, query.offset);
+        self.with_connection(move |connection| {
+            let filter = "WHERE ?1 IS NULL OR actor = ?1";
+            let total = connection.query_row(&format!("SELECT COUNT(*) FROM audit_log {}", filter), [&actor], |row| row.get(0))?;
+            let sql = format!(
+                "SELECT id, actor, action, path, entity_id, status, old_value, new_value, created_at \
+                 FROM audit_log {} ORDER BY id DESC LIMIT ?2 OFFSET ?3",
+                filter
+            );
+            let entries = connection
+                .prepare(&sql)?
+                .query_map(params![actor, limit, offset], audit_entry_from_row)?
+                .collect::<rusqlite::Result<Vec<AuditEntry>>>()?;
+            Ok(AuditList { entries, total })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for SqliteUserRepository {
+    async fn claim(&self, key_hash: &str, request_hash: &str, expires_at: DateTime<Utc>) -> Result<IdempotencyClaim, RepositoryError> {
+        let (key_hash, request_hash) = (key_hash.to_string(), request_hash.to_string());
+        self.with_connection(move |connection| {
+            let now = Utc::now();
+            connection.execute("DELETE FROM idempotency_keys WHERE expires_at <= ?1", [now])?;
+            let inserted = connection.execute(
+                "INSERT OR IGNORE INTO idempotency_keys (key_hash, request_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+                params![key_hash, request_hash, now, expires_at],
+            )?;
+            if inserted == 1 {
+                return Ok(IdempotencyClaim::Claimed);
+            }
+            let (record_hash, response) = connection.query_row(
+                "SELECT request_hash, status, headers, body FROM idempotency_keys WHERE key_hash = ?1",
+                [&key_hash],
+                |row| {
+                    let response = match row.get::<_, Option<u16>>(1)? {
+                        Some(status) => Some(StoredResponse {
+                            status,
+                            headers: row
+                                .get::<_, Option<serde_json::Value>>(2)?
+                                .and_then(|headers| serde_json::from_value(headers).ok())
+                                .unwrap_or_default(),
+                            body: row.get::<_, Option<Vec<u8>>>(3)?.unwrap_or_default(),
+                        }),
+                        None => None,
+                    };
+                    Ok((row.get::<_, String>(0)?, response))
+                },
+            )?;
+            Ok(IdempotencyClaim::of_record(&request_hash, &record_hash, response))
+        })
+        .await
+    }
+
+    async fn complete(&self, key_hash: &str, response: &StoredResponse, expires_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let (key_hash, response) = (key_hash.to_string(), response.clone());
+        let headers = serde_json::to_value(&response.headers).map_err(|e| RepositoryError::Internal(e.to_string()))?;
+        self.with_connection(move |connection| {
+            connection.execute(
+                "UPDATE idempotency_keys SET status = ?2, headers = ?3, body = ?4, expires_at = ?5 WHERE key_hash = ?1",
+                params![key_hash, response.status, headers, response.body, expires_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release(&self, key_hash: &str) -> Result<(), RepositoryError> {
+        let key_hash = key_hash.to_string();
+        self.with_connection(move |connection| {
+            connection.execute("DELETE FROM idempotency_keys WHERE key_hash = ?1 AND status IS NULL", [key_hash])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for SqliteUserRepository {
+    async fn create(&self, url: &str, secret: &str, events: &[String]) -> Result<Webhook, RepositoryError> {
+        let (url, secret, events) = (url.to_string(), secret.to_string(), super::join_events(events));
+        self.with_connection(move |connection| {
+            let webhook = connection.query_row(
+                &format!(
+                    "INSERT INTO webhooks (url, secret, events, created_at) VALUES (?1, ?2, ?3, ?4) RETURNING {}",
+                    WEBHOOK_COLUMNS
+                ),
+                params![url, secret, events, Utc::now()],
+                webhook_from_row,
+            )?;
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let webhooks = connection
+                .prepare(&format!("SELECT {} FROM webhooks ORDER BY id", WEBHOOK_COLUMNS))?
+                .query_map([], webhook_from_row)?
+                .collect::<rusqlite::Result<Vec<Webhook>>>()?;
+            Ok(webhooks)
+        })
+        .await
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Webhook>, RepositoryError> {
+        self.with_connection(move |connection| {
+            let webhook = connection
+                .query_row(&format!("SELECT {} FROM webhooks WHERE id = ?1", WEBHOOK_COLUMNS), [id], webhook_from_row)
+                .optional()?;
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn update(&self, id: i32, url: &str, secret: Option<&str>, events: &[String]) -> Result<Option<Webhook>, RepositoryError> {
+        let (url, secret, events) = (url.to_string(), secret.map(str::to_string), super::join_events(events));
+        self.with_connection(move |connection| {
+            let webhook = connection
+                .query_row(
+                    &format!(
+                        "UPDATE webhooks SET url = ?2, secret = COALESCE(?3, secret), events = ?4 WHERE id = ?1 RETURNING {}",
+                        WEBHOOK_COLUMNS
+                    ),
+                    params![id, url, secret, events],
+                    webhook_from_row,
+                )
+                .optional()?;
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepositoryError> {
+        self.with_connection(move |connection| Ok(connection.execute("DELETE FROM webhooks WHERE id = ?1", [id])? > 0)).await
+    }
+
+    async fn subscribers(&self, event_type: &str) -> Result<Vec<WebhookTarget>, RepositoryError> {
+        let event_type = event_type.to_string();
+        self.with_connection(move |connection| {
+            let targets = connection
+                .prepare("SELECT id, url, secret FROM webhooks WHERE ',' || events || ',' LIKE '%,' || ?1 || ',%' ORDER BY id")?
+                .query_map([event_type], |row| {
+                    Ok(WebhookTarget {
+                        id: row.get(0)?,
+                        url: row.get(1)?,
+                        secret: row.get(2)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<WebhookTarget>>>()?;
+            Ok(targets)
+        })
+        .await
+    }
+}
+
+fn webhook_from_row(row: &Row) -> rusqlite::Result<Webhook> {
+    Ok(Webhook {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        events: super::split_events(&row.get::<_, String>(2)?),
+        created_at: row.get(3)?,
+    })
+}
+
+fn audit_entry_from_row(row: &Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        actor: row.get(1)?,
+        action: row.get(2)?,
+        path: row.get(3)?,
+        entity_id: row.get(4)?,
+        status: row.get(5)?,
+        old_value: row.get(6)?,
+        new_value: row.get(7)?,
+        created_at: row.get(8)?,
+    })
+}
+
+fn api_key_from_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        role: role_from_column(&row.get::<_, String>(2)?),
+        created_at: row.get(3)?,
+        revoked_at: row.get(4)?,
+    })
+}
+
+// The CHECK constraint admits only known roles; anything else would get the least privilege
+fn role_from_column(role: &str) -> Role {
+    Role::parse(role).unwrap_or_default()
+}
+
+fn group_from_row(row: &Row) -> rusqlite::Result<Group> {
+    Ok(Group {
+        id: row.get(0)?,
+        name: row.get(1)?,
+    })
+}
+
+fn post_from_row(row: &Row) -> rusqlite::Result<Post> {
+    Ok(Post {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        title: row.get(2)?,
+        body: row.get(3)?,
+    })
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        email: row.get(2)?,
+        created_at: row.get(3)?,
+        deleted_at: row.get(4)?,
+        version: row.get(5)?,
+        updated_at: row.get(6)?,
+    })
+}
+
+// Build the WHERE clause and its positional parameters, mirroring the Postgres filters
+fn where_clause(list: &UserListQuery, with_cursor: bool) -> (String, Vec<Value>) {
+    let mut conditions = Vec::new();
+    let mut params = Vec::new();
+
+    if !list.include_deleted {
+        conditions.push("deleted_at IS NULL");
+    }
+    if let Some(name) = &list.name {
+        params.push(Value::Text(name.clone()));
+        conditions.push("name = ?");
+    }
+    if let Some(fragment) = &list.email_contains {
+        // LIKE is case-insensitive for ASCII in SQLite, matching ILIKE
+        params.push(Value::Text(format!("%{}%", escape_like(fragment))));
+        conditions.push("email LIKE ? ESCAPE '\\'");
+    }
+    if let (true, Some(after)) = (with_cursor, list.after) {
+        params.push(Value::Integer(after.into()));
+        conditions.push(if list.order == "DESC" { "id < ?" } else { "id > ?" });
+    }
+
+    if conditions.is_empty() {
+        (String::new(), params)
+    } else {
+        (format!(" WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+// Escape LIKE wildcards so user input only matches literally
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Unique violations become conflicts; everything else is an internal error
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(e: rusqlite::Error) -> Self {
+        if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) {
+            if e.to_string().contains(USERS_EMAIL_INDEX) {
+                return RepositoryError::EmailTaken;
+            }
+            if e.to_string().contains(GROUPS_NAME_INDEX) {
+                return RepositoryError::GroupNameTaken;
+            }
+            if e.to_string().contains("UNIQUE") {
+                return RepositoryError::Conflict;
+            }
+        }
+        RepositoryError::Internal(e.to_string())
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use tokio_postgres::error::SqlState;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::{PostgresUserRepository, RepositoryError, Stores};
+use crate::config::{Config, RedisConfig, WebhookConfig};
+use crate::models::Tenant;
+use crate::{migrations, webhooks};
+
+// Each tenant's tables are in a schema named after it: `acme` is kept in `tenant_acme`
+const SCHEMA_PREFIX: &str = "tenant_";
+
+// The tenants there are, and stores for each of them. Every tenant's data is in its own Postgres
+// schema, migrated like the default one, and reached through the shared pool: each connection
+// checked out for a tenant first puts its schema on the search_path. Tenant ids must be valid
+// (`validation::is_valid_tenant_id`), since they are spliced into schema names
+pub struct Tenants {
+    // The registry of tenants, in the database's own schema
+    registry: PostgresUserRepository,
+    // The database's search_path, which each tenant's schema is put in front of, so extensions
+    // such as pg_trgm are still found
+    search_path: String,
+    redis: Option<RedisConfig>,
+    user_cache_size: usize,
+    webhooks: WebhookConfig,
+    // Stores of the tenants used since startup, and the token that stops their webhook deliveries
+    opened: Mutex<HashMap<String, (Stores, CancellationToken)>>,
+}
+
+impl Tenants {
+    // Create the registry if it's missing; `registry` works in the database's own schema
+    pub async fn open(registry: PostgresUserRepository, config: &Config) -> Result<Tenants, RepositoryError> {
+        let client = registry.connect().await?;
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS tenants (
+                    id VARCHAR PRIMARY KEY,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                )",
+            )
+            .await?;
+        let search_path: String = client.query_one("SHOW search_path", &[]).await?.get(0);
+        Ok(Tenants {
+            registry,
+            search_path,
+            redis: config.redis.clone(),
+            user_cache_size: config.user_cache_size,
+            webhooks: config.webhooks.clone(),
+            opened: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Create the tenant's schema and bring it up to date; None if the tenant already exists. It
+    // is only registered, and so usable, once migrated
+    pub async fn create(&self, id: &str) -> Result<Option<Tenant>, RepositoryError> {
+        let mut client = self.registry.connect().await?;
+        match client.batch_execute(&format!("CREATE SCHEMA {}", schema(id))).await {
+            Err(e) if e.code() == Some(&SqlState::DUPLICATE_SCHEMA) => return Ok(None),
+            result => result?,
+        }
+        let created = async {
+            client.batch_execute(&self.search_path_for(id)).await?;
+            for migration in migrations::migrate(client.untraced()).await? {
+                info!("Applied migration {:04} {} for tenant {}", migration.version, migration.name, id);
+            }
+            client.batch_execute("RESET search_path").await?;
+            let row = client.query_one("INSERT INTO tenants (id) VALUES ($1) RETURNING id, created_at", &[&id]).await?;
+            Ok::<_, RepositoryError>(tenant_from_row(&row))
+        }
+        .await;
+        if created.is_err() {
+            if let Err(e) = client.batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema(id))).await {
+                warn!("Error dropping the schema of tenant {} after it failed to be created: {}", id, e);
+            }
+        }
+        created.map(Some)
+    }
+
+    // Oldest first
+    pub async fn list(&self) -> Result<Vec<Tenant>, RepositoryError> {
+        let client = self.registry.connect().await?;
+        let rows = client.query("SELECT id, created_at FROM tenants ORDER BY created_at, id", &[]).await?;
+        Ok(rows.iter().map(tenant_from_row).collect())
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Tenant>, RepositoryError> {
+        let client = self.registry.connect().await?;
+        let row = client.query_opt("SELECT id, created_at FROM tenants WHERE id = $1", &[&id]).await?;
+        Ok(row.as_ref().map(tenant_from_row))
+    }
+
+    // Drop the tenant's schema, and every row in it; false if there is no such tenant
+    pub async fn delete(&self, id: &str) -> Result<bool, RepositoryError> {
+        let client = self.registry.connect().await?;
+        if client.execute("DELETE FROM tenants WHERE id = $1", &[&id]).await? == 0 {
+            return Ok(false);
+        }
+        if let Some((stores, stop)) = self.lock().remove(id) {
+            stop.cancel();
+            stores.events.close();
+        }
+        client.batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema(id))).await?;
+        Ok(true)
+    }
+
+    // The tenant's stores, opened on first use; None if there is no such tenant
+    pub async fn stores(&self, id: &str) -> Result<Option<Stores>, RepositoryError> {
+        if let Some((stores, _)) = self.lock().get(id) {
+            return Ok(Some(stores.clone()));
+        }
+        if self.get(id).await?.is_none() {
+            return Ok(None);
+        }
+        let repository = self.registry.sharing_pool().on_checkout(self.search_path_for(id));
+        // Its changes are published as they are made: the NOTIFY listener only follows the
+        // default schema
+        let mut stores = Stores::shared(repository, true);
+        stores.tenant = Some(id.to_string());
+        let stores = super::cache(stores, self.redis.as_ref(), self.user_cache_size).map_err(RepositoryError::Internal)?;
+
+        // Another request may have opened it meanwhile
+        let mut opened = self.lock();
+        if let Some((stores, _)) = opened.get(id) {
+            return Ok(Some(stores.clone()));
+        }
+        let stop = CancellationToken::new();
+        webhooks::spawn(stores.clone(), self.webhooks.clone(), stop.clone());
+        opened.insert(id.to_string(), (stores.clone(), stop));
+        info!("Opened the stores of tenant {}", id);
+        Ok(Some(stores))
+    }
+
+    // Stop every tenant's webhook deliveries and event streams, when shutting down. Tenants'
+    // stores share the default one's pool, which is closed with it
+    pub fn close(&self) {
+        for (stores, stop) in self.lock().values() {
+            stop.cancel();
+            stores.events.close();
+        }
+    }
+
+    fn search_path_for(&self, id: &str) -> String {
+        format!("SET search_path TO {}, {}", schema(id), self.search_path)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, (Stores, CancellationToken)>> {
+        self.opened.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// The tenant's schema, quoted; ids are lowercase letters, digits and hyphens, so nothing needs escaping
+fn schema(id: &str) -> String {
+    format!("\"{}{}\"", SCHEMA_PREFIX, id)
+}
+
+fn tenant_from_row(row: &tokio_postgres::Row) -> Tenant {
+    Tenant {
+        id: row.get("id"),
+        created_at: row.get("created_at"),
+    }
+}
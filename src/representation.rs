@@ -0,0 +1,122 @@
+use crate::config::ResponseFormat;
+use crate::http::Response;
+use crate::jsonapi::{self, Location};
+use crate::{etag, msgpack, xml};
+
+// Response formats a client can ask for with Accept; handlers always produce JSON
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    JsonApi,
+    Xml,
+    MessagePack,
+}
+
+impl Format {
+    // In order of preference when the client likes several equally, after the configured kind of JSON
+    const ALL: [Format; 4] = [Format::Json, Format::JsonApi, Format::Xml, Format::MessagePack];
+
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::JsonApi => &[jsonapi::MEDIA_TYPE],
+            Format::Xml => &["application/xml", "text/xml"],
+            Format::MessagePack => &["application/msgpack", "application/x-msgpack", "application/vnd.msgpack"],
+        }
+    }
+}
+
+// Pick a format from an Accept header (RFC 9110 12.5.1). Each format takes the q-value of the most
+// specific range that matches it; the highest q wins, a format named outright beats one matched
+// by a wildcard, and `json`, the configured kind of JSON, wins ties and is the fallback when
+// nothing acceptable is supported
+pub fn negotiate(accept: &str, json: Format) -> Format {
+    let ranges: Vec<(String, f32)> = accept
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let range = params.next()?.trim().to_ascii_lowercase();
+            let q = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map(|(_, value)| value.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            (!range.is_empty()).then_some((range, q))
+        })
+        .collect();
+
+    let mut best = (json, 0.0, 0);
+    for format in std::iter::once(json).chain(Format::ALL.into_iter().filter(|format| *format != json)) {
+        // (q, specificity) of the most specific matching range: 2 named, 1 `type/*`, 0 `*/*`
+        let matched = ranges
+            .iter()
+            .filter_map(|(range, q)| {
+                let specificity = format.media_types().iter().find_map(|media_type| match range.as_str() {
+                    range if range == *media_type => Some(2),
+                    range if range.strip_suffix("/*").is_some_and(|kind| media_type.starts_with(&format!("{}/", kind))) => Some(1),
+                    "*/*" => Some(0),
+                    _ => None,
+                })?;
+                Some((*q, specificity))
+            })
+            .max_by_key(|(_, specificity)| *specificity);
+        if let Some((q, specificity)) = matched {
+            if q > best.1 || (q == best.1 && q > 0.0 && specificity > best.2) {
+                best = (format, q, specificity);
+            }
+        }
+    }
+    best.0
+}
+
+// The format to answer in: the one Accept asks for, or the configured kind of JSON when it asks for
+// none in particular. Clients naming application/json outright get plain JSON either way
+pub fn choose(accept: Option<&str>, configured: ResponseFormat) -> Format {
+    let json = match configured {
+        ResponseFormat::Json => Format::Json,
+        ResponseFormat::JsonApi => Format::JsonApi,
+    };
+    accept.map_or(json, |accept| negotiate(accept, json))
+}
+
+// Re-encode a JSON response in `format`; JSON:API documents are shaped by where they were asked for,
+// which `location` holds. JSON responses say Vary: Accept, since their format depends on it
+pub fn apply(response: Response, format: Format, location: Option<&Location>) -> Response {
+    if response.stream.is_some() || !is_json(&response) {
+        return response;
+    }
+    let response = response.with_header("Vary", "Accept");
+    if format == Format::Json {
+        return response;
+    }
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&response.body) else {
+        return response;
+    };
+    let (body, content_type, variant) = match format {
+        Format::Json => return response,
+        Format::JsonApi => match location.and_then(|location| jsonapi::document(value, response.status, location)) {
+            Some(document) => (serde_json::to_vec(&document).unwrap_or_default(), jsonapi::MEDIA_TYPE, "jsonapi"),
+            // Routes that don't serve resources answer plain JSON, as they would any unsupported format
+            None => return response,
+        },
+        Format::Xml => (xml::from_json(&value).into_bytes(), "application/xml", "xml"),
+        Format::MessagePack => (msgpack::from_json(&value), "application/msgpack", "msgpack"),
+    };
+    let mut response = Response { body, ..response };
+    for (name, value) in response.headers.iter_mut() {
+        if name.eq_ignore_ascii_case("content-type") {
+            *value = content_type.to_string();
+        }
+    }
+    etag::add_variant(&mut response, variant);
+    response
+}
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .and_then(|(_, value)| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
@@ -0,0 +1,25 @@
+use uuid::Uuid;
+
+use crate::http::Request;
+
+pub const HEADER: &str = "X-Request-Id";
+
+// Longest incoming id that is passed on; anything longer is replaced
+const MAX_LENGTH: usize = 128;
+
+// The id an upstream proxy gave the request, if it is usable, otherwise a new one
+pub fn for_request(request: &Request) -> String {
+    match request.header(HEADER) {
+        Some(id) if is_valid(id) => id.to_string(),
+        _ => generate(),
+    }
+}
+
+pub fn generate() -> String {
+    Uuid::new_v4().to_string()
+}
+
+// Printable ASCII only, so an id can't break up a log line or a header
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
@@ -0,0 +1,43 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::config::RetryConfig;
+
+impl RetryConfig {
+    // Delay before retry number `retry` (starting at 1): doubles each time, capped at max_backoff
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry - 1);
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+// Run `op` until it succeeds, fails permanently, or runs out of attempts,
+// sleeping with exponential backoff between tries
+pub async fn retry<T, E, F, Fut>(policy: &RetryConfig, is_transient: impl Fn(&E) -> bool, mut op: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if attempt < policy.attempts && is_transient(&e) => {
+                let delay = policy.delay(attempt);
+                warn!(
+                    "Transient database error on attempt {} of {}, retrying in {}ms: {}",
+                    attempt,
+                    policy.attempts,
+                    delay.as_millis(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
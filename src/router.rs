@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tracing::{error, warn, Instrument};
+
+use crate::error::AppError;
+use crate::http::{Request, Response};
+use crate::json_schema::Schema;
+use crate::context::{self, Context};
+use crate::request_id;
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<Response, AppError>> + Send>>;
+type BoxedHandler<S> = Box<dyn Fn(Request, S) -> HandlerFuture + Send + Sync>;
+
+// One piece of a route pattern such as `/users/{id}`. A `{*name}` at the end captures the rest of
+// the path, one segment or more, as `a/b/c`
+enum Segment {
+    Static(String),
+    Param(String),
+    Rest(String),
+}
+
+struct Route<S> {
+    method: String,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: BoxedHandler<S>,
+    // What JSON bodies sent to the route must look like, when it says
+    schema: Option<Arc<Schema>>,
+}
+
+impl<S> Route<S> {
+    // Match the path against this route's pattern, returning the captured parameters
+    fn matches(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        let rest = matches!(self.segments.last(), Some(Segment::Rest(_)));
+        if path.len() != self.segments.len() && !(rest && path.len() > self.segments.len()) {
+            return None;
+        }
+        let mut params = HashMap::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(expected) if expected == path[index] => {}
+                Segment::Static(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), path[index].to_string());
+                }
+                Segment::Rest(name) => {
+                    params.insert(name.clone(), path[index..].join("/"));
+                }
+            }
+        }
+        Some(params)
+    }
+
+    // Static segments outrank parameters, so `/users/all` wins over `/users/{id}`
+    fn specificity(&self) -> Vec<bool> {
+        self.segments.iter().map(|s| matches!(s, Segment::Static(_))).collect()
+    }
+}
+
+// What a handler takes of the router's shared state: the whole of it, or a part it can be made into
+pub trait FromState<S> {
+    fn from_state(state: S) -> Self;
+}
+
+impl<S> FromState<S> for S {
+    fn from_state(state: S) -> S {
+        state
+    }
+}
+
+// The most specific route matching the method and path, and the other methods the path allows
+type Found<'a, S> = (Option<(&'a Route<S>, HashMap<String, String>)>, Vec<&'a str>);
+
+// Method + path pattern router; handlers receive the request and shared state `S`, or what they
+// take of it, and return their response or the error to answer with
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Router<S> {
+        Router { routes: Vec::new() }
+    }
+}
+
+impl<S> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Router<S> {
+        Router::default()
+    }
+
+    // Register a handler for a method and a pattern like `/users/{id}`, replacing any registered for
+    // the same ones
+    pub fn route<T, F, Fut>(mut self, method: &str, pattern: &str, handler: F) -> Router<S>
+    where
+        T: FromState<S>,
+        F: Fn(Request, T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response, AppError>> + Send + 'static,
+    {
+        let segments = split_path(pattern)
+            .into_iter()
+            .map(|part| match part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                Some(name) => match name.strip_prefix('*') {
+                    Some(name) => Segment::Rest(name.to_string()),
+                    None => Segment::Param(name.to_string()),
+                },
+                None => Segment::Static(part.to_string()),
+            })
+            .collect();
+
+        self.routes.retain(|route| route.method != method || route.pattern != pattern);
+        self.routes.push(Route {
+            method: method.to_string(),
+            pattern: pattern.to_string(),
+            segments,
+            handler: Box::new(move |request, state| Box::pin(handler(request, T::from_state(state)))),
+            schema: None,
+        });
+        self
+    }
+
+    // Check the JSON bodies of requests to the route registered for a method and pattern against
+    // `schema`, answering 400 with where they don't match instead of running its handler
+    pub fn body_schema(mut self, method: &str, pattern: &str, schema: Schema) -> Router<S> {
+        if let Some(route) = self.routes.iter_mut().find(|route| route.method == method && route.pattern == pattern) {
+            route.schema = Some(Arc::new(schema));
+        }
+        self
+    }
+
+    // Mount every route of `routes` under a path prefix such as `/v1`, e.g. `/users` as `/v1/users`
+    pub fn nest(mut self, prefix: &str, routes: Router<S>) -> Router<S> {
+        for mut route in routes.routes {
+            let mut segments: Vec<Segment> = split_path(prefix).into_iter().map(|part| Segment::Static(part.to_string())).collect();
+            segments.append(&mut route.segments);
+            route.pattern = format!("{}{}", prefix.trim_end_matches('/'), route.pattern);
+            route.segments = segments;
+            self.routes.push(route);
+        }
+        self
+    }
+
+    // Every registered method and pattern, in registration order
+    pub fn routes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.routes.iter().map(|route| (route.method.as_str(), route.pattern.as_str()))
+    }
+
+    // The pattern of the route a request would run, e.g. `/users/{id}` for `/users/7`
+    pub fn pattern(&self, method: &str, path: &str) -> Option<&str> {
+        let (best, _) = self.find(method, &split_path(path));
+        best.map(|(route, _)| route.pattern.as_str())
+    }
+
+    // Whether any route, whatever its method, matches the path
+    pub fn recognizes(&self, path: &str) -> bool {
+        let path = split_path(path);
+        self.routes.iter().any(|route| route.matches(&path).is_some())
+    }
+
+    // Run the most specific matching route, turning a handler's error into its response and a panic
+    // into a 500; 405 if only the method differs, 404 otherwise. OPTIONS without a route of its own
+    // is answered with the methods the path allows, or for `*`, those the server does
+    pub async fn dispatch(&self, mut request: Request, state: S) -> Response {
+        let path = request.path.clone();
+        if request.method == "OPTIONS" && path == "*" {
+            return Response::new(204).with_header("Allow", allow(self.routes.iter().map(|route| route.method.as_str())).join(", "));
+        }
+        let (best, allowed) = self.find(&request.method, &split_path(&path));
+
+        match best {
+            Some((route, params)) => {
+                request.params = params;
+                if let Some(Err(response)) = route.schema.as_ref().map(|schema| schema.check_request(&request)) {
+                    return response;
+                }
+                let request_id = request.header(request_id::HEADER).map(str::to_string);
+                // The handler runs as its own task, so a panic in it is caught there instead of taking
+                // the connection down with it. It runs in the request's context, and is cancelled when the
+                // request's deadline passes
+                let deadline = Context::current().deadline;
+                let mut handle = tokio::spawn(context::inherit((route.handler)(request, state)).in_current_span());
+                let joined = match deadline {
+                    Some(deadline) => match tokio::time::timeout_at(deadline.into(), &mut handle).await {
+                        Ok(joined) => joined,
+                        Err(_) => {
+                            handle.abort();
+                            warn!("Handler for {} {} ran out of time", route.method, route.pattern);
+                            let details = serde_json::json!({ "request_id": request_id });
+                            return Response::error_with_details(504, "timeout", "The request took too long", details)
+                                .with_cause(format!("Handler for {} {} ran out of time", route.method, route.pattern));
+                        }
+                    },
+                    None => handle.await,
+                };
+                match joined {
+                    Ok(result) => result.unwrap_or_else(Response::from),
+                    Err(e) => {
+                        error!("Handler for {} {} failed: {}", route.method, route.pattern, e);
+                        let details = serde_json::json!({ "request_id": request_id });
+                        Response::error_with_details(500, "internal_error", "Internal server error", details)
+                            .with_cause(e.to_string())
+                    }
+                }
+            }
+            None if request.method == "OPTIONS" && !allowed.is_empty() => Response::new(204).with_header("Allow", allowed.join(", ")),
+            None if !allowed.is_empty() => {
+                let details = serde_json::json!({ "allowed": allowed });
+                Response::error_with_details(405, "method_not_allowed", "Method not allowed", details)
+                    .with_header("Allow", allowed.join(", "))
+            }
+            None => Response::error(404, "not_found", "Not found"),
+        }
+    }
+
+    // HEAD runs the GET route where no HEAD route of its own matches
+    fn find(&self, method: &str, path: &[&str]) -> Found<'_, S> {
+        let (best, matched) = self.find_exact(method, path);
+        let allowed = if matched.is_empty() { matched } else { allow(matched.into_iter()) };
+        match best {
+            None if method == "HEAD" => (self.find_exact("GET", path).0, allowed),
+            best => (best, allowed),
+        }
+    }
+
+    fn find_exact(&self, method: &str, path: &[&str]) -> Found<'_, S> {
+        let mut allowed = Vec::new();
+        let mut best: Option<(&Route<S>, HashMap<String, String>)> = None;
+        for route in &self.routes {
+            let Some(params) = route.matches(path) else {
+                continue;
+            };
+            if route.method != method {
+                allowed.push(route.method.as_str());
+                continue;
+            }
+            let more_specific = match &best {
+                Some((current, _)) => route.specificity() > current.specificity(),
+                None => true,
+            };
+            if more_specific {
+                best = Some((route, params));
+            }
+        }
+        (best, allowed)
+    }
+}
+
+// The order methods are listed in, as in Allow; others go before OPTIONS
+const METHOD_ORDER: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+// The methods routes were registered for, once each, with the ones every path gets: HEAD wherever
+// GET is, and OPTIONS
+fn allow<'a>(methods: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut allowed = Vec::new();
+    for method in methods {
+        let implied = (method == "GET").then_some("HEAD");
+        for method in [Some(method), implied].into_iter().flatten() {
+            if !allowed.contains(&method) {
+                allowed.push(method);
+            }
+        }
+    }
+    if !allowed.contains(&"OPTIONS") {
+        allowed.push("OPTIONS");
+    }
+    allowed.sort_by_key(|method| METHOD_ORDER.iter().position(|known| known == method).unwrap_or(METHOD_ORDER.len() - 1));
+    allowed
+}
+
+// Split a path into its non-empty segments
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
@@ -0,0 +1,190 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use schemars::JsonSchema;
+
+use crate::models::User;
+use crate::repository::{AddMember, RepositoryError, Stores};
+
+// Users inserted per transaction
+const BATCH_SIZE: usize = 1000;
+
+const FIRST_NAMES: [&str; 24] = [
+    "Ada", "Alan", "Amara", "Bea", "Carlos", "Chen", "Dana", "Elif", "Farah", "Grace", "Hiro", "Ines", "Jonas", "Kavya", "Leon",
+    "Maya", "Nia", "Omar", "Priya", "Quinn", "Rosa", "Sven", "Tariq", "Yuki",
+];
+const LAST_NAMES: [&str; 24] = [
+    "Adeyemi", "Bauer", "Costa", "Dubois", "Eriksen", "Fischer", "Garcia", "Haddad", "Ivanova", "Jensen", "Kim", "Lopez", "Moreau",
+    "Nakamura", "Okafor", "Patel", "Rossi", "Schmidt", "Silva", "Tanaka", "Usman", "Varga", "Wong", "Zhang",
+];
+const GROUP_ADJECTIVES: [&str; 12] =
+    ["Agile", "Bold", "Bright", "Calm", "Clever", "Daring", "Eager", "Gentle", "Lucky", "Nimble", "Quiet", "Swift"];
+const GROUP_NOUNS: [&str; 12] =
+    ["Badgers", "Cranes", "Dolphins", "Falcons", "Foxes", "Herons", "Lynxes", "Otters", "Owls", "Ravens", "Tigers", "Wolves"];
+const WORDS: [&str; 32] = [
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do", "eiusmod", "tempor", "incididunt",
+    "ut", "labore", "et", "dolore", "magna", "aliqua", "enim", "ad", "minim", "veniam", "quis", "nostrud", "exercitation",
+    "ullamco", "laboris", "nisi", "aliquip", "commodo", "consequat",
+];
+
+// What to insert. Numbered data (`Seed User 1`, ...) is the same on every run, so a repeat run
+// creates nothing new; fake data is drawn from `seed`, or a random seed that the report returns
+#[derive(Deserialize, JsonSchema)]
+pub struct SeedPlan {
+    pub users: usize,
+    // Posts written by each created user
+    #[serde(default)]
+    pub posts_per_user: usize,
+    // Groups created; each created user joins one of them
+    #[serde(default)]
+    pub groups: usize,
+    // Realistic-looking names and text instead of numbered ones
+    #[serde(default)]
+    pub fake: bool,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+// What a seeding run did. Users whose email, or groups whose name, already existed are skipped
+// rather than failing the run
+#[derive(Serialize, JsonSchema)]
+pub struct SeedReport {
+    pub users_created: usize,
+    pub users_skipped: usize,
+    pub posts_created: usize,
+    pub groups_created: usize,
+    pub groups_skipped: usize,
+    pub memberships_added: usize,
+    // Pass back as `seed` to generate the same fake data again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+// Insert the planned users, then their posts, then the groups and memberships
+pub async fn run(stores: &Stores, plan: &SeedPlan) -> Result<SeedReport, RepositoryError> {
+    // Generated seeds stay small enough to survive a round trip through a JavaScript number
+    let seed = plan.fake.then(|| plan.seed.unwrap_or_else(|| u64::from(rand::random::<u32>())));
+    let mut generator = Generator { rng: StdRng::seed_from_u64(seed.unwrap_or_default()), fake: plan.fake };
+    let mut report = SeedReport {
+        users_created: 0,
+        users_skipped: 0,
+        posts_created: 0,
+        groups_created: 0,
+        groups_skipped: 0,
+        memberships_added: 0,
+        seed,
+    };
+
+    let mut user_ids = Vec::new();
+    let mut next = 1;
+    while next <= plan.users {
+        let end = (next + BATCH_SIZE).min(plan.users + 1);
+        let batch = (next..end).map(|n| generator.user(n)).collect();
+        for result in stores.users.create_many(batch).await? {
+            match result {
+                Ok(user) => user_ids.extend(user.id),
+                Err(RepositoryError::EmailTaken) => report.users_skipped += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        next = end;
+    }
+    report.users_created = user_ids.len();
+
+    for (n, user_id) in user_ids.iter().enumerate() {
+        for m in 1..=plan.posts_per_user {
+            let (title, body) = generator.post(n + 1, m);
+            if stores.posts.create(*user_id, &title, &body).await?.is_some() {
+                report.posts_created += 1;
+            }
+        }
+    }
+
+    let mut group_ids = Vec::new();
+    for n in 1..=plan.groups {
+        match stores.groups.create(&generator.group(n)).await {
+            Ok(group) => group_ids.push(group.id),
+            Err(RepositoryError::GroupNameTaken) => report.groups_skipped += 1,
+            Err(e) => return Err(e),
+        }
+    }
+    report.groups_created = group_ids.len();
+
+    if !group_ids.is_empty() {
+        for (n, user_id) in user_ids.iter().enumerate() {
+            let group_id = generator.pick(&group_ids, n);
+            if let AddMember::Added = stores.groups.add_member(group_id, *user_id).await? {
+                report.memberships_added += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+// Names and text for seeded rows, numbered or drawn from the seeded RNG
+struct Generator {
+    rng: StdRng,
+    fake: bool,
+}
+
+impl Generator {
+    fn user(&mut self, n: usize) -> User {
+        let (name, email) = if self.fake {
+            let first = *FIRST_NAMES.choose(&mut self.rng).unwrap_or(&"Sam");
+            let last = *LAST_NAMES.choose(&mut self.rng).unwrap_or(&"Smith");
+            // The random tag keeps emails apart across runs with different seeds
+            let tag: u32 = self.rng.gen_range(1000..100_000);
+            let email = format!("{}.{}.{}@example.com", first, last, tag).to_ascii_lowercase();
+            (format!("{} {}", first, last), email)
+        } else {
+            (format!("Seed User {}", n), format!("seed-{}@example.com", n))
+        };
+        User {
+            id: None,
+            name,
+            email,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        }
+    }
+
+    // Post `m` of the `n`th created user
+    fn post(&mut self, n: usize, m: usize) -> (String, String) {
+        if !self.fake {
+            return (format!("Post {} by seed user {}", m, n), "Generated by the seeder.".to_string());
+        }
+        let mut title = self.words(4);
+        title[..1].make_ascii_uppercase();
+        let sentences: Vec<String> = (0..3)
+            .map(|_| {
+                let mut sentence = self.words(10);
+                sentence[..1].make_ascii_uppercase();
+                sentence + "."
+            })
+            .collect();
+        (title, sentences.join(" "))
+    }
+
+    fn group(&mut self, n: usize) -> String {
+        if !self.fake {
+            return format!("Seed Group {}", n);
+        }
+        let adjective = GROUP_ADJECTIVES.choose(&mut self.rng).unwrap_or(&"Brave");
+        let noun = GROUP_NOUNS.choose(&mut self.rng).unwrap_or(&"Bears");
+        format!("{} {} {}", adjective, noun, self.rng.gen_range(100..1000))
+    }
+
+    // Round robin for numbered data, a random pick for fake data
+    fn pick(&mut self, ids: &[i32], n: usize) -> i32 {
+        match self.fake {
+            true => ids[self.rng.gen_range(0..ids.len())],
+            false => ids[n % ids.len()],
+        }
+    }
+
+    fn words(&mut self, count: usize) -> String {
+        (0..count).map(|_| *WORDS.choose(&mut self.rng).unwrap_or(&"lorem")).collect::<Vec<_>>().join(" ")
+    }
+}
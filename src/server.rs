@@ -0,0 +1,461 @@
+use chrono::Utc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn, Instrument};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::access_log::{self, AccessLog};
+use crate::config::Config;
+use crate::error_reporting::{ErrorReporter, Sentry};
+use crate::forwarded::{self, Client};
+use crate::handlers;
+use crate::http::{self, RequestError, Response};
+use crate::load_shed::LoadShedder;
+use crate::metrics::Metrics;
+use crate::middleware::{self, Pipeline};
+use crate::ratelimit::RateLimiter;
+use crate::repository;
+use crate::router::Router;
+use crate::state::AppState;
+use crate::versioning::{self, Version};
+use crate::{grpc, request_id, tls, webhooks, websocket};
+
+// Set up the database and serve connections until the process receives SIGINT or SIGTERM
+pub async fn run(config: Arc<Config>) {
+    // The plaintext listener, unless TLS is the only one
+    let listener = match config.tls.as_ref().is_some_and(|tls| tls.only) {
+        true => None,
+        false => match TcpListener::bind(("0.0.0.0", config.port)).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                error!("Error binding port {}: {}", config.port, e);
+                return;
+            }
+        },
+    };
+    serve_until(config, listener, wait_for_signal()).await
+}
+
+// Set up the database, serve `listener` (and the TLS port when one is configured) until `stop`
+// resolves, then drain connections. Taking the listener lets tests bind port 0
+pub async fn serve_until(config: Arc<Config>, listener: Option<TcpListener>, stop: impl Future<Output = ()>) {
+    // Open the stores, bringing their schema up to date
+    let stores = match repository::connect(&config).await {
+        Ok(stores) => stores,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    info!("Serving with {} worker threads", config.worker_threads);
+
+    let access_log = match config.access_log.as_ref().map(AccessLog::open).transpose() {
+        Ok(access_log) => access_log.map(Arc::new),
+        Err(e) => {
+            error!("Error opening the access log: {}", e);
+            return;
+        }
+    };
+    // Cancelled on SIGINT/SIGTERM; connection tasks are tracked so they can be drained
+    let shutdown = CancellationToken::new();
+    let connections = TaskTracker::new();
+    let mut servers = Vec::new();
+
+    let metrics = Arc::new(Metrics::new());
+    let router = Arc::new(handlers::build_router(&config));
+    let limiter = config.rate_limit.as_ref().map(|rate_limit| Arc::new(RateLimiter::new(rate_limit)));
+    let shedder = config.load_shedding.as_ref().map(|load_shedding| Arc::new(LoadShedder::new(load_shedding, Arc::clone(&metrics))));
+    let state = AppState::new(Arc::clone(&config), stores.clone(), metrics, access_log, shutdown.clone());
+    let pipeline = Arc::new(pipeline(router, state, limiter.clone(), shedder));
+    let webhooks = webhooks::spawn(stores.clone(), config.webhooks.clone(), shutdown.clone());
+
+    // Start the HTTPS listener when a certificate is configured
+    if let Some(tls_config) = &config.tls {
+        let acceptor = match tls::load_acceptor(tls_config) {
+            Ok(acceptor) => acceptor,
+            Err(e) => {
+                error!("Error loading TLS certificate: {}", e);
+                return;
+            }
+        };
+        let listener = match TcpListener::bind(("0.0.0.0", tls_config.port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Error binding port {}: {}", tls_config.port, e);
+                return;
+            }
+        };
+        info!("TLS server started at port {}", tls_config.port);
+        servers.push(tokio::spawn(serve_tls(listener, acceptor, Arc::clone(&pipeline), connections.clone())));
+    }
+
+    // Start the gRPC listener when a port is configured
+    if let Some(port) = config.grpc_port {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Error binding port {}: {}", port, e);
+                return;
+            }
+        };
+        info!("gRPC server started at port {}", port);
+        servers.push(tokio::spawn(grpc::serve(
+            listener,
+            Arc::clone(&config),
+            stores.clone(),
+            limiter.clone(),
+            shutdown.clone(),
+            connections.clone(),
+        )));
+    }
+
+    // Start server
+    if let Some(listener) = listener {
+        match listener.local_addr() {
+            Ok(addr) => info!("Server started at port {}", addr.port()),
+            Err(e) => warn!("Server started on an unknown address: {}", e),
+        }
+        servers.push(tokio::spawn(serve(listener, Arc::clone(&pipeline), connections.clone())));
+    }
+
+    stop.await;
+    info!("Shutting down; waiting up to {}s for active connections", config.shutdown_timeout.as_secs());
+
+    // Stop accepting, then give in-flight requests until the deadline to finish
+    shutdown.cancel();
+    stores.events.close();
+    if let Some(tenants) = &stores.tenants {
+        tenants.close();
+    }
+    for server in servers {
+        let _ = server.await;
+    }
+    connections.close();
+    if tokio::time::timeout(config.shutdown_timeout, connections.wait()).await.is_err() {
+        warn!("Shutdown deadline reached with {} connections still open", connections.len());
+    }
+    let _ = webhooks.await;
+    stores.users.close();
+    info!("Server stopped");
+}
+
+// Resolve when the process receives SIGINT (Ctrl+C) or SIGTERM
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Error listening for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Error listening for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// Accept plaintext connections until shutdown, each running on its own task
+async fn serve(listener: TcpListener, pipeline: Arc<Pipeline>, connections: TaskTracker) {
+    let shutdown = &pipeline.state().shutdown;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => return,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                // Responses go out as a head write then a body write; without this, Nagle's algorithm holds the
+                // body back until the client's delayed ACK, adding ~40ms to every keep-alive request
+                let _ = stream.set_nodelay(true);
+                let pipeline = Arc::clone(&pipeline);
+                connections.spawn(async move {
+                    let peer = Client { ip: peer.ip(), scheme: "http" };
+                    handle_client(stream, peer, &pipeline).await;
+                });
+            }
+            Err(e) => {
+                error!("Error handling client: {}", e);
+            }
+        }
+    }
+}
+
+// Accept HTTPS connections until shutdown, completing the TLS handshake on the connection task
+async fn serve_tls(listener: TcpListener, acceptor: TlsAcceptor, pipeline: Arc<Pipeline>, connections: TaskTracker) {
+    let shutdown = &pipeline.state().shutdown;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => return,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                let _ = stream.set_nodelay(true);
+                let acceptor = acceptor.clone();
+                let pipeline = Arc::clone(&pipeline);
+                connections.spawn(async move {
+                    let metrics = &pipeline.state().metrics;
+                    let handshake = tokio::time::timeout(pipeline.state().config.read_timeout, acceptor.accept(stream));
+                    match handshake.await {
+                        Ok(Ok(stream)) => {
+                            let peer = Client { ip: peer.ip(), scheme: "https" };
+                            handle_client(stream, peer, &pipeline).await
+                        }
+                        Ok(Err(e)) => {
+                            metrics.connection_error();
+                            warn!("TLS handshake failed: {}", e);
+                        }
+                        Err(_) => {
+                            metrics.connection_error();
+                            warn!("TLS handshake timed out");
+                        }
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Error handling client: {}", e);
+            }
+        }
+    }
+}
+
+// Handle client connection, serving requests until it closes or goes idle
+pub async fn handle_client<S>(stream: S, peer: Client, pipeline: &Pipeline)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let AppState { config, metrics, shutdown, .. } = pipeline.state();
+    let access_log = pipeline.state().access_log.as_deref();
+    let router = pipeline.router();
+    let _open = metrics.connection_opened();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        // Wait for the first byte of the next request, bounded by the idle timeout
+        let idle = tokio::time::timeout(config.keep_alive_timeout, reader.fill_buf());
+        tokio::select! {
+            ready = idle => match ready {
+                Ok(Ok(buffered)) if !buffered.is_empty() => {}
+                // Closed by the client, failed, or idle for too long
+                _ => return,
+            },
+            // Don't wait for further requests once shutdown has begun
+            _ = shutdown.cancelled() => return,
+        }
+
+        // The rest of the headers and body must arrive within the read timeout
+        let started = Instant::now();
+        let received = Utc::now();
+        let next_request = http::read_request(&mut reader, config.max_body_size);
+        let mut result = match tokio::time::timeout(config.read_timeout, next_request).await {
+            Ok(result) => result,
+            Err(_) => {
+                metrics.malformed_request();
+                let response = Response::error(408, "request_timeout", "Request timeout");
+                let _ = http::write_response(&mut writer, response, false, config.write_timeout).await;
+                return;
+            }
+        };
+
+        // Everything logged while handling the request carries its id, method and path; the status
+        // and latency are filled in once it is answered. The id is taken from the upstream proxy
+        // when it sent one; requests that can't be parsed get a fresh one
+        let request_id = match &result {
+            Ok(request) => request_id::for_request(request),
+            Err(_) => request_id::generate(),
+        };
+        // Behind trusted proxies, the client they forwarded for rather than the last of them
+        let client = match &result {
+            Ok(request) => forwarded::client(request, peer, config),
+            Err(_) => peer,
+        };
+        // The request line for the access log, as sent bar normalization, before routing rewrites it
+        let request_line = match (&result, access_log) {
+            (Ok(request), Some(_)) => Some((request.method.clone(), request.query.link(&request.path, &[]), request.version.clone())),
+            _ => None,
+        };
+        // Unprefixed paths are served by the legacy API version's routes
+        let version = match &mut result {
+            Ok(request) => versioning::resolve(request, router),
+            Err(_) => None,
+        };
+        // Metrics are labelled with the route pattern rather than the path, to keep series few.
+        // Legacy requests are labelled with the pattern they used, so what's left of them shows
+        let route = match &result {
+            Ok(request) => {
+                let pattern = router.pattern(&request.method, &request.path);
+                let pattern = match version {
+                    Some(Version::Legacy(_)) => pattern.map(versioning::unversioned),
+                    _ => pattern,
+                };
+                Some((request.method.clone(), pattern))
+            }
+            Err(_) => None,
+        };
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = tracing::field::Empty,
+            path = tracing::field::Empty,
+            client = tracing::field::Empty,
+            scheme = tracing::field::Empty,
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+        );
+        let (response, mut keep_alive) = match result {
+            Ok(mut request) => {
+                // Handlers, and the 500 for one that panics, see the id even when it was generated here
+                request.headers.insert(request_id::HEADER.to_ascii_lowercase(), request_id.clone());
+                span.record("method", request.method.as_str());
+                span.record("path", request.path.as_str());
+                span.record("client", client.ip.to_string());
+                span.record("scheme", client.scheme);
+                let keep_alive = request.keep_alive();
+                let head = request.method == "HEAD";
+                let response = pipeline.handle(request, client.ip).instrument(span.clone()).await;
+                let mut response = match version {
+                    Some(version) => version.apply(response),
+                    None => response,
+                };
+                // A HEAD request gets the head a GET would have, so validators and lengths can be checked
+                response.omit_body = head;
+                (response, keep_alive)
+            }
+            Err(RequestError::Io(e)) => {
+                if !http::is_disconnect(&e) {
+                    metrics.connection_error();
+                    warn!("Error reading from stream: {}", e);
+                }
+                return;
+            }
+            Err(RequestError::PayloadTooLarge(len)) => {
+                span.in_scope(|| warn!("Rejecting request body of {} bytes", len));
+                (Response::error(413, "payload_too_large", "Payload too large"), false)
+            }
+            Err(RequestError::UnsupportedTransferEncoding(encoding)) => {
+                span.in_scope(|| warn!("Unsupported transfer-encoding: {}", encoding));
+                (Response::error(501, "unsupported_transfer_encoding", "Transfer-Encoding not supported"), false)
+            }
+            Err(RequestError::HeadersTooLarge) => {
+                (Response::error(431, "headers_too_large", "Request headers too large"), false)
+            },
+            Err(e) => {
+                span.in_scope(|| warn!("Error parsing request: {}", e));
+                (Response::error(400, "bad_request", e.to_string()), false)
+            }
+        };
+
+        keep_alive &= !shutdown.is_cancelled();
+        let status = response.status;
+        span.record("status", status);
+        let mut response = response.with_header(request_id::HEADER, request_id.as_str());
+        let upgrade = match status {
+            101 => response.upgrade.take(),
+            _ => None,
+        };
+        let written = http::write_response(&mut writer, response, keep_alive, config.write_timeout).instrument(span.clone()).await;
+        let elapsed = started.elapsed();
+        span.record("latency_ms", elapsed.as_micros() as f64 / 1000.0);
+        match &route {
+            Some((method, pattern)) => metrics.observe(method, *pattern, status, elapsed),
+            None => metrics.malformed_request(),
+        }
+        if let Some(access_log) = access_log {
+            let (method, target, version) = match &request_line {
+                Some((method, target, version)) => (Some(method.as_str()), Some(target.as_str()), Some(version.as_str())),
+                None => (None, None, None),
+            };
+            let bytes = written.as_ref().copied().unwrap_or_default();
+            access_log.record(&access_log::Entry {
+                time: received,
+                client: client.ip,
+                request_id: &request_id,
+                method,
+                target,
+                version,
+                status,
+                bytes,
+                duration: elapsed,
+            });
+        }
+        match written {
+            Ok(_) => span.in_scope(|| info!("Request completed")),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                metrics.connection_error();
+                span.in_scope(|| warn!("Timed out writing response"));
+                return;
+            }
+            Err(e) => {
+                if !http::is_disconnect(&e) {
+                    metrics.connection_error();
+                    span.in_scope(|| warn!("Error writing to stream: {}", e));
+                }
+                return;
+            }
+        }
+        // The connection now belongs to the protocol it was upgraded to
+        if let Some(session) = upgrade {
+            websocket::serve(session, &mut reader, &mut writer, config.write_timeout, shutdown).instrument(span).await;
+            return;
+        }
+        if !keep_alive {
+            return;
+        }
+    }
+}
+
+// The layers a request passes through on its way to its route, outermost first: failures are
+// reported from outside the rest, so none is missed. Conditional requests are answered from the
+// response as sent, once it has been shaped for the client, so its tag and Vary are those of the
+// format and coding it is in. That shaping comes after CORS and anything that can turn the request
+// away early; only then is it counted, scoped to its tenant and checked, from the cheapest of those
+// to the dearest
+fn pipeline(
+    router: Arc<Router<AppState>>,
+    state: AppState,
+    limiter: Option<Arc<RateLimiter>>,
+    shedder: Option<Arc<LoadShedder>>,
+) -> Pipeline {
+    let cors = state.config.cors.is_some();
+    let reporter = state.config.sentry.as_ref().map(|sentry| Arc::new(Sentry::new(sentry)) as Arc<dyn ErrorReporter>);
+    Pipeline::builder(router, state)
+        .optional_layer(reporter.map(middleware::ErrorReporting))
+        .layer(middleware::ConditionalRequests)
+        .layer(middleware::Compression)
+        .layer(middleware::Representation)
+        .layer(middleware::EntityTags)
+        .optional_layer(cors.then_some(middleware::Cors))
+        .layer(middleware::TrailingSlashRedirect)
+        .optional_layer(shedder.map(middleware::LoadShedding))
+        .layer(middleware::RouteBudgets)
+        .optional_layer(limiter.map(middleware::RateLimit))
+        .layer(middleware::Tenancy)
+        .layer(middleware::Authorization)
+        .layer(middleware::Idempotency)
+        .layer(middleware::Audit)
+        .build()
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::access_log::AccessLog;
+use crate::auth::TokenSettings;
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::repository::Stores;
+use crate::router::FromState;
+use crate::static_files::StaticFiles;
+
+// Everything handlers and middleware share, set up once when the server starts and cloned into
+// each request. Handlers take the whole of it, or just the stores when that's all they need
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    // The request's tenant's stores, with the caches in front of them; the default tenant's until
+    // tenancy has picked
+    pub stores: Stores,
+    pub metrics: Arc<Metrics>,
+    // How sessions' tokens are signed and how long they last
+    pub tokens: Arc<TokenSettings>,
+    // Files served to browsers: the docs explorer, the admin page and STATIC_DIR
+    pub files: Arc<StaticFiles>,
+    // Where each request is logged once answered, when ACCESS_LOG is on
+    pub access_log: Option<Arc<AccessLog>>,
+    // Cancelled when the server starts shutting down
+    pub shutdown: CancellationToken,
+}
+
+impl AppState {
+    pub fn new(
+        config: Arc<Config>,
+        stores: Stores,
+        metrics: Arc<Metrics>,
+        access_log: Option<Arc<AccessLog>>,
+        shutdown: CancellationToken,
+    ) -> AppState {
+        let tokens = Arc::new(TokenSettings {
+            secret: config.jwt_secret.clone(),
+            access_ttl: config.token_ttl,
+            refresh_ttl: config.refresh_ttl,
+        });
+        let files = Arc::new(StaticFiles::new(config.static_files.as_ref()));
+        AppState { config, stores, metrics, tokens, files, access_log, shutdown }
+    }
+}
+
+impl FromState<AppState> for Stores {
+    fn from_state(state: AppState) -> Stores {
+        state.stores
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::config::StaticConfig;
+use crate::error::AppError;
+use crate::etag;
+use crate::http::{self, Request, Response};
+
+// Content types by file extension; files with other extensions are sent as application/octet-stream
+const CONTENT_TYPES: [(&str, &str); 22] = [
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("pdf", "application/pdf"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("wasm", "application/wasm"),
+];
+
+// Files served from a directory on disk, which may also hold replacements for the files of the
+// pages compiled into the binary
+pub struct StaticFiles {
+    // Canonical, so files can be checked to be under it once symlinks are resolved
+    dir: Option<PathBuf>,
+    max_age: Duration,
+}
+
+impl StaticFiles {
+    pub fn new(config: Option<&StaticConfig>) -> StaticFiles {
+        let dir = config.and_then(|config| match config.dir.canonicalize() {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                warn!("Static files are off: {} can't be resolved: {}", config.dir.display(), e);
+                None
+            }
+        });
+        StaticFiles { dir, max_age: config.map(|config| config.max_age).unwrap_or_default() }
+    }
+
+    // The file at `path`, as it came in the URL (`/`-separated and percent-encoded), under the
+    // directory; a directory's index.html for a directory. None when there's no such file, and for
+    // paths that would leave the directory or name hidden files: `..`, absolute and `.` segments,
+    // and symlinks out of it are all treated as missing
+    pub async fn read(&self, path: &str) -> Option<Response> {
+        let dir = self.dir.as_ref()?;
+        let mut file = dir.clone();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            let segment = decode_segment(segment)?;
+            let mut components = Path::new(&segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) if !segment.starts_with('.') => file.push(&segment),
+                _ => return None,
+            }
+        }
+
+        let found = async {
+            if tokio::fs::metadata(&file).await?.is_dir() {
+                file.push("index.html");
+            }
+            let file = tokio::fs::canonicalize(&file).await?;
+            if !file.starts_with(dir) {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            let metadata = tokio::fs::metadata(&file).await?;
+            if !metadata.is_file() {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            let body = tokio::fs::read(&file).await?;
+            Ok((file, metadata, body))
+        };
+        let (file, metadata, body) = match found.await {
+            Ok(found) => found,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) => return None,
+            Err(e) => {
+                warn!("Error reading static file {}: {}", file.display(), e);
+                return None;
+            }
+        };
+
+        let mut response = Response::new(200)
+            .with_header("Content-Type", content_type(&file))
+            .with_header("Cache-Control", format!("public, max-age={}", self.max_age.as_secs()))
+            .with_header("X-Content-Type-Options", "nosniff");
+        // Validators from the file's size and modification time, so a revalidation needn't read it
+        if let Ok(modified) = metadata.modified() {
+            let nanos = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+            response = response
+                .with_header("ETag", format!("W/\"{:x}-{:x}\"", metadata.len(), nanos))
+                .with_header("Last-Modified", http::http_date(DateTime::<Utc>::from(modified)));
+        }
+        response.body = body;
+        Some(response)
+    }
+
+    // The file at `path` under the directory when there is one, otherwise `content`, the one compiled
+    // into the binary. Built-in files are revalidated on every load, so a new build's are picked up
+    // straight away; their entity tags make that cheap
+    pub async fn or_embedded(&self, path: &str, content: &'static str) -> Response {
+        if let Some(response) = self.read(path).await {
+            return response;
+        }
+        let mut response = Response::new(200)
+            .with_header("Content-Type", content_type(Path::new(path)))
+            .with_header("Cache-Control", "no-cache")
+            .with_header("ETag", etag::for_body(content.as_bytes()));
+        response.body = content.as_bytes().to_vec();
+        response
+    }
+}
+
+// GET /static/{*path}
+pub async fn handle_request(request: Request, files: Arc<StaticFiles>) -> Result<Response, AppError> {
+    let path = request.params.get("path").map(String::as_str).unwrap_or_default();
+    files.read(path).await.ok_or(AppError::NotFound("Not found"))
+}
+
+fn content_type(file: &Path) -> &'static str {
+    let extension = file.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_ascii_lowercase();
+    CONTENT_TYPES.iter().find(|(known, _)| *known == extension).map(|(_, content_type)| *content_type).unwrap_or("application/octet-stream")
+}
+
+// Decode a path segment's %XX escapes. None when one is malformed, or decodes to something a
+// segment can't hold: a separator, a NUL or bytes that aren't UTF-8
+fn decode_segment(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3).filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    let decoded = String::from_utf8(decoded).ok()?;
+    (!decoded.contains(['/', '\\', '\0'])).then_some(decoded)
+}
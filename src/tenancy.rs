@@ -0,0 +1,39 @@
+use crate::config::Config;
+use crate::http::{Request, Response};
+use crate::repository::Stores;
+use crate::validation;
+
+pub const HEADER: &str = "X-Tenant-Id";
+
+// The stores a request works with: its tenant's when the X-Tenant-Id header or, with TENANT_DOMAIN
+// set, the subdomain names one, otherwise the default ones. 400 for a tenant when tenancy is off,
+// rather than quietly using the default data, and 404 for one that doesn't exist
+pub async fn stores_for(config: &Config, stores: &Stores, request: &Request) -> Result<Stores, Response> {
+    let Some(id) = tenant_of(config, request) else {
+        return Ok(stores.clone());
+    };
+    let Some(tenants) = &stores.tenants else {
+        return Err(Response::error(400, "tenancy_disabled", format!("{} was sent, but this server has no tenants", HEADER)));
+    };
+    if !validation::is_valid_tenant_id(&id) {
+        return Err(Response::error(400, "invalid_tenant", format!("{:?} is not a valid tenant id", id)));
+    }
+    match tenants.stores(&id).await {
+        Ok(Some(stores)) => Ok(stores),
+        Ok(None) => Err(Response::error(404, "tenant_not_found", format!("There is no tenant {}", id))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The header wins over the subdomain. The domain itself, and hosts outside it, are the default tenant
+fn tenant_of(config: &Config, request: &Request) -> Option<String> {
+    if let Some(id) = request.header(HEADER) {
+        return Some(id.trim().to_string());
+    }
+    let domain = config.tenancy.as_ref()?.domain.as_deref()?;
+    let host = request.header("host")?.to_ascii_lowercase();
+    // Without the port, if any; IPv6 literals never end in the domain anyway
+    let host = host.rsplit_once(':').map_or(host.as_str(), |(host, _)| host);
+    let subdomain = host.strip_suffix(domain)?.strip_suffix('.')?;
+    Some(subdomain.to_string()).filter(|subdomain| !subdomain.is_empty())
+}
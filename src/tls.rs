@@ -0,0 +1,30 @@
+use rustls_pemfile::{certs, private_key};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+
+// Build a TLS acceptor from the PEM certificate chain and private key on disk
+pub fn load_acceptor(config: &TlsConfig) -> io::Result<TlsAcceptor> {
+    let cert_chain = certs(&mut BufReader::new(File::open(&config.cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+    if cert_chain.is_empty() {
+        return Err(invalid_data(format!("no certificates found in {}", config.cert_path)));
+    }
+
+    let key = private_key(&mut BufReader::new(File::open(&config.key_path)?))?
+        .ok_or_else(|| invalid_data(format!("no private key found in {}", config.key_path)))?;
+
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(|e| invalid_data(e.to_string()))?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,257 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+// A value in the subset of TOML that config files use
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    // The value as it would be written in an environment variable; arrays become comma-separated
+    pub fn to_setting(&self) -> String {
+        match self {
+            Value::String(s) => s.clone(),
+            Value::Integer(n) => n.to_string(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Array(items) => items.iter().map(Value::to_setting).collect::<Vec<_>>().join(","),
+        }
+    }
+}
+
+// Parse TOML (https://toml.io) limited to what a flat config needs: `[table]` headers, `key = value`
+// lines with bare, quoted or dotted keys, basic and literal strings, integers, booleans, arrays of
+// those, and `#` comments. Keys come back with their table prefixed, e.g. `database.url`, in file
+// order. Floats, dates, inline tables and arrays of tables are rejected
+pub fn parse(input: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut parser = Parser {
+        chars: input.strip_prefix('\u{feff}').unwrap_or(input).chars().peekable(),
+        line: 1,
+    };
+    let mut entries: Vec<(String, Value)> = Vec::new();
+    let mut table = String::new();
+    loop {
+        parser.skip_blank_lines();
+        match parser.chars.peek() {
+            None => return Ok(entries),
+            Some('[') => {
+                parser.chars.next();
+                if parser.chars.peek() == Some(&'[') {
+                    return Err(parser.error("arrays of tables are not supported"));
+                }
+                parser.skip_spaces();
+                table = parser.key()?;
+                parser.skip_spaces();
+                if parser.chars.next() != Some(']') {
+                    return Err(parser.error("expected ] after the table name"));
+                }
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_spaces();
+                if parser.chars.next() != Some('=') {
+                    return Err(parser.error("expected = after the key"));
+                }
+                parser.skip_spaces();
+                let value = parser.value()?;
+                let key = if table.is_empty() { key } else { format!("{}.{}", table, key) };
+                if entries.iter().any(|(existing, _)| *existing == key) {
+                    return Err(parser.error(&format!("{} is defined more than once", key)));
+                }
+                entries.push((key, value));
+            }
+        }
+        parser.end_line()?;
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.chars.peek(), Some(' ') | Some('\t')) {
+            self.chars.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.chars.peek() == Some(&'#') {
+            while !matches!(self.chars.peek(), None | Some('\n')) {
+                self.chars.next();
+            }
+        }
+    }
+
+    // Skip whitespace, comments and line breaks, e.g. between entries or array items
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.chars.peek() {
+                Some('\n') => self.line += 1,
+                Some('\r') => {}
+                _ => return,
+            }
+            self.chars.next();
+        }
+    }
+
+    // Nothing but a comment may follow an entry on its line
+    fn end_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        self.skip_comment();
+        if self.chars.peek() == Some(&'\r') {
+            self.chars.next();
+        }
+        match self.chars.next() {
+            None => Ok(()),
+            Some('\n') => {
+                self.line += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("unexpected {:?} after the value", c))),
+        }
+    }
+
+    // A dotted key, whose parts are bare (A-Z, a-z, 0-9, _ and -) or quoted
+    fn key(&mut self) -> Result<String, String> {
+        let mut parts = Vec::new();
+        loop {
+            let part = match self.chars.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let mut part = String::new();
+                    while let Some(c) = self.chars.peek().copied().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-') {
+                        part.push(c);
+                        self.chars.next();
+                    }
+                    if part.is_empty() {
+                        return Err(self.error("expected a key"));
+                    }
+                    part
+                }
+            };
+            parts.push(part);
+            self.skip_spaces();
+            if self.chars.peek() != Some(&'.') {
+                return Ok(parts.join("."));
+            }
+            self.chars.next();
+            self.skip_spaces();
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.chars.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.peek().copied().filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n' | ',' | ']' | '#')) {
+                    word.push(c);
+                    self.chars.next();
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => self.integer(&word),
+                }
+            }
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    // A decimal integer, optionally signed, with `_` allowed between digits
+    fn integer(&self, word: &str) -> Result<Value, String> {
+        let digits = word.strip_prefix(['+', '-']).unwrap_or(word);
+        let well_formed = !digits.is_empty()
+            && digits.bytes().all(|b| b.is_ascii_digit() || b == b'_')
+            && !digits.starts_with('_')
+            && !digits.ends_with('_')
+            && !digits.contains("__");
+        match word.replace('_', "").parse() {
+            Ok(n) if well_formed => Ok(Value::Integer(n)),
+            _ => Err(self.error(&format!("unsupported value {:?}; expected a string, integer, boolean or array", word))),
+        }
+    }
+
+    // `[a, b, c]`, which may span lines and end with a comma
+    fn array(&mut self) -> Result<Value, String> {
+        self.chars.next();
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.chars.peek() == Some(&']') {
+                self.chars.next();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank_lines();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("expected , or ] in the array")),
+            }
+        }
+    }
+
+    // "..." with backslash escapes
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.chars.next();
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => {
+                    let escaped = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('u') => self.unicode_escape(4)?,
+                        Some('U') => self.unicode_escape(8)?,
+                        _ => return Err(self.error("invalid escape in string")),
+                    };
+                    value.push(escaped);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, len: usize) -> Result<char, String> {
+        let hex: String = (0..len).filter_map(|_| self.chars.next()).collect();
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == len)
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape in string"))
+    }
+
+    // '...' taken as written
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.chars.next();
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(value),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+}
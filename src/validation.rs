@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use crate::events::EventType;
+use crate::http::Response;
+use crate::outbound::Url;
+
+pub const MAX_NAME_LENGTH: usize = 100;
+// RFC 5321 limits: 64 octets for the local part, 254 for the whole address
+pub const MAX_EMAIL_LENGTH: usize = 254;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+pub const MAX_TITLE_LENGTH: usize = 200;
+pub const MAX_POST_BODY_LENGTH: usize = 10_000;
+pub const MAX_URL_LENGTH: usize = 2048;
+// Webhook secrets key an HMAC-SHA256, so shorter ones are weaker than the hash
+pub const MIN_WEBHOOK_SECRET_LENGTH: usize = 32;
+pub const MAX_WEBHOOK_SECRET_LENGTH: usize = 255;
+// Tenant ids become part of a schema name, which Postgres limits to 63 bytes
+pub const MAX_TENANT_ID_LENGTH: usize = 48;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_DOMAIN_LABEL_LENGTH: usize = 63;
+
+// Per-field validation messages, keyed by field name
+#[derive(Default)]
+pub struct ValidationErrors {
+    fields: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.fields.entry(field).or_default().push(message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    // 422 response listing every failing field
+    pub fn into_response(self) -> Response {
+        Response::error_with_details(
+            422,
+            "validation_failed",
+            "One or more fields are invalid",
+            serde_json::json!({ "fields": self.fields }),
+        )
+    }
+}
+
+// Validate a full user (POST/PUT and patched documents)
+pub fn validate_user(name: &str, email: &str) -> Result<(), Response> {
+    validate_fields(Some(name), Some(email))
+}
+
+// Validate only the fields present in a partial update
+pub fn validate_fields(name: Option<&str>, email: Option<&str>) -> Result<(), Response> {
+    let mut errors = ValidationErrors::default();
+    if let Some(name) = name {
+        check_name(name, &mut errors);
+    }
+    if let Some(email) = email {
+        check_email(email, &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into_response())
+    }
+}
+
+// Validate a new password's length; anything else about it is up to the user
+pub fn validate_password(password: &str) -> Result<(), Response> {
+    let length = password.chars().count();
+    if (MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Ok(());
+    }
+    let mut errors = ValidationErrors::default();
+    errors.add(
+        "password",
+        format!("must be {} to {} characters", MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH),
+    );
+    Err(errors.into_response())
+}
+
+// Validate a new tenant's id
+pub fn validate_tenant_id(id: &str) -> Result<(), Response> {
+    if is_valid_tenant_id(id) {
+        return Ok(());
+    }
+    let mut errors = ValidationErrors::default();
+    errors.add(
+        "id",
+        format!("must be 1 to {} lowercase letters, digits and hyphens, starting with a letter", MAX_TENANT_ID_LENGTH),
+    );
+    Err(errors.into_response())
+}
+
+// Tenant ids are also DNS labels, so a tenant can be picked by subdomain
+pub fn is_valid_tenant_id(id: &str) -> bool {
+    id.len() <= MAX_TENANT_ID_LENGTH
+        && id.starts_with(|c: char| c.is_ascii_lowercase())
+        && id.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+// Validate the name of a group or API key; names follow the same rules as user names
+pub fn validate_name(name: &str) -> Result<(), Response> {
+    let mut errors = ValidationErrors::default();
+    check_name(name, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into_response())
+    }
+}
+
+// Validate a post's title and body
+pub fn validate_post(title: &str, body: &str) -> Result<(), Response> {
+    let mut errors = ValidationErrors::default();
+    if title.trim().is_empty() {
+        errors.add("title", "must not be empty");
+    }
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        errors.add("title", format!("must be at most {} characters", MAX_TITLE_LENGTH));
+    }
+    if body.chars().count() > MAX_POST_BODY_LENGTH {
+        errors.add("body", format!("must be at most {} characters", MAX_POST_BODY_LENGTH));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into_response())
+    }
+}
+
+// Validate a webhook: an http(s) URL, at least one known event type, and a long enough secret if one is given
+pub fn validate_webhook(url: &str, events: &[String], secret: Option<&str>) -> Result<(), Response> {
+    let mut errors = ValidationErrors::default();
+    if url.len() > MAX_URL_LENGTH {
+        errors.add("url", format!("must be at most {} characters", MAX_URL_LENGTH));
+    } else if let Err(e) = Url::parse(url) {
+        errors.add("url", e);
+    }
+    if events.is_empty() {
+        errors.add("events", "must name at least one event type");
+    }
+    for event in events.iter().filter(|event| EventType::parse(event).is_none()) {
+        let known: Vec<&str> = EventType::ALL.iter().map(|event_type| event_type.as_str()).collect();
+        errors.add("events", format!("{} is not one of {}", event, known.join(", ")));
+    }
+    if let Some(secret) = secret {
+        let length = secret.chars().count();
+        if !(MIN_WEBHOOK_SECRET_LENGTH..=MAX_WEBHOOK_SECRET_LENGTH).contains(&length) {
+            errors.add(
+                "secret",
+                format!("must be {} to {} characters", MIN_WEBHOOK_SECRET_LENGTH, MAX_WEBHOOK_SECRET_LENGTH),
+            );
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into_response())
+    }
+}
+
+fn check_name(name: &str, errors: &mut ValidationErrors) {
+    if name.trim().is_empty() {
+        errors.add("name", "must not be empty");
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        errors.add("name", format!("must be at most {} characters", MAX_NAME_LENGTH));
+    }
+}
+
+fn check_email(email: &str, errors: &mut ValidationErrors) {
+    if email.len() > MAX_EMAIL_LENGTH {
+        errors.add("email", format!("must be at most {} characters", MAX_EMAIL_LENGTH));
+    }
+    if let Err(message) = check_email_syntax(email) {
+        errors.add("email", message);
+    }
+}
+
+// Dot-atom address syntax from RFC 5322 with a DNS hostname as the domain
+fn check_email_syntax(email: &str) -> Result<(), &'static str> {
+    let (local, domain) = email.rsplit_once('@').ok_or("must contain @")?;
+
+    if local.is_empty() || local.len() > MAX_LOCAL_PART_LENGTH {
+        return Err("local part must be 1 to 64 characters");
+    }
+    let is_atext = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c);
+    if local.split('.').any(|atom| atom.is_empty() || !atom.chars().all(is_atext)) {
+        return Err("local part contains invalid characters");
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return Err("domain must contain a dot");
+    }
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= MAX_DOMAIN_LABEL_LENGTH
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if !labels.iter().all(valid_label) {
+        return Err("domain is not a valid hostname");
+    }
+    Ok(())
+}
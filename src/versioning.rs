@@ -0,0 +1,80 @@
+use crate::http::{Request, Response};
+use crate::router::Router;
+
+// Each API version is mounted under its own path prefix, so a later one can change some routes and
+// share the rest. Only v1 exists so far
+pub const V1: &str = "/v1";
+const VERSIONS: [&str; 1] = [V1];
+
+// Paths from before versioning, without a prefix, are still answered by this version
+const LEGACY: &str = V1;
+
+// When the unprefixed paths were deprecated (RFC 9745) and when they may stop working (RFC 8594)
+const DEPRECATED_SINCE: &str = "@1792022400";
+const SUNSET: &str = "Fri, 15 Oct 2027 00:00:00 GMT";
+
+// How a request named its version: with a prefix, or not at all, in which case it has since moved
+// to the path given
+pub enum Version {
+    Prefixed(&'static str),
+    Legacy(String),
+}
+
+// The request's version, pointing an unprefixed path that a legacy route matches at that route.
+// None when the path is neither, so it gets the router's usual 404
+pub fn resolve<S>(request: &mut Request, router: &Router<S>) -> Option<Version>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if let Some(version) = VERSIONS.into_iter().find(|version| strip(&request.path, version).is_some()) {
+        return Some(Version::Prefixed(version));
+    }
+    let path = format!("{}{}", LEGACY, request.path);
+    if !router.recognizes(&path) {
+        return None;
+    }
+    request.path = path.clone();
+    Some(Version::Legacy(path))
+}
+
+impl Version {
+    // Answer in the version's terms: a Location under the version's prefix, or, for a legacy path,
+    // headers saying it is deprecated and where it moved
+    pub fn apply(self, response: Response) -> Response {
+        match self {
+            Version::Prefixed(version) => {
+                let mut response = response;
+                for (name, value) in &mut response.headers {
+                    if name.eq_ignore_ascii_case("location") && value.starts_with('/') && strip(value, version).is_none() {
+                        *value = format!("{}{}", version, value);
+                    }
+                }
+                response
+            }
+            Version::Legacy(path) => response
+                .with_header("Deprecation", DEPRECATED_SINCE)
+                .with_header("Sunset", SUNSET)
+                .with_header("Link", format!("<{}>; rel=\"successor-version\"", path)),
+        }
+    }
+}
+
+// The path without its version prefix, as the version's routes were written, e.g. `/users/7` for
+// `/v1/users/7`. Other paths are returned as they are
+pub fn unversioned(path: &str) -> &str {
+    VERSIONS.into_iter().find_map(|version| strip(path, version)).unwrap_or(path)
+}
+
+// The version prefix `path` is under, e.g. `/v1` for `/v1/users/7`; empty for other paths
+pub fn prefix(path: &str) -> &'static str {
+    VERSIONS.into_iter().find(|version| strip(path, version).is_some()).unwrap_or_default()
+}
+
+// `/v1/users` and `/v1` are under `/v1`; `/v10` isn't
+fn strip<'a>(path: &'a str, version: &str) -> Option<&'a str> {
+    match path.strip_prefix(version)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
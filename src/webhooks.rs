@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+
+use crate::config::WebhookConfig;
+use crate::events::Event;
+use crate::outbound::{self, Url};
+use crate::repository::{Stores, WebhookTarget};
+
+// Headers of every delivery. The signature lets receivers check a delivery came from here and
+// wasn't replayed: it covers the timestamp as well as the body
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const ID_HEADER: &str = "X-Webhook-Id";
+
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "-webhooks/", env!("CARGO_PKG_VERSION"));
+
+// Deliver user events to the webhooks subscribed to them until `shutdown`. Each delivery runs on
+// its own task, so a slow receiver doesn't hold up the others; ones still retrying at shutdown are dropped
+pub fn spawn(stores: Stores, config: WebhookConfig, shutdown: CancellationToken) -> JoinHandle<()> {
+    let mut events = stores.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                event = events.recv() => event,
+            };
+            match event {
+                Ok(event) => dispatch(&stores, &config, event).await,
+                Err(RecvError::Lagged(missed)) => warn!("Webhook dispatch fell behind; {} events were not delivered", missed),
+                Err(RecvError::Closed) => return,
+            }
+        }
+    })
+}
+
+async fn dispatch(stores: &Stores, config: &WebhookConfig, event: Event) {
+    let targets = match stores.webhooks.subscribers(event.event_type.as_str()).await {
+        Ok(targets) => targets,
+        Err(e) => {
+            error!("Failed to look up webhooks for {} {}: {}", event.event_type.as_str(), event.id, e);
+            return;
+        }
+    };
+    if targets.is_empty() {
+        return;
+    }
+    let body = match serde_json::to_vec(&event) {
+        Ok(body) => Arc::new(body),
+        Err(e) => {
+            error!("Error serializing event {}: {}", event.id, e);
+            return;
+        }
+    };
+    let event = Arc::new(event);
+    for target in targets {
+        tokio::spawn(deliver(target, Arc::clone(&event), Arc::clone(&body), config.clone()));
+    }
+}
+
+// POST the event until the receiver answers 2xx or the attempts run out
+async fn deliver(target: WebhookTarget, event: Arc<Event>, body: Arc<Vec<u8>>, config: WebhookConfig) {
+    let url = match Url::parse(&target.url) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("Webhook {} has an unusable URL: it {}", target.id, e);
+            return;
+        }
+    };
+    let attempts = config.retry.attempts;
+    for attempt in 1..=attempts {
+        // Signed afresh each time, so the timestamp says when this attempt was made
+        let timestamp = Utc::now().timestamp();
+        let headers = [
+            ("Content-Type", "application/json".to_string()),
+            ("User-Agent", USER_AGENT.to_string()),
+            (EVENT_HEADER, event.event_type.as_str().to_string()),
+            (ID_HEADER, event.id.clone()),
+            (TIMESTAMP_HEADER, timestamp.to_string()),
+            (SIGNATURE_HEADER, sign(&target.secret, timestamp, &body)),
+        ];
+        let failure = match outbound::post(&url, &headers, &body, config.timeout).await {
+            Ok(status) if (200..300).contains(&status) => {
+                debug!("Delivered {} {} to webhook {}", event.event_type.as_str(), event.id, target.id);
+                return;
+            }
+            Ok(status) => format!("answered {}", status),
+            Err(e) => e,
+        };
+        if attempt == attempts {
+            warn!("Giving up delivering {} {} to webhook {} after {} attempts: {}", event.event_type.as_str(), event.id, target.id, attempts, failure);
+            return;
+        }
+        let delay = config.retry.delay(attempt);
+        warn!(
+            "Delivering {} {} to webhook {} failed on attempt {} of {}, retrying in {}ms: {}",
+            event.event_type.as_str(),
+            event.id,
+            target.id,
+            attempt,
+            attempts,
+            delay.as_millis(),
+            failure
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}`, keyed with the webhook's secret
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    // HMAC takes keys of any length
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return String::new();
+    };
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    mac.finalize().into_bytes().iter().fold(String::from("sha256="), |mut hex, byte| {
+        hex.push_str(&format!("{:02x}", byte));
+        hex
+    })
+}
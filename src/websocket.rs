@@ -0,0 +1,318 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::events::{Event, Events};
+use crate::http::{Request, Response};
+
+// Appended to the client's key before hashing it into Sec-WebSocket-Accept (RFC 6455 1.3)
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const VERSION: &str = "13";
+
+// Largest message a client may send, across all of its fragments; subscriptions are small
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+// Most user ids a subscription may name
+const MAX_SUBSCRIBED_IDS: usize = 1000;
+// How often the client is pinged; one that hasn't sent anything by the next ping is dropped
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// Close codes (RFC 6455 7.4.1)
+const GOING_AWAY: u16 = 1001;
+const PROTOCOL_ERROR: u16 = 1002;
+const UNSUPPORTED_DATA: u16 = 1003;
+const INVALID_PAYLOAD: u16 = 1007;
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+// How a session ends: with a close frame carrying a code and reason, or by just hanging up
+type Ending = Option<(u16, &'static str)>;
+
+// The Sec-WebSocket-Accept value for a valid handshake, or the response refusing it
+pub fn handshake(request: &Request) -> Result<String, Response> {
+    // Only a GET can be upgraded; a HEAD gets the head of the response to a GET that wasn't
+    if request.method != "GET" || !request.has_token("connection", "upgrade") || !request.has_token("upgrade", "websocket") {
+        return Err(Response::error(426, "upgrade_required", "This endpoint only speaks WebSocket")
+            .with_header("Upgrade", "websocket"));
+    }
+    if request.header("sec-websocket-version").map(str::trim) != Some(VERSION) {
+        return Err(Response::error(426, "upgrade_required", format!("Only WebSocket version {} is supported", VERSION))
+            .with_header("Sec-WebSocket-Version", VERSION));
+    }
+    // The key is 16 random bytes, base64-encoded
+    let key = request.header("sec-websocket-key").unwrap_or_default().trim();
+    if STANDARD.decode(key).map(|bytes| bytes.len()) != Ok(16) {
+        return Err(Response::error(400, "bad_request", "Sec-WebSocket-Key must be 16 base64-encoded bytes"));
+    }
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    Ok(STANDARD.encode(sha1.finalize()))
+}
+
+// A WebSocket connection sending user events. Subscribed when the handshake is accepted, so
+// nothing committed after that is missed
+#[derive(Debug)]
+pub struct Session {
+    subscription: broadcast::Receiver<Event>,
+}
+
+impl Session {
+    pub fn new(events: &Events) -> Session {
+        Session { subscription: events.subscribe() }
+    }
+}
+
+// Messages clients send. Events about every user are sent until a subscription names some
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Command {
+    // Only send events about these users, or about every user when `user_ids` is null or left out
+    Subscribe {
+        #[serde(default)]
+        user_ids: Option<BTreeSet<i32>>,
+    },
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+struct Connection<'a, R, W> {
+    reader: &'a mut R,
+    writer: &'a mut W,
+    write_timeout: Duration,
+    // Bytes read but not yet parsed into frames
+    received: Vec<u8>,
+    // Opcode and payload so far of a fragmented message
+    message: Option<(u8, Vec<u8>)>,
+    // Users whose events are sent; None sends everyone's
+    filter: Option<BTreeSet<i32>>,
+}
+
+// Run the session on an upgraded connection until either side closes it or `shutdown`
+pub async fn serve<R, W>(session: Session, reader: &mut R, writer: &mut W, write_timeout: Duration, shutdown: &CancellationToken)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let Session { mut subscription } = session;
+    let mut connection = Connection {
+        reader,
+        writer,
+        write_timeout,
+        received: Vec::new(),
+        message: None,
+        filter: None,
+    };
+    let mut ping = time::interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut heard = true;
+    let mut chunk = [0; 4096];
+    let ending = loop {
+        let step = tokio::select! {
+            _ = shutdown.cancelled() => Err(Some((GOING_AWAY, "The server is shutting down"))),
+            read = connection.reader.read(&mut chunk) => match read {
+                Ok(0) | Err(_) => Err(None),
+                Ok(read) => {
+                    heard = true;
+                    connection.received.extend_from_slice(&chunk[..read]);
+                    connection.process().await
+                }
+            },
+            event = subscription.recv() => match event {
+                Ok(event) => connection.event(&event).await,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("WebSocket session fell behind; {} events were not sent", missed);
+                    Ok(())
+                }
+                Err(RecvError::Closed) => Err(Some((GOING_AWAY, "The server is shutting down"))),
+            },
+            _ = ping.tick() => match std::mem::replace(&mut heard, false) {
+                true => connection.send(PING, b"").await,
+                // Not even a pong since the last ping
+                false => Err(None),
+            },
+        };
+        if let Err(ending) = step {
+            break ending;
+        }
+    };
+    if let Some((code, reason)) = ending {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        let _ = connection.send(CLOSE, &payload).await;
+    }
+}
+
+impl<R, W> Connection<'_, R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Act on every complete frame received so far
+    async fn process(&mut self) -> Result<(), Ending> {
+        while let Some((frame, length)) = parse_frame(&self.received)? {
+            self.received.drain(..length);
+            self.frame(frame).await?;
+        }
+        Ok(())
+    }
+
+    async fn frame(&mut self, frame: Frame) -> Result<(), Ending> {
+        match frame.opcode {
+            PING => self.send(PONG, &frame.payload).await,
+            PONG => Ok(()),
+            CLOSE => {
+                // Answer with the client's code, then hang up
+                let _ = self.send(CLOSE, frame.payload.get(..2).unwrap_or_default()).await;
+                Err(None)
+            }
+            opcode => {
+                let (opcode, mut payload) = match (opcode, self.message.take()) {
+                    (CONTINUATION, Some(message)) => message,
+                    (CONTINUATION, None) => return Err(Some((PROTOCOL_ERROR, "No message to continue"))),
+                    (_, Some(_)) => return Err(Some((PROTOCOL_ERROR, "Expected a continuation frame"))),
+                    (opcode, None) => (opcode, Vec::new()),
+                };
+                payload.extend_from_slice(&frame.payload);
+                if payload.len() > MAX_MESSAGE_SIZE {
+                    return Err(Some((MESSAGE_TOO_BIG, "Message too big")));
+                }
+                match frame.fin {
+                    true => self.command(opcode, payload).await,
+                    false => {
+                        self.message = Some((opcode, payload));
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    // Apply a message from the client. One that can't be understood is answered with an error
+    // message, and the session carries on
+    async fn command(&mut self, opcode: u8, payload: Vec<u8>) -> Result<(), Ending> {
+        if opcode == BINARY {
+            return Err(Some((UNSUPPORTED_DATA, "Only text messages are accepted")));
+        }
+        let Ok(text) = String::from_utf8(payload) else {
+            return Err(Some((INVALID_PAYLOAD, "Text messages must be UTF-8")));
+        };
+        let reply = match serde_json::from_str(&text) {
+            Ok(Command::Subscribe { user_ids: Some(ids) }) if ids.len() > MAX_SUBSCRIBED_IDS => {
+                json!({ "type": "error", "message": format!("At most {} user ids can be subscribed to", MAX_SUBSCRIBED_IDS) })
+            }
+            Ok(Command::Subscribe { user_ids }) => {
+                let reply = json!({ "type": "subscribed", "user_ids": user_ids });
+                self.filter = user_ids;
+                reply
+            }
+            Err(e) => json!({ "type": "error", "message": format!("Invalid message: {}", e) }),
+        };
+        self.send(TEXT, reply.to_string().as_bytes()).await
+    }
+
+    // Send the event unless the subscription leaves its user out
+    async fn event(&mut self, event: &Event) -> Result<(), Ending> {
+        if let Some(ids) = &self.filter {
+            match event.data["id"].as_i64().and_then(|id| i32::try_from(id).ok()) {
+                Some(id) if ids.contains(&id) => {}
+                _ => return Ok(()),
+            }
+        }
+        match serde_json::to_vec(event) {
+            Ok(json) => self.send(TEXT, &json).await,
+            Err(e) => {
+                error!("Error serializing event {}: {}", event.id, e);
+                Ok(())
+            }
+        }
+    }
+
+    // Write a single unfragmented frame; servers don't mask theirs
+    async fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<(), Ending> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            length @ 0..=125 => frame.push(length as u8),
+            length @ 126..=0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        let write = async {
+            self.writer.write_all(&frame).await?;
+            self.writer.flush().await
+        };
+        match time::timeout(self.write_timeout, write).await {
+            Ok(Ok(())) => Ok(()),
+            _ => Err(None),
+        }
+    }
+}
+
+// The first frame in `buffer` and how many bytes it took up, or None until all of it has arrived.
+// Frames too big to accept are refused from their header, before their payload is buffered
+fn parse_frame(buffer: &[u8]) -> Result<Option<(Frame, usize)>, Ending> {
+    let [first, second, ..] = *buffer else {
+        return Ok(None);
+    };
+    let (fin, opcode) = (first & 0x80 != 0, first & 0x0f);
+    if first & 0x70 != 0 {
+        return Err(Some((PROTOCOL_ERROR, "Reserved bits must not be set")));
+    }
+    if !matches!(opcode, CONTINUATION | TEXT | BINARY | CLOSE | PING | PONG) {
+        return Err(Some((PROTOCOL_ERROR, "Unknown opcode")));
+    }
+    if second & 0x80 == 0 {
+        return Err(Some((PROTOCOL_ERROR, "Client frames must be masked")));
+    }
+    let (length, start) = match second & 0x7f {
+        126 => match buffer.get(2..4) {
+            Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+            None => return Ok(None),
+        },
+        127 => match buffer.get(2..10) {
+            Some(bytes) => {
+                let mut length = [0; 8];
+                length.copy_from_slice(bytes);
+                (u64::from_be_bytes(length), 10)
+            }
+            None => return Ok(None),
+        },
+        length => (u64::from(length), 2),
+    };
+    // Control frames are small and can't be fragmented, so they can come between a message's fragments
+    if opcode >= CLOSE && (length > 125 || !fin) {
+        return Err(Some((PROTOCOL_ERROR, "Control frames must be short and unfragmented")));
+    }
+    if length > MAX_MESSAGE_SIZE as u64 {
+        return Err(Some((MESSAGE_TOO_BIG, "Message too big")));
+    }
+    let end = start + 4 + length as usize;
+    let (Some(mask), Some(payload)) = (buffer.get(start..start + 4), buffer.get(start + 4..end)) else {
+        return Ok(None);
+    };
+    let payload = payload.iter().enumerate().map(|(at, byte)| byte ^ mask[at % 4]).collect();
+    Ok(Some((Frame { fin, opcode, payload }, end)))
+}
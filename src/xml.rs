@@ -0,0 +1,79 @@
+use serde_json::Value;
+
+// Render a JSON value as an XML document under a `<response>` root. Object fields become child
+// elements; array items repeat an element named for the singular of their field (`users` holds
+// `<user>`s), or `<item>`; null is an empty element
+pub fn from_json(value: &Value) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    write_element("response", value, &mut out);
+    out
+}
+
+fn write_element(name: &str, value: &Value, out: &mut String) {
+    let name = element_name(name);
+    match value {
+        Value::Null => {
+            out.push_str(&format!("<{}/>", name));
+            return;
+        }
+        // Repeated children can't sit directly in the parent, or an empty array would vanish
+        Value::Array(items) => {
+            out.push_str(&format!("<{}>", name));
+            let item = singular(&name);
+            for value in items {
+                write_element(&item, value, out);
+            }
+        }
+        Value::Object(fields) => {
+            out.push_str(&format!("<{}>", name));
+            for (key, value) in fields {
+                write_element(key, value, out);
+            }
+        }
+        Value::String(text) => {
+            out.push_str(&format!("<{}>", name));
+            escape(text, out);
+        }
+        Value::Bool(_) | Value::Number(_) => out.push_str(&format!("<{}>{}", name, value)),
+    }
+    out.push_str(&format!("</{}>", name));
+}
+
+// Field names made into valid XML names: other characters become `_`, and one that can't start a
+// name gets a leading `_`
+fn element_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') || name.to_ascii_lowercase().starts_with("xml") {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn singular(name: &str) -> String {
+    match name {
+        _ if name.ends_with("ies") => format!("{}y", &name[..name.len() - 3]),
+        _ if name.ends_with('s') && !name.ends_with("ss") => name[..name.len() - 1].to_string(),
+        _ => "item".to_string(),
+    }
+}
+
+// Escape text content; characters XML 1.0 can't hold at all are dropped
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '\t' | '\n' => out.push(c),
+            '\r' => out.push_str("&#13;"),
+            c if c < ' ' || c == '\u{fffe}' || c == '\u{ffff}' => {}
+            c => out.push(c),
+        }
+    }
+}
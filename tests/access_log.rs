@@ -0,0 +1,73 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+use uuid::Uuid;
+
+static ACCESS_LOG: OnceLock<PathBuf> = OnceLock::new();
+
+// The shared server, writing a JSON access log to a file of its own
+fn server() -> Option<(&'static common::TestServer, &'static PathBuf)> {
+    let path = ACCESS_LOG.get_or_init(|| {
+        let path = env::temp_dir().join(format!("rust-crud-api-access-{}.log", Uuid::new_v4()));
+        // Read when the shared server starts, which is just below
+        env::set_var("ACCESS_LOG", "json");
+        env::set_var("ACCESS_LOG_PATH", &path);
+        path
+    });
+    common::server().map(|server| (server, path))
+}
+
+// The access log entry of the request with `request_id`; it is written just after the response
+fn entry(path: &PathBuf, request_id: &str) -> Value {
+    for _ in 0..50 {
+        let log = fs::read_to_string(path).unwrap_or_default();
+        let found = log.lines().map(|line| serde_json::from_str::<Value>(line).expect("a JSON line")).find(|entry| entry["request_id"] == request_id);
+        if let Some(entry) = found {
+            return entry;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("no access log entry for {}", request_id);
+}
+
+#[test]
+fn requests_are_logged() {
+    let Some((server, path)) = server() else { return };
+    let request_id = Uuid::new_v4().to_string();
+    let response = server.get("/v1/users?limit=2&sort=id").header("X-Request-Id", &request_id).send();
+    assert_eq!(response.status, 200);
+
+    let entry = entry(path, &request_id);
+    assert_eq!(entry["method"], "GET");
+    assert_eq!(entry["target"], "/v1/users?limit=2&sort=id");
+    assert_eq!(entry["version"], "HTTP/1.1");
+    assert_eq!(entry["status"], 200);
+    assert_eq!(entry["bytes"], response.body.len());
+    assert_eq!(entry["client"], "127.0.0.1");
+    assert!(entry["duration_ms"].as_f64().is_some_and(|ms| ms > 0.0));
+    assert!(entry["time"].as_str().is_some_and(|time| time.ends_with('Z')));
+}
+
+#[test]
+fn errors_and_streams_are_logged() {
+    let Some((server, path)) = server() else { return };
+    let request_id = Uuid::new_v4().to_string();
+    let response = server.get("/v1/users/999999999").header("X-Request-Id", &request_id).send();
+    let missing = entry(path, &request_id);
+    assert_eq!((missing["status"].as_u64(), missing["bytes"].as_u64()), (Some(404), Some(response.body.len() as u64)));
+
+    // A streamed body is counted as it goes; a HEAD sends none
+    let request_id = Uuid::new_v4().to_string();
+    let response = server.get("/v1/users/all").header("X-Request-Id", &request_id).send();
+    assert_eq!(entry(path, &request_id)["bytes"], response.body.len());
+    let request_id = Uuid::new_v4().to_string();
+    server.request("HEAD", "/v1/users/all").header("X-Request-Id", &request_id).send();
+    assert_eq!(entry(path, &request_id)["bytes"], 0);
+}
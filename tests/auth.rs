@@ -0,0 +1,107 @@
+mod common;
+
+use serde_json::json;
+
+#[test]
+fn login_and_bad_credentials() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("login");
+    let email = user["email"].as_str().unwrap();
+    let id = user["id"].as_i64().unwrap();
+    server.put(&format!("/users/{}/password", id)).admin(server).json(json!({ "password": common::PASSWORD })).send();
+
+    let response = server.post("/auth/login").json(json!({ "email": email, "password": common::PASSWORD })).send();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("cache-control"), Some("no-store"));
+    let tokens = response.json();
+    assert_eq!(tokens["token_type"], "Bearer");
+    assert!(tokens["refresh_token"].is_string());
+
+    let response = server.post("/auth/login").json(json!({ "email": email, "password": "wrong password" })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (401, "invalid_credentials"));
+    let response = server.post("/auth/login").json(json!({ "email": "nobody@example.test", "password": "whatever" })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (401, "invalid_credentials"));
+    assert_eq!(server.post("/auth/login").body("application/json", "{").send().status, 400);
+}
+
+#[test]
+fn refresh_rotates_the_refresh_token() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("refresh");
+    let id = user["id"].as_i64().unwrap();
+    server.put(&format!("/users/{}/password", id)).admin(server).json(json!({ "password": common::PASSWORD })).send();
+    let login = server.post("/auth/login").json(json!({ "email": user["email"], "password": common::PASSWORD })).send().json();
+    let old = login["refresh_token"].as_str().unwrap();
+
+    let refreshed = server.post("/auth/refresh").json(json!({ "refresh_token": old })).send();
+    assert_eq!(refreshed.status, 200);
+    let new = refreshed.json()["refresh_token"].as_str().unwrap().to_string();
+    assert_ne!(new, old);
+
+    let replayed = server.post("/auth/refresh").json(json!({ "refresh_token": old })).send();
+    assert_eq!((replayed.status, replayed.error_code().as_str()), (401, "invalid_refresh_token"));
+    assert_eq!(server.post("/auth/refresh").json(json!({ "refresh_token": new })).send().status, 200);
+    assert_eq!(server.post("/auth/refresh").json(json!({ "refresh_token": "not-a-token" })).send().status, 401);
+}
+
+#[test]
+fn logout_revokes_the_access_token() {
+    let Some(server) = common::server() else { return };
+    let token = server.token_for("editor");
+    let body = json!({ "name": "Before Logout", "email": common::unique_email("logout") });
+    assert_eq!(server.post("/users").bearer(&token).json(body).send().status, 201);
+
+    assert_eq!(server.post("/auth/logout").bearer(&token).send().status, 204);
+    let body = json!({ "name": "After Logout", "email": common::unique_email("logout") });
+    let response = server.post("/users").bearer(&token).json(body).send();
+    assert_eq!((response.status, response.error_code().as_str()), (401, "invalid_token"));
+    assert_eq!(server.post("/auth/logout").send().status, 401);
+}
+
+#[test]
+fn bad_bearer_tokens() {
+    let Some(server) = common::server() else { return };
+    let body = json!({ "name": "Forged", "email": common::unique_email("forged") });
+    let response = server.post("/users").bearer("not.a.jwt").json(body.clone()).send();
+    assert_eq!((response.status, response.error_code().as_str()), (401, "invalid_token"));
+    let response = server.post("/users").header("Authorization", "Basic dXNlcjpwYXNz").json(body).send();
+    assert_eq!((response.status, response.error_code().as_str()), (401, "unauthorized"));
+}
+
+#[test]
+fn api_keys() {
+    let Some(server) = common::server() else { return };
+    let response = server.post("/api-keys").admin(server).json(json!({ "name": "CI deploys" })).send();
+    assert_eq!(response.status, 201);
+    let created = response.json();
+    let (id, key) = (created["id"].as_i64().unwrap(), created["key"].as_str().unwrap().to_string());
+    assert_eq!(created["role"], "editor");
+
+    let listed = server.get("/api-keys").admin(server).send().json();
+    let listing = listed["api_keys"].as_array().unwrap().iter().find(|k| k["id"] == id).expect("the new key is listed").clone();
+    assert!(listing.get("key").is_none());
+
+    let body = json!({ "name": "Made By Key", "email": common::unique_email("api-key") });
+    assert_eq!(server.post("/users").header("X-Api-Key", &key).json(body.clone()).send().status, 201);
+    // An editor key can't reach admin routes
+    assert_eq!(server.get("/api-keys").header("X-Api-Key", &key).send().status, 403);
+
+    assert_eq!(server.delete(&format!("/api-keys/{}", id)).admin(server).send().status, 204);
+    assert_eq!(server.delete(&format!("/api-keys/{}", id)).admin(server).send().status, 404);
+    let response = server.post("/users").header("X-Api-Key", &key).json(body).send();
+    assert_eq!((response.status, response.error_code().as_str()), (401, "invalid_api_key"));
+
+    assert_eq!(server.post("/api-keys").admin(server).json(json!({ "name": "" })).send().status, 422);
+    assert_eq!(server.post("/api-keys").admin(server).json(json!({ "name": "x", "extra": 1 })).send().status, 400);
+}
+
+#[test]
+fn admin_routes_need_the_admin_role() {
+    let Some(server) = common::server() else { return };
+    let editor = server.token_for("editor");
+    for (method, path) in [("GET", "/api-keys"), ("GET", "/audit"), ("POST", "/admin/seed")] {
+        let response = server.request(method, path).bearer(&editor).json(json!({ "users": 1 })).send();
+        assert_eq!(response.status, 403, "{} {}", method, path);
+        assert_eq!(response.json()["error"]["details"]["required_role"], "admin");
+    }
+}
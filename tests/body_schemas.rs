@@ -0,0 +1,76 @@
+mod common;
+
+use serde_json::{json, Value};
+
+// The violations of a 400 response, as (pointer, message) pairs
+fn violations(response: &common::TestResponse) -> Vec<(String, String)> {
+    assert_eq!((response.status, response.error_code().as_str()), (400, "invalid_json"), "{}", response.text());
+    let body = response.json();
+    let violations = body["error"]["details"]["violations"].as_array().cloned().unwrap_or_default();
+    violations
+        .iter()
+        .map(|violation| {
+            let field = |name: &str| violation[name].as_str().unwrap_or_default().to_string();
+            (field("pointer"), field("message"))
+        })
+        .collect()
+}
+
+fn pointers(violations: &[(String, String)]) -> Vec<&str> {
+    violations.iter().map(|(pointer, _)| pointer.as_str()).collect()
+}
+
+#[test]
+fn every_mismatch_is_reported_with_its_pointer() {
+    let Some(server) = common::server() else { return };
+
+    let response = server.post("/users").admin(server).json(json!({ "name": 7, "email": ["a@example.com"] })).send();
+    let found = violations(&response);
+    assert_eq!(pointers(&found), ["/email", "/name"], "{:?}", found);
+    assert!(found.iter().all(|(_, message)| message == "must be a string"), "{:?}", found);
+
+    let response = server.post("/users").admin(server).json(json!({ "name": "No Email" })).send();
+    assert_eq!(violations(&response), [("/email".to_string(), "is required".to_string())]);
+
+    // Fields the server sets are ignored, as they always were
+    let body = json!({ "name": "Schema User", "email": common::unique_email("schema"), "created_at": 12, "version": "x" });
+    assert_eq!(server.post("/users").admin(server).json(body).send().status, 201);
+}
+
+#[test]
+fn nested_values_and_unknown_fields_are_pointed_at() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("schema-nested");
+    let id = user["id"].as_i64().unwrap();
+
+    let response = server.patch(&format!("/users/{}", id)).admin(server).json(json!({ "nickname": "x" })).send();
+    assert_eq!(violations(&response), [("/nickname".to_string(), "is not a known field".to_string())]);
+
+    let response = server.put(&format!("/users/{}/role", id)).admin(server).json(json!({ "role": "overlord" })).send();
+    let found = violations(&response);
+    assert_eq!(pointers(&found), ["/role"]);
+    assert!(found[0].1.starts_with("must be one of"), "{:?}", found);
+
+    let webhook = json!({ "url": "https://example.com/hook", "events": ["user.created", 3] });
+    let response = server.post("/webhooks").admin(server).json(webhook).send();
+    assert_eq!(violations(&response), [("/events/1".to_string(), "must be a string".to_string())]);
+
+    let response = server.post("/admin/seed").admin(server).json(json!({ "users": -1, "fake": "yes" })).send();
+    assert_eq!(pointers(&violations(&response)), ["/fake", "/users"]);
+}
+
+#[test]
+fn bodies_the_schema_does_not_cover_reach_their_handler() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("schema-merge");
+    let path = format!("/users/{}", user["id"]);
+
+    // Merge patches have a schema of their own, which the route's doesn't apply to
+    let response = server.patch(&path).admin(server).body("application/merge-patch+json", r#"{"name": "Merged"}"#).send();
+    assert_eq!(response.status, 200, "{}", response.text());
+
+    // A body that isn't JSON at all is rejected by the handler, with its own message
+    let response = server.post("/users").admin(server).body("application/json", "{not json").send();
+    assert_eq!((response.status, response.error_code().as_str()), (400, "invalid_json"));
+    assert_eq!(response.json()["error"]["details"], Value::Null);
+}
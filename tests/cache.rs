@@ -0,0 +1,114 @@
+#![cfg(feature = "redis")]
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::{env, thread};
+
+use serde_json::{json, Value};
+
+type Entries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+static REDIS: OnceLock<Entries> = OnceLock::new();
+
+#[test]
+fn users_are_cached_until_changed() {
+    let entries = redis();
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("cached");
+    let path = format!("/users/{}", user["id"]);
+    let key = format!("rust-crud-api:user:{}", user["id"]);
+
+    assert_eq!(server.get(&path).admin(server).send().json()["name"], user["name"]);
+    let cached: Value = serde_json::from_slice(&entries.lock().unwrap()[&key]).expect("a cached user");
+    assert_eq!((&cached["email"], &cached["version"]), (&user["email"], &user["version"]));
+
+    // Reads are served from the cache while the entry lasts
+    let mut edited = cached.clone();
+    edited["name"] = json!("Edited In Redis");
+    entries.lock().unwrap().insert(key.clone(), serde_json::to_vec(&edited).unwrap());
+    assert_eq!(server.get(&path).admin(server).send().json()["name"], "Edited In Redis");
+
+    // A change drops the entry, so the next read sees it
+    let response = server.patch(&path).admin(server).json(json!({ "name": "Renamed" })).send();
+    assert_eq!(response.status, 200);
+    assert!(!entries.lock().unwrap().contains_key(&key));
+    assert_eq!(server.get(&path).admin(server).send().json()["name"], "Renamed");
+
+    // Deleted users aren't cached
+    server.delete(&path).admin(server).send();
+    assert_eq!(server.get(&path).admin(server).send().status, 404);
+    assert!(!entries.lock().unwrap().contains_key(&key));
+}
+
+// A stand-in Redis that keeps GET, SET and DEL in memory, pointed to by REDIS_URL before the server
+// starts. Expiry is left out; the tests don't wait for it
+fn redis() -> Entries {
+    REDIS
+        .get_or_init(|| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            env::set_var("REDIS_URL", format!("redis://{}", listener.local_addr().unwrap()));
+            let entries = Entries::default();
+            let shared = Arc::clone(&entries);
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let entries = Arc::clone(&shared);
+                    thread::spawn(move || serve(stream, entries));
+                }
+            });
+            entries
+        })
+        .clone()
+}
+
+fn serve(stream: TcpStream, entries: Entries) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    while let Some(command) = read_command(&mut reader) {
+        let mut entries = entries.lock().unwrap();
+        let reply = match command.iter().map(Vec::as_slice).collect::<Vec<_>>().as_slice() {
+            [b"GET", key] => match entries.get(&*String::from_utf8_lossy(key)) {
+                Some(value) => [format!("${}\r\n", value.len()).into_bytes(), value.clone(), b"\r\n".to_vec()].concat(),
+                None => b"$-1\r\n".to_vec(),
+            },
+            [b"SET", key, value, b"PX", _] => {
+                entries.insert(String::from_utf8_lossy(key).into_owned(), value.to_vec());
+                b"+OK\r\n".to_vec()
+            }
+            [b"DEL", keys @ ..] => {
+                let removed = keys.iter().filter(|key| entries.remove(&*String::from_utf8_lossy(key)).is_some()).count();
+                format!(":{}\r\n", removed).into_bytes()
+            }
+            _ => b"-ERR unknown command\r\n".to_vec(),
+        };
+        drop(entries);
+        if writer.write_all(&reply).is_err() {
+            return;
+        }
+    }
+}
+
+// One command sent as an array of bulk strings, or None once the client goes away
+fn read_command(reader: &mut BufReader<TcpStream>) -> Option<Vec<Vec<u8>>> {
+    let count: usize = read_line(reader)?.strip_prefix('*')?.parse().ok()?;
+    let mut command = Vec::with_capacity(count);
+    for _ in 0..count {
+        let length: usize = read_line(reader)?.strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; length + 2];
+        reader.read_exact(&mut arg).ok()?;
+        arg.truncate(length);
+        command.push(arg);
+    }
+    Some(command)
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end().to_string()),
+    }
+}
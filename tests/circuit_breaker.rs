@@ -0,0 +1,113 @@
+mod common;
+
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+// Stands between the server and the test database, so the test can take the database down: while
+// it's down, connections through it are cut and new ones are closed at once
+struct Proxy {
+    down: AtomicBool,
+    connections: Mutex<Vec<TcpStream>>,
+}
+
+impl Proxy {
+    // Forward connections on a new port to `upstream`; returns that port
+    fn start(upstream: String) -> (Arc<Proxy>, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("binding the proxy");
+        let port = listener.local_addr().expect("proxy address").port();
+        let proxy = Arc::new(Proxy { down: AtomicBool::new(false), connections: Mutex::new(Vec::new()) });
+        let accepting = Arc::clone(&proxy);
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                if accepting.down.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let Ok(server) = TcpStream::connect(&upstream) else { continue };
+                let mut connections = accepting.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                for stream in [&client, &server] {
+                    if let Ok(stream) = stream.try_clone() {
+                        connections.push(stream);
+                    }
+                }
+                pipe(&client, &server);
+                pipe(&server, &client);
+            }
+        });
+        (proxy, port)
+    }
+
+    fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+        if down {
+            for stream in self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).drain(..) {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+fn pipe(from: &TcpStream, to: &TcpStream) {
+    let (Ok(mut from), Ok(mut to)) = (from.try_clone(), to.try_clone()) else { return };
+    thread::spawn(move || {
+        let _ = io::copy(&mut from, &mut to);
+        let _ = to.shutdown(Shutdown::Both);
+    });
+}
+
+static PROXY: OnceLock<Option<Arc<Proxy>>> = OnceLock::new();
+
+// The shared server, reaching the test database through the proxy, with a breaker that opens after
+// two failures for a second. Only the Postgres store has a breaker
+fn server() -> Option<(&'static common::TestServer, &'static Proxy)> {
+    let proxy = PROXY.get_or_init(|| {
+        let url = env::var("TEST_DATABASE_URL").ok()?;
+        if url.starts_with("sqlite:") || url.starts_with("mysql:") || url.starts_with("mariadb:") {
+            return None;
+        }
+        // postgres://user@host:port/database: the host and port are between the @ and the next /
+        let (scheme, rest) = url.split_once("://")?;
+        let (credentials, rest) = rest.rsplit_once('@').map_or(("", rest), |(credentials, rest)| (credentials, rest));
+        let (address, database) = rest.split_once('/').unwrap_or((rest, ""));
+        let upstream = if address.contains(':') { address.to_string() } else { format!("{}:5432", address) };
+        let (proxy, port) = Proxy::start(upstream);
+        let at = if credentials.is_empty() { "" } else { "@" };
+        // Read when the shared server starts, which is just below
+        env::set_var("TEST_DATABASE_URL", format!("{}://{}{}127.0.0.1:{}/{}", scheme, credentials, at, port, database));
+        env::set_var("DB_BREAKER_FAILURES", "2");
+        env::set_var("DB_BREAKER_COOLDOWN", "1");
+        env::set_var("DB_RETRY_ATTEMPTS", "1");
+        env::set_var("DB_POOL_TIMEOUT", "1");
+        Some(proxy)
+    });
+    let proxy = proxy.as_deref()?;
+    Some((common::server()?, proxy))
+}
+
+#[test]
+fn requests_fail_fast_while_the_database_is_down() {
+    let Some((server, proxy)) = server() else { return };
+    assert_eq!(server.get("/users").send().status, 200);
+
+    proxy.set_down(true);
+    // Failures to reach the database open the breaker
+    for _ in 0..2 {
+        let failed = server.get("/users").send();
+        assert!(failed.status >= 500, "{}", failed.text());
+    }
+    let started = Instant::now();
+    let refused = server.get("/users").send();
+    assert_eq!((refused.status, refused.error_code().as_str()), (503, "database_unavailable"));
+    assert_eq!(refused.header("retry-after"), Some("1"));
+    assert!(started.elapsed() < Duration::from_millis(500), "took {:?}", started.elapsed());
+
+    // Once the cooldown is over a request goes through to probe the database, and closes the breaker
+    proxy.set_down(false);
+    thread::sleep(Duration::from_millis(1100));
+    let probe = server.get("/users").send();
+    assert_eq!(probe.status, 200, "{}", probe.text());
+    assert_eq!(server.get("/users").send().status, 200);
+}
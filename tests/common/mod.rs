@@ -0,0 +1,255 @@
+// End-to-end harness: each test binary starts one server on a random port, backed by the database
+// in TEST_DATABASE_URL, and talks to it over plain HTTP/1.1. Without TEST_DATABASE_URL the tests
+// pass without running, so `cargo test` works on machines with no database
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::{env, fs, thread};
+
+use rust_crud_api::config::Config;
+use rust_crud_api::models::Role;
+use rust_crud_api::{auth, repository, server};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+pub const PASSWORD: &str = "correct horse battery";
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    // Bearer token of an admin created for this run
+    pub admin: String,
+}
+
+static SERVER: OnceLock<Option<TestServer>> = OnceLock::new();
+
+// The shared server, or None (after saying so) when TEST_DATABASE_URL isn't set
+pub fn server() -> Option<&'static TestServer> {
+    SERVER.get_or_init(start).as_ref()
+}
+
+fn start() -> Option<TestServer> {
+    let Ok(database_url) = env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set; skipping end-to-end tests");
+        return None;
+    };
+
+    // Settings come from a file of their own, so a developer's environment can only add to them
+    let path = env::temp_dir().join(format!("rust-crud-api-test-{}.toml", Uuid::new_v4()));
+    let file = format!(
+        "log_level = \"warn\"\n[database]\nurl = {:?}\npool_size = 8\n[auth]\njwt_secret = \"{}\"\n",
+        database_url,
+        "x".repeat(48)
+    );
+    fs::write(&path, file).expect("writing the test config");
+    let mut config = Config::load(path.to_str()).expect("loading the test config");
+    let _ = fs::remove_file(&path);
+    config.db_url = database_url;
+    config.tls = None;
+    config.cors = None;
+    // Only binaries testing rate limits keep the limit they set
+    if env::var_os("TEST_RATE_LIMIT").is_none() {
+        config.rate_limit = None;
+    }
+    config.put_upsert = true;
+    let config = Arc::new(config);
+
+    let admin_email = unique_email("admin");
+    let (sender, receiver) = mpsc::channel();
+    let server_config = Arc::clone(&config);
+    let email = admin_email.clone();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().expect("building the runtime");
+        runtime.block_on(async move {
+            // The first admin can't be made through the API, so it is written to the store directly
+            let stores = repository::connect(&server_config).await.expect("connecting to TEST_DATABASE_URL");
+            let admin = stores.users.create("Test Admin", &email).await.expect("creating the admin");
+            let id = admin.id.expect("admin id");
+            let hash = auth::hash_password(PASSWORD).expect("hashing the password");
+            stores.users.set_password(id, &hash).await.expect("setting the admin password");
+            stores.users.set_role(id, Role::Admin).await.expect("making the admin");
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("binding a port");
+            sender.send(listener.local_addr().expect("local address")).expect("reporting the address");
+            server::serve_until(server_config, Some(listener), std::future::pending()).await;
+        });
+    });
+
+    let addr = receiver.recv().expect("the server failed to start");
+    let mut server = TestServer { addr, admin: String::new() };
+    server.admin = server.login(&admin_email, PASSWORD);
+    Some(server)
+}
+
+// An address no other test or run has used
+pub fn unique_email(prefix: &str) -> String {
+    format!("{}-{}@example.test", prefix, Uuid::new_v4().simple())
+}
+
+impl TestServer {
+    pub fn request(&self, method: &str, path: &str) -> TestRequest {
+        TestRequest {
+            addr: self.addr,
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request("GET", path)
+    }
+
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request("POST", path)
+    }
+
+    pub fn put(&self, path: &str) -> TestRequest {
+        self.request("PUT", path)
+    }
+
+    pub fn patch(&self, path: &str) -> TestRequest {
+        self.request("PATCH", path)
+    }
+
+    pub fn delete(&self, path: &str) -> TestRequest {
+        self.request("DELETE", path)
+    }
+
+    // An access token for the credentials
+    pub fn login(&self, email: &str, password: &str) -> String {
+        let response = self.post("/auth/login").json(json!({ "email": email, "password": password })).send();
+        assert_eq!(response.status, 200, "login failed: {}", response.text());
+        response.json()["access_token"].as_str().expect("access_token").to_string()
+    }
+
+    // A new user, created by the admin
+    pub fn create_user(&self, prefix: &str) -> Value {
+        let response = self.post("/users").admin(self).json(json!({ "name": "Test User", "email": unique_email(prefix) })).send();
+        assert_eq!(response.status, 201, "creating a user failed: {}", response.text());
+        response.json()
+    }
+
+    // A token for a new user with `role`
+    pub fn token_for(&self, role: &str) -> String {
+        let user = self.create_user(role);
+        let id = user["id"].as_i64().expect("user id");
+        let set = self.put(&format!("/users/{}/password", id)).admin(self).json(json!({ "password": PASSWORD })).send();
+        assert_eq!(set.status, 204, "setting a password failed: {}", set.text());
+        let set = self.put(&format!("/users/{}/role", id)).admin(self).json(json!({ "role": role })).send();
+        assert_eq!(set.status, 204, "setting a role failed: {}", set.text());
+        self.login(user["email"].as_str().expect("email"), PASSWORD)
+    }
+}
+
+pub struct TestRequest {
+    addr: SocketAddr,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn header(mut self, name: &str, value: &str) -> TestRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn bearer(self, token: &str) -> TestRequest {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+
+    pub fn admin(self, server: &TestServer) -> TestRequest {
+        self.bearer(&server.admin)
+    }
+
+    pub fn json(self, body: Value) -> TestRequest {
+        self.body("application/json", body.to_string().into_bytes())
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Vec<u8>>) -> TestRequest {
+        self.body = body.into();
+        self.header("Content-Type", content_type)
+    }
+
+    // Send the request on a fresh connection and read the whole response
+    pub fn send(self) -> TestResponse {
+        let mut stream = TcpStream::connect(self.addr).expect("connecting to the test server");
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", self.method, self.path);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        stream.write_all(head.as_bytes()).expect("writing the request");
+        stream.write_all(&self.body).expect("writing the body");
+
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).expect("reading the response");
+        TestResponse::parse(&raw)
+    }
+}
+
+pub struct TestResponse {
+    pub status: u16,
+    // The reason phrase after the status code
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    fn parse(raw: &[u8]) -> TestResponse {
+        let split = raw.windows(4).position(|w| w == b"\r\n\r\n").expect("no end of headers in the response");
+        let head = String::from_utf8_lossy(&raw[..split]).to_string();
+        let mut lines = head.split("\r\n");
+        let mut status_line = lines.next().unwrap_or_default().splitn(3, ' ').skip(1);
+        let status = status_line.next().and_then(|s| s.parse().ok()).expect("status line");
+        let reason = status_line.next().unwrap_or_default().to_string();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        let mut body = raw[split + 4..].to_vec();
+        // Answers to HEAD have the head of a chunked body without the body
+        if !body.is_empty() && headers.iter().any(|(name, value)| name == "transfer-encoding" && value.eq_ignore_ascii_case("chunked")) {
+            body = dechunk(&body);
+        }
+        TestResponse { status, reason, headers, body }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers.iter().find(|(n, _)| *n == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| panic!("response is not JSON ({}): {}", e, self.text()))
+    }
+
+    // The `code` of an error response
+    pub fn error_code(&self) -> String {
+        self.json()["error"]["code"].as_str().unwrap_or_default().to_string()
+    }
+}
+
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n").expect("chunk size line");
+        let size = usize::from_str_radix(String::from_utf8_lossy(&body[..line_end]).trim(), 16).expect("chunk size");
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return out;
+        }
+        out.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use std::env;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+static TRACKER: OnceLock<TcpListener> = OnceLock::new();
+
+// How long a report may take to arrive before the test fails
+const REPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The shared server, reporting to a tracker listening here, and giving seeding a millisecond so
+// it fails with a 504
+fn server() -> Option<(&'static common::TestServer, &'static TcpListener)> {
+    let tracker = TRACKER.get_or_init(|| {
+        let tracker = TcpListener::bind("127.0.0.1:0").expect("binding the tracker");
+        // Polled, so a report that never comes fails the test rather than hanging it
+        tracker.set_nonblocking(true).expect("making the tracker nonblocking");
+        // Read when the shared server starts, which is just below
+        env::set_var("SENTRY_DSN", format!("http://public@{}/42", tracker.local_addr().unwrap()));
+        env::set_var("SENTRY_ENVIRONMENT", "e2e");
+        env::set_var("ROUTE_TIMEOUTS_MS", "POST /admin/seed=1");
+        tracker
+    });
+    Some((common::server()?, tracker))
+}
+
+#[test]
+fn server_errors_are_reported() {
+    let Some((server, tracker)) = server() else { return };
+    let request_id = Uuid::new_v4().to_string();
+    let response = server
+        .post("/v1/admin/seed")
+        .admin(server)
+        .header("X-Request-Id", &request_id)
+        .json(json!({ "users": 10000, "posts_per_user": 10 }))
+        .send();
+    assert_eq!(response.status, 504, "{}", response.text());
+
+    let (head, event) = loop {
+        let (head, event) = receive(tracker);
+        if event["tags"]["request_id"] == request_id.as_str() {
+            break (head, event);
+        }
+    };
+    assert!(head[0].starts_with("POST /api/42/store/ HTTP/1.1"), "{:?}", head);
+    let auth = header(&head, "x-sentry-auth").unwrap_or_default();
+    assert!(auth.starts_with("Sentry sentry_version=7") && auth.contains("sentry_key=public"), "{}", auth);
+    assert_eq!(event["tags"]["route"], "POST /v1/admin/seed");
+    assert_eq!(event["tags"]["status"], "504");
+    assert_eq!(event["transaction"], "POST /v1/admin/seed");
+    assert_eq!(event["environment"], "e2e");
+    assert_eq!(event["level"], "error");
+    assert_eq!(event["event_id"].as_str().map(str::len), Some(32));
+    let message = event["message"]["formatted"].as_str().unwrap_or_default();
+    assert!(message.starts_with("POST /v1/admin/seed"), "{}", message);
+}
+
+// Read one report and accept it, failing if none arrives in time
+fn receive(listener: &TcpListener) -> (Vec<String>, Value) {
+    let deadline = Instant::now() + REPORT_TIMEOUT;
+    let stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                assert!(Instant::now() < deadline, "no report within {:?}", REPORT_TIMEOUT);
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => panic!("accepting a report: {}", e),
+        }
+    };
+    stream.set_nonblocking(false).unwrap();
+    stream.set_read_timeout(Some(REPORT_TIMEOUT)).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut head = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("a request line");
+        if line.trim_end().is_empty() {
+            break;
+        }
+        head.push(line.trim_end().to_string());
+    }
+    let length = header(&head, "content-length").and_then(|length| length.parse().ok()).expect("a Content-Length");
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).expect("the body");
+    reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+    (head, serde_json::from_slice(&body).expect("a JSON event"))
+}
+
+fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    head.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
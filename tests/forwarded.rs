@@ -0,0 +1,63 @@
+mod common;
+
+use std::env;
+use std::sync::OnceLock;
+
+static PROXIES: OnceLock<()> = OnceLock::new();
+
+// The shared server, behind a trusted proxy on the loopback address and a 10.0.0.0/8 network of
+// them, counting requests per client against a large burst that refills once a second
+fn server() -> Option<&'static common::TestServer> {
+    PROXIES.get_or_init(|| {
+        // Read when the shared server starts, which is just below
+        env::set_var("TRUSTED_PROXIES", "127.0.0.1, ::1, 10.0.0.0/8");
+        env::set_var("RATE_LIMIT", "60");
+        env::set_var("RATE_LIMIT_BURST", "1000");
+        env::set_var("TEST_RATE_LIMIT", "1");
+    });
+    common::server()
+}
+
+// How many requests the client the server sees for these headers has left
+fn remaining(server: &common::TestServer, headers: &[(&str, &str)]) -> u32 {
+    let mut request = server.get("/v1/healthz");
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request.send();
+    assert_eq!(response.status, 200, "{}", response.text());
+    response.header("x-ratelimit-remaining").and_then(|remaining| remaining.parse().ok()).expect("a rate limit")
+}
+
+#[test]
+fn clients_are_recovered_from_x_forwarded_for() {
+    let Some(server) = server() else { return };
+    assert_eq!(remaining(server, &[("X-Forwarded-For", "203.0.113.1")]), 999);
+    // Through another trusted proxy, it's the same client
+    assert_eq!(remaining(server, &[("X-Forwarded-For", "203.0.113.1, 10.1.2.3")]), 998);
+    // Entries before the first untrusted one could be made up by the client
+    assert_eq!(remaining(server, &[("X-Forwarded-For", "198.51.100.1, 203.0.113.1")]), 997);
+    assert_eq!(remaining(server, &[("X-Forwarded-For", "198.51.100.1, 203.0.113.2"), ("X-Forwarded-Proto", "https")]), 999);
+}
+
+#[test]
+fn clients_are_recovered_from_forwarded() {
+    let Some(server) = server() else { return };
+    assert_eq!(remaining(server, &[("Forwarded", "for=203.0.113.10;proto=https")]), 999);
+    assert_eq!(remaining(server, &[("Forwarded", "for=\"203.0.113.10:4711\", for=10.0.0.1;proto=http")]), 998);
+    assert_eq!(remaining(server, &[("Forwarded", "for=\"[2001:db8::10]:443\";proto=https")]), 999);
+    assert_eq!(remaining(server, &[("Forwarded", "For=\"[2001:db8::10]\"")]), 998);
+    // Forwarded wins over X-Forwarded-For
+    let both = [("Forwarded", "for=203.0.113.10"), ("X-Forwarded-For", "203.0.113.11")];
+    assert_eq!(remaining(server, &both), 997);
+}
+
+#[test]
+fn unknown_senders_stop_at_the_last_proxy() {
+    let Some(server) = server() else { return };
+    let proxy = [("Forwarded", "for=203.0.113.20, for=10.9.9.9")];
+    assert_eq!(remaining(server, &proxy), 999);
+    // 10.9.9.9 forwarded for a client it wouldn't name, so 10.9.9.9 is all that's known
+    assert_eq!(remaining(server, &[("Forwarded", "for=unknown, for=10.9.9.9")]), 999);
+    assert_eq!(remaining(server, &[("Forwarded", "for=_hidden;proto=https, for=10.9.9.9")]), 998);
+}
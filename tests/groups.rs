@@ -0,0 +1,54 @@
+mod common;
+
+use serde_json::json;
+use uuid::Uuid;
+
+#[test]
+fn groups_and_members() {
+    let Some(server) = common::server() else { return };
+    let name = format!("Group {}", &Uuid::new_v4().simple().to_string()[..12]);
+
+    let response = server.post("/groups").admin(server).json(json!({ "name": name })).send();
+    assert_eq!(response.status, 201);
+    let id = response.json()["id"].as_i64().unwrap();
+    assert_eq!(response.header("location"), Some(format!("/groups/{}", id).as_str()));
+
+    let response = server.post("/groups").admin(server).json(json!({ "name": name })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (409, "group_name_taken"));
+
+    assert_eq!(server.get(&format!("/groups/{}", id)).send().json()["name"], name.as_str());
+    assert!(server.get("/groups?limit=100").send().json()["total"].as_i64().unwrap() >= 1);
+
+    let user_id = server.create_user("member")["id"].as_i64().unwrap();
+    let member = format!("/groups/{}/members/{}", id, user_id);
+    assert_eq!(server.put(&member).admin(server).send().status, 201);
+    assert_eq!(server.put(&member).admin(server).send().status, 204);
+
+    let members = server.get(&format!("/groups/{}/members", id)).send().json();
+    assert_eq!((members["total"].as_i64(), members["users"][0]["id"].as_i64()), (Some(1), Some(user_id)));
+    let groups = server.get(&format!("/users/{}/groups", user_id)).send().json();
+    assert_eq!(groups["groups"][0]["id"], id);
+
+    assert_eq!(server.delete(&member).admin(server).send().status, 204);
+    assert_eq!(server.delete(&member).admin(server).send().status, 404);
+    assert_eq!(server.get(&format!("/users/{}/groups", user_id)).send().json()["groups"], json!([]));
+}
+
+#[test]
+fn group_errors() {
+    let Some(server) = common::server() else { return };
+    assert_eq!(server.get("/groups/2147483647").send().status, 404);
+    assert_eq!(server.get("/groups/2147483647/members").send().status, 404);
+    assert_eq!(server.get("/users/2147483647/groups").send().status, 404);
+    assert_eq!(server.post("/groups").admin(server).json(json!({ "name": "" })).send().status, 422);
+    assert_eq!(server.post("/groups").admin(server).json(json!({ "name": "x", "owner": 1 })).send().status, 400);
+    assert_eq!(server.post("/groups").json(json!({ "name": "Anonymous" })).send().status, 401);
+
+    let name = format!("Group {}", &Uuid::new_v4().simple().to_string()[..12]);
+    let id = server.post("/groups").admin(server).json(json!({ "name": name })).send().json()["id"].as_i64().unwrap();
+    let response = server.put(&format!("/groups/{}/members/2147483647", id)).admin(server).send();
+    assert_eq!(response.status, 404);
+    assert_eq!(response.json()["error"]["message"], "User not found");
+    let response = server.put("/groups/2147483647/members/1").admin(server).send();
+    assert_eq!(response.json()["error"]["message"], "Group not found");
+}
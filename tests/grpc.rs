@@ -0,0 +1,305 @@
+mod common;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const SERVICE: &str = "/users.v1.UserService/";
+
+// `www.example.com` Huffman-coded (RFC 7541 C.4.1)
+const AUTHORITY: [u8; 12] = [0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff];
+
+static GRPC_PORT: OnceLock<u16> = OnceLock::new();
+
+// The shared server, with its gRPC listener on a port that was free a moment ago
+fn server() -> Option<(&'static common::TestServer, u16)> {
+    let port = *GRPC_PORT.get_or_init(|| {
+        let port = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).expect("a free port").port();
+        // Read when the shared server starts, which is just below
+        env::set_var("GRPC_PORT", port.to_string());
+        port
+    });
+    common::server().map(|server| (server, port))
+}
+
+#[test]
+fn users_can_be_managed_over_grpc() {
+    let Some((server, port)) = server() else { return };
+    let mut client = Client::connect(port);
+    let admin = format!("Bearer {}", server.admin);
+    let email = common::unique_email("grpc");
+
+    let reply = client.call("CreateUser", &[("authorization", &admin)], &[string(1, "Grpc User"), string(2, &email)].concat());
+    assert_eq!(reply.status(), "0", "{:?}", reply.trailers);
+    let created = fields(&reply.message);
+    let id = created[&1].clone();
+    assert_eq!(created[&3], email.as_bytes());
+    // The REST API sees the same user
+    let path = format!("/users/{}", read_varint(&mut id.as_slice()));
+    assert_eq!(server.get(&path).send().json()["email"], email.as_str());
+
+    let reply = client.call("GetUser", &[], &[key(1, 0), id.clone()].concat());
+    assert_eq!(reply.status(), "0", "{:?}", reply.trailers);
+    assert_eq!(fields(&reply.message)[&2], b"Grpc User");
+
+    let update = [key(1, 0), id.clone(), string(2, "Renamed"), string(3, &email)].concat();
+    let reply = client.call("UpdateUser", &[("authorization", &admin)], &update);
+    assert_eq!(reply.status(), "0", "{:?}", reply.trailers);
+    assert_eq!(fields(&reply.message)[&2], b"Renamed");
+    assert_eq!(fields(&reply.message)[&7], [2]);
+
+    let reply = client.call("ListUsers", &[], &string(3, "Renamed"));
+    assert_eq!(reply.status(), "0", "{:?}", reply.trailers);
+    let mut listed = reply.message.as_slice();
+    let mut ids = Vec::new();
+    while !listed.is_empty() {
+        let (field, value) = next_field(&mut listed);
+        if field == 1 {
+            ids.push(fields(&value)[&1].clone());
+        }
+    }
+    assert!(ids.contains(&id), "the renamed user is listed");
+
+    let reply = client.call("DeleteUser", &[("authorization", &admin)], &[key(1, 0), id.clone()].concat());
+    assert_eq!((reply.status(), reply.message.len()), ("0", 0), "{:?}", reply.trailers);
+    let reply = client.call("GetUser", &[], &[key(1, 0), id].concat());
+    assert_eq!(reply.status(), "5");
+    assert_eq!(server.get(&path).send().status, 404);
+}
+
+#[test]
+fn grpc_errors_have_status_codes() {
+    let Some((server, port)) = server() else { return };
+    let mut client = Client::connect(port);
+    let user = [string(1, "Grpc User"), string(2, &common::unique_email("grpc"))].concat();
+
+    let reply = client.call("CreateUser", &[], &user);
+    assert_eq!(reply.status(), "16");
+    let viewer = format!("Bearer {}", server.token_for("viewer"));
+    let reply = client.call("CreateUser", &[("authorization", &viewer)], &user);
+    assert_eq!(reply.status(), "7");
+
+    let admin = format!("Bearer {}", server.admin);
+    let reply = client.call("CreateUser", &[("authorization", &admin)], &[string(1, "Grpc User"), string(2, "not-an-email")].concat());
+    assert_eq!(reply.status(), "3");
+    assert!(reply.trailers["grpc-message"].contains("email"), "{:?}", reply.trailers);
+    let reply = client.call("CreateUser", &[("authorization", &admin)], &user);
+    assert_eq!(reply.status(), "0");
+    let reply = client.call("CreateUser", &[("authorization", &admin)], &user);
+    assert_eq!(reply.status(), "6");
+
+    let reply = client.call("GetUser", &[], &[key(1, 0), vec![0xff, 0xff, 0xff, 0xff, 0x07]].concat());
+    assert_eq!(reply.status(), "5");
+    assert_eq!(reply.trailers["grpc-message"], "User not found");
+    let reply = client.call("ListUsers", &[], &string(2, "not a token"));
+    assert_eq!(reply.status(), "3");
+    let reply = client.call("FindUser", &[], &[]);
+    assert_eq!(reply.status(), "12");
+}
+
+// The answer to one call: the response headers and trailers (merged for a trailers-only response)
+// and the reply message
+struct Reply {
+    headers: HashMap<String, String>,
+    trailers: HashMap<String, String>,
+    message: Vec<u8>,
+}
+
+impl Reply {
+    fn status(&self) -> &str {
+        assert_eq!(self.headers[":status"], "200");
+        assert_eq!(self.headers["content-type"], "application/grpc");
+        self.trailers.get("grpc-status").map(String::as_str).expect("a grpc-status")
+    }
+}
+
+// Just enough HTTP/2 to make calls one after another on one connection
+struct Client {
+    stream: TcpStream,
+    next_stream_id: u32,
+}
+
+impl Client {
+    fn connect(port: u16) -> Client {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("connecting to the gRPC port");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).expect("setting a timeout");
+        stream.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").expect("sending the preface");
+        let mut client = Client { stream, next_stream_id: 1 };
+        client.send_frame(0x4, 0, 0, &[]);
+        client
+    }
+
+    fn call(&mut self, method: &str, metadata: &[(&str, &str)], message: &[u8]) -> Reply {
+        let id = self.next_stream_id;
+        self.next_stream_id += 2;
+
+        // :method POST and :scheme http from the static table; :authority is added to the dynamic
+        // table by the first call and taken from it by later ones
+        let mut block = vec![0x83, 0x86];
+        match id {
+            1 => block.extend([&[0x41, 0x80 | AUTHORITY.len() as u8][..], &AUTHORITY].concat()),
+            _ => block.push(0xbe),
+        }
+        let path = format!("{}{}", SERVICE, method);
+        for (name, value) in [(":path", path.as_str()), ("content-type", "application/grpc"), ("te", "trailers")].iter().chain(metadata) {
+            block.push(0x00);
+            block.extend(literal(name));
+            block.extend(literal(value));
+        }
+        self.send_frame(0x1, 0x4, id, &block);
+        let mut body = vec![0];
+        body.extend((message.len() as u32).to_be_bytes());
+        body.extend(message);
+        self.send_frame(0x0, 0x1, id, &body);
+
+        let mut reply = Reply { headers: HashMap::new(), trailers: HashMap::new(), message: Vec::new() };
+        let mut body = Vec::new();
+        loop {
+            let (kind, flags, stream_id, payload) = self.read_frame();
+            match kind {
+                0x4 if flags & 0x1 == 0 => self.send_frame(0x4, 0x1, 0, &[]),
+                0x0 if stream_id == id => body.extend(payload),
+                0x1 if stream_id == id => {
+                    assert_eq!(flags & 0x4, 0x4, "no CONTINUATION expected");
+                    let fields = decode(&payload);
+                    match reply.headers.is_empty() {
+                        true if flags & 0x1 == 0x1 => (reply.headers, reply.trailers) = (fields.clone(), fields),
+                        true => reply.headers = fields,
+                        false => reply.trailers = fields,
+                    }
+                    if flags & 0x1 == 0x1 {
+                        break;
+                    }
+                }
+                0x3 | 0x7 => panic!("the server reset the call or connection: {:?}", payload),
+                _ => {}
+            }
+        }
+        if !body.is_empty() {
+            assert_eq!(body[0], 0, "uncompressed");
+            assert_eq!(u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize, body.len() - 5);
+            reply.message = body[5..].to_vec();
+        }
+        reply
+    }
+
+    fn send_frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend([kind, flags]);
+        frame.extend(stream_id.to_be_bytes());
+        frame.extend(payload);
+        self.stream.write_all(&frame).expect("sending a frame");
+    }
+
+    fn read_frame(&mut self) -> (u8, u8, u32, Vec<u8>) {
+        let mut header = [0; 9];
+        self.stream.read_exact(&mut header).expect("reading a frame");
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0; length];
+        self.stream.read_exact(&mut payload).expect("reading a frame");
+        (header[3], header[4], u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff, payload)
+    }
+}
+
+// A raw string literal: its length as an integer with a 7-bit prefix, then the bytes
+fn literal(value: &str) -> Vec<u8> {
+    let mut bytes = match value.len() {
+        length if length < 127 => vec![length as u8],
+        length => [vec![127], varint(length as u64 - 127)].concat(),
+    };
+    bytes.extend(value.as_bytes());
+    bytes
+}
+
+// The server's header blocks: literals without indexing, names by static index or as raw strings
+fn decode(mut block: &[u8]) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    while let [first, rest @ ..] = block {
+        assert_eq!(first & 0xf0, 0x00, "a literal without indexing");
+        block = rest;
+        let name = match first & 0x0f {
+            0 => read_string(&mut block),
+            8 => ":status".to_string(),
+            0x0f => {
+                let index = 15 + block[0] as usize;
+                block = &block[1..];
+                assert_eq!(index, 31);
+                "content-type".to_string()
+            }
+            index => panic!("unexpected name index {}", index),
+        };
+        fields.insert(name, read_string(&mut block));
+    }
+    fields
+}
+
+fn read_string(block: &mut &[u8]) -> String {
+    let length = block[0] as usize;
+    assert!(length < 127, "raw and short");
+    let value = String::from_utf8(block[1..1 + length].to_vec()).expect("UTF-8");
+    *block = &block[1 + length..];
+    value
+}
+
+fn key(field: u64, wire_type: u64) -> Vec<u8> {
+    varint((field << 3) | wire_type)
+}
+
+fn string(field: u64, value: &str) -> Vec<u8> {
+    [key(field, 2), varint(value.len() as u64), value.as_bytes().to_vec()].concat()
+}
+
+fn varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    while value >= 0x80 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+    bytes
+}
+
+fn read_varint(buf: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = buf[0];
+        *buf = &buf[1..];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+// A field of a message: a varint's encoded bytes, or a length-delimited field's contents
+fn next_field(buf: &mut &[u8]) -> (u64, Vec<u8>) {
+    let key = read_varint(buf);
+    match key & 0x7 {
+        0 => {
+            let before = *buf;
+            read_varint(buf);
+            (key >> 3, before[..before.len() - buf.len()].to_vec())
+        }
+        2 => {
+            let length = read_varint(buf) as usize;
+            let value = buf[..length].to_vec();
+            *buf = &buf[length..];
+            (key >> 3, value)
+        }
+        wire_type => panic!("unexpected wire type {}", wire_type),
+    }
+}
+
+// A message's fields by number; repeated fields keep their last value
+fn fields(mut message: &[u8]) -> HashMap<u64, Vec<u8>> {
+    let mut fields = HashMap::new();
+    while !message.is_empty() {
+        let (field, value) = next_field(&mut message);
+        fields.insert(field, value);
+    }
+    fields
+}
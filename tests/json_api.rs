@@ -0,0 +1,107 @@
+mod common;
+
+use std::env;
+use std::sync::OnceLock;
+
+use serde_json::{json, Value};
+
+static RESPONSE_FORMAT: OnceLock<()> = OnceLock::new();
+
+// The shared server, answering JSON:API documents unless a client asks for another format
+fn server() -> Option<&'static common::TestServer> {
+    RESPONSE_FORMAT.get_or_init(|| {
+        // Read when the shared server starts, which is just below
+        env::set_var("RESPONSE_FORMAT", "jsonapi");
+    });
+    common::server()
+}
+
+// A new user's resource object
+fn create_user(server: &common::TestServer, email: &str) -> Value {
+    let response = server.post("/users").admin(server).json(json!({ "name": "Api User", "email": email })).send();
+    assert_eq!(response.status, 201, "{}", response.text());
+    assert_eq!(response.header("content-type"), Some("application/vnd.api+json"));
+    response.json()["data"].clone()
+}
+
+#[test]
+fn resources_come_with_their_relationships() {
+    let Some(server) = server() else { return };
+    let user = create_user(server, &common::unique_email("jsonapi"));
+    assert_eq!(user["type"], "users");
+    assert_eq!(user["attributes"]["name"], "Api User");
+    assert_eq!(user["attributes"].get("id"), None);
+    let id = user["id"].as_str().expect("ids are strings").to_string();
+
+    let post = server.post(&format!("/users/{}/posts", id)).admin(server).json(json!({ "title": "Hello", "body": "World" })).send();
+    assert_eq!(post.status, 201, "{}", post.text());
+    let post = post.json()["data"].clone();
+    assert_eq!(post["type"], "posts");
+    assert_eq!(post["relationships"]["user"]["data"], json!({ "type": "users", "id": id }));
+
+    let response = server.get(&format!("/users/{}?include=posts", id)).send();
+    assert_eq!(response.status, 200, "{}", response.text());
+    let document = response.json();
+    let posts = &document["data"]["relationships"]["posts"];
+    assert_eq!(posts["data"], json!([{ "type": "posts", "id": post["id"] }]));
+    assert_eq!(posts["meta"]["total"], 1);
+    assert_eq!(document["included"][0]["attributes"]["title"], "Hello");
+    assert_eq!(document["links"]["self"], format!("/v1/users/{}?include=posts", id));
+}
+
+#[test]
+fn pages_link_to_their_neighbours() {
+    let Some(server) = server() else { return };
+    let prefix = format!("jsonapi-page-{}", uuid::Uuid::new_v4().simple());
+    for n in 0..3 {
+        create_user(server, &format!("{}-{}@example.test", prefix, n));
+    }
+
+    let response = server.get(&format!("/users?email_contains={}&limit=2", prefix)).send();
+    assert_eq!(response.status, 200, "{}", response.text());
+    let document = response.json();
+    assert_eq!(document["data"].as_array().map(Vec::len), Some(2));
+    assert_eq!(document["meta"]["total"], 3);
+    let links = &document["links"];
+    assert_eq!(links["prev"], Value::Null);
+    assert_eq!(links["next"], format!("/v1/users?email_contains={}&limit=2&offset=2", prefix));
+    assert_eq!(links["last"], links["next"]);
+
+    let response = server.get(links["next"].as_str().unwrap_or_default()).send();
+    let document = response.json();
+    assert_eq!(document["data"].as_array().map(Vec::len), Some(1));
+    assert_eq!(document["links"]["next"], Value::Null);
+    assert_eq!(document["links"]["prev"], format!("/v1/users?email_contains={}&limit=2&offset=0", prefix));
+}
+
+#[test]
+fn errors_point_at_what_is_wrong() {
+    let Some(server) = server() else { return };
+
+    let response = server.post("/users").admin(server).json(json!({ "name": "", "email": "not an email" })).send();
+    assert_eq!(response.status, 422, "{}", response.text());
+    let errors = response.json()["errors"].as_array().cloned().unwrap_or_default();
+    let pointers: Vec<&str> = errors.iter().filter_map(|error| error["source"]["pointer"].as_str()).collect();
+    assert!(pointers.contains(&"/email") && pointers.contains(&"/name"), "{:?}", errors);
+    assert!(errors.iter().all(|error| error["status"] == "422" && error["code"] == "validation_failed"), "{:?}", errors);
+
+    let response = server.get("/users/2147483000").send();
+    assert_eq!(response.status, 404);
+    assert_eq!(response.json()["errors"][0]["code"], "not_found");
+}
+
+#[test]
+fn other_formats_and_routes_without_resources_are_unchanged() {
+    let Some(server) = server() else { return };
+
+    let health = server.get("/healthz").send();
+    assert_eq!(health.header("content-type"), Some("application/json"));
+
+    // Naming plain JSON outright still gets it
+    let json = server.get("/users?limit=1").header("Accept", "application/json").send();
+    assert_eq!(json.header("content-type"), Some("application/json"));
+    assert!(json.json()["users"].is_array(), "{}", json.text());
+
+    let xml = server.get("/users?limit=1").header("Accept", "application/xml").send();
+    assert_eq!(xml.header("content-type"), Some("application/xml"));
+}
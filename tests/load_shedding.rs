@@ -0,0 +1,56 @@
+mod common;
+
+use std::env;
+use std::sync::{Arc, Barrier, OnceLock};
+use std::thread;
+
+use serde_json::json;
+
+static LOAD_SHEDDING: OnceLock<()> = OnceLock::new();
+
+// The shared server, handling one request at a time with none waiting
+fn server() -> Option<&'static common::TestServer> {
+    LOAD_SHEDDING.get_or_init(|| {
+        // Read when the shared server starts, which is just below
+        env::set_var("MAX_CONCURRENT_REQUESTS", "1");
+        env::set_var("MAX_QUEUED_REQUESTS", "0");
+    });
+    common::server()
+}
+
+#[test]
+fn requests_beyond_capacity_are_shed() {
+    let Some(server) = server() else { return };
+
+    // Logins take a while, as checking a password is slow on purpose, so these overlap
+    let barrier = Arc::new(Barrier::new(8));
+    let attempts: Vec<_> = (0..8)
+        .map(|_| {
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                let body = json!({ "email": common::unique_email("shed"), "password": "not the password" });
+                server.post("/auth/login").json(body).send()
+            })
+        })
+        .collect();
+    let responses: Vec<_> = attempts.into_iter().map(|attempt| attempt.join().expect("login attempt")).collect();
+
+    let shed: Vec<_> = responses.iter().filter(|response| response.status == 503).collect();
+    assert!(!shed.is_empty(), "no request was shed");
+    assert!(shed.len() < responses.len(), "every request was shed");
+    assert!(responses.iter().all(|response| matches!(response.status, 401 | 503)));
+    for response in &shed {
+        assert_eq!(response.error_code(), "overloaded");
+        assert_eq!(response.header("retry-after"), Some("1"));
+    }
+
+    let metrics = server.get("/metrics").send().text();
+    let count = metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("http_requests_shed_total "))
+        .and_then(|count| count.parse::<usize>().ok())
+        .expect("http_requests_shed_total");
+    assert!(count >= shed.len(), "{}", metrics);
+    assert!(metrics.contains("http_requests_queued 0"), "{}", metrics);
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use std::env;
+
+use serde_json::json;
+
+#[test]
+fn users_are_cached_in_memory_until_changed() {
+    // Read when the shared server starts, which is just below
+    env::set_var("USER_CACHE_SIZE", "100");
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("lru");
+    let path = format!("/users/{}", user["id"]);
+
+    let before = cache_requests(server);
+    assert_eq!(server.get(&path).admin(server).send().json()["name"], user["name"]);
+    assert_eq!(server.get(&path).admin(server).send().json()["name"], user["name"]);
+    let after = cache_requests(server);
+    assert_eq!((after.0 - before.0, after.1 - before.1), (1, 1), "a miss, then a hit");
+
+    // A change drops the user, so the next read sees it
+    let response = server.patch(&path).admin(server).json(json!({ "name": "Renamed" })).send();
+    assert_eq!(response.status, 200);
+    assert_eq!(server.get(&path).admin(server).send().json()["name"], "Renamed");
+    server.delete(&path).admin(server).send();
+    assert_eq!(server.get(&path).admin(server).send().status, 404);
+
+    let metrics = server.get("/metrics").send().text();
+    assert!(metrics.contains("user_cache_capacity 100"), "{}", metrics);
+}
+
+// Hits and misses so far, from /metrics
+fn cache_requests(server: &common::TestServer) -> (u64, u64) {
+    let metrics = server.get("/metrics").send().text();
+    let count = |result: &str| {
+        let prefix = format!("user_cache_requests_total{{result=\"{}\"}} ", result);
+        metrics.lines().find_map(|line| line.strip_prefix(&prefix)).and_then(|count| count.parse().ok()).expect("a cache metric")
+    };
+    (count("hit"), count("miss"))
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use serde_json::json;
+
+#[test]
+fn post_lifecycle() {
+    let Some(server) = common::server() else { return };
+    let user_id = server.create_user("poster")["id"].as_i64().unwrap();
+
+    let response = server.post("/posts").admin(server).json(json!({ "user_id": user_id, "title": "Hello", "body": "First post" })).send();
+    assert_eq!(response.status, 201);
+    let post = response.json();
+    let id = post["id"].as_i64().unwrap();
+    assert_eq!(response.header("location"), Some(format!("/posts/{}", id).as_str()));
+
+    let path = format!("/posts/{}", id);
+    assert_eq!(server.get(&path).send().json()["title"], "Hello");
+
+    let response = server.put(&path).admin(server).json(json!({ "title": "Hello again", "body": "Edited" })).send();
+    assert_eq!((response.status, response.json()["title"].as_str()), (200, Some("Hello again")));
+    let response = server.put(&path).admin(server).json(json!({ "user_id": user_id + 1, "title": "Moved", "body": "No" })).send();
+    assert_eq!(response.status, 422);
+    assert!(response.json()["error"]["details"]["fields"].get("user_id").is_some());
+
+    let listed = server.get(&format!("/posts?user_id={}", user_id)).send().json();
+    assert_eq!((listed["total"].as_i64(), listed["posts"][0]["id"].as_i64()), (Some(1), Some(id)));
+
+    assert_eq!(server.delete(&path).admin(server).send().status, 204);
+    assert_eq!(server.get(&path).send().status, 404);
+    assert_eq!(server.delete(&path).admin(server).send().status, 404);
+    assert_eq!(server.put(&path).admin(server).json(json!({ "title": "Gone", "body": "Gone" })).send().status, 404);
+}
+
+#[test]
+fn posts_under_a_user() {
+    let Some(server) = common::server() else { return };
+    let user_id = server.create_user("nested")["id"].as_i64().unwrap();
+    let path = format!("/users/{}/posts", user_id);
+
+    let response = server.post(&path).admin(server).json(json!({ "title": "Nested", "body": "Under the user" })).send();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.json()["user_id"], user_id);
+
+    let response = server.post(&path).admin(server).json(json!({ "user_id": user_id + 1, "title": "Elsewhere", "body": "x" })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (422, "validation_failed"));
+
+    let page = server.get(&path).send().json();
+    assert_eq!(page["total"], 1);
+    assert_eq!(server.get("/users/2147483647/posts").send().status, 404);
+    let response = server.post("/users/2147483647/posts").admin(server).json(json!({ "title": "Orphan", "body": "x" })).send();
+    assert_eq!(response.status, 404);
+}
+
+#[test]
+fn post_validation() {
+    let Some(server) = common::server() else { return };
+    let user_id = server.create_user("invalid-post")["id"].as_i64().unwrap();
+
+    let response = server.post("/posts").admin(server).json(json!({ "title": "No user", "body": "x" })).send();
+    assert_eq!(response.status, 422);
+    let response = server.post("/posts").admin(server).json(json!({ "user_id": 2147483647, "title": "Ghost", "body": "x" })).send();
+    assert_eq!(response.status, 422);
+    let response = server.post("/posts").admin(server).json(json!({ "user_id": user_id, "title": "", "body": "x" })).send();
+    assert_eq!(response.status, 422);
+    assert!(response.json()["error"]["details"]["fields"].get("title").is_some());
+    let response = server.post("/posts").admin(server).json(json!({ "user_id": user_id })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (400, "invalid_json"));
+
+    assert_eq!(server.get("/posts/2147483647").send().status, 404);
+    assert_eq!(server.get("/posts/first").send().status, 400);
+    assert_eq!(server.get("/posts?user_id=me").send().status, 400);
+    assert_eq!(server.post("/posts").json(json!({ "user_id": user_id, "title": "Anon", "body": "x" })).send().status, 401);
+}
+
+#[test]
+fn users_embed_their_posts() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("embedded");
+    let path = format!("/users/{}", user["id"]);
+    for title in ["First", "Second"] {
+        let post = json!({ "title": title, "body": "Embedded" });
+        assert_eq!(server.post(&format!("{}/posts", path)).admin(server).json(post).send().status, 201);
+    }
+
+    let response = server.get(&format!("{}?include=posts", path)).send();
+    assert_eq!(response.status, 200);
+    let body = response.json();
+    assert_eq!(body["email"], user["email"]);
+    let titles: Vec<&str> = body["posts"].as_array().unwrap().iter().filter_map(|post| post["title"].as_str()).collect();
+    assert_eq!((titles, body["posts_total"].as_i64()), (vec!["First", "Second"], Some(2)));
+
+    // The entity tag covers the posts, so a new post makes a cached copy stale
+    let etag = response.header("etag").unwrap().to_string();
+    assert_ne!(Some(etag.as_str()), server.get(&path).send().header("etag"));
+    let cached = server.get(&format!("{}?include=posts", path)).header("If-None-Match", &etag).send();
+    assert_eq!(cached.status, 304);
+    let post = json!({ "title": "Third", "body": "Embedded" });
+    assert_eq!(server.post(&format!("{}/posts", path)).admin(server).json(post).send().status, 201);
+    let stale = server.get(&format!("{}?include=posts", path)).header("If-None-Match", &etag).send();
+    assert_eq!((stale.status, stale.json()["posts_total"].as_i64()), (200, Some(3)));
+
+    let trimmed = server.get(&format!("{}?include=posts&fields=id", path)).send().json();
+    let mut keys: Vec<&String> = trimmed.as_object().unwrap().keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["_links", "id", "posts", "posts_total"]);
+
+    let response = server.get(&format!("{}?include=comments", path)).send();
+    assert_eq!((response.status, response.error_code().as_str()), (400, "invalid_query_parameter"));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::env;
+use std::sync::OnceLock;
+
+use serde_json::json;
+
+static REPLICAS: OnceLock<bool> = OnceLock::new();
+
+// The shared server, reading from two replicas: the test database through read-only sessions, so
+// a write sent there fails, and one that is never up. Only Postgres has replicas
+fn server() -> Option<&'static common::TestServer> {
+    let postgres = *REPLICAS.get_or_init(|| {
+        let url = env::var("TEST_DATABASE_URL").unwrap_or_default();
+        if url.is_empty() || url.starts_with("sqlite:") || url.starts_with("mysql:") || url.starts_with("mariadb:") {
+            return false;
+        }
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let read_only = format!("{}{}options=-c%20default_transaction_read_only%3Don", url, separator);
+        // Read when the shared server starts, which is just below
+        env::set_var("DATABASE_REPLICA_URLS", format!("{},postgres://postgres@127.0.0.1:1/postgres", read_only));
+        true
+    });
+    match postgres {
+        true => common::server(),
+        false => None,
+    }
+}
+
+#[test]
+fn reads_go_to_replicas_and_writes_to_the_primary() {
+    let Some(server) = server() else { return };
+
+    let email = common::unique_email("replica");
+    let created = server.post("/users").admin(server).json(json!({ "name": "Replica User", "email": email })).send();
+    assert_eq!(created.status, 201, "{}", created.text());
+    let id = created.json()["id"].as_i64().expect("user id");
+
+    // Each turn lands on another replica; the one that is down is skipped
+    for _ in 0..4 {
+        let fetched = server.get(&format!("/users/{}", id)).send();
+        assert_eq!(fetched.status, 200, "{}", fetched.text());
+        let listed = server.get(&format!("/users?email_contains={}", email)).admin(server).send();
+        assert_eq!(listed.json()["users"].as_array().map(Vec::len), Some(1), "{}", listed.text());
+    }
+
+    let patched = server.patch(&format!("/users/{}", id)).admin(server).json(json!({ "name": "Renamed" })).send();
+    assert_eq!(patched.status, 200, "{}", patched.text());
+    assert_eq!(server.get(&format!("/users/{}", id)).send().json()["name"], "Renamed");
+    assert_eq!(server.delete(&format!("/users/{}", id)).admin(server).send().status, 204);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use std::env;
+use std::sync::OnceLock;
+
+use serde_json::json;
+
+static ROUTE_TIMEOUTS: OnceLock<()> = OnceLock::new();
+
+// The shared server, giving seeding a millisecond and every other route all the time it needs
+fn server() -> Option<&'static common::TestServer> {
+    ROUTE_TIMEOUTS.get_or_init(|| {
+        // Read when the shared server starts, which is just below
+        env::set_var("ROUTE_TIMEOUTS_MS", "POST /admin/seed=1");
+    });
+    common::server()
+}
+
+#[test]
+fn handlers_over_their_budget_time_out() {
+    let Some(server) = server() else { return };
+
+    let response = server.post("/admin/seed").admin(server).json(json!({ "users": 10000, "posts_per_user": 10 })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (504, "timeout"), "{}", response.text());
+
+    // The cancelled work leaves nothing behind that holds up other routes
+    for _ in 0..3 {
+        let users = server.get("/users").send();
+        assert_eq!(users.status, 200, "{}", users.text());
+    }
+    let created = server.create_user("budget");
+    assert!(created["id"].is_number(), "{}", created);
+}
+
+#[test]
+fn the_budget_applies_under_the_version_prefix() {
+    let Some(server) = server() else { return };
+
+    let response = server.post("/v1/admin/seed").admin(server).json(json!({ "users": 10000, "posts_per_user": 10 })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (504, "timeout"), "{}", response.text());
+}
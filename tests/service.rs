@@ -0,0 +1,342 @@
+mod common;
+
+use std::io::Read;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde_json::json;
+
+#[test]
+fn health_and_readiness() {
+    let Some(server) = common::server() else { return };
+    assert_eq!(server.get("/healthz").send().json(), json!({ "status": "ok" }));
+    let ready = server.get("/readyz").send();
+    assert_eq!(ready.status, 200);
+    assert_eq!(ready.json()["checks"]["database"]["status"], "up");
+}
+
+#[test]
+fn version_describes_the_build() {
+    let Some(server) = common::server() else { return };
+    let response = server.get("/v1/version").send();
+    assert_eq!(response.status, 200, "{}", response.text());
+    let build = response.json();
+    assert_eq!(build["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(build["commit"], env!("GIT_COMMIT"));
+    assert!(build["built_at"].as_str().is_some_and(|built_at| chrono::DateTime::parse_from_rfc3339(built_at).is_ok()), "{}", build);
+    let features: Vec<&str> = build["features"].as_array().unwrap().iter().filter_map(|feature| feature.as_str()).collect();
+    assert_eq!(features.contains(&"sqlite"), cfg!(feature = "sqlite"), "{:?}", features);
+    assert_eq!(features.contains(&"redis"), cfg!(feature = "redis"), "{:?}", features);
+}
+
+#[test]
+fn metrics_count_requests() {
+    let Some(server) = common::server() else { return };
+    server.get("/healthz").send();
+    let response = server.get("/metrics").send();
+    assert_eq!(response.status, 200);
+    assert!(response.header("content-type").unwrap().starts_with("text/plain"));
+    let text = response.text();
+    assert!(text.contains("http_requests_total{method=\"GET\",route=\"/healthz\",status=\"200\"}"));
+    assert!(text.contains("# TYPE http_request_duration_seconds histogram"));
+}
+
+#[test]
+fn openapi_and_docs() {
+    let Some(server) = common::server() else { return };
+    let spec = server.get("/openapi.json").send().json();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    for path in ["/users", "/users/{id}", "/posts", "/groups/{id}/members/{user_id}", "/admin/seed"] {
+        assert!(spec["paths"].get(path).is_some(), "{} is missing from the spec", path);
+    }
+
+    let docs = server.get("/docs").send();
+    assert_eq!(docs.status, 200);
+    assert!(docs.header("content-type").unwrap().starts_with("text/html"));
+    assert_eq!(server.get("/docs/missing.js").send().status, 404);
+}
+
+#[test]
+fn admin_dashboard() {
+    let Some(server) = common::server() else { return };
+    let page = server.get("/v1/admin").send();
+    assert_eq!(page.status, 200);
+    assert!(page.header("content-type").unwrap().starts_with("text/html"));
+    assert_eq!(page.header("x-frame-options"), Some("DENY"));
+    let html = page.text();
+    for asset in ["/v1/admin/admin.js", "/v1/admin/admin.css"] {
+        assert!(html.contains(asset), "{}", html);
+        assert_eq!(server.get(asset).send().status, 200, "{}", asset);
+    }
+
+    // It's a page for browsers, not part of the API; the admin routes beside it still need an admin
+    assert!(server.get("/openapi.json").send().json()["paths"].get("/admin").is_none());
+    assert_eq!(server.get("/v1/admin/seed").send().status, 404);
+    assert_eq!(server.post("/v1/admin/seed").json(json!({ "users": 1 })).send().status, 401);
+}
+
+#[test]
+fn compresses_large_json() {
+    let Some(server) = common::server() else { return };
+    let plain = server.get("/openapi.json").send();
+    assert_eq!(plain.header("content-encoding"), None);
+    assert!(plain.headers.iter().any(|(name, value)| name == "vary" && value == "Accept-Encoding"));
+
+    let gzip = server.get("/openapi.json").header("Accept-Encoding", "deflate;q=0.5, gzip").send();
+    assert_eq!(gzip.header("content-encoding"), Some("gzip"));
+    assert!(gzip.body.len() < plain.body.len());
+    let mut body = Vec::new();
+    GzDecoder::new(gzip.body.as_slice()).read_to_end(&mut body).expect("a gzip body");
+    assert_eq!(body, plain.body);
+
+    let deflate = server.get("/openapi.json").header("Accept-Encoding", "gzip;q=0, deflate").send();
+    assert_eq!(deflate.header("content-encoding"), Some("deflate"));
+    let mut body = Vec::new();
+    ZlibDecoder::new(deflate.body.as_slice()).read_to_end(&mut body).expect("a zlib body");
+    assert_eq!(body, plain.body);
+
+    // Small bodies are sent as they are
+    let health = server.get("/healthz").header("Accept-Encoding", "gzip").send();
+    assert_eq!(health.header("content-encoding"), None);
+}
+
+#[test]
+fn negotiates_response_formats() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("formats");
+    let path = format!("/users/{}", user["id"]);
+
+    let json = server.get(&path).header("Accept", "application/xml;q=0.5, application/json").send();
+    assert_eq!(json.header("content-type"), Some("application/json"));
+    assert_eq!(json.header("vary"), Some("Accept"));
+
+    let xml = server.get(&path).header("Accept", "text/html, application/xml;q=0.9, */*;q=0.8").send();
+    assert_eq!(xml.header("content-type"), Some("application/xml"));
+    let text = xml.text();
+    assert!(text.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?><response>"), "{}", text);
+    assert!(text.contains(&format!("<id>{}</id>", user["id"])) && text.contains("<name>Test User</name>"), "{}", text);
+    let list = server.get("/users?limit=2").header("Accept", "application/xml").send().text();
+    assert!(list.contains("<users><user><_links><collection><href>/v1/users</href></collection>"), "{}", list);
+
+    let msgpack = server.get(&path).header("Accept", "application/msgpack").send();
+    assert_eq!(msgpack.header("content-type"), Some("application/msgpack"));
+    // A fixmap of the user's fields, the first of them a fixstr key
+    assert_eq!(msgpack.body[0] & 0xf0, 0x80);
+    assert_eq!(msgpack.body[1] & 0xe0, 0xa0);
+
+    let document = server.get(&path).header("Accept", "application/vnd.api+json").send();
+    assert_eq!(document.header("content-type"), Some("application/vnd.api+json"));
+    let document = document.json();
+    assert_eq!((&document["data"]["type"], &document["data"]["id"]), (&json!("users"), &json!(user["id"].to_string())));
+
+    // Errors come in the requested format too
+    let missing = server.get("/users/0").header("Accept", "application/xml").send();
+    assert_eq!(missing.status, 404);
+    assert!(missing.text().contains("<error><code>not_found</code>"), "{}", missing.text());
+}
+
+#[test]
+fn unknown_routes_and_methods() {
+    let Some(server) = common::server() else { return };
+    let response = server.get("/nowhere").send();
+    assert_eq!((response.status, response.error_code().as_str()), (404, "not_found"));
+
+    let response = server.request("PATCH", "/posts/1").admin(server).json(json!({})).send();
+    assert_eq!((response.status, response.error_code().as_str()), (405, "method_not_allowed"));
+    let allow = response.header("allow").unwrap();
+    assert!(allow.contains("GET") && allow.contains("PUT") && allow.contains("DELETE"));
+    assert!(allow.contains("HEAD"), "{}", allow);
+}
+
+#[test]
+fn representations_have_entity_tags_of_their_own() {
+    let Some(server) = common::server() else { return };
+    let plain = server.get("/openapi.json").send();
+    let etag = plain.header("etag").unwrap().to_string();
+
+    // The gzip copy is another representation: the identity copy's tag doesn't validate it
+    let gzip = server.get("/openapi.json").header("Accept-Encoding", "gzip").header("If-None-Match", &etag).send();
+    assert_eq!((gzip.status, gzip.header("content-encoding")), (200, Some("gzip")));
+    let gzip_etag = gzip.header("etag").unwrap().to_string();
+    assert_ne!(gzip_etag, etag);
+
+    // Its own tag does, and the 304 varies as the 200 did
+    let cached = server.get("/openapi.json").header("Accept-Encoding", "gzip").header("If-None-Match", &gzip_etag).send();
+    assert_eq!(cached.status, 304);
+    let vary = |response: &common::TestResponse| -> Vec<String> {
+        response.headers.iter().filter(|(name, _)| name == "vary").map(|(_, value)| value.clone()).collect()
+    };
+    assert_eq!(vary(&cached), vary(&gzip));
+    assert!(vary(&cached).iter().any(|value| value == "Accept-Encoding"), "{:?}", vary(&cached));
+
+    // Likewise for formats: the XML of a user version isn't its JSON
+    let user = server.create_user("variants");
+    let path = format!("/v1/users/{}", user["id"]);
+    let etag = server.get(&path).send().header("etag").unwrap().to_string();
+    let xml = server.get(&path).header("Accept", "application/xml").header("If-None-Match", &etag).send();
+    assert_eq!(xml.status, 200);
+    let xml_etag = xml.header("etag").unwrap().to_string();
+    assert_ne!(xml_etag, etag);
+    let cached = server.get(&path).header("Accept", "application/xml").header("If-None-Match", &xml_etag).send();
+    assert_eq!(cached.status, 304);
+    assert!(vary(&cached).iter().any(|value| value == "Accept"), "{:?}", vary(&cached));
+
+    // Any representation's tag names the version for If-Match
+    let updated = server
+        .put(&path)
+        .admin(server)
+        .header("If-Match", &xml_etag)
+        .json(serde_json::json!({ "name": "Variants", "email": format!("renamed-{}", user["email"].as_str().unwrap()) }))
+        .send();
+    assert_eq!(updated.status, 200, "{}", updated.text());
+}
+
+#[test]
+fn head_requests() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("head");
+    let path = format!("/v1/users/{}", user["id"]);
+    let get = server.get(&path).send();
+    let head = server.request("HEAD", &path).send();
+    assert_eq!(head.status, 200);
+    assert!(head.body.is_empty());
+    for name in ["content-type", "content-length", "etag", "last-modified"] {
+        assert!(head.header(name).is_some(), "{}", name);
+        assert_eq!(head.header(name), get.header(name), "{}", name);
+    }
+    assert_eq!(head.header("content-length"), Some(get.body.len().to_string().as_str()));
+
+    // Validators work as for GET
+    let etag = head.header("etag").unwrap();
+    assert_eq!(server.request("HEAD", &path).header("If-None-Match", etag).send().status, 304);
+
+    // Compressed, the length is the compressed body's
+    let gzip = server.get("/openapi.json").header("Accept-Encoding", "gzip").send();
+    let head = server.request("HEAD", "/openapi.json").header("Accept-Encoding", "gzip").send();
+    assert_eq!(head.header("content-encoding"), Some("gzip"));
+    assert_eq!(head.header("content-length"), Some(gzip.body.len().to_string().as_str()));
+    assert!(head.body.is_empty());
+
+    // Streamed bodies are announced as chunked, and errors carry no body either
+    let stream = server.request("HEAD", "/v1/users/all").send();
+    assert_eq!((stream.status, stream.header("transfer-encoding")), (200, Some("chunked")));
+    assert!(stream.body.is_empty());
+    let missing = server.request("HEAD", "/v1/users/999999999").send();
+    assert_eq!(missing.status, 404);
+    assert!(missing.body.is_empty());
+    let login = server.request("HEAD", "/v1/auth/login").send();
+    assert_eq!((login.status, login.header("allow")), (405, Some("POST, OPTIONS")));
+}
+
+#[test]
+fn options_requests() {
+    let Some(server) = common::server() else { return };
+    let users = server.request("OPTIONS", "/v1/users").send();
+    assert_eq!(users.status, 204);
+    assert!(users.body.is_empty());
+    assert_eq!(users.header("allow"), Some("GET, HEAD, POST, DELETE, OPTIONS"));
+
+    let user = server.request("OPTIONS", "/v1/users/1").send();
+    assert_eq!(user.header("allow"), Some("GET, HEAD, PUT, PATCH, DELETE, OPTIONS"));
+    // Legacy paths allow what their successors do
+    assert_eq!(server.request("OPTIONS", "/users/1").send().header("allow"), user.header("allow"));
+    assert_eq!(server.request("OPTIONS", "/v1/auth/login").send().header("allow"), Some("POST, OPTIONS"));
+    assert_eq!(server.request("OPTIONS", "/v1/nowhere").send().status, 404);
+
+    // The server as a whole
+    let everything = server.request("OPTIONS", "*").send();
+    assert_eq!(everything.status, 204);
+    let allow = everything.header("allow").unwrap();
+    for method in ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"] {
+        assert!(allow.split(", ").any(|allowed| allowed == method), "{}", allow);
+    }
+}
+
+#[test]
+fn api_versions() {
+    let Some(server) = common::server() else { return };
+    let created = server.post("/v1/users").admin(server).json(json!({ "name": "Versioned", "email": common::unique_email("v1") })).send();
+    assert_eq!(created.status, 201);
+    let path = format!("/v1/users/{}", created.json()["id"]);
+    assert_eq!(created.header("location"), Some(path.as_str()));
+    let current = server.get(&path).send();
+    assert_eq!((current.status, current.header("deprecation")), (200, None));
+    // Credentials are still needed for writes under the prefix
+    assert_eq!(server.delete(&path).send().status, 401);
+
+    // Unprefixed paths still work, but say they are deprecated and where they moved
+    let legacy = server.get(path.strip_prefix("/v1").unwrap()).send();
+    assert_eq!(legacy.status, 200);
+    assert!(legacy.header("deprecation").is_some_and(|value| value.starts_with('@')));
+    assert!(legacy.header("sunset").is_some());
+    assert_eq!(legacy.header("link"), Some(format!("<{}>; rel=\"successor-version\"", path).as_str()));
+    let missing = server.get("/nowhere").send();
+    assert_eq!((missing.status, missing.header("deprecation")), (404, None));
+    assert_eq!(server.get("/v10/users").send().status, 404);
+
+    let metrics = server.get("/v1/metrics").send().text();
+    assert!(metrics.contains("route=\"/v1/users/{id}\""), "{}", metrics);
+    assert!(metrics.contains("route=\"/users/{id}\""), "{}", metrics);
+    assert_eq!(server.get("/v1/openapi.json").send().json()["servers"], json!([{ "url": "/v1" }]));
+}
+
+#[test]
+fn request_ids() {
+    let Some(server) = common::server() else { return };
+    let response = server.get("/healthz").header("X-Request-Id", "e2e-request-1").send();
+    assert_eq!(response.header("x-request-id"), Some("e2e-request-1"));
+    let generated = server.get("/healthz").send();
+    assert!(!generated.header("x-request-id").unwrap_or_default().is_empty());
+}
+
+#[test]
+fn standard_response_headers() {
+    let Some(server) = common::server() else { return };
+    let response = server.get("/healthz").send();
+    assert_eq!(response.header("content-length"), Some(response.body.len().to_string().as_str()));
+    assert!(response.header("date").unwrap().ends_with(" GMT"));
+    assert!(response.header("server").unwrap().starts_with("rust-crud-api/"));
+    assert_eq!(response.header("connection"), Some("close"));
+
+    // Streamed bodies are chunked instead
+    let response = server.get("/users/all?limit=1").send();
+    assert_eq!((response.header("transfer-encoding"), response.header("content-length")), (Some("chunked"), None));
+    assert!(response.header("date").is_some());
+}
+
+#[test]
+fn audit_records_mutations() {
+    let Some(server) = common::server() else { return };
+    let editor = server.token_for("editor");
+    let body = json!({ "name": "Audited", "email": common::unique_email("audit") });
+    let id = server.post("/users").bearer(&editor).json(body).send().json()["id"].as_i64().unwrap();
+
+    let actor = audit_actor(editor.split('.').nth(1).unwrap());
+    let page = server.get(&format!("/audit?actor={}", actor)).admin(server).send().json();
+    let entry = &page["entries"][0];
+    assert_eq!((entry["action"].as_str(), entry["entity_id"].as_i64()), (Some("POST"), Some(id)));
+    assert_eq!(entry["status"], 201);
+    assert_eq!(entry["new_value"]["name"], "Audited");
+}
+
+#[test]
+fn admin_seed() {
+    let Some(server) = common::server() else { return };
+    let response = server.post("/admin/seed").admin(server).json(json!({ "users": 2, "posts_per_user": 1, "fake": true })).send();
+    assert_eq!(response.status, 201);
+    let report = response.json();
+    assert_eq!(report["users_created"].as_u64().unwrap() + report["users_skipped"].as_u64().unwrap(), 2);
+    assert!(report["seed"].is_u64());
+
+    let response = server.post("/admin/seed").admin(server).json(json!({ "users": 10001 })).send();
+    assert_eq!(response.status, 422);
+    assert_eq!(server.post("/admin/seed").admin(server).json(json!({})).send().status, 400);
+}
+
+// `user:<id>` from the payload of a token, which is base64url JSON with the id in `sub`
+fn audit_actor(payload: &str) -> String {
+    let bytes = URL_SAFE_NO_PAD.decode(payload).expect("base64url");
+    let claims: serde_json::Value = serde_json::from_slice(&bytes).expect("token claims");
+    format!("user:{}", claims["sub"].as_str().expect("sub"))
+}
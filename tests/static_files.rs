@@ -0,0 +1,101 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use uuid::Uuid;
+
+static STATIC_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// The shared server, serving files from a directory of its own
+fn server() -> Option<&'static common::TestServer> {
+    STATIC_DIR.get_or_init(|| {
+        let dir = env::temp_dir().join(format!("rust-crud-api-static-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("css")).expect("a static directory");
+        fs::create_dir_all(dir.join("guide")).expect("a static directory");
+        fs::create_dir_all(dir.join("admin")).expect("a static directory");
+        fs::write(dir.join("css/site.css"), "body { color: red; }").expect("a file");
+        fs::write(dir.join("logo.svg"), "<svg xmlns=\"http://www.w3.org/2000/svg\"/>").expect("a file");
+        fs::write(dir.join("data.bin"), [0u8, 1, 2]).expect("a file");
+        fs::write(dir.join("guide/index.html"), "<h1>Guide</h1>").expect("a file");
+        fs::write(dir.join(".env"), "SECRET=1").expect("a file");
+        fs::write(dir.join("admin/admin.css"), "body { color: blue; }").expect("a file");
+        // Read when the shared server starts, which is just below
+        env::set_var("STATIC_DIR", &dir);
+        env::set_var("STATIC_MAX_AGE", "600");
+        dir
+    });
+    common::server()
+}
+
+#[test]
+fn serves_files_with_their_types() {
+    let Some(server) = server() else { return };
+    let css = server.get("/v1/static/css/site.css").send();
+    assert_eq!(css.status, 200, "{}", css.text());
+    assert_eq!(css.text(), "body { color: red; }");
+    assert_eq!(css.header("content-type"), Some("text/css; charset=utf-8"));
+    assert_eq!(css.header("cache-control"), Some("public, max-age=600"));
+    assert_eq!(css.header("x-content-type-options"), Some("nosniff"));
+    assert!(css.header("last-modified").is_some());
+
+    assert_eq!(server.get("/v1/static/logo.svg").send().header("content-type"), Some("image/svg+xml"));
+    assert_eq!(server.get("/v1/static/data.bin").send().header("content-type"), Some("application/octet-stream"));
+
+    // A directory is its index page
+    let guide = server.get("/v1/static/guide/").send();
+    assert_eq!(guide.status, 200);
+    assert_eq!(guide.text(), "<h1>Guide</h1>");
+    assert_eq!(guide.header("content-type"), Some("text/html; charset=utf-8"));
+    assert_eq!(server.get("/v1/static/guide").send().status, 200);
+}
+
+#[test]
+fn revalidates_unchanged_files() {
+    let Some(server) = server() else { return };
+    let first = server.get("/v1/static/css/site.css").send();
+    let etag = first.header("etag").expect("an entity tag");
+    let again = server.get("/v1/static/css/site.css").header("If-None-Match", etag).send();
+    assert_eq!(again.status, 304);
+    assert!(again.body.is_empty());
+}
+
+#[test]
+fn stays_inside_its_directory() {
+    let Some(server) = server() else { return };
+    for path in [
+        "/v1/static/../Cargo.toml",
+        "/v1/static/css/../../Cargo.toml",
+        "/v1/static/%2e%2e/Cargo.toml",
+        "/v1/static/css%2F..%2F..%2FCargo.toml",
+        "/v1/static/%2Fetc%2Fpasswd",
+        "/v1/static/css%5c..%5csite.css",
+        "/v1/static/.env",
+        "/v1/static/%2eenv",
+        "/v1/static/missing.css",
+        "/v1/static/bad%zzescape",
+    ] {
+        let response = server.get(path).send();
+        assert_eq!(response.status, 404, "{}: {}", path, response.text());
+    }
+    // Percent-encoded names that stay inside are fine
+    assert_eq!(server.get("/v1/static/css/site%2ecss").send().status, 200);
+}
+
+#[test]
+fn replaces_built_in_pages() {
+    let Some(server) = server() else { return };
+    let css = server.get("/v1/admin/admin.css").send();
+    assert_eq!(css.status, 200);
+    assert_eq!(css.text(), "body { color: blue; }");
+    assert_eq!(css.header("x-frame-options"), Some("DENY"));
+
+    // Files the directory doesn't replace are the built-in ones
+    let js = server.get("/v1/admin/admin.js").send();
+    assert_eq!(js.status, 200);
+    assert_eq!(js.header("content-type"), Some("text/javascript; charset=utf-8"));
+    assert_eq!(js.header("cache-control"), Some("no-cache"));
+    assert_eq!(server.get("/v1/docs").send().status, 200);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::env;
+use std::sync::OnceLock;
+
+use serde_json::json;
+use uuid::Uuid;
+
+static TENANCY: OnceLock<bool> = OnceLock::new();
+
+// The shared server, with tenancy on. Tenants are Postgres schemas, so there is nothing to test
+// against SQLite or MySQL
+fn server() -> Option<&'static common::TestServer> {
+    let postgres = *TENANCY.get_or_init(|| {
+        let url = env::var("TEST_DATABASE_URL").unwrap_or_default();
+        // Read when the shared server starts, which is just below
+        env::set_var("TENANCY_ENABLED", "true");
+        !url.starts_with("sqlite:") && !url.starts_with("mysql:") && !url.starts_with("mariadb:")
+    });
+    match postgres {
+        true => common::server(),
+        false => None,
+    }
+}
+
+// A tenant id no other run has used
+fn unique_tenant() -> String {
+    format!("t{}", &Uuid::new_v4().simple().to_string()[..12])
+}
+
+#[test]
+fn tenants_have_datasets_of_their_own() {
+    let Some(server) = server() else { return };
+    let tenant = unique_tenant();
+
+    let created = server.post("/tenants").admin(server).json(json!({ "id": tenant })).send();
+    assert_eq!(created.status, 201, "{}", created.text());
+    assert_eq!(created.header("location"), Some(format!("/tenants/{}", tenant).as_str()));
+    let body = created.json();
+    assert_eq!(body["id"], tenant.as_str());
+    assert_eq!(body["api_key"]["role"], "admin");
+    let key = body["api_key"]["key"].as_str().expect("the tenant's API key").to_string();
+    let again = server.post("/tenants").admin(server).json(json!({ "id": tenant })).send();
+    assert_eq!((again.status, again.error_code().as_str()), (409, "tenant_exists"));
+    assert_eq!(server.get(&format!("/tenants/{}", tenant)).admin(server).send().json()["id"], tenant.as_str());
+
+    // A user made in the tenant is only found there
+    let email = common::unique_email("tenant");
+    let user = server
+        .post("/users")
+        .header("X-Tenant-Id", &tenant)
+        .header("X-Api-Key", &key)
+        .json(json!({ "name": "Tenant User", "email": email }))
+        .send();
+    assert_eq!(user.status, 201, "{}", user.text());
+    let path = format!("/users?email_contains={}", email);
+    let listed = server.get(&path).header("X-Tenant-Id", &tenant).send().json();
+    assert_eq!(listed["users"].as_array().map(Vec::len), Some(1));
+    let listed = server.get(&path).send().json();
+    assert_eq!(listed["users"].as_array().map(Vec::len), Some(0));
+
+    // Credentials only work in the dataset they belong to
+    let outside = server.post("/users").header("X-Api-Key", &key).json(json!({ "name": "Nope", "email": email })).send();
+    assert_eq!((outside.status, outside.error_code().as_str()), (401, "invalid_api_key"));
+    let admin = server.post("/users").admin(server).header("X-Tenant-Id", &tenant);
+    let admin = admin.json(json!({ "name": "Nope", "email": email })).send();
+    assert_eq!((admin.status, admin.error_code().as_str()), (401, "invalid_token"));
+
+    let id = user.json()["id"].as_i64().expect("user id");
+    let set = server
+        .put(&format!("/users/{}/password", id))
+        .header("X-Tenant-Id", &tenant)
+        .header("X-Api-Key", &key)
+        .json(json!({ "password": common::PASSWORD }))
+        .send();
+    assert_eq!(set.status, 204, "{}", set.text());
+    let login = server.post("/auth/login").header("X-Tenant-Id", &tenant);
+    let login = login.json(json!({ "email": email, "password": common::PASSWORD })).send();
+    assert_eq!(login.status, 200, "{}", login.text());
+    let token = login.json()["access_token"].as_str().expect("access_token").to_string();
+    let user = json!({ "name": "Viewer's User", "email": common::unique_email("tenant") });
+    // Known to the tenant, though a viewer may not make users; unknown to the default dataset
+    let inside = server.post("/users").bearer(&token).header("X-Tenant-Id", &tenant).json(user.clone()).send();
+    assert_eq!(inside.status, 403, "{}", inside.text());
+    let outside = server.post("/users").bearer(&token).json(user).send();
+    assert_eq!((outside.status, outside.error_code().as_str()), (401, "invalid_token"));
+
+    let deleted = server.delete(&format!("/tenants/{}", tenant)).admin(server).send();
+    assert_eq!(deleted.status, 204, "{}", deleted.text());
+    let gone = server.get("/users").header("X-Tenant-Id", &tenant).send();
+    assert_eq!((gone.status, gone.error_code().as_str()), (404, "tenant_not_found"));
+    assert_eq!(server.delete(&format!("/tenants/{}", tenant)).admin(server).send().status, 404);
+}
+
+#[test]
+fn tenants_are_managed_by_admins() {
+    let Some(server) = server() else { return };
+
+    assert_eq!(server.get("/tenants").send().status, 401);
+    let editor = server.token_for("editor");
+    let created = server.post("/tenants").bearer(&editor).json(json!({ "id": unique_tenant() })).send();
+    assert_eq!(created.status, 403);
+    let invalid = server.post("/tenants").admin(server).json(json!({ "id": "Not_Valid" })).send();
+    assert_eq!((invalid.status, invalid.error_code().as_str()), (422, "validation_failed"));
+
+    let unknown = server.get("/users").header("X-Tenant-Id", &unique_tenant()).send();
+    assert_eq!((unknown.status, unknown.error_code().as_str()), (404, "tenant_not_found"));
+    let invalid = server.get("/users").header("X-Tenant-Id", "../public").send();
+    assert_eq!((invalid.status, invalid.error_code().as_str()), (400, "invalid_tenant"));
+    let listed = server.get("/tenants").admin(server).send();
+    assert_eq!(listed.status, 200);
+    assert!(listed.json()["tenants"].is_array());
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::env;
+use std::sync::OnceLock;
+
+use serde_json::json;
+
+static TRAILING_SLASH: OnceLock<()> = OnceLock::new();
+
+// The shared server, redirecting paths with a trailing slash to the ones without
+fn server() -> Option<&'static common::TestServer> {
+    TRAILING_SLASH.get_or_init(|| {
+        // Read when the shared server starts, which is just below
+        env::set_var("TRAILING_SLASH", "redirect");
+    });
+    common::server()
+}
+
+#[test]
+fn equivalent_paths_route_alike() {
+    let Some(server) = server() else { return };
+    let user = server.create_user("urls");
+    let id = user["id"].as_i64().expect("user id");
+    let escaped: String = id.to_string().bytes().map(|digit| format!("%{:x}", digit)).collect();
+
+    for path in [
+        format!("/v1/users/{}", escaped),
+        format!("/v1//users///{}", id),
+        format!("//v1/users/./{}", id),
+        format!("/v1/posts/../users/{}", id),
+        format!("/v1/../../users/{}", id),
+        format!("/%76%31/%75sers/{}", id),
+        format!("/users/{}", escaped),
+    ] {
+        let response = server.get(&path).send();
+        assert_eq!(response.status, 200, "{}: {}", path, response.text());
+        assert_eq!(response.json()["id"], id, "{}", path);
+    }
+
+    // Escapes of characters that mean something in a path stay escaped
+    let response = server.get(&format!("/v1/users%2F{}", id)).send();
+    assert_eq!(response.status, 404);
+    let response = server.get("/v1/users/1%3F").send();
+    assert_eq!(response.status, 400, "{}", response.text());
+}
+
+#[test]
+fn trailing_slashes_redirect() {
+    let Some(server) = server() else { return };
+    let response = server.get("/v1/users/?limit=1&sort=id").send();
+    assert_eq!((response.status, response.reason.as_str()), (308, "Permanent Redirect"));
+    assert_eq!(response.header("location"), Some("/v1/users?limit=1&sort=id"));
+
+    // The redirect keeps the method; it's sent before credentials are checked
+    let response = server.post("/v1//users//").json(json!({ "name": "Slash", "email": "slash@example.test" })).send();
+    assert_eq!(response.status, 308);
+    assert_eq!(response.header("location"), Some("/v1/users"));
+
+    // Paths nothing would match either way are left to 404; unprefixed ones go to their successor
+    assert_eq!(server.get("/v1/nothing/").send().status, 404);
+    let response = server.get("/healthz/").send();
+    assert_eq!(response.status, 308);
+    assert_eq!(response.header("location"), Some("/v1/healthz"));
+    assert!(response.header("deprecation").is_some());
+}
@@ -0,0 +1,459 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::unique_email;
+use serde_json::{json, Value};
+
+#[test]
+fn create_get_and_list_users() {
+    let Some(server) = common::server() else { return };
+    let email = unique_email("create");
+    let created = server.post("/users").admin(server).json(json!({ "name": "Grace Hopper", "email": email })).send();
+    assert_eq!(created.status, 201);
+    let user = created.json();
+    let id = user["id"].as_i64().unwrap();
+    assert_eq!(created.header("location"), Some(format!("/users/{}", id).as_str()));
+    assert_eq!(created.header("etag"), Some("\"1\""));
+    assert_eq!(user["email"], email.as_str());
+
+    let fetched = server.get(&format!("/users/{}", id)).send();
+    assert_eq!(fetched.status, 200);
+    assert_eq!(fetched.json()["name"], "Grace Hopper");
+
+    let listed = server.get(&format!("/users?email_contains={}&limit=5", email)).send();
+    assert_eq!(listed.status, 200);
+    let page = listed.json();
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["users"][0]["id"], id);
+
+    let count = server.get(&format!("/users/count?email_contains={}", email)).send();
+    assert_eq!(count.json()["count"], 1);
+}
+
+#[test]
+fn conditional_get() {
+    let Some(server) = common::server() else { return };
+    let user = server.create_user("conditional");
+    let path = format!("/users/{}", user["id"]);
+    let first = server.get(&path).send();
+    let etag = first.header("etag").unwrap().to_string();
+    let last_modified = first.header("last-modified").unwrap().to_string();
+    assert!(last_modified.ends_with(" GMT"), "{}", last_modified);
+
+    let cached = server.get(&path).header("If-None-Match", &format!("\"0\", W/{}", etag)).send();
+    assert_eq!(cached.status, 304);
+    assert!(cached.body.is_empty());
+    assert_eq!((cached.header("etag"), cached.header("content-length")), (Some(etag.as_str()), None));
+    assert_eq!(server.get(&path).header("If-Modified-Since", &last_modified).send().status, 304);
+    assert_eq!(server.get(&path).header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT").send().status, 200);
+
+    let patched = server.patch(&path).admin(server).json(json!({ "name": "Changed" })).send();
+    assert_eq!(patched.status, 200);
+    assert_eq!(server.get(&path).header("If-None-Match", &etag).send().status, 200);
+
+    // Lists get an entity tag computed from the page
+    let list = format!("/users?email_contains={}", user["email"].as_str().unwrap());
+    let page = server.get(&list).send();
+    let etag = page.header("etag").unwrap().to_string();
+    assert_eq!(server.get(&list).header("If-None-Match", &etag).send().status, 304);
+}
+
+#[test]
+fn create_rejects_bad_input() {
+    let Some(server) = common::server() else { return };
+    let response = server.post("/users").admin(server).body("application/json", "{not json").send();
+    assert_eq!((response.status, response.error_code().as_str()), (400, "invalid_json"));
+
+    let response = server.post("/users").admin(server).json(json!({ "name": "", "email": "nope" })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (422, "validation_failed"));
+    let fields = &response.json()["error"]["details"]["fields"];
+    assert!(fields.get("name").is_some() && fields.get("email").is_some());
+
+    let email = unique_email("taken");
+    server.post("/users").admin(server).json(json!({ "name": "First", "email": email })).send();
+    let response = server.post("/users").admin(server).json(json!({ "name": "Second", "email": email })).send();
+    assert_eq!((response.status, response.error_code().as_str()), (409, "email_taken"));
+}
+
+#[test]
+fn idempotency_keys() {
+    let Some(server) = common::server() else { return };
+    let key = unique_email("idempotency");
+    let body = json!({ "name": "Once", "email": unique_email("once") });
+    let create = || server.post("/users").admin(server).header("Idempotency-Key", &key).json(body.clone()).send();
+    let first = create();
+    assert_eq!((first.status, first.header("idempotent-replayed")), (201, None));
+
+    let repeat = create();
+    assert_eq!((repeat.status, repeat.header("idempotent-replayed")), (201, Some("true")));
+    assert_eq!(repeat.json()["id"], first.json()["id"]);
+    assert_eq!(repeat.header("location"), first.header("location"));
+
+    let other = json!({ "name": "Twice", "email": unique_email("twice") });
+    let other = server.post("/users").admin(server).header("Idempotency-Key", &key).json(other).send();
+    assert_eq!((other.status, other.error_code().as_str()), (422, "idempotency_key_reused"));
+
+    // Keys belong to the caller that sent them
+    let editor = server.token_for("editor");
+    let response = server.post("/users").bearer(&editor).header("Idempotency-Key", &key).json(body.clone()).send();
+    assert_eq!((response.status, response.error_code().as_str()), (409, "email_taken"));
+}
+
+#[test]
+fn writes_need_credentials_and_roles() {
+    let Some(server) = common::server() else { return };
+    let body = json!({ "name": "Nobody", "email": unique_email("anon") });
+    let response = server.post("/users").json(body.clone()).send();
+    assert_eq!(response.status, 401);
+    assert_eq!(response.header("www-authenticate"), Some("Bearer"));
+
+    let viewer = server.token_for("viewer");
+    let response = server.post("/users").bearer(&viewer).json(body.clone()).send();
+    assert_eq!((response.status, response.error_code().as_str()), (403, "forbidden"));
+
+    let editor = server.token_for("editor");
+    assert_eq!(server.post("/users").bearer(&editor).json(body).send().status, 201);
+    let id = server.create_user("editor-delete")["id"].as_i64().unwrap();
+    assert_eq!(server.delete(&format!("/users/{}", id)).bearer(&editor).send().status, 403);
+}
+
+#[test]
+fn missing_and_malformed_ids() {
+    let Some(server) = common::server() else { return };
+    let response = server.get("/users/2147483647").send();
+    assert_eq!((response.status, response.error_code().as_str()), (404, "not_found"));
+    assert_eq!(server.get("/users/abc").send().status, 400);
+    assert_eq!(server.put("/users/2147483647/role").admin(server).json(json!({ "role": "editor" })).send().status, 404);
+}
+
+#[test]
+fn put_replaces_guards_with_if_match_and_upserts() {
+    let Some(server) = common::server() else { return };
+    let id = server.create_user("put")["id"].as_i64().unwrap();
+    let path = format!("/users/{}", id);
+
+    let email = unique_email("put-new");
+    let response = server.put(&path).admin(server).json(json!({ "name": "Renamed", "email": email })).send();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("etag"), Some("\"2\""));
+
+    let stale = server.put(&path).admin(server).header("If-Match", "\"1\"").json(json!({ "name": "Stale", "email": email })).send();
+    assert_eq!((stale.status, stale.error_code().as_str()), (412, "precondition_failed"));
+    let fresh = server.put(&path).admin(server).header("If-Match", "\"2\"").json(json!({ "name": "Fresh", "email": email })).send();
+    assert_eq!(fresh.status, 200);
+
+    // Upsert creates at an id nobody has used yet
+    let unused = id + 1_000_000;
+    let response = server
+        .put(&format!("/users/{}", unused))
+        .admin(server)
+        .json(json!({ "name": "Upserted", "email": unique_email("upsert") }))
+        .send();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.json()["id"], unused);
+}
+
+#[test]
+fn patch_formats() {
+    let Some(server) = common::server() else { return };
+    let id = server.create_user("patch")["id"].as_i64().unwrap();
+    let path = format!("/users/{}", id);
+
+    let response = server.patch(&path).admin(server).json(json!({ "name": "Plain" })).send();
+    assert_eq!((response.status, response.json()["name"].as_str()), (200, Some("Plain")));
+
+    let response = server.patch(&path).admin(server).body("application/merge-patch+json", r#"{"name":"Merged"}"#).send();
+    assert_eq!((response.status, response.json()["name"].as_str()), (200, Some("Merged")));
+
+    let ops = r#"[{"op":"test","path":"/name","value":"Merged"},{"op":"replace","path":"/name","value":"Patched"}]"#;
+    let response = server.patch(&path).admin(server).body("application/json-patch+json", ops).send();
+    assert_eq!((response.status, response.json()["name"].as_str()), (200, Some("Patched")));
+
+    let ops = r#"[{"op":"replace","path":"/id","value":1}]"#;
+    let response = server.patch(&path).admin(server).body("application/json-patch+json", ops).send();
+    assert_eq!((response.status, response.error_code().as_str()), (422, "patch_failed"));
+
+    let response = server.patch(&path).admin(server).body("text/plain", "name=x").send();
+    assert_eq!((response.status, response.error_code().as_str()), (415, "unsupported_media_type"));
+}
+
+#[test]
+fn delete_and_restore() {
+    let Some(server) = common::server() else { return };
+    let id = server.create_user("delete")["id"].as_i64().unwrap();
+    let path = format!("/users/{}", id);
+
+    assert_eq!(server.delete(&path).admin(server).send().status, 204);
+    assert_eq!(server.get(&path).send().status, 404);
+    assert_eq!(server.get(&format!("{}?include_deleted=true", path)).send().status, 200);
+    assert_eq!(server.delete(&path).admin(server).send().status, 404);
+
+    let restored = server.post(&format!("{}/restore", path)).admin(server).send();
+    assert_eq!(restored.status, 200);
+    assert_eq!(server.get(&path).send().status, 200);
+}
+
+#[test]
+fn batch_create_patch_and_delete() {
+    let Some(server) = common::server() else { return };
+    let taken = unique_email("batch-taken");
+    server.post("/users").admin(server).json(json!({ "name": "Taken", "email": taken })).send();
+
+    let items = json!([
+        { "name": "One", "email": unique_email("batch") },
+        { "name": "", "email": unique_email("batch") },
+        { "name": "Three", "email": taken },
+    ]);
+    let response = server.post("/users/batch").admin(server).json(items).send();
+    assert_eq!(response.status, 207);
+    let result = response.json();
+    assert_eq!((result["succeeded"].as_u64(), result["failed"].as_u64()), (Some(1), Some(2)));
+    let statuses: Vec<u64> = result["results"].as_array().unwrap().iter().map(|r| r["status"].as_u64().unwrap()).collect();
+    assert_eq!(statuses, [201, 422, 409]);
+    let id = result["results"][0]["user"]["id"].as_i64().unwrap();
+
+    let response = server.patch("/users/batch").admin(server).json(json!([{ "id": id, "name": "Uno" }, { "id": 2147483647 }])).send();
+    assert_eq!(response.status, 207);
+    assert_eq!(response.json()["results"][0]["user"]["name"], "Uno");
+
+    let response = server.delete(&format!("/users?ids={},2147483647", id)).admin(server).send();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.json(), json!({ "deleted": 1, "ids": [id], "not_found": [2147483647] }));
+    assert_eq!(server.delete("/users?ids=x").admin(server).send().status, 400);
+
+    let too_many: Vec<_> = (0..1001).map(|_| json!({})).collect();
+    let response = server.post("/users/batch").admin(server).json(json!(too_many)).send();
+    assert_eq!((response.status, response.error_code().as_str()), (400, "batch_too_large"));
+}
+
+#[test]
+fn import_and_export_csv() {
+    let Some(server) = common::server() else { return };
+    let (first, second) = (unique_email("import"), unique_email("import"));
+    let csv = format!("name,email,ignored\nAda,{},x\nBad Row\n,{},y\n", first, second);
+    let response = server.post("/users/import").admin(server).body("text/csv", csv).send();
+    assert_eq!(response.status, 207);
+    let report = response.json();
+    assert_eq!((report["created"].as_u64(), report["failed"].as_u64()), (Some(1), Some(2)));
+
+    let response = server.post("/users/import").admin(server).json(json!({})).send();
+    assert_eq!(response.status, 415);
+
+    let response = server.get(&format!("/users/export?email_contains={}", first)).header("Accept", "text/csv").send();
+    assert_eq!(response.status, 200);
+    assert!(response.header("content-disposition").unwrap().contains("users.csv"));
+    assert!(response.text().contains(&first));
+
+    let response = server.get("/users/export").header("Accept", "image/png").send();
+    assert_eq!(response.status, 406);
+}
+
+#[test]
+fn list_options_and_errors() {
+    let Some(server) = common::server() else { return };
+    for _ in 0..3 {
+        server.create_user("page");
+    }
+    let response = server.get("/users?limit=2&sort=id").send();
+    let page = response.json();
+    assert_eq!(page["users"].as_array().unwrap().len(), 2);
+    let cursor = page["next_cursor"].as_str().expect("a next cursor");
+    let next = server.get(&format!("/users?limit=2&after={}", cursor)).send().json();
+    assert!(next["users"][0]["id"].as_i64() > page["users"][1]["id"].as_i64());
+
+    assert_eq!(server.get("/users?limit=0").send().error_code(), "invalid_pagination");
+    assert_eq!(server.get("/users?sort=password").send().error_code(), "invalid_query_parameter");
+    assert_eq!(server.get("/users?after=garbage").send().error_code(), "invalid_cursor");
+
+    let streamed = server.get("/users/all?email_contains=page-").send();
+    assert_eq!(streamed.status, 200);
+    assert!(streamed.json().as_array().unwrap().len() >= 3);
+}
+
+#[test]
+fn field_selection() {
+    let Some(server) = common::server() else { return };
+    // This run's users share a part of their emails no other users have
+    let email = unique_email("fields");
+    let run = email.split('@').next().unwrap();
+    let users: Vec<Value> = ["Fields B", "Fields A", "Fields C"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let body = json!({ "name": name, "email": format!("{}.{}@example.test", run, i) });
+            server.post("/users").admin(server).json(body).send().json()
+        })
+        .collect();
+
+    // Filtering and sorting still use the columns left out
+    let page = server.get(&format!("/users?fields=email&sort=name&email_contains={}", run)).send().json();
+    let listed = page["users"].as_array().unwrap();
+    assert!(listed.iter().all(|user| user.as_object().unwrap().keys().eq(["_links", "email"])), "{}", page);
+    let emails: Vec<&Value> = listed.iter().map(|user| &user["email"]).collect();
+    assert_eq!(emails, [&users[1]["email"], &users[0]["email"], &users[2]["email"]]);
+
+    let page = server.get(&format!("/users?fields=id,%20name,id&name=Fields%20A&email_contains={}", run)).send().json();
+    let links = user_links(&users[1]);
+    assert_eq!(page["users"][0], json!({ "id": users[1]["id"], "name": "Fields A", "_links": links }), "{}", page);
+
+    let path = format!("/users/{}?fields=name,version", users[2]["id"]);
+    let user = server.get(&path).send();
+    // Links aren't fields, so they stay; the user's id is in the URL they were asked at
+    assert_eq!(user.json(), json!({ "name": "Fields C", "version": 1, "_links": user_links(&users[2]) }));
+    assert!(user.header("etag").is_some());
+
+    for fields in ["password_hash", "", "id,role"] {
+        let response = server.get(&format!("/users?fields={}", fields)).send();
+        assert_eq!((response.status, response.error_code().as_str()), (400, "invalid_query_parameter"), "{}", fields);
+    }
+}
+
+#[test]
+fn search_and_stats() {
+    let Some(server) = common::server() else { return };
+    let marker = format!("Zebulon{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    server.post("/users").admin(server).json(json!({ "name": marker, "email": unique_email("search") })).send();
+
+    let response = server.get(&format!("/users/search?q={}", marker)).send();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.json()["users"][0]["name"], marker.as_str());
+    assert_eq!(server.get("/users/search").send().status, 400);
+
+    let stats = server.get("/users/stats?days=7").send();
+    assert_eq!(stats.status, 200);
+    assert!(stats.json()["total"].as_i64().unwrap() > 0);
+    assert_eq!(server.get("/users/stats?days=0").send().status, 400);
+}
+
+#[test]
+fn passwords_and_roles() {
+    let Some(server) = common::server() else { return };
+    let id = server.create_user("password")["id"].as_i64().unwrap();
+    let path = format!("/users/{}/password", id);
+    let response = server.put(&path).admin(server).json(json!({ "password": "short" })).send();
+    assert_eq!(response.status, 422);
+    assert_eq!(server.put(&path).admin(server).json(json!({ "password": common::PASSWORD })).send().status, 204);
+
+    let response = server.put(&format!("/users/{}/role", id)).admin(server).json(json!({ "role": "owner" })).send();
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn user_events_stream() {
+    let Some(server) = common::server() else { return };
+    let mut events = open_events(server);
+    let user = server.create_user("events");
+    server.patch(&format!("/users/{}", user["id"])).admin(server).json(json!({ "name": "Renamed" })).send();
+    let seen = [next_event(&mut events, &user["id"]), next_event(&mut events, &user["id"])];
+    assert_eq!((seen[0].0.as_str(), &seen[0].1["name"]), ("user.created", &json!("Test User")));
+    assert_eq!((seen[1].0.as_str(), &seen[1].1["name"]), ("user.updated", &json!("Renamed")));
+}
+
+#[test]
+fn user_events_include_changes_made_outside_the_api() {
+    let Some(server) = common::server() else { return };
+    let database_url = std::env::var("TEST_DATABASE_URL").unwrap();
+    if !database_url.starts_with("postgres") {
+        return;
+    }
+    let user = server.create_user("outside");
+    let mut events = open_events(server);
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let (client, connection) = tokio_postgres::connect(&database_url, tokio_postgres::NoTls).await.unwrap();
+        tokio::spawn(connection);
+        let id = user["id"].as_i64().unwrap() as i32;
+        // Neither a password nor a role is visible, so this is only announced once
+        client.execute("UPDATE users SET password_hash = 'x' WHERE id = $1", &[&id]).await.unwrap();
+        client.execute("UPDATE users SET name = 'Renamed in SQL' WHERE id = $1", &[&id]).await.unwrap();
+        client.execute("DELETE FROM users WHERE id = $1", &[&id]).await.unwrap();
+    });
+    let (event, data) = next_event(&mut events, &user["id"]);
+    assert_eq!((event.as_str(), &data["name"]), ("user.updated", &json!("Renamed in SQL")));
+    assert_eq!(next_event(&mut events, &user["id"]), ("user.deleted".to_string(), json!({ "id": user["id"] })));
+}
+
+// GET /users/events, read up to its opening comment so the subscription is in place
+fn open_events(server: &common::TestServer) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let request = format!("GET /users/events HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\r\n", server.admin);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut lines = Vec::new();
+    while !lines.iter().any(|line: &String| line.starts_with(": connected")) {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("reading the stream");
+        lines.push(line.trim_end().to_string());
+    }
+    assert_eq!(lines[0], "HTTP/1.1 200 OK");
+    assert!(lines.iter().any(|line| line.eq_ignore_ascii_case("content-type: text/event-stream")), "{:?}", lines);
+    reader
+}
+
+// The type and data of the next event about user `id`; other tests change users too
+fn next_event(events: &mut BufReader<TcpStream>, id: &Value) -> (String, Value) {
+    let mut event = String::new();
+    loop {
+        let mut line = String::new();
+        events.read_line(&mut line).expect("reading the stream");
+        if let Some(name) = line.trim_end().strip_prefix("event: ") {
+            event = name.to_string();
+        } else if let Some(data) = line.trim_end().strip_prefix("data: ") {
+            let data: Value = serde_json::from_str(data).unwrap();
+            assert_eq!(data["type"].as_str(), Some(event.as_str()));
+            if &data["data"]["id"] == id {
+                return (event, data["data"].clone());
+            }
+        }
+    }
+}
+
+// The `_links` of a user created through the API
+fn user_links(user: &Value) -> Value {
+    let href = format!("/v1/users/{}", user["id"]);
+    json!({ "self": { "href": href }, "collection": { "href": "/v1/users" }, "update": { "href": href }, "delete": { "href": href } })
+}
+
+#[test]
+fn hal_links() {
+    let Some(server) = common::server() else { return };
+    let email = unique_email("hal");
+    let run = email.split('@').next().unwrap();
+    let users: Vec<Value> = (0..3)
+        .map(|i| {
+            let body = json!({ "name": "Hal User", "email": format!("{}.{}@example.test", run, i) });
+            server.post("/users").admin(server).json(body).send().json()
+        })
+        .collect();
+    assert_eq!(users[0]["_links"], user_links(&users[0]));
+
+    // Followed as given, the links lead back to the user
+    let own = users[0]["_links"]["self"]["href"].as_str().unwrap();
+    assert_eq!(server.get(own).send().json()["id"], users[0]["id"]);
+    let renamed = server.patch(users[0]["_links"]["update"]["href"].as_str().unwrap()).admin(server).json(json!({ "name": "Hal Renamed" })).send();
+    assert_eq!(renamed.status, 200, "{}", renamed.text());
+
+    // Pages link to their neighbours in a Link header, keeping the rest of the query
+    let response = server.get(&format!("/users?email_contains={}&limit=1&offset=1", run)).send();
+    assert_eq!(response.json()["users"][0]["_links"], user_links(&users[1]));
+    let expected = [
+        format!("</v1/users?email_contains={}&limit=1&offset=0>; rel=\"first\"", run),
+        format!("</v1/users?email_contains={}&limit=1&offset=0>; rel=\"prev\"", run),
+        format!("</v1/users?email_contains={}&limit=1&offset=2>; rel=\"next\"", run),
+        format!("</v1/users?email_contains={}&limit=1&offset=2>; rel=\"last\"", run),
+    ];
+    assert_eq!(response.header("link"), Some(expected.join(", ").as_str()));
+
+    // Cursor pages only know the next one
+    let first = server.get(&format!("/users?email_contains={}&limit=1", run)).send().json();
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let response = server.get(&format!("/users?email_contains={}&limit=1&after={}", run, cursor)).send();
+    let next = response.json()["next_cursor"].as_str().unwrap_or_default().to_string();
+    let expected = format!("</v1/users?after={}&email_contains={}&limit=1>; rel=\"next\"", next, run);
+    assert_eq!(response.header("link"), Some(expected.as_str()));
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+#[test]
+fn webhooks_deliver_signed_events() {
+    let Some(server) = common::server() else { return };
+    let receiver = TcpListener::bind("127.0.0.1:0").expect("binding the receiver");
+    let url = format!("http://{}/hooks?source=e2e", receiver.local_addr().unwrap());
+
+    let response = server.post("/webhooks").admin(server).json(json!({ "url": url, "events": ["user.created"] })).send();
+    assert_eq!(response.status, 201, "{}", response.text());
+    assert_eq!(response.header("cache-control"), Some("no-store"));
+    let webhook = response.json();
+    let secret = webhook["secret"].as_str().unwrap().to_string();
+    assert!(secret.starts_with("whsec_"));
+    let path = format!("/webhooks/{}", webhook["id"]);
+    assert!(server.get(&path).admin(server).send().json().get("secret").is_none());
+
+    // Nor is it kept in the audit log, newest first, where other tests' entries may be mixed in
+    let audit = server.get("/audit?limit=500").admin(server).send().json();
+    let entries = audit["entries"].as_array().unwrap();
+    let entry = entries.iter().find(|entry| entry["path"] == "/v1/webhooks" && entry["new_value"]["id"] == webhook["id"]);
+    let entry = entry.expect("the webhook's audit entry");
+    assert_eq!(entry["new_value"]["secret"], "[redacted]");
+    assert!(!entry.to_string().contains(&secret));
+
+    // Other tests create users too, so wait for the delivery about this one
+    let user = server.create_user("webhook");
+    let (head, raw) = loop {
+        let (head, raw) = receive(&receiver);
+        let body: Value = serde_json::from_slice(&raw).expect("a JSON body");
+        if body["data"]["id"] == user["id"] {
+            break (head, raw);
+        }
+    };
+    let body: Value = serde_json::from_slice(&raw).unwrap();
+    assert!(head[0].starts_with("POST /hooks?source=e2e HTTP/1.1"), "{:?}", head);
+    assert_eq!(header(&head, "x-webhook-event"), Some("user.created"));
+    assert_eq!(body["type"], "user.created");
+    assert_eq!(header(&head, "x-webhook-id"), body["id"].as_str());
+    assert_eq!(body["data"]["email"], user["email"]);
+
+    let timestamp = header(&head, "x-webhook-timestamp").unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(&raw);
+    let signature = header(&head, "x-webhook-signature").unwrap().strip_prefix("sha256=").unwrap();
+    mac.verify_slice(&decode_hex(signature)).expect("a valid signature");
+
+    assert_eq!(server.delete(&path).admin(server).send().status, 204);
+    assert_eq!(server.get(&path).admin(server).send().status, 404);
+}
+
+#[test]
+fn webhook_validation_and_access() {
+    let Some(server) = common::server() else { return };
+    for body in [
+        json!({ "url": "ftp://example.com/", "events": ["user.created"] }),
+        json!({ "url": "https://example.com/", "events": [] }),
+        json!({ "url": "https://example.com/", "events": ["user.renamed"] }),
+        json!({ "url": "https://example.com/", "events": ["user.deleted"], "secret": "short" }),
+    ] {
+        let response = server.post("/webhooks").admin(server).json(body.clone()).send();
+        assert_eq!((response.status, response.error_code().as_str()), (422, "validation_failed"), "{}", body);
+    }
+
+    let editor = server.token_for("editor");
+    let body = json!({ "url": "https://example.com/", "events": ["user.created"] });
+    assert_eq!(server.post("/webhooks").bearer(&editor).json(body).send().status, 403);
+    assert_eq!(server.get("/webhooks").send().status, 401);
+}
+
+// Read one request and answer it 200
+fn receive(listener: &TcpListener) -> (Vec<String>, Vec<u8>) {
+    let (stream, _) = listener.accept().expect("a delivery");
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut head = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("a request line");
+        if line.trim_end().is_empty() {
+            break;
+        }
+        head.push(line.trim_end().to_string());
+    }
+    let length = header(&head, "content-length").and_then(|length| length.parse().ok()).expect("a Content-Length");
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).expect("the body");
+    reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+    (head, body)
+}
+
+fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    head.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+fn decode_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|at| u8::from_str_radix(&hex[at..at + 2], 16).expect("hex")).collect()
+}
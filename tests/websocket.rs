@@ -0,0 +1,126 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+// The handshake from RFC 6455 1.3
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+#[test]
+fn websocket_sends_subscribed_events() {
+    let Some(server) = common::server() else { return };
+    let watched = server.create_user("ws-watched");
+    let other = server.create_user("ws-other");
+
+    let mut socket = connect(server.addr);
+    send(&mut socket, 0x1, json!({ "type": "subscribe", "user_ids": [watched["id"]] }).to_string().as_bytes());
+    assert_eq!(receive_json(&mut socket), json!({ "type": "subscribed", "user_ids": [watched["id"]] }));
+
+    server.patch(&format!("/users/{}", other["id"])).admin(server).json(json!({ "name": "Other" })).send();
+    server.patch(&format!("/users/{}", watched["id"])).admin(server).json(json!({ "name": "Watched" })).send();
+    let event = receive_json(&mut socket);
+    assert_eq!((event["type"].as_str(), &event["data"]["id"]), (Some("user.updated"), &watched["id"]));
+    assert_eq!(event["data"]["name"], "Watched");
+    server.delete(&format!("/users/{}", watched["id"])).admin(server).send();
+    let event = receive_json(&mut socket);
+    assert_eq!(event["type"], "user.deleted");
+    assert_eq!(event["data"], json!({ "id": watched["id"] }));
+
+    // Bad messages are answered, and the session carries on
+    send(&mut socket, 0x1, br#"{"type": "unsubscribe"}"#);
+    assert_eq!(receive_json(&mut socket)["type"], "error");
+    send(&mut socket, 0x9, b"are you there");
+    assert_eq!(receive(&mut socket), (0xa, b"are you there".to_vec()));
+    send(&mut socket, 0x8, &1000u16.to_be_bytes());
+    assert_eq!(receive(&mut socket), (0x8, 1000u16.to_be_bytes().to_vec()));
+}
+
+#[test]
+fn websocket_handshake_errors() {
+    let Some(server) = common::server() else { return };
+    let response = server.get("/ws").send();
+    assert_eq!((response.status, response.header("upgrade")), (426, Some("websocket")));
+
+    let response = server
+        .get("/ws")
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "8")
+        .header("Sec-WebSocket-Key", KEY)
+        .send();
+    assert_eq!((response.status, response.header("sec-websocket-version")), (426, Some("13")));
+
+    let response = server
+        .get("/ws")
+        .header("Connection", "keep-alive, Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", "too short")
+        .send();
+    assert_eq!(response.status, 400);
+}
+
+// Complete the handshake, checking the server's answer to it
+fn connect(addr: std::net::SocketAddr) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let request = format!(
+        "GET /ws HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n",
+        KEY
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut head = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("reading the handshake");
+        if line.trim_end().is_empty() {
+            break;
+        }
+        head.push(line.trim_end().to_ascii_lowercase());
+    }
+    assert_eq!(head[0], "http/1.1 101 switching protocols");
+    for header in ["connection: upgrade", "upgrade: websocket", &format!("sec-websocket-accept: {}", ACCEPT.to_ascii_lowercase())] {
+        assert!(head.iter().any(|line| line == header), "{} is missing from {:?}", header, head);
+    }
+    reader
+}
+
+// Clients mask every frame they send
+fn send(socket: &mut BufReader<TcpStream>, opcode: u8, payload: &[u8]) {
+    assert!(payload.len() < 126);
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(at, byte)| byte ^ mask[at % 4]));
+    socket.get_mut().write_all(&frame).unwrap();
+}
+
+fn receive(socket: &mut BufReader<TcpStream>) -> (u8, Vec<u8>) {
+    let mut header = [0; 2];
+    socket.read_exact(&mut header).expect("a frame");
+    assert_eq!(header[0] & 0x80, 0x80, "a final frame");
+    assert_eq!(header[1] & 0x80, 0, "an unmasked frame");
+    let length = match header[1] & 0x7f {
+        126 => {
+            let mut length = [0; 2];
+            socket.read_exact(&mut length).unwrap();
+            u16::from_be_bytes(length) as usize
+        }
+        127 => panic!("unexpectedly large frame"),
+        length => length as usize,
+    };
+    let mut payload = vec![0; length];
+    socket.read_exact(&mut payload).unwrap();
+    (header[0] & 0x0f, payload)
+}
+
+fn receive_json(socket: &mut BufReader<TcpStream>) -> Value {
+    let (opcode, payload) = receive(socket);
+    assert_eq!(opcode, 0x1, "a text frame");
+    serde_json::from_slice(&payload).expect("a JSON message")
+}